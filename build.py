@@ -431,6 +431,8 @@ def build_flutter_deb(version, features):
         'cp ../res/cloudydesk-link.desktop tmpdeb/usr/share/applications/cloudydesk-link.desktop')
     system2(
         'cp ../res/startwm.sh tmpdeb/etc/cloudydesk/')
+    system2(
+        'cp ../res/session-templates.toml tmpdeb/etc/cloudydesk/')
     system2(
         'cp ../res/xorg.conf tmpdeb/etc/cloudydesk/')
     system2(
@@ -780,6 +782,7 @@ def main():
                     'cp res/cloudydesk-link.desktop tmpdeb/usr/share/applications/cloudydesk-link.desktop')
                 os.system('mkdir -p tmpdeb/etc/cloudydesk/')
                 os.system('cp -a res/startwm.sh tmpdeb/etc/cloudydesk/')
+                os.system('cp res/session-templates.toml tmpdeb/etc/cloudydesk/')
                 os.system('mkdir -p tmpdeb/etc/X11/cloudydesk/')
                 os.system('cp res/xorg.conf tmpdeb/etc/X11/cloudydesk/')
                 os.system('cp -a DEBIAN/* tmpdeb/DEBIAN/')
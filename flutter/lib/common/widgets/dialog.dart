@@ -822,7 +822,8 @@ void enterUserLoginDialog(
     SessionID sessionId,
     OverlayDialogManager dialogManager,
     String osAccountDescTip,
-    bool canRememberAccount) async {
+    bool canRememberAccount,
+    {List<String> sessionTemplates = const []}) async {
   await _connectDialog(
     sessionId,
     dialogManager,
@@ -830,6 +831,7 @@ void enterUserLoginDialog(
     osPasswordController: TextEditingController(),
     osAccountDescTip: osAccountDescTip,
     canRememberAccount: canRememberAccount,
+    sessionTemplates: sessionTemplates,
   );
 }
 
@@ -837,7 +839,8 @@ void enterUserLoginAndPasswordDialog(
     SessionID sessionId,
     OverlayDialogManager dialogManager,
     String osAccountDescTip,
-    bool canRememberAccount) async {
+    bool canRememberAccount,
+    {List<String> sessionTemplates = const []}) async {
   await _connectDialog(
     sessionId,
     dialogManager,
@@ -846,6 +849,7 @@ void enterUserLoginAndPasswordDialog(
     passwordController: TextEditingController(),
     osAccountDescTip: osAccountDescTip,
     canRememberAccount: canRememberAccount,
+    sessionTemplates: sessionTemplates,
   );
 }
 
@@ -857,8 +861,11 @@ _connectDialog(
   TextEditingController? passwordController,
   String? osAccountDescTip,
   bool canRememberAccount = true,
+  List<String> sessionTemplates = const [],
 }) async {
   final errUsername = ''.obs;
+  // Empty for the template picked before or the default one on the remote side.
+  var sessionTemplate = '';
   var rememberPassword = false;
   if (passwordController != null) {
     rememberPassword =
@@ -903,7 +910,8 @@ _connectDialog(
             sessionId: sessionId, name: 'os-password', value: osPassword);
       }
       gFFI.login(
-        osUsername,
+        // "username:template", see `res/session-templates.toml`.
+        sessionTemplate.isEmpty ? osUsername : '$osUsername:$sessionTemplate',
         osPassword,
         sessionId,
         password,
@@ -978,6 +986,22 @@ _connectDialog(
             controller: osPasswordController,
             autoFocus: false,
           ),
+          if (sessionTemplates.isNotEmpty)
+            DropdownButtonFormField<String>(
+              value: sessionTemplate,
+              decoration:
+                  InputDecoration(labelText: translate('Session template')),
+              items: [
+                DropdownMenuItem(value: '', child: Text(translate('Default'))),
+                ...sessionTemplates.map(
+                    (name) => DropdownMenuItem(value: name, child: Text(name))),
+              ],
+              onChanged: (v) {
+                if (v != null) {
+                  setState(() => sessionTemplate = v);
+                }
+              },
+            ).paddingOnly(top: 8),
           if (canRememberAccount)
             rememberWidget(
               translate('remember_account_tip'),
@@ -1914,8 +1938,174 @@ void changeBot({Function()? callback}) async {
   });
 }
 
+// The TOTP only, `mainHasValid2FaSync` also counts the other second factors.
+bool hasValidTotp() => bind.mainGetOptionSync(key: '2fa').isNotEmpty;
+
+void change2faWebhook({Function()? callback}) async {
+  if (bind.mainGetOptionSync(key: '2fa-webhook').isNotEmpty) {
+    await bind.mainSetOption(key: '2fa-webhook', value: '');
+    callback?.call();
+    return;
+  }
+  String errorText = '';
+  final urlController = TextEditingController();
+  final headerController = TextEditingController();
+  gFFI.dialogManager.show((setState, close, context) {
+    onSubmit() async {
+      final url = urlController.text.trim();
+      if (url == '') return;
+      final error = await bind.mainSet2FaWebhook(
+          url: url, header: headerController.text.trim());
+      if (error == '') {
+        callback?.call();
+        close();
+      } else {
+        errorText = translate(error);
+        setState(() {});
+      }
+    }
+
+    return CustomAlertDialog(
+      title: Text(translate('2FA webhook')),
+      content: Column(
+        crossAxisAlignment: CrossAxisAlignment.start,
+        children: [
+          SelectableText(translate('enable-2fa-webhook-tip'),
+                  style: TextStyle(fontSize: 12))
+              .marginOnly(bottom: 12),
+          TextField(
+            autofocus: true,
+            controller: urlController,
+            decoration: InputDecoration(labelText: translate('Webhook URL')),
+          ).workaroundFreezeLinuxMint(),
+          TextField(
+            controller: headerController,
+            decoration: InputDecoration(
+                labelText: translate('HTTP header'),
+                hintText: 'Authorization: Bearer ...'),
+          ).workaroundFreezeLinuxMint(),
+          if (errorText != '')
+            Text(errorText, style: TextStyle(color: Colors.red))
+                .marginOnly(top: 12),
+        ],
+      ),
+      actions: [
+        dialogButton('Cancel', onPressed: close, isOutline: true),
+        dialogButton('OK', onPressed: onSubmit),
+      ],
+      onSubmit: onSubmit,
+      onCancel: close,
+    );
+  });
+}
+
+void change2faEmail({Function()? callback}) async {
+  if (bind.mainGetOptionSync(key: '2fa-smtp').isNotEmpty) {
+    await bind.mainSetOption(key: '2fa-smtp', value: '');
+    callback?.call();
+    return;
+  }
+  String errorText = '';
+  bool loading = false;
+  String security = 'tls';
+  const labels = {
+    'server': 'SMTP server',
+    'port': 'Port',
+    'username': 'Username',
+    'password': 'Password',
+    'from': 'From',
+    'to': 'To',
+  };
+  final controllers = labels.map((k, _) => MapEntry(k, TextEditingController()));
+  gFFI.dialogManager.show((setState, close, context) {
+    onSubmit() async {
+      if (controllers['server']!.text.trim() == '') return;
+      final config = <String, dynamic>{'security': security};
+      controllers.forEach((k, v) => config[k] = v.text.trim());
+      config['port'] = int.tryParse(config['port']) ?? 0;
+      loading = true;
+      errorText = '';
+      setState(() {});
+      // A test email is sent before saving.
+      final error = await bind.mainSet2FaSmtp(config: jsonEncode(config));
+      if (error == '') {
+        callback?.call();
+        close();
+      } else {
+        errorText = translate(error);
+        loading = false;
+        setState(() {});
+      }
+    }
+
+    return CustomAlertDialog(
+      title: Text(translate('2FA email')),
+      content: Column(
+        crossAxisAlignment: CrossAxisAlignment.start,
+        children: [
+          SelectableText(translate('enable-2fa-email-tip'),
+                  style: TextStyle(fontSize: 12))
+              .marginOnly(bottom: 12),
+          ...labels.entries.map((e) => TextField(
+                controller: controllers[e.key],
+                obscureText: e.key == 'password',
+                decoration: InputDecoration(labelText: translate(e.value)),
+              ).workaroundFreezeLinuxMint()),
+          Row(children: [
+            Text(translate('Security')).marginOnly(right: 12),
+            DropdownButton<String>(
+              value: security,
+              items: ['tls', 'starttls', 'none']
+                  .map((e) => DropdownMenuItem(value: e, child: Text(e)))
+                  .toList(),
+              onChanged: (v) => setState(() => security = v ?? security),
+            ),
+          ]).marginOnly(top: 12),
+          if (errorText != '')
+            Text(errorText, style: TextStyle(color: Colors.red))
+                .marginOnly(top: 12),
+        ],
+      ),
+      actions: [
+        dialogButton('Cancel', onPressed: close, isOutline: true),
+        loading
+            ? CircularProgressIndicator()
+            : dialogButton('OK', onPressed: onSubmit),
+      ],
+      onCancel: close,
+    );
+  });
+}
+
+void show2faBackupCodesDialog({Function()? callback}) async {
+  final res = await bind.mainGenerate2FaBackupCodes();
+  callback?.call();
+  if (res.isEmpty) return;
+  final codes = List<String>.from(jsonDecode(res));
+  gFFI.dialogManager.show((setState, close, context) {
+    return CustomAlertDialog(
+      title: Text(translate('Backup codes')),
+      content: Column(
+        crossAxisAlignment: CrossAxisAlignment.start,
+        children: [
+          SelectableText(translate('backup-codes-tip'),
+                  style: TextStyle(fontSize: 12))
+              .marginOnly(bottom: 12),
+          SelectableText(codes.join('\n'),
+              style: TextStyle(fontFamily: 'monospace')),
+        ],
+      ),
+      actions: [
+        dialogButton('OK', onPressed: close),
+      ],
+      onSubmit: close,
+      onCancel: close,
+    );
+  });
+}
+
 void change2fa({Function()? callback}) async {
-  if (bind.mainHasValid2FaSync()) {
+  if (hasValidTotp()) {
     await bind.mainSetOption(key: "2fa", value: "");
     await bind.mainClearTrustedDevices();
     callback?.call();
@@ -2576,3 +2766,252 @@ Widget trustedDevicesTable(
         )),
   );
 }
+
+Future<List<dynamic>> _loadClipboardHistory(SessionID sessionId) async {
+  final value = await bind.sessionGetClipboardHistory(sessionId: sessionId);
+  if (value.isEmpty) return [];
+  try {
+    return jsonDecode(value);
+  } catch (e) {
+    debugPrint('Failed to decode clipboard history: $e');
+    return [];
+  }
+}
+
+void showClipboardHistoryDialog(SessionID sessionId, String peerId,
+    OverlayDialogManager dialogManager) async {
+  var entries = await _loadClipboardHistory(sessionId);
+  dialogManager.show((setState, close, context) {
+    reload() async {
+      final value = await _loadClipboardHistory(sessionId);
+      setState(() => entries = value);
+    }
+
+    push(int id) {
+      close();
+      bind.sessionPushClipboardHistory(sessionId: sessionId, entryId: id);
+    }
+
+    clear() async {
+      await bind.mainClearClipboardHistory(peerId: '');
+      await bind.mainClearClipboardHistory(peerId: peerId);
+      await reload();
+    }
+
+    Widget item(Map<String, dynamic> entry) {
+      final int id = entry['id'];
+      final List<dynamic> formats = entry['formats'] ?? [];
+      final String from =
+          (entry['peer'] ?? '').isEmpty ? translate('Local') : peerId;
+      final time = DateTime.fromMillisecondsSinceEpoch(entry['time'] ?? 0)
+          .toLocal()
+          .toString()
+          .split('.')
+          .first;
+      return ListTile(
+        title: Text(entry['preview'] ?? '',
+            maxLines: 2, overflow: TextOverflow.ellipsis),
+        subtitle: Text('$from, ${formats.join(', ')}, $time',
+            maxLines: 1, overflow: TextOverflow.ellipsis),
+        trailing: Row(
+          mainAxisSize: MainAxisSize.min,
+          children: [
+            IconButton(
+                icon: const Icon(Icons.copy),
+                onPressed: () =>
+                    bind.mainSetClipboardFromHistory(entryId: id)),
+            IconButton(
+                icon: const Icon(Icons.delete_outline),
+                onPressed: () async {
+                  await bind.mainRemoveClipboardHistory(entryId: id);
+                  await reload();
+                }),
+          ],
+        ),
+        onTap: () => push(id),
+      );
+    }
+
+    return CustomAlertDialog(
+      title: Text(translate('Clipboard history')),
+      content: SizedBox(
+        width: 500,
+        height: 360,
+        child: entries.isEmpty
+            ? Center(child: Text(translate('clipboard-history-tip')))
+            : ListView(
+                children: entries
+                    .map((e) => item(Map<String, dynamic>.from(e)))
+                    .toList(),
+              ),
+      ),
+      actions: [
+        dialogButton('Clear',
+            icon: const Icon(Icons.delete_sweep_outlined),
+            onPressed: clear,
+            isOutline: true),
+        dialogButton('Close', onPressed: close),
+      ],
+      onCancel: close,
+    );
+  });
+}
+
+Future<List<Map<String, dynamic>>> _loadInputMacros(SessionID sessionId) async {
+  final value = await bind.sessionGetOption(
+          sessionId: sessionId, arg: kOptionInputMacros) ??
+      '';
+  if (value.isEmpty) return [];
+  try {
+    return List<Map<String, dynamic>>.from(jsonDecode(value));
+  } catch (e) {
+    debugPrint('Failed to decode input macros: $e');
+    return [];
+  }
+}
+
+void _saveInputMacros(SessionID sessionId, List<Map<String, dynamic>> macros) {
+  bind.sessionPeerOption(
+      sessionId: sessionId, name: kOptionInputMacros, value: jsonEncode(macros));
+}
+
+void showInputMacrosDialog(
+    SessionID sessionId, OverlayDialogManager dialogManager) async {
+  final macros = await _loadInputMacros(sessionId);
+  dialogManager.show((setState, close, context) {
+    play(String script) {
+      close();
+      bind.sessionPlayInputMacro(sessionId: sessionId, script: script);
+    }
+
+    record() {
+      close();
+      bind.sessionStartInputMacroRecording(sessionId: sessionId);
+    }
+
+    edit(int? index) {
+      close();
+      showEditInputMacroDialog(sessionId, dialogManager, index: index);
+    }
+
+    delete(int index) {
+      setState(() => macros.removeAt(index));
+      _saveInputMacros(sessionId, macros);
+    }
+
+    Widget item(int index, Map<String, dynamic> macro) => ListTile(
+          title: Text(macro['name'] ?? '', overflow: TextOverflow.ellipsis),
+          subtitle: Text(macro['script'] ?? '',
+              maxLines: 1, overflow: TextOverflow.ellipsis),
+          trailing: Row(
+            mainAxisSize: MainAxisSize.min,
+            children: [
+              IconButton(
+                  icon: const Icon(Icons.edit), onPressed: () => edit(index)),
+              IconButton(
+                  icon: const Icon(Icons.delete_outline),
+                  onPressed: () => delete(index)),
+            ],
+          ),
+          onTap: () => play(macro['script'] ?? ''),
+        );
+
+    return CustomAlertDialog(
+      title: Text(translate('Input macros')),
+      content: SizedBox(
+        width: 400,
+        height: 300,
+        child: macros.isEmpty
+            ? Center(child: Text(translate('input-macros-tip')))
+            : ListView(
+                children: macros
+                    .asMap()
+                    .entries
+                    .map((e) => item(e.key, e.value))
+                    .toList(),
+              ),
+      ),
+      actions: [
+        dialogButton('Record',
+            icon: const Icon(Icons.fiber_manual_record_rounded),
+            onPressed: record,
+            isOutline: true),
+        dialogButton('Add',
+            icon: const Icon(Icons.add_rounded),
+            onPressed: () => edit(null),
+            isOutline: true),
+        dialogButton('Close', onPressed: close),
+      ],
+      onCancel: close,
+    );
+  });
+}
+
+// `index` is null for a new macro.
+void showEditInputMacroDialog(
+    SessionID sessionId, OverlayDialogManager dialogManager,
+    {int? index, String script = ''}) async {
+  final macros = await _loadInputMacros(sessionId);
+  final editIndex = index != null && index < macros.length ? index : null;
+  var name = '${translate('Input macros')} ${macros.length + 1}';
+  if (editIndex != null) {
+    name = macros[editIndex]['name'] ?? '';
+    script = macros[editIndex]['script'] ?? '';
+  }
+  final nameController = TextEditingController(text: name);
+  final scriptController = TextEditingController(text: script);
+  var error = '';
+  dialogManager.show((setState, close, context) {
+    submit() async {
+      final script = scriptController.text;
+      final err = await bind.sessionCheckInputMacro(
+          sessionId: sessionId, script: script);
+      if (err.isNotEmpty) {
+        setState(() => error = err);
+        return;
+      }
+      final macro = {'name': nameController.text.trim(), 'script': script};
+      if (editIndex == null) {
+        macros.add(macro);
+      } else {
+        macros[editIndex] = macro;
+      }
+      _saveInputMacros(sessionId, macros);
+      close();
+      showInputMacrosDialog(sessionId, dialogManager);
+    }
+
+    return CustomAlertDialog(
+      title: Text(translate('Input macros')),
+      content: SizedBox(
+        width: 500,
+        child: Column(
+          mainAxisSize: MainAxisSize.min,
+          children: [
+            TextField(
+              decoration: InputDecoration(labelText: translate('Name')),
+              controller: nameController,
+            ).workaroundFreezeLinuxMint(),
+            TextField(
+              minLines: 5,
+              maxLines: 10,
+              decoration: InputDecoration(
+                labelText: translate('Script'),
+                helperText: translate('input-macros-tip'),
+                helperMaxLines: 3,
+                errorText: error.isEmpty ? null : error,
+              ),
+              controller: scriptController,
+              autofocus: true,
+            ).workaroundFreezeLinuxMint(),
+          ],
+        ),
+      ),
+      actions: [
+        dialogButton('Cancel', onPressed: close, isOutline: true),
+        dialogButton('OK', onPressed: submit),
+      ],
+      onCancel: close,
+    );
+  });
+}
@@ -596,6 +596,8 @@ class QualityMonitor extends StatelessWidget {
                       _row(
                           "Codec", qualityMonitorModel.data.codecFormat ?? '-'),
                       _row("Chroma", qualityMonitorModel.data.chroma ?? '-'),
+                      _row("Transport",
+                          qualityMonitorModel.data.transport ?? '-'),
                     ],
                   ),
                 )
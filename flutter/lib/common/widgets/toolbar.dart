@@ -276,6 +276,61 @@ List<TTextMenu> toolbarControls(BuildContext context, String id, FFI ffi) {
       onPressed: () => sessionRefreshVideo(sessionId, pi),
     ));
   }
+  // clipboard history
+  if (isDefaultConn && isDesktop && perms['clipboard'] != false) {
+    v.add(TTextMenu(
+      child: Text(translate('Clipboard history')),
+      onPressed: () =>
+          showClipboardHistoryDialog(sessionId, id, ffi.dialogManager),
+    ));
+  }
+  // share window
+  if (isDefaultConn &&
+      isDesktop &&
+      pi.platformAdditions[kPlatformAdditionsSupportWindowShare] == true) {
+    v.add(TTextMenu(
+      child: Text(translate('Share window')),
+      onPressed: () =>
+          bind.sessionRequestShareableWindows(sessionId: sessionId),
+    ));
+  }
+  // input macros
+  if (isDefaultConn &&
+      isDesktop &&
+      perms['keyboard'] != false &&
+      !ffiModel.viewOnly) {
+    if (ffiModel.inputMacroRecording) {
+      v.add(TTextMenu(
+        child: Text(translate('Stop recording macro')),
+        onPressed: () async {
+          final script =
+              await bind.sessionStopInputMacroRecording(sessionId: sessionId);
+          showEditInputMacroDialog(sessionId, ffi.dialogManager,
+              script: script);
+        },
+      ));
+    } else if (ffiModel.inputMacroPlaying) {
+      v.add(TTextMenu(
+        child: Text(translate('Stop input macro')),
+        onPressed: () => bind.sessionStopInputMacro(sessionId: sessionId),
+      ));
+    } else {
+      v.add(TTextMenu(
+        child: Text(translate('Input macros')),
+        onPressed: () => showInputMacrosDialog(sessionId, ffi.dialogManager),
+      ));
+    }
+  }
+  // application audio
+  if (isDefaultConn &&
+      isDesktop &&
+      perms['audio'] != false &&
+      pi.platformAdditions[kPlatformAdditionsSupportAppAudio] == true) {
+    v.add(TTextMenu(
+      child: Text(translate('Capture application audio')),
+      onPressed: () => bind.sessionRequestAudioApps(sessionId: sessionId),
+    ));
+  }
   // record
   if (!(isDesktop || isWeb) &&
       (ffi.recordingModel.start || (perms["recording"] != false))) {
@@ -605,6 +660,33 @@ Future<List<TToggleMenu>> toolbarDisplayToggle(
         },
         child: Text(translate('Mute'))));
   }
+  // high fidelity audio
+  if (isDefaultConn && perms['audio'] != false) {
+    final value = bind.sessionGetToggleOptionSync(
+        sessionId: sessionId, arg: kOptionHifiAudio);
+    v.add(TToggleMenu(
+        value: value,
+        onChanged: (value) {
+          if (value == null) return;
+          bind.sessionToggleOption(
+              sessionId: sessionId, value: kOptionHifiAudio);
+        },
+        child: Text(translate('High fidelity audio'))));
+  }
+  // microphone passthrough
+  if (isDefaultConn &&
+      isDesktop &&
+      perms['audio'] != false &&
+      ffiModel.keyboard &&
+      pi.platformAdditions[kPlatformAdditionsSupportAppAudio] == true) {
+    v.add(TToggleMenu(
+        value: ffiModel.micPassthrough,
+        onChanged: (value) {
+          if (value == null) return;
+          bind.sessionSetMicPassthrough(sessionId: sessionId, enable: value);
+        },
+        child: Text(translate('Microphone passthrough'))));
+  }
   // file copy and paste
   // If the version is less than 1.2.4, file copy and paste is supported on Windows only.
   final isSupportIfPeer_1_2_3 = versionCmp(pi.version, '1.2.4') < 0 &&
@@ -873,6 +955,9 @@ List<TToggleMenu> toolbarKeyboardToggles(FFI ffi) {
 }
 
 bool showVirtualDisplayMenu(FFI ffi) {
+  if (ffi.ffiModel.pi.platform == kPeerPlatformLinux) {
+    return ffi.ffiModel.pi.isLinuxOutput;
+  }
   if (ffi.ffiModel.pi.platform != kPeerPlatformWindows) {
     return false;
   }
@@ -892,7 +977,7 @@ List<Widget> getVirtualDisplayMenuChildren(
   }
   final pi = ffi.ffiModel.pi;
   final privacyModeState = PrivacyModeState.find(id);
-  if (pi.isCloudyDeskIdd) {
+  if (pi.isCloudyDeskIdd || pi.isLinuxOutput) {
     final virtualDisplays = ffi.ffiModel.pi.CloudyDeskVirtualDisplays;
     final children = <Widget>[];
     for (var i = 0; i < kMaxVirtualDisplayCount; i++) {
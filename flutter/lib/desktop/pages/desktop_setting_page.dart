@@ -560,6 +560,10 @@ class _GeneralState extends State<_General> {
       children.add(_OptionCheckBox(
           context, 'Allow linux headless', kOptionAllowLinuxHeadless));
     }
+    if (!isWeb && bind.mainShowOption(key: kOptionWaylandHeadless)) {
+      children.add(_OptionCheckBox(
+          context, 'Allow unattended Wayland capture', kOptionWaylandHeadless));
+    }
     return _Card(title: 'Other', children: children);
   }
 
@@ -822,9 +826,16 @@ class _SafetyState extends State<_Safety> with AutomaticKeepAliveClientMixin {
     // Simple temp wrapper for PR check
     tmpWrapper() {
       RxBool has2fa = bind.mainHasValid2FaSync().obs;
+      RxBool hasTotp = hasValidTotp().obs;
       RxBool hasBot = bind.mainHasValidBotSync().obs;
+      RxBool hasWebhook =
+          bind.mainGetOptionSync(key: '2fa-webhook').isNotEmpty.obs;
+      RxBool hasEmail = bind.mainGetOptionSync(key: '2fa-smtp').isNotEmpty.obs;
       update() async {
         has2fa.value = bind.mainHasValid2FaSync();
+        hasTotp.value = hasValidTotp();
+        hasWebhook.value = bind.mainGetOptionSync(key: '2fa-webhook').isNotEmpty;
+        hasEmail.value = bind.mainGetOptionSync(key: '2fa-smtp').isNotEmpty;
         setState(() {});
       }
 
@@ -844,7 +855,7 @@ class _SafetyState extends State<_Safety> with AutomaticKeepAliveClientMixin {
           child: Obx(() => Row(
                 children: [
                   Checkbox(
-                          value: has2fa.value,
+                          value: hasTotp.value,
                           onChanged: enabled ? onChanged : null)
                       .marginOnly(right: 5),
                   Expanded(
@@ -857,11 +868,46 @@ class _SafetyState extends State<_Safety> with AutomaticKeepAliveClientMixin {
               )),
         ),
         onTap: () {
-          onChanged(!has2fa.value);
+          onChanged(!hasTotp.value);
         },
       ).marginOnly(left: _kCheckBoxLeftMargin);
+
+      // The second factors delivering a one time code for each login.
+      Widget codeDelivery(
+          RxBool value, String label, String tip, Function() onChanged) {
+        return GestureDetector(
+          child: Tooltip(
+            waitDuration: Duration(milliseconds: 300),
+            message: translate(tip),
+            child: InkWell(
+                child: Obx(() => Row(
+                      children: [
+                        Checkbox(
+                                value: value.value,
+                                onChanged:
+                                    enabled ? (_) => onChanged() : null)
+                            .marginOnly(right: 5),
+                        Expanded(
+                            child: Text(
+                          translate(label),
+                          style: TextStyle(
+                              color: disabledTextColor(context, enabled)),
+                        ))
+                      ],
+                    ))),
+          ),
+          onTap: onChanged,
+        ).marginOnly(left: _kCheckBoxLeftMargin);
+      }
+
+      final webhook = codeDelivery(hasWebhook, '2FA webhook',
+          'enable-2fa-webhook-tip', () => change2faWebhook(callback: update));
+      final email = codeDelivery(hasEmail, '2FA email', 'enable-2fa-email-tip',
+          () => change2faEmail(callback: update));
       if (!has2fa.value) {
-        return tfa;
+        return Column(
+          children: [tfa, webhook, email],
+        );
       }
       updateBot() async {
         hasBot.value = bind.mainHasValidBotSync();
@@ -904,6 +950,31 @@ class _SafetyState extends State<_Safety> with AutomaticKeepAliveClientMixin {
         },
       ).marginOnly(left: _kCheckBoxLeftMargin + 30);
 
+      final backupCodesLeft = bind.mainGet2FaBackupCodesLeft();
+      onGenerateBackupCodes() {
+        if (backupCodesLeft > 0) {
+          CommonConfirmDialog(
+              gFFI.dialogManager, translate('regenerate-backup-codes-tip'), () {
+            show2faBackupCodesDialog(callback: update);
+          });
+        } else {
+          show2faBackupCodesDialog(callback: update);
+        }
+      }
+
+      final backupCodes = Row(
+        children: [
+          Expanded(
+              child: Text(
+            '${translate('Backup codes left')}: $backupCodesLeft',
+            style: TextStyle(color: disabledTextColor(context, enabled)),
+          )),
+          ElevatedButton(
+              onPressed: locked ? null : onGenerateBackupCodes,
+              child: Text(translate('Generate backup codes')))
+        ],
+      ).marginOnly(left: 30);
+
       final trust = Row(
         children: [
           Flexible(
@@ -929,7 +1000,14 @@ class _SafetyState extends State<_Safety> with AutomaticKeepAliveClientMixin {
       ).marginOnly(left: 30);
 
       return Column(
-        children: [tfa, bot, trust],
+        children: [
+          tfa,
+          if (hasTotp.value) bot,
+          webhook,
+          email,
+          backupCodes,
+          trust
+        ],
       );
     }
 
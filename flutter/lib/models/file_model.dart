@@ -846,6 +846,8 @@ class FileController {
 }
 
 const _kOneWayFileTransferError = 'one-way-file-transfer-tip';
+// Same as QUEUE_JOB_ID_BASE in src/client/transfer_queue.rs.
+const _kTransferQueueJobIdBase = 1 << 24;
 
 class JobController {
   static final JobID jobID = JobID();
@@ -1067,6 +1069,52 @@ class JobController {
     );
   }
 
+  // Shows the queued jobs as transfer rows, progress events then update them.
+  void updateTransferQueue(Map<String, dynamic> evt) {
+    try {
+      final List<dynamic> queue = json.decode(evt['value']);
+      final ids = <int>{};
+      for (final queued in queue) {
+        final int id = queued['id'];
+        final bool isRemote = queued['is_remote'];
+        final String from = queued['path'];
+        final String to = queued['to'];
+        ids.add(id);
+        var jobIndex = getJob(id);
+        if (jobIndex == -1) {
+          jobTable.add(JobProgress()
+            ..type = JobType.transfer
+            ..fileName = path.basename(from)
+            ..jobName = from
+            ..id = id
+            ..isRemoteToLocal = isRemote
+            ..remote = isRemote ? from : to
+            ..to = isRemote ? to : from
+            ..showHidden = queued['include_hidden']);
+          jobIndex = jobTable.length - 1;
+        }
+        final job = jobTable[jobIndex];
+        final String status = queued['status'];
+        if (status == 'running') {
+          job.state = JobState.inProgress;
+        } else if (status == 'failed') {
+          job.state = JobState.error;
+          job.err = queued['last_error'];
+        } else if (status == 'done') {
+          job.state = JobState.done;
+        } else {
+          job.state = JobState.none;
+        }
+        job.fileNum = queued['file_num'];
+      }
+      jobTable.removeWhere((job) =>
+          job.id >= _kTransferQueueJobIdBase && !ids.contains(job.id));
+      jobTable.refresh();
+    } catch (e) {
+      debugPrint("Failed to updateTransferQueue, evt: ${evt.toString()}");
+    }
+  }
+
   void resumeJob(int jobId) {
     final jobIndex = getJob(jobId);
     if (jobIndex != -1) {
@@ -127,6 +127,13 @@ class FfiModel with ChangeNotifier {
 
   Timer? timerScreenshot;
 
+  // The session templates of the headless desktops, picked in the login dialog.
+  List<String> sessionTemplates = [];
+
+  bool micPassthrough = false;
+  bool inputMacroRecording = false;
+  bool inputMacroPlaying = false;
+
   Rect? get rect => _rect;
   bool get isOriginalResolutionSet =>
       _pi.tryGetDisplayIfNotAllDisplay()?.isOriginalResolutionSet ?? false;
@@ -357,6 +364,8 @@ class FfiModel with ChangeNotifier {
         parent.target?.fileModel.postOverrideFileConfirm(evt);
       } else if (name == 'load_last_job') {
         parent.target?.fileModel.jobController.loadLastJob(evt);
+      } else if (name == 'update_transfer_queue') {
+        parent.target?.fileModel.jobController.updateTransferQueue(evt);
       } else if (name == 'update_folder_files') {
         parent.target?.fileModel.jobController.updateFolderFiles(evt);
       } else if (name == 'add_connection') {
@@ -446,12 +455,127 @@ class FfiModel with ChangeNotifier {
         _handlePrinterRequest(evt, sessionId, peerId);
       } else if (name == 'screenshot') {
         _handleScreenshot(evt, sessionId, peerId);
+      } else if (name == 'update_session_templates') {
+        try {
+          sessionTemplates =
+              List<String>.from(jsonDecode(evt['value'] ?? '[]'));
+        } catch (e) {
+          debugPrint('Failed to decode session templates: $e');
+        }
+      } else if (name == 'update_shareable_windows') {
+        _handleShareableWindows(evt, sessionId);
+      } else if (name == 'update_audio_apps') {
+        _handleAudioApps(evt, sessionId);
+      } else if (name == 'update_mic_passthrough') {
+        micPassthrough = evt['value'] == true;
+        notifyListeners();
+      } else if (name == 'update_input_macro') {
+        inputMacroRecording = evt['recording'] == true;
+        inputMacroPlaying = evt['playing'] == true;
+        notifyListeners();
       } else {
         debugPrint('Event is not handled in the fixed branch: $name');
       }
     };
   }
 
+  _handleShareableWindows(Map<String, dynamic> evt, SessionID sessionId) {
+    final List<dynamic> windows;
+    try {
+      windows = jsonDecode(evt['value'] ?? '[]');
+    } catch (e) {
+      debugPrint('Failed to decode shareable windows: $e');
+      return;
+    }
+    final displays = _pi.currentDisplay == kAllDisplayValue
+        ? List.generate(_pi.displays.length, (i) => i)
+        : [_pi.currentDisplay];
+    parent.target?.dialogManager.show((setState, close, context) {
+      stopSharing() {
+        for (final display in displays) {
+          bind.sessionStopSharingWindow(sessionId: sessionId, display: display);
+        }
+        close();
+      }
+
+      return CustomAlertDialog(
+        title: Text(translate('Share window')),
+        content: SizedBox(
+          width: 400,
+          height: 300,
+          child: windows.isEmpty
+              ? Center(child: Text(translate('Empty')))
+              : ListView(
+                  children: windows
+                      .map((w) => ListTile(
+                            title: Text(w['title'] ?? '',
+                                overflow: TextOverflow.ellipsis),
+                            subtitle: Text(
+                                '${translate('Display')} ${(w['display'] ?? 0) + 1}'),
+                            onTap: () {
+                              bind.sessionShareWindow(
+                                  sessionId: sessionId,
+                                  display: w['display'] ?? 0,
+                                  window: w['id']);
+                              close();
+                            },
+                          ))
+                      .toList(),
+                ),
+        ),
+        actions: [
+          dialogButton('Cancel', onPressed: close, isOutline: true),
+          dialogButton('Stop sharing window', onPressed: stopSharing),
+        ],
+        onCancel: close,
+      );
+    });
+  }
+
+  _handleAudioApps(Map<String, dynamic> evt, SessionID sessionId) {
+    final Map<String, dynamic> value;
+    try {
+      value = jsonDecode(evt['value'] ?? '{}');
+    } catch (e) {
+      debugPrint('Failed to decode audio apps: $e');
+      return;
+    }
+    final List<String> apps = List<String>.from(value['apps'] ?? []);
+    final String current = value['current'] ?? '';
+    parent.target?.dialogManager.show((setState, close, context) {
+      capture(String app) {
+        bind.sessionCaptureAppAudio(sessionId: sessionId, app: app);
+        close();
+      }
+
+      Widget item(String app, String title) => ListTile(
+            title: Text(title, overflow: TextOverflow.ellipsis),
+            trailing: app == current
+                ? Icon(Icons.check, color: MyTheme.accent)
+                : null,
+            onTap: () => capture(app),
+          );
+
+      return CustomAlertDialog(
+        title: Text(translate('Capture application audio')),
+        content: SizedBox(
+          width: 400,
+          height: 300,
+          child: ListView(
+            children: [
+              item('', translate('All audio')),
+              ...apps.map((app) => item(app, app)),
+            ],
+          ),
+        ),
+        actions: [
+          dialogButton('Cancel', onPressed: close, isOutline: true),
+        ],
+        onCancel: close,
+      );
+    });
+  }
+
   _handleScreenshot(
       Map<String, dynamic> evt, SessionID sessionId, String peerId) {
     timerScreenshot?.cancel();
@@ -859,10 +983,12 @@ class FfiModel with ChangeNotifier {
     } else if (type == 'input-password') {
       enterPasswordDialog(sessionId, dialogManager);
     } else if (type == 'session-login' || type == 'session-re-login') {
-      enterUserLoginDialog(sessionId, dialogManager, 'login_linux_tip', true);
+      enterUserLoginDialog(sessionId, dialogManager, 'login_linux_tip', true,
+          sessionTemplates: sessionTemplates);
     } else if (type == 'session-login-password') {
       enterUserLoginAndPasswordDialog(
-          sessionId, dialogManager, 'login_linux_tip', true);
+          sessionId, dialogManager, 'login_linux_tip', true,
+          sessionTemplates: sessionTemplates);
     } else if (type == 'terminal-admin-login') {
       enterUserLoginDialog(
           sessionId, dialogManager, 'terminal-admin-login-tip', false);
@@ -2765,6 +2891,7 @@ class QualityMonitorData {
   String? targetBitrate;
   String? codecFormat;
   String? chroma;
+  String? transport;
 }
 
 class QualityMonitorModel with ChangeNotifier {
@@ -2827,6 +2954,10 @@ class QualityMonitorModel with ChangeNotifier {
       if (evt.containsKey('chroma') && (evt['chroma'] as String).isNotEmpty) {
         _data.chroma = evt['chroma'];
       }
+      if (evt.containsKey('transport') &&
+          (evt['transport'] as String).isNotEmpty) {
+        _data.transport = evt['transport'];
+      }
       notifyListeners();
     } catch (e) {
       //
@@ -3357,6 +3488,8 @@ class PeerInfo with ChangeNotifier {
       platformAdditions[kPlatformAdditionsIddImpl] == 'cloudydesk_idd';
   bool get isAmyuniIdd =>
       platformAdditions[kPlatformAdditionsIddImpl] == 'amyuni_idd';
+  bool get isLinuxOutput =>
+      platformAdditions[kPlatformAdditionsIddImpl] == 'linux_output';
 
   Display? tryGetDisplay({int? display}) {
     if (displays.isEmpty) {
@@ -1781,6 +1781,23 @@ class CloudydeskImpl {
     throw UnimplementedError("mainVerifyBot");
   }
 
+  Future<String> mainGenerate2FaBackupCodes({dynamic hint}) {
+    throw UnimplementedError("mainGenerate2FaBackupCodes");
+  }
+
+  int mainGet2FaBackupCodesLeft({dynamic hint}) {
+    throw UnimplementedError("mainGet2FaBackupCodesLeft");
+  }
+
+  Future<String> mainSet2FaWebhook(
+      {required String url, required String header, dynamic hint}) {
+    throw UnimplementedError("mainSet2FaWebhook");
+  }
+
+  Future<String> mainSet2FaSmtp({required String config, dynamic hint}) {
+    throw UnimplementedError("mainSet2FaSmtp");
+  }
+
   String mainGetUnlockPin({dynamic hint}) {
     throw UnimplementedError("mainGetUnlockPin");
   }
@@ -1923,6 +1940,89 @@ class CloudydeskImpl {
     throw UnimplementedError("sessionTakeScreenshot");
   }
 
+  Future<String> sessionGetClipboardHistory(
+      {required UuidValue sessionId, dynamic hint}) {
+    throw UnimplementedError("sessionGetClipboardHistory");
+  }
+
+  Future<void> sessionPushClipboardHistory(
+      {required UuidValue sessionId, required int entryId, dynamic hint}) {
+    throw UnimplementedError("sessionPushClipboardHistory");
+  }
+
+  Future<void> mainSetClipboardFromHistory(
+      {required int entryId, dynamic hint}) {
+    throw UnimplementedError("mainSetClipboardFromHistory");
+  }
+
+  Future<void> mainRemoveClipboardHistory(
+      {required int entryId, dynamic hint}) {
+    throw UnimplementedError("mainRemoveClipboardHistory");
+  }
+
+  Future<void> mainClearClipboardHistory(
+      {required String peerId, dynamic hint}) {
+    throw UnimplementedError("mainClearClipboardHistory");
+  }
+
+  Future<void> sessionRequestShareableWindows(
+      {required UuidValue sessionId, dynamic hint}) {
+    throw UnimplementedError("sessionRequestShareableWindows");
+  }
+
+  Future<void> sessionShareWindow(
+      {required UuidValue sessionId,
+      required int display,
+      required int window,
+      dynamic hint}) {
+    throw UnimplementedError("sessionShareWindow");
+  }
+
+  Future<void> sessionStopSharingWindow(
+      {required UuidValue sessionId, required int display, dynamic hint}) {
+    throw UnimplementedError("sessionStopSharingWindow");
+  }
+
+  Future<void> sessionRequestAudioApps(
+      {required UuidValue sessionId, dynamic hint}) {
+    throw UnimplementedError("sessionRequestAudioApps");
+  }
+
+  Future<void> sessionCaptureAppAudio(
+      {required UuidValue sessionId, required String app, dynamic hint}) {
+    throw UnimplementedError("sessionCaptureAppAudio");
+  }
+
+  Future<void> sessionSetMicPassthrough(
+      {required UuidValue sessionId, required bool enable, dynamic hint}) {
+    throw UnimplementedError("sessionSetMicPassthrough");
+  }
+
+  Future<void> sessionStartInputMacroRecording(
+      {required UuidValue sessionId, dynamic hint}) {
+    throw UnimplementedError("sessionStartInputMacroRecording");
+  }
+
+  Future<String> sessionStopInputMacroRecording(
+      {required UuidValue sessionId, dynamic hint}) {
+    throw UnimplementedError("sessionStopInputMacroRecording");
+  }
+
+  Future<String> sessionCheckInputMacro(
+      {required UuidValue sessionId, required String script, dynamic hint}) {
+    throw UnimplementedError("sessionCheckInputMacro");
+  }
+
+  Future<void> sessionPlayInputMacro(
+      {required UuidValue sessionId, required String script, dynamic hint}) {
+    throw UnimplementedError("sessionPlayInputMacro");
+  }
+
+  Future<void> sessionStopInputMacro(
+      {required UuidValue sessionId, dynamic hint}) {
+    throw UnimplementedError("sessionStopInputMacro");
+  }
+
   Future<void> sessionOpenTerminal(
       {required UuidValue sessionId,
       required int terminalId,
@@ -1,6 +1,111 @@
-use crate::{Key, KeyboardControllable};
+//! The DSL of key sequences.
+//!
+//! Text is typed as is, `{{` and `}}` are the escaped brackets. The tags are:
+//! - `{+KEY}` and `{-KEY}` press and release a key, `{KEY}` clicks it.
+//!   `KEY` is a name in [`KEY_NAMES`] or a single character, like `{+SHIFT}` or `{-a}`.
+//! - `{CTRL+ALT+DEL}` clicks the last key with the modifiers held.
+//! - `{+UNICODE}` and `{-UNICODE}` type the text between them as a unicode sequence.
+//! - `{WAIT:500}` waits in milliseconds.
+//! - `{MOVE:100,200}` moves the mouse to the absolute position.
+//! - `{+LBUTTON}`, `{-LBUTTON}` and `{LBUTTON}` like the keys, for the buttons in [`MOUSE_BUTTON_NAMES`].
+
+use crate::{Key, KeyboardControllable, MouseButton};
 use std::error::Error;
 use std::fmt;
+use std::time::Duration;
+
+/// The names of the keys in the tags.
+pub const KEY_NAMES: &[(&str, Key)] = &[
+    ("SHIFT", Key::Shift),
+    ("CTRL", Key::Control),
+    ("ALT", Key::Alt),
+    ("META", Key::Meta),
+    ("RSHIFT", Key::RightShift),
+    ("RCTRL", Key::RightControl),
+    ("RALT", Key::RightAlt),
+    ("ENTER", Key::Return),
+    ("TAB", Key::Tab),
+    ("ESC", Key::Escape),
+    ("SPACE", Key::Space),
+    ("BACKSPACE", Key::Backspace),
+    ("DEL", Key::Delete),
+    ("INSERT", Key::Insert),
+    ("HOME", Key::Home),
+    ("END", Key::End),
+    ("PGUP", Key::PageUp),
+    ("PGDN", Key::PageDown),
+    ("UP", Key::UpArrow),
+    ("DOWN", Key::DownArrow),
+    ("LEFT", Key::LeftArrow),
+    ("RIGHT", Key::RightArrow),
+    ("CAPSLOCK", Key::CapsLock),
+    ("NUMLOCK", Key::NumLock),
+    ("PRINT", Key::Snapshot),
+    ("PAUSE", Key::Pause),
+    ("APPS", Key::Apps),
+    ("F1", Key::F1),
+    ("F2", Key::F2),
+    ("F3", Key::F3),
+    ("F4", Key::F4),
+    ("F5", Key::F5),
+    ("F6", Key::F6),
+    ("F7", Key::F7),
+    ("F8", Key::F8),
+    ("F9", Key::F9),
+    ("F10", Key::F10),
+    ("F11", Key::F11),
+    ("F12", Key::F12),
+];
+
+/// The names of the mouse buttons in the tags.
+pub const MOUSE_BUTTON_NAMES: &[(&str, MouseButton)] = &[
+    ("LBUTTON", MouseButton::Left),
+    ("RBUTTON", MouseButton::Right),
+    ("MBUTTON", MouseButton::Middle),
+    ("XBUTTON1", MouseButton::Back),
+    ("XBUTTON2", MouseButton::Forward),
+];
+
+/// The tag name of a key, the inverse of the parsing.
+pub fn key_name(key: Key) -> Option<String> {
+    if let Key::Layout(c) = key {
+        return match c {
+            '{' => Some("{{".to_owned()),
+            '}' => Some("}}".to_owned()),
+            _ => Some(c.to_string()),
+        };
+    }
+    KEY_NAMES
+        .iter()
+        .find(|(_, k)| *k == key)
+        .map(|(name, _)| name.to_string())
+}
+
+/// The tag name of a mouse button.
+pub fn mouse_button_name(button: MouseButton) -> Option<&'static str> {
+    MOUSE_BUTTON_NAMES
+        .iter()
+        .find(|(_, b)| *b == button)
+        .map(|(name, _)| *name)
+}
+
+fn parse_key(name: &str) -> Option<Key> {
+    if let Some((_, key)) = KEY_NAMES.iter().find(|(n, _)| *n == name) {
+        return Some(*key);
+    }
+    let mut chars = name.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Some(Key::Layout(c)),
+        _ => None,
+    }
+}
+
+fn parse_mouse_button(name: &str) -> Option<MouseButton> {
+    MOUSE_BUTTON_NAMES
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, b)| *b)
+}
 
 /// An error that can occur when parsing DSL
 #[derive(Debug, PartialEq, Eq)]
@@ -37,11 +142,17 @@ impl Error for ParseError {
 }
 impl fmt::Display for ParseError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.write_str(&self.to_string())
+        #[allow(deprecated)]
+        f.write_str(self.description())?;
+        if let ParseError::UnknownTag(tag) = self {
+            write!(f, ": {}", tag)?;
+        }
+        Ok(())
     }
 }
 
 /// Evaluate the DSL. This tokenizes the input and presses the keys.
+/// The mouse tokens are skipped, they need a mouse, see [`tokenize`] to handle them.
 pub fn eval<K>(enigo: &mut K, input: &str) -> Result<(), ParseError>
 where
     K: KeyboardControllable,
@@ -56,20 +167,94 @@ where
             Token::Unicode(buffer) => enigo.key_sequence(&buffer),
             Token::KeyUp(key) => enigo.key_up(key),
             Token::KeyDown(key) => enigo.key_down(key).unwrap_or(()),
+            Token::KeyClick(key) => enigo.key_click(key),
+            Token::Wait(ms) => std::thread::sleep(Duration::from_millis(ms)),
+            Token::MouseMove(..)
+            | Token::MouseDown(_)
+            | Token::MouseUp(_)
+            | Token::MouseClick(_) => {}
         }
     }
     Ok(())
 }
 
+/// A parsed item of the DSL.
 #[derive(Debug, PartialEq, Eq)]
-enum Token {
+pub enum Token {
+    /// Text typed key by key.
     Sequence(String),
+    /// Text typed as a unicode sequence.
     Unicode(String),
     KeyUp(Key),
     KeyDown(Key),
+    KeyClick(Key),
+    /// Milliseconds.
+    Wait(u64),
+    /// The absolute position.
+    MouseMove(i32, i32),
+    MouseDown(MouseButton),
+    MouseUp(MouseButton),
+    MouseClick(MouseButton),
 }
 
-fn tokenize(input: &str) -> Result<Vec<Token>, ParseError> {
+fn parse_tag(tag: &str, tokens: &mut Vec<Token>) -> Result<(), ParseError> {
+    let unknown = || ParseError::UnknownTag(tag.to_owned());
+    if let Some(ms) = tag.strip_prefix("WAIT:") {
+        tokens.push(Token::Wait(ms.trim().parse().map_err(|_| unknown())?));
+        return Ok(());
+    }
+    if let Some(pos) = tag.strip_prefix("MOVE:") {
+        let (x, y) = pos.split_once(',').ok_or_else(unknown)?;
+        let x = x.trim().parse().map_err(|_| unknown())?;
+        let y = y.trim().parse().map_err(|_| unknown())?;
+        tokens.push(Token::MouseMove(x, y));
+        return Ok(());
+    }
+    if tag.len() > 1 {
+        if let Some(name) = tag.strip_prefix('+') {
+            if let Some(button) = parse_mouse_button(name) {
+                tokens.push(Token::MouseDown(button));
+            } else {
+                tokens.push(Token::KeyDown(parse_key(name).ok_or_else(unknown)?));
+            }
+            return Ok(());
+        }
+        if let Some(name) = tag.strip_prefix('-') {
+            if let Some(button) = parse_mouse_button(name) {
+                tokens.push(Token::MouseUp(button));
+            } else {
+                tokens.push(Token::KeyUp(parse_key(name).ok_or_else(unknown)?));
+            }
+            return Ok(());
+        }
+    }
+    // `{CTRL++}` is the plus key with ctrl.
+    let (modifiers, name) = match tag.strip_suffix("++") {
+        Some(modifiers) => (modifiers, "+"),
+        None => tag.rsplit_once('+').unwrap_or(("", tag)),
+    };
+    let mut keys = vec![];
+    if !modifiers.is_empty() {
+        for modifier in modifiers.split('+') {
+            keys.push(parse_key(modifier).ok_or_else(unknown)?);
+        }
+    }
+    for key in keys.iter() {
+        tokens.push(Token::KeyDown(*key));
+    }
+    if let Some(button) = parse_mouse_button(name) {
+        tokens.push(Token::MouseClick(button));
+    } else {
+        tokens.push(Token::KeyClick(parse_key(name).ok_or_else(unknown)?));
+    }
+    for key in keys.iter().rev() {
+        tokens.push(Token::KeyUp(*key));
+    }
+    Ok(())
+}
+
+/// Tokenize the DSL.
+pub fn tokenize(input: &str) -> Result<Vec<Token>, ParseError> {
     let mut unicode = false;
 
     let mut tokens = Vec::new();
@@ -119,15 +304,7 @@ fn tokenize(input: &str) -> Result<Vec<Token>, ParseError> {
                     match &*tag {
                         "+UNICODE" => unicode = true,
                         "-UNICODE" => unicode = false,
-                        "+SHIFT" => tokens.push(Token::KeyDown(Key::Shift)),
-                        "-SHIFT" => tokens.push(Token::KeyUp(Key::Shift)),
-                        "+CTRL" => tokens.push(Token::KeyDown(Key::Control)),
-                        "-CTRL" => tokens.push(Token::KeyUp(Key::Control)),
-                        "+META" => tokens.push(Token::KeyDown(Key::Meta)),
-                        "-META" => tokens.push(Token::KeyUp(Key::Meta)),
-                        "+ALT" => tokens.push(Token::KeyDown(Key::Alt)),
-                        "-ALT" => tokens.push(Token::KeyUp(Key::Alt)),
-                        _ => return Err(ParseError::UnknownTag(tag)),
+                        _ => parse_tag(&tag, &mut tokens)?,
                     }
                 }
                 None => return Err(ParseError::UnmatchedOpen),
@@ -164,6 +341,28 @@ mod tests {
         );
     }
     #[test]
+    fn macro_tags() {
+        assert_eq!(
+            tokenize("{MOVE:10,20}{LBUTTON}{WAIT:500}{CTRL+ALT+DEL}{ENTER}{+a}"),
+            Ok(vec![
+                Token::MouseMove(10, 20),
+                Token::MouseClick(MouseButton::Left),
+                Token::Wait(500),
+                Token::KeyDown(Key::Control),
+                Token::KeyDown(Key::Alt),
+                Token::KeyClick(Key::Delete),
+                Token::KeyUp(Key::Alt),
+                Token::KeyUp(Key::Control),
+                Token::KeyClick(Key::Return),
+                Token::KeyDown(Key::Layout('a')),
+            ])
+        );
+        assert_eq!(
+            tokenize("{WAIT:x}"),
+            Err(ParseError::UnknownTag("WAIT:x".into()))
+        );
+    }
+    #[test]
     fn unexpected_open() {
         assert_eq!(tokenize("{hello{}world}"), Err(ParseError::UnexpectedOpen));
     }
@@ -14,6 +14,7 @@ use crate::vram::*;
 use crate::{
     aom::{self, AomDecoder, AomEncoder, AomEncoderConfig},
     common::GoogleImage,
+    tile::{self, TileDecoder},
     vpxcodec::{self, VpxDecoder, VpxDecoderConfig, VpxEncoder, VpxEncoderConfig, VpxVideoCodecId},
     CodecFormat, EncodeInput, EncodeYuvFormat, ImageRgb, ImageTexture,
 };
@@ -57,6 +58,15 @@ pub enum EncoderCfg {
     VRAM(VRamEncoderConfig),
 }
 
+/// Region of interest of the frame, in pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Roi {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
 pub trait EncoderApi {
     fn new(cfg: EncoderCfg, i444: bool) -> ResultType<Self>
     where
@@ -80,6 +90,15 @@ pub trait EncoderApi {
     fn is_hardware(&self) -> bool;
 
     fn disable(&self);
+
+    /// Encodes the region with higher quality than the rest of the frame, `None` to clear it.
+    fn set_roi(&mut self, _roi: Option<Roi>) -> ResultType<()> {
+        Ok(())
+    }
+
+    fn support_roi(&self) -> bool {
+        false
+    }
 }
 
 pub struct Encoder {
@@ -120,6 +139,7 @@ pub struct Decoder {
     valid: bool,
     #[cfg(feature = "hwcodec")]
     i420: Vec<u8>,
+    tiles: TileDecoder,
 }
 
 #[derive(Debug, Clone)]
@@ -616,6 +636,7 @@ impl Decoder {
             valid,
             #[cfg(feature = "hwcodec")]
             i420: vec![],
+            tiles: Default::default(),
         }
     }
 
@@ -629,6 +650,47 @@ impl Decoder {
 
     // rgb [in/out] fmt and stride must be set in ImageRgb
     pub fn handle_video_frame(
+        &mut self,
+        frame: &video_frame::Union,
+        rgb: &mut ImageRgb,
+        texture: &mut ImageTexture,
+        pixelbuffer: &mut bool,
+        chroma: &mut Option<Chroma>,
+    ) -> ResultType<bool> {
+        let Some((frame, tiles)) = tile::split(frame) else {
+            let decoded = self.handle_codec_frame(frame, rgb, texture, pixelbuffer, chroma)?;
+            if decoded && *pixelbuffer {
+                self.tiles.draw(rgb);
+            }
+            return Ok(decoded);
+        };
+        let has_codec_frame = match &frame {
+            video_frame::Union::Vp8s(x)
+            | video_frame::Union::Vp9s(x)
+            | video_frame::Union::Av1s(x)
+            | video_frame::Union::H264s(x)
+            | video_frame::Union::H265s(x) => !x.frames.is_empty(),
+            _ => false,
+        };
+        let mut decoded = if has_codec_frame {
+            self.handle_codec_frame(&frame, rgb, texture, pixelbuffer, chroma)?
+        } else {
+            false
+        };
+        for data in tiles.iter() {
+            self.tiles.update(data)?;
+        }
+        // Tiles of a static screen are drawn over the last decoded frame
+        if !has_codec_frame && rgb.w > 0 && *pixelbuffer {
+            decoded = true;
+        }
+        if decoded && *pixelbuffer {
+            self.tiles.draw(rgb);
+        }
+        Ok(decoded)
+    }
+
+    fn handle_codec_frame(
         &mut self,
         frame: &video_frame::Union,
         rgb: &mut ImageRgb,
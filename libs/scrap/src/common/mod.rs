@@ -41,6 +41,7 @@ pub mod convert;
 pub mod hwcodec;
 #[cfg(feature = "mediacodec")]
 pub mod mediacodec;
+pub mod tile;
 pub mod vpxcodec;
 #[cfg(feature = "vram")]
 pub mod vram;
@@ -1,4 +1,4 @@
-use crate::CodecFormat;
+use crate::{tile::is_tile_data, CodecFormat};
 #[cfg(feature = "hwcodec")]
 use hbb_common::anyhow::anyhow;
 use hbb_common::{
@@ -199,33 +199,33 @@ impl Recorder {
         }
         match frame {
             video_frame::Union::Vp8s(vp8s) => {
-                for f in vp8s.frames.iter() {
+                for f in vp8s.frames.iter().filter(|f| !is_tile_data(&f.data)) {
                     self.check_pts(f.pts, f.key, w, h, format)?;
                     self.as_mut().map(|x| x.write_video(f));
                 }
             }
             video_frame::Union::Vp9s(vp9s) => {
-                for f in vp9s.frames.iter() {
+                for f in vp9s.frames.iter().filter(|f| !is_tile_data(&f.data)) {
                     self.check_pts(f.pts, f.key, w, h, format)?;
                     self.as_mut().map(|x| x.write_video(f));
                 }
             }
             video_frame::Union::Av1s(av1s) => {
-                for f in av1s.frames.iter() {
+                for f in av1s.frames.iter().filter(|f| !is_tile_data(&f.data)) {
                     self.check_pts(f.pts, f.key, w, h, format)?;
                     self.as_mut().map(|x| x.write_video(f));
                 }
             }
             #[cfg(feature = "hwcodec")]
             video_frame::Union::H264s(h264s) => {
-                for f in h264s.frames.iter() {
+                for f in h264s.frames.iter().filter(|f| !is_tile_data(&f.data)) {
                     self.check_pts(f.pts, f.key, w, h, format)?;
                     self.as_mut().map(|x| x.write_video(f));
                 }
             }
             #[cfg(feature = "hwcodec")]
             video_frame::Union::H265s(h265s) => {
-                for f in h265s.frames.iter() {
+                for f in h265s.frames.iter().filter(|f| !is_tile_data(&f.data)) {
                     self.check_pts(f.pts, f.key, w, h, format)?;
                     self.as_mut().map(|x| x.write_video(f));
                 }
@@ -0,0 +1,596 @@
+// Lossless tiles for static text.
+//
+// The full frame codec blurs text at low bitrates. The encoder tracks the damaged tiles by diffing
+// the captured frames, and once a tile settles (not damaged for SETTLE_MS), it is sent losslessly
+// (QOI) if it looks like text or UI (few colors, a dominant background). The client keeps the
+// lossless tiles and draws them over the decoded frames until the tile is damaged again, then the
+// tile is cleared and the full frame codec shows the motion.
+//
+// The tiles are sent in an extra `EncodedVideoFrame` starting with TILE_MAGIC, appended to the
+// frames of the codec, or alone in a video frame of the codec when the screen is static.
+
+use crate::{CodecFormat, ImageFormat, ImageRgb, Pixfmt};
+use hbb_common::{
+    bail,
+    bytes::Bytes,
+    message_proto::{video_frame, EncodedVideoFrame, EncodedVideoFrames, VideoFrame},
+    ResultType,
+};
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+pub const OPTION_LOSSLESS_TILES: &str = "allow-lossless-tiles";
+
+const TILE_MAGIC: &[u8; 4] = b"RDTL";
+const TILE_VERSION: u8 = 1;
+const TILE_SIZE: usize = 64;
+const SETTLE_MS: u64 = 250;
+const MAX_TILES_PER_FRAME: usize = 96;
+const TEXT_MAX_COLORS: usize = 64;
+const TEXT_MIN_BACKGROUND: f32 = 0.4;
+const FLAG_RESET: u8 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TileState {
+    Changed(Instant),
+    Lossy,
+    Lossless,
+}
+
+pub struct TileEncoder {
+    width: usize,
+    height: usize,
+    cols: usize,
+    rows: usize,
+    pixfmt: Option<Pixfmt>,
+    // The last frame, packed rows of 4 bytes per pixel
+    prev: Vec<u8>,
+    states: Vec<TileState>,
+    cleared: Vec<(u16, u16)>,
+    reset: bool,
+}
+
+impl TileEncoder {
+    pub fn new(width: usize, height: usize) -> Self {
+        let cols = (width + TILE_SIZE - 1) / TILE_SIZE;
+        let rows = (height + TILE_SIZE - 1) / TILE_SIZE;
+        Self {
+            width,
+            height,
+            cols,
+            rows,
+            pixfmt: None,
+            prev: Vec::new(),
+            states: vec![TileState::Changed(Instant::now()); cols * rows],
+            cleared: Vec::new(),
+            // The client may keep the tiles of the last encoder
+            reset: true,
+        }
+    }
+
+    /// Tracks the damaged tiles of the captured frame.
+    pub fn update(&mut self, pixfmt: Pixfmt, data: &[u8], stride: usize) {
+        if !matches!(pixfmt, Pixfmt::BGRA | Pixfmt::RGBA) {
+            return;
+        }
+        let row_bytes = self.width * 4;
+        if stride < row_bytes || data.len() < stride * (self.height.max(1) - 1) + row_bytes {
+            return;
+        }
+        let now = Instant::now();
+        if self.pixfmt != Some(pixfmt) || self.prev.len() != row_bytes * self.height {
+            self.pixfmt = Some(pixfmt);
+            self.prev.resize(row_bytes * self.height, 0);
+            for y in 0..self.height {
+                self.prev[y * row_bytes..(y + 1) * row_bytes]
+                    .copy_from_slice(&data[y * stride..y * stride + row_bytes]);
+            }
+            for (i, state) in self.states.iter_mut().enumerate() {
+                if *state == TileState::Lossless {
+                    self.cleared
+                        .push(((i % self.cols) as u16, (i / self.cols) as u16));
+                }
+                *state = TileState::Changed(now);
+            }
+            return;
+        }
+        for row in 0..self.rows {
+            let y0 = row * TILE_SIZE;
+            let y1 = (y0 + TILE_SIZE).min(self.height);
+            for col in 0..self.cols {
+                let x0 = col * TILE_SIZE * 4;
+                let x1 = ((col + 1) * TILE_SIZE).min(self.width) * 4;
+                let mut changed = false;
+                for y in y0..y1 {
+                    let src = &data[y * stride + x0..y * stride + x1];
+                    let dst = &mut self.prev[y * row_bytes + x0..y * row_bytes + x1];
+                    if changed || src != dst {
+                        changed = true;
+                        dst.copy_from_slice(src);
+                    }
+                }
+                if changed {
+                    let state = &mut self.states[row * self.cols + col];
+                    if *state == TileState::Lossless {
+                        self.cleared.push((col as u16, row as u16));
+                    }
+                    *state = TileState::Changed(now);
+                }
+            }
+        }
+    }
+
+    /// Returns the tile data to send, the settled text tiles and the cleared tiles.
+    pub fn poll(&mut self) -> Option<Vec<u8>> {
+        let pixfmt = self.pixfmt?;
+        let settle = Duration::from_millis(SETTLE_MS);
+        let mut tiles = Vec::new();
+        for i in 0..self.states.len() {
+            if tiles.len() >= MAX_TILES_PER_FRAME {
+                break;
+            }
+            let TileState::Changed(t) = self.states[i] else {
+                continue;
+            };
+            if t.elapsed() < settle {
+                continue;
+            }
+            let (col, row) = (i % self.cols, i / self.cols);
+            let (w, h, rgba) = self.tile_rgba(col, row, pixfmt);
+            if is_text_like(&rgba) {
+                tiles.push((col as u16, row as u16, qoi_encode(&rgba, w, h)));
+                self.states[i] = TileState::Lossless;
+            } else {
+                self.states[i] = TileState::Lossy;
+            }
+        }
+        if tiles.is_empty() && self.cleared.is_empty() && !self.reset {
+            return None;
+        }
+        let mut out = Vec::new();
+        out.extend_from_slice(TILE_MAGIC);
+        out.push(TILE_VERSION);
+        out.push(if self.reset { FLAG_RESET } else { 0 });
+        out.extend_from_slice(&(self.width as u32).to_le_bytes());
+        out.extend_from_slice(&(self.height as u32).to_le_bytes());
+        out.extend_from_slice(&(TILE_SIZE as u16).to_le_bytes());
+        out.extend_from_slice(&(self.cleared.len() as u32).to_le_bytes());
+        for (col, row) in self.cleared.drain(..) {
+            out.extend_from_slice(&col.to_le_bytes());
+            out.extend_from_slice(&row.to_le_bytes());
+        }
+        out.extend_from_slice(&(tiles.len() as u32).to_le_bytes());
+        for (col, row, data) in tiles {
+            out.extend_from_slice(&col.to_le_bytes());
+            out.extend_from_slice(&row.to_le_bytes());
+            out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            out.extend_from_slice(&data);
+        }
+        self.reset = false;
+        Some(out)
+    }
+
+    fn tile_rgba(&self, col: usize, row: usize, pixfmt: Pixfmt) -> (usize, usize, Vec<u8>) {
+        let (x0, y0) = (col * TILE_SIZE, row * TILE_SIZE);
+        let w = TILE_SIZE.min(self.width - x0);
+        let h = TILE_SIZE.min(self.height - y0);
+        let row_bytes = self.width * 4;
+        let mut rgba = Vec::with_capacity(w * h * 4);
+        for y in y0..y0 + h {
+            let src = &self.prev[y * row_bytes + x0 * 4..y * row_bytes + (x0 + w) * 4];
+            match pixfmt {
+                Pixfmt::BGRA => {
+                    for px in src.chunks_exact(4) {
+                        rgba.extend_from_slice(&[px[2], px[1], px[0], 255]);
+                    }
+                }
+                _ => {
+                    for px in src.chunks_exact(4) {
+                        rgba.extend_from_slice(&[px[0], px[1], px[2], 255]);
+                    }
+                }
+            }
+        }
+        (w, h, rgba)
+    }
+}
+
+// Text and UI have few colors and a dominant background, photos and videos do not.
+fn is_text_like(rgba: &[u8]) -> bool {
+    let mut counts: HashMap<[u8; 4], usize> = HashMap::new();
+    for px in rgba.chunks_exact(4) {
+        *counts.entry([px[0], px[1], px[2], px[3]]).or_default() += 1;
+        if counts.len() > TEXT_MAX_COLORS {
+            return false;
+        }
+    }
+    let total = rgba.len() / 4;
+    let background = counts.values().max().copied().unwrap_or(0);
+    total > 0 && background as f32 >= total as f32 * TEXT_MIN_BACKGROUND
+}
+
+/// Keeps the lossless tiles and draws them over the decoded frames.
+#[derive(Default)]
+pub struct TileDecoder {
+    width: usize,
+    height: usize,
+    tile_size: usize,
+    // (col, row) -> (width, height, rgba)
+    tiles: HashMap<(u16, u16), (usize, usize, Vec<u8>)>,
+}
+
+impl TileDecoder {
+    pub fn is_empty(&self) -> bool {
+        self.tiles.is_empty()
+    }
+
+    pub fn update(&mut self, data: &[u8]) -> ResultType<()> {
+        let mut r = Reader(data);
+        if r.take(4)? != TILE_MAGIC {
+            bail!("invalid tile data");
+        }
+        if r.u8()? != TILE_VERSION {
+            bail!("unsupported tile version");
+        }
+        let flags = r.u8()?;
+        let width = r.u32()? as usize;
+        let height = r.u32()? as usize;
+        let tile_size = r.u16()? as usize;
+        if flags & FLAG_RESET != 0
+            || (width, height, tile_size) != (self.width, self.height, self.tile_size)
+        {
+            self.tiles.clear();
+            (self.width, self.height, self.tile_size) = (width, height, tile_size);
+        }
+        for _ in 0..r.u32()? {
+            let key = (r.u16()?, r.u16()?);
+            self.tiles.remove(&key);
+        }
+        for _ in 0..r.u32()? {
+            let key = (r.u16()?, r.u16()?);
+            let len = r.u32()? as usize;
+            let (w, h, rgba) = qoi_decode(r.take(len)?)?;
+            self.tiles.insert(key, (w, h, rgba));
+        }
+        Ok(())
+    }
+
+    // rgb [in/out] fmt and stride must be set in ImageRgb
+    pub fn draw(&self, rgb: &mut ImageRgb) {
+        if self.tiles.is_empty() || rgb.w != self.width || rgb.h != self.height {
+            return;
+        }
+        let bytes_per_pixel = match rgb.fmt() {
+            ImageFormat::Raw => 3,
+            ImageFormat::ARGB | ImageFormat::ABGR => 4,
+        };
+        let align = rgb.align().max(1);
+        let bytes_per_row = (rgb.w * bytes_per_pixel + align - 1) & !(align - 1);
+        if rgb.raw.len() < bytes_per_row * rgb.h {
+            return;
+        }
+        for ((col, row), (w, h, rgba)) in self.tiles.iter() {
+            let (x0, y0) = (
+                *col as usize * self.tile_size,
+                *row as usize * self.tile_size,
+            );
+            if x0 + w > rgb.w || y0 + h > rgb.h {
+                continue;
+            }
+            for y in 0..*h {
+                let src = &rgba[y * w * 4..(y + 1) * w * 4];
+                let start = (y0 + y) * bytes_per_row + x0 * bytes_per_pixel;
+                let dst = &mut rgb.raw[start..start + w * bytes_per_pixel];
+                for (s, d) in src
+                    .chunks_exact(4)
+                    .zip(dst.chunks_exact_mut(bytes_per_pixel))
+                {
+                    match rgb.fmt {
+                        // libyuv names, ARGB is B, G, R, A in memory
+                        ImageFormat::ARGB => d.copy_from_slice(&[s[2], s[1], s[0], s[3]]),
+                        ImageFormat::ABGR => d.copy_from_slice(s),
+                        ImageFormat::Raw => d.copy_from_slice(&s[..3]),
+                    }
+                }
+            }
+        }
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> ResultType<&'a [u8]> {
+        if self.0.len() < n {
+            bail!("truncated tile data");
+        }
+        let (v, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(v)
+    }
+
+    fn u8(&mut self) -> ResultType<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> ResultType<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into()?))
+    }
+
+    fn u32(&mut self) -> ResultType<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+}
+
+#[inline]
+pub fn is_tile_data(data: &[u8]) -> bool {
+    data.starts_with(TILE_MAGIC)
+}
+
+fn encoded_frames_mut(union: &mut video_frame::Union) -> Option<&mut EncodedVideoFrames> {
+    match union {
+        video_frame::Union::Vp8s(x)
+        | video_frame::Union::Vp9s(x)
+        | video_frame::Union::Av1s(x)
+        | video_frame::Union::H264s(x)
+        | video_frame::Union::H265s(x) => Some(x),
+        _ => None,
+    }
+}
+
+/// Appends the tile data to the frames of the codec.
+pub fn append(vf: &mut VideoFrame, data: Vec<u8>) {
+    if let Some(frames) = vf.union.as_mut().and_then(encoded_frames_mut) {
+        frames.frames.push(EncodedVideoFrame {
+            data: Bytes::from(data),
+            ..Default::default()
+        });
+    }
+}
+
+/// A video frame of the codec with the tile data only, when the screen is static.
+pub fn video_frame(format: CodecFormat, data: Vec<u8>) -> Option<VideoFrame> {
+    let frames = EncodedVideoFrames {
+        frames: vec![EncodedVideoFrame {
+            data: Bytes::from(data),
+            ..Default::default()
+        }]
+        .into(),
+        ..Default::default()
+    };
+    let mut vf = VideoFrame::new();
+    match format {
+        CodecFormat::VP8 => vf.set_vp8s(frames),
+        CodecFormat::VP9 => vf.set_vp9s(frames),
+        CodecFormat::AV1 => vf.set_av1s(frames),
+        CodecFormat::H264 => vf.set_h264s(frames),
+        CodecFormat::H265 => vf.set_h265s(frames),
+        CodecFormat::Unknown => return None,
+    }
+    Some(vf)
+}
+
+/// Splits the tile data from the frames of the codec, `None` if there is none.
+pub fn split(frame: &video_frame::Union) -> Option<(video_frame::Union, Vec<Bytes>)> {
+    let mut frame = frame.clone();
+    let frames = encoded_frames_mut(&mut frame)?;
+    if !frames.frames.iter().any(|f| is_tile_data(&f.data)) {
+        return None;
+    }
+    let (tiles, codec): (Vec<_>, Vec<_>) =
+        frames.frames.drain(..).partition(|f| is_tile_data(&f.data));
+    frames.frames = codec.into();
+    Some((frame, tiles.into_iter().map(|f| f.data).collect()))
+}
+
+const QOI_OP_INDEX: u8 = 0x00;
+const QOI_OP_DIFF: u8 = 0x40;
+const QOI_OP_LUMA: u8 = 0x80;
+const QOI_OP_RUN: u8 = 0xc0;
+const QOI_OP_RGB: u8 = 0xfe;
+const QOI_OP_RGBA: u8 = 0xff;
+const QOI_MASK: u8 = 0xc0;
+const QOI_END: [u8; 8] = [0, 0, 0, 0, 0, 0, 0, 1];
+
+#[inline]
+fn qoi_hash(px: [u8; 4]) -> usize {
+    (px[0] as usize * 3 + px[1] as usize * 5 + px[2] as usize * 7 + px[3] as usize * 11) % 64
+}
+
+/// https://qoiformat.org/qoi-specification.pdf
+pub fn qoi_encode(rgba: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(rgba.len() / 4 + 22);
+    out.extend_from_slice(b"qoif");
+    out.extend_from_slice(&(width as u32).to_be_bytes());
+    out.extend_from_slice(&(height as u32).to_be_bytes());
+    out.extend_from_slice(&[4, 0]);
+    let mut index = [[0u8; 4]; 64];
+    let mut prev = [0u8, 0, 0, 255];
+    let mut run = 0u8;
+    let count = width * height;
+    for (i, px) in rgba.chunks_exact(4).take(count).enumerate() {
+        let px = [px[0], px[1], px[2], px[3]];
+        if px == prev {
+            run += 1;
+            if run == 62 || i == count - 1 {
+                out.push(QOI_OP_RUN | (run - 1));
+                run = 0;
+            }
+            continue;
+        }
+        if run > 0 {
+            out.push(QOI_OP_RUN | (run - 1));
+            run = 0;
+        }
+        let hash = qoi_hash(px);
+        if index[hash] == px {
+            out.push(QOI_OP_INDEX | hash as u8);
+        } else {
+            index[hash] = px;
+            if px[3] == prev[3] {
+                let vr = px[0].wrapping_sub(prev[0]) as i8;
+                let vg = px[1].wrapping_sub(prev[1]) as i8;
+                let vb = px[2].wrapping_sub(prev[2]) as i8;
+                let vg_r = vr.wrapping_sub(vg);
+                let vg_b = vb.wrapping_sub(vg);
+                if (-2..=1).contains(&vr) && (-2..=1).contains(&vg) && (-2..=1).contains(&vb) {
+                    out.push(
+                        QOI_OP_DIFF
+                            | ((vr + 2) as u8) << 4
+                            | ((vg + 2) as u8) << 2
+                            | (vb + 2) as u8,
+                    );
+                } else if (-8..=7).contains(&vg_r)
+                    && (-32..=31).contains(&vg)
+                    && (-8..=7).contains(&vg_b)
+                {
+                    out.push(QOI_OP_LUMA | (vg + 32) as u8);
+                    out.push(((vg_r + 8) as u8) << 4 | (vg_b + 8) as u8);
+                } else {
+                    out.extend_from_slice(&[QOI_OP_RGB, px[0], px[1], px[2]]);
+                }
+            } else {
+                out.extend_from_slice(&[QOI_OP_RGBA, px[0], px[1], px[2], px[3]]);
+            }
+        }
+        prev = px;
+    }
+    out.extend_from_slice(&QOI_END);
+    out
+}
+
+pub fn qoi_decode(data: &[u8]) -> ResultType<(usize, usize, Vec<u8>)> {
+    let mut r = Reader(data);
+    if r.take(4)? != b"qoif" {
+        bail!("invalid qoi data");
+    }
+    let width = u32::from_be_bytes(r.take(4)?.try_into()?) as usize;
+    let height = u32::from_be_bytes(r.take(4)?.try_into()?) as usize;
+    r.take(2)?;
+    let count = match width.checked_mul(height) {
+        Some(count) if count <= TILE_SIZE * TILE_SIZE * 64 => count,
+        _ => bail!("qoi image too large"),
+    };
+    let mut out = Vec::with_capacity(count * 4);
+    let mut index = [[0u8; 4]; 64];
+    let mut px = [0u8, 0, 0, 255];
+    let mut run = 0;
+    for _ in 0..count {
+        if run > 0 {
+            run -= 1;
+        } else {
+            let b1 = r.u8()?;
+            if b1 == QOI_OP_RGB {
+                px[..3].copy_from_slice(r.take(3)?);
+            } else if b1 == QOI_OP_RGBA {
+                px.copy_from_slice(r.take(4)?);
+            } else {
+                match b1 & QOI_MASK {
+                    QOI_OP_INDEX => px = index[b1 as usize],
+                    QOI_OP_DIFF => {
+                        px[0] = px[0].wrapping_add((b1 >> 4) & 0x03).wrapping_sub(2);
+                        px[1] = px[1].wrapping_add((b1 >> 2) & 0x03).wrapping_sub(2);
+                        px[2] = px[2].wrapping_add(b1 & 0x03).wrapping_sub(2);
+                    }
+                    QOI_OP_LUMA => {
+                        let b2 = r.u8()?;
+                        let vg = (b1 & 0x3f).wrapping_sub(32);
+                        px[0] = px[0]
+                            .wrapping_add(vg)
+                            .wrapping_sub(8)
+                            .wrapping_add((b2 >> 4) & 0x0f);
+                        px[1] = px[1].wrapping_add(vg);
+                        px[2] = px[2]
+                            .wrapping_add(vg)
+                            .wrapping_sub(8)
+                            .wrapping_add(b2 & 0x0f);
+                    }
+                    _ => run = b1 & 0x3f,
+                }
+            }
+            index[qoi_hash(px)] = px;
+        }
+        out.extend_from_slice(&px);
+    }
+    Ok((width, height, out))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text_tile() -> Vec<u8> {
+        let mut rgba = vec![255u8; TILE_SIZE * TILE_SIZE * 4];
+        for (i, px) in rgba.chunks_exact_mut(4).enumerate() {
+            if i % 7 == 0 {
+                px.copy_from_slice(&[0, 0, 0, 255]);
+            }
+        }
+        rgba
+    }
+
+    #[test]
+    fn test_qoi_roundtrip() {
+        let mut rgba = text_tile();
+        for (i, px) in rgba.chunks_exact_mut(4).enumerate().skip(100).take(300) {
+            px.copy_from_slice(&[(i * 7) as u8, (i * 13) as u8, (i * 3) as u8, 255]);
+        }
+        let encoded = qoi_encode(&rgba, TILE_SIZE, TILE_SIZE);
+        assert!(encoded.len() < rgba.len());
+        let (w, h, decoded) = qoi_decode(&encoded).unwrap();
+        assert_eq!((w, h), (TILE_SIZE, TILE_SIZE));
+        assert_eq!(decoded, rgba);
+    }
+
+    #[test]
+    fn test_qoi_decode_too_large() {
+        let mut data = b"qoif".to_vec();
+        data.extend_from_slice(&u32::MAX.to_be_bytes());
+        data.extend_from_slice(&u32::MAX.to_be_bytes());
+        data.extend_from_slice(&[4, 0]);
+        data.extend_from_slice(&QOI_END);
+        assert!(qoi_decode(&data).is_err());
+    }
+
+    #[test]
+    fn test_settled_text_tiles() {
+        let (width, height) = (TILE_SIZE * 2 + 10, TILE_SIZE);
+        let mut frame = vec![255u8; width * height * 4];
+        let mut encoder = TileEncoder::new(width, height);
+        encoder.update(Pixfmt::RGBA, &frame, width * 4);
+        let mut decoder = TileDecoder::default();
+        // Reset only, nothing settled
+        decoder.update(&encoder.poll().unwrap()).unwrap();
+        assert!(decoder.is_empty());
+        std::thread::sleep(Duration::from_millis(SETTLE_MS + 10));
+        decoder.update(&encoder.poll().unwrap()).unwrap();
+        assert_eq!(decoder.tiles.len(), 3);
+        assert!(encoder.poll().is_none());
+
+        // Damage the first tile
+        frame[0] = 0;
+        encoder.update(Pixfmt::RGBA, &frame, width * 4);
+        decoder.update(&encoder.poll().unwrap()).unwrap();
+        assert_eq!(decoder.tiles.len(), 2);
+        assert!(!decoder.tiles.contains_key(&(0, 0)));
+
+        let mut rgb = ImageRgb::new(ImageFormat::ABGR, 1);
+        rgb.w = width;
+        rgb.h = height;
+        rgb.raw = vec![0; width * height * 4];
+        decoder.draw(&mut rgb);
+        assert_eq!(rgb.raw[0], 0);
+        assert_eq!(rgb.raw[TILE_SIZE * 4], 255);
+    }
+
+    #[test]
+    fn test_photo_is_not_text() {
+        let rgba: Vec<u8> = (0..TILE_SIZE * TILE_SIZE)
+            .flat_map(|i| [(i % 251) as u8, (i / 251) as u8, (i % 13) as u8, 255])
+            .collect();
+        assert!(!is_text_like(&rgba));
+        assert!(is_text_like(&text_tile()));
+    }
+}
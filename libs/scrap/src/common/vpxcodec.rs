@@ -8,7 +8,7 @@ use hbb_common::log;
 use hbb_common::message_proto::{Chroma, EncodedVideoFrame, EncodedVideoFrames, VideoFrame};
 use hbb_common::ResultType;
 
-use crate::codec::{base_bitrate, codec_thread_num, EncoderApi};
+use crate::codec::{base_bitrate, codec_thread_num, EncoderApi, Roi};
 use crate::{EncodeInput, EncodeYuvFormat, GoogleImage, Pixfmt, STRIDE_ALIGN};
 
 use super::vpx::{vp8e_enc_control_id::*, vpx_codec_err_t::*, *};
@@ -32,6 +32,9 @@ impl Default for VpxVideoCodecId {
     }
 }
 
+// The quantizer index delta of the region of interest, lower is better quality.
+const ROI_DELTA_Q: c_int = -20;
+
 pub struct VpxEncoder {
     ctx: vpx_codec_ctx_t,
     width: usize,
@@ -39,6 +42,7 @@ pub struct VpxEncoder {
     id: VpxVideoCodecId,
     i444: bool,
     yuvfmt: EncodeYuvFormat,
+    roi: Option<Roi>,
 }
 
 pub struct VpxDecoder {
@@ -165,6 +169,7 @@ impl EncoderApi for VpxEncoder {
                     id: config.codec,
                     i444,
                     yuvfmt: Self::get_yuvfmt(config.width, config.height, i444),
+                    roi: None,
                 })
             }
             _ => Err(anyhow!("encoder type mismatch")),
@@ -228,6 +233,54 @@ impl EncoderApi for VpxEncoder {
     }
 
     fn disable(&self) {}
+
+    fn set_roi(&mut self, roi: Option<Roi>) -> ResultType<()> {
+        // The segment map is never touched unless a region is requested.
+        if roi == self.roi {
+            return Ok(());
+        }
+        // VP8 maps the segments per 16x16 macroblock, VP9 per 8x8 block.
+        let block = match self.id {
+            VpxVideoCodecId::VP8 => 16,
+            VpxVideoCodecId::VP9 => 8,
+        };
+        let cols = (self.width + block - 1) / block;
+        let rows = (self.height + block - 1) / block;
+        let mut map = Vec::new();
+        if let Some(roi) = roi {
+            map = vec![0u8; rows * cols];
+            let (x0, y0) = (roi.x / block, roi.y / block);
+            let x1 = ((roi.x + roi.width + block - 1) / block).min(cols);
+            let y1 = ((roi.y + roi.height + block - 1) / block).min(rows);
+            for y in y0..y1 {
+                map[y * cols + x0.min(x1)..y * cols + x1].fill(1);
+            }
+        }
+        let mut roi_map: vpx_roi_map_t = unsafe { std::mem::zeroed() };
+        roi_map.enabled = (!map.is_empty()) as _;
+        roi_map.roi_map = if map.is_empty() {
+            ptr::null_mut()
+        } else {
+            map.as_mut_ptr()
+        };
+        roi_map.rows = rows as _;
+        roi_map.cols = cols as _;
+        roi_map.delta_q[1] = ROI_DELTA_Q;
+        // No fixed reference frame for the segments.
+        roi_map.ref_frame.fill(-1);
+        // The map is copied by the encoder.
+        call_vpx!(vpx_codec_control_(
+            &mut self.ctx,
+            VP8E_SET_ROI_MAP as _,
+            &mut roi_map as *mut vpx_roi_map_t,
+        ));
+        self.roi = roi;
+        Ok(())
+    }
+
+    fn support_roi(&self) -> bool {
+        true
+    }
 }
 
 impl VpxEncoder {
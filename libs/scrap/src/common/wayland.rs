@@ -27,7 +27,7 @@ fn map_err<E: ToString>(err: E) -> io::Error {
 
 impl Capturer {
     pub fn new(display: Display) -> io::Result<Capturer> {
-        let r = display.0.recorder(false).map_err(map_err)?;
+        let r = display.capturable.recorder(false).map_err(map_err)?;
         Ok(Capturer(display, r, Default::default()))
     }
 
@@ -61,7 +61,11 @@ impl TraitCapturer for Capturer {
     }
 }
 
-pub struct Display(pipewire::PipeWireCapturable);
+pub struct Display {
+    capturable: Box<dyn Capturable>,
+    position: (i32, i32),
+    size: (usize, usize),
+}
 
 impl Display {
     pub fn primary() -> io::Result<Display> {
@@ -73,23 +77,39 @@ impl Display {
     }
 
     pub fn all() -> io::Result<Vec<Display>> {
+        // wlroots compositors have no Mutter API, and the portal always asks the user.
+        if pipewire::is_headless_capture() && wlr_screencopy::is_supported() {
+            return Ok(wlr_screencopy::get_capturables()
+                .map_err(map_err)?
+                .drain(..)
+                .map(|x| Display {
+                    position: x.position,
+                    size: x.size,
+                    capturable: Box::new(x),
+                })
+                .collect());
+        }
         Ok(pipewire::get_capturables()
             .map_err(map_err)?
             .drain(..)
-            .map(|x| Display(x))
+            .map(|x| Display {
+                position: x.position,
+                size: x.size,
+                capturable: Box::new(x),
+            })
             .collect())
     }
 
     pub fn width(&self) -> usize {
-        self.0.size.0
+        self.size.0
     }
 
     pub fn height(&self) -> usize {
-        self.0.size.1
+        self.size.1
     }
 
     pub fn origin(&self) -> (i32, i32) {
-        self.0.position
+        self.position
     }
 
     pub fn is_online(&self) -> bool {
@@ -1,5 +1,7 @@
 pub mod capturable;
 pub mod pipewire;
+pub mod mutter_screencast;
 mod screencast_portal;
 mod request_portal;
 pub mod remote_desktop_portal;
+pub mod wlr_screencopy;
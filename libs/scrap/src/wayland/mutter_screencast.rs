@@ -0,0 +1,286 @@
+// Screen cast through the D-Bus API of Mutter, which is also used by gnome-remote-desktop.
+//
+// Unlike the portal, no consent dialog is shown. So it is used for unattended access on GNOME,
+// also if there is no physical monitor, a virtual monitor is recorded then.
+// KWin has no such API, KDE relies on the restore tokens of the screen cast portal.
+//
+// The screen cast session is linked to a remote desktop session of Mutter, which takes the input.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use dbus::{
+    arg::{PropMap, Variant},
+    blocking::{stdintf::org_freedesktop_dbus::Properties, SyncConnection},
+    message::{MatchRule, MessageType},
+};
+use tracing::{debug, warn};
+
+use super::pipewire::{DBusError, PwStreamInfo};
+
+const MUTTER_REMOTE_DESKTOP: &str = "org.gnome.Mutter.RemoteDesktop";
+const MUTTER_REMOTE_DESKTOP_PATH: &str = "/org/gnome/Mutter/RemoteDesktop";
+const MUTTER_REMOTE_DESKTOP_SESSION: &str = "org.gnome.Mutter.RemoteDesktop.Session";
+const MUTTER_SCREEN_CAST: &str = "org.gnome.Mutter.ScreenCast";
+const MUTTER_SCREEN_CAST_PATH: &str = "/org/gnome/Mutter/ScreenCast";
+const MUTTER_SCREEN_CAST_SESSION: &str = "org.gnome.Mutter.ScreenCast.Session";
+const MUTTER_SCREEN_CAST_STREAM: &str = "org.gnome.Mutter.ScreenCast.Stream";
+const MUTTER_DISPLAY_CONFIG: &str = "org.gnome.Mutter.DisplayConfig";
+const MUTTER_DISPLAY_CONFIG_PATH: &str = "/org/gnome/Mutter/DisplayConfig";
+const DBUS_TIMEOUT: Duration = Duration::from_millis(3_000);
+const STREAM_ADDED_TIMEOUT: Duration = Duration::from_secs(5);
+// Hidden, the cursor is sent to the peer separately, the same as the portal.
+const CURSOR_MODE_HIDDEN: u32 = 0;
+
+// (connector, vendor, product, serial)
+type MonitorSpec = (String, String, String, String);
+// (x, y, scale, transform, primary, monitors, properties)
+type LogicalMonitor = (i32, i32, f64, u32, bool, Vec<MonitorSpec>, PropMap);
+// (id, width, height, refresh rate, preferred scale, supported scales, properties)
+type MonitorMode = (String, i32, i32, f64, f64, Vec<f64>, PropMap);
+type Monitor = (MonitorSpec, Vec<MonitorMode>, PropMap);
+
+pub fn is_available() -> bool {
+    let conn = match SyncConnection::new_session() {
+        Ok(conn) => conn,
+        Err(_) => return false,
+    };
+    let proxy = conn.with_proxy(
+        "org.freedesktop.DBus",
+        "/org/freedesktop/DBus",
+        DBUS_TIMEOUT,
+    );
+    let res: Result<(bool,), _> = proxy.method_call(
+        "org.freedesktop.DBus",
+        "NameHasOwner",
+        (MUTTER_SCREEN_CAST,),
+    );
+    matches!(res, Ok((true,)))
+}
+
+// The connectors of the enabled monitors, primary first.
+fn get_active_connectors(conn: &SyncConnection) -> Result<Vec<String>, dbus::Error> {
+    let proxy = conn.with_proxy(
+        MUTTER_DISPLAY_CONFIG,
+        MUTTER_DISPLAY_CONFIG_PATH,
+        DBUS_TIMEOUT,
+    );
+    let (_serial, _monitors, logical_monitors, _props): (
+        u32,
+        Vec<Monitor>,
+        Vec<LogicalMonitor>,
+        PropMap,
+    ) = proxy.method_call(MUTTER_DISPLAY_CONFIG, "GetCurrentState", ())?;
+    let mut connectors = Vec::new();
+    for (_x, _y, _scale, _transform, primary, monitors, _props) in logical_monitors {
+        for (connector, ..) in monitors {
+            if primary {
+                connectors.insert(0, connector);
+            } else {
+                connectors.push(connector);
+            }
+        }
+    }
+    Ok(connectors)
+}
+
+fn get_stream_info(
+    conn: &SyncConnection,
+    stream: &dbus::Path<'static>,
+    node_id: u32,
+) -> PwStreamInfo {
+    let mut info = PwStreamInfo {
+        path: node_id as _,
+        source_type: 1,
+        position: (0, 0),
+        size: (0, 0),
+    };
+    let proxy = conn.with_proxy(MUTTER_SCREEN_CAST, stream.clone(), DBUS_TIMEOUT);
+    match proxy.get::<PropMap>(MUTTER_SCREEN_CAST_STREAM, "Parameters") {
+        Ok(params) => {
+            let get_pair = |key: &str| -> Option<(i64, i64)> {
+                let mut itr = params.get(key)?.0.as_iter()?;
+                Some((itr.next()?.as_i64()?, itr.next()?.as_i64()?))
+            };
+            if let Some((x, y)) = get_pair("position") {
+                info.position = (x as _, y as _);
+            }
+            if let Some((w, h)) = get_pair("size") {
+                info.size = (w as _, h as _);
+            }
+        }
+        Err(e) => {
+            // The size is also read from the first frame, see `PipeWireCapturable::new`.
+            warn!("Failed to get the parameters of stream {}: {}", stream, e);
+        }
+    }
+    info
+}
+
+fn record_props() -> PropMap {
+    let mut props: PropMap = HashMap::new();
+    props.insert(
+        "cursor-mode".to_string(),
+        Variant(Box::new(CURSOR_MODE_HIDDEN)),
+    );
+    props
+}
+
+// The remote desktop session, and the streams with their objects.
+pub fn request_screen_cast() -> Result<
+    (
+        SyncConnection,
+        dbus::Path<'static>,
+        Vec<(PwStreamInfo, dbus::Path<'static>)>,
+    ),
+    Box<dyn Error>,
+> {
+    let conn = SyncConnection::new_session()?;
+    let proxy = conn.with_proxy(
+        MUTTER_REMOTE_DESKTOP,
+        MUTTER_REMOTE_DESKTOP_PATH,
+        DBUS_TIMEOUT,
+    );
+    let (rd_session,): (dbus::Path<'static>,) =
+        proxy.method_call(MUTTER_REMOTE_DESKTOP, "CreateSession", ())?;
+    let rd_session_proxy = conn.with_proxy(MUTTER_REMOTE_DESKTOP, rd_session.clone(), DBUS_TIMEOUT);
+    let session_id: String = rd_session_proxy.get(MUTTER_REMOTE_DESKTOP_SESSION, "SessionId")?;
+
+    let proxy = conn.with_proxy(MUTTER_SCREEN_CAST, MUTTER_SCREEN_CAST_PATH, DBUS_TIMEOUT);
+    let mut props = PropMap::new();
+    props.insert(
+        "remote-desktop-session-id".to_string(),
+        Variant(Box::new(session_id)),
+    );
+    let (session,): (dbus::Path<'static>,) =
+        proxy.method_call(MUTTER_SCREEN_CAST, "CreateSession", (props,))?;
+    let session_proxy = conn.with_proxy(MUTTER_SCREEN_CAST, session.clone(), DBUS_TIMEOUT);
+
+    let connectors = get_active_connectors(&conn).unwrap_or_else(|e| {
+        warn!("Failed to get the monitors of Mutter: {}", e);
+        Vec::new()
+    });
+    let mut streams: Vec<dbus::Path<'static>> = Vec::new();
+    if connectors.is_empty() {
+        debug!("No active monitor, record a virtual monitor");
+        let (stream,): (dbus::Path<'static>,) = session_proxy.method_call(
+            MUTTER_SCREEN_CAST_SESSION,
+            "RecordVirtual",
+            (record_props(),),
+        )?;
+        streams.push(stream);
+    } else {
+        for connector in connectors.iter() {
+            let (stream,): (dbus::Path<'static>,) = session_proxy.method_call(
+                MUTTER_SCREEN_CAST_SESSION,
+                "RecordMonitor",
+                (connector.as_str(), record_props()),
+            )?;
+            debug!("Record monitor {}, stream {}", connector, stream);
+            streams.push(stream);
+        }
+    }
+
+    let node_ids: Arc<Mutex<HashMap<dbus::Path<'static>, u32>>> = Default::default();
+    for stream in streams.iter() {
+        let mut m = MatchRule::new();
+        m.path = Some(stream.clone());
+        m.msg_type = Some(MessageType::Signal);
+        m.interface = Some(MUTTER_SCREEN_CAST_STREAM.into());
+        m.member = Some("PipeWireStreamAdded".into());
+        let node_ids = node_ids.clone();
+        let stream = stream.clone();
+        conn.add_match(m, move |(node_id,): (u32,), _, _| {
+            node_ids.lock().unwrap().insert(stream.clone(), node_id);
+            true
+        })?;
+    }
+    // The linked screen cast session is started by the remote desktop session.
+    rd_session_proxy.method_call::<(), _, _, _>(MUTTER_REMOTE_DESKTOP_SESSION, "Start", ())?;
+
+    let start = Instant::now();
+    while node_ids.lock().unwrap().len() < streams.len() {
+        if start.elapsed() > STREAM_ADDED_TIMEOUT {
+            let _ = rd_session_proxy.method_call::<(), _, _, _>(
+                MUTTER_REMOTE_DESKTOP_SESSION,
+                "Stop",
+                (),
+            );
+            return Err(Box::new(DBusError(
+                "Timeout waiting for the pipewire streams of Mutter.".into(),
+            )));
+        }
+        conn.process(Duration::from_millis(100))?;
+    }
+    let node_ids = node_ids.lock().unwrap().clone();
+    let infos = streams
+        .iter()
+        .filter_map(|s| Some((get_stream_info(&conn, s, *node_ids.get(s)?), s.clone())))
+        .collect();
+    Ok((conn, rd_session, infos))
+}
+
+// The input of the remote desktop session, see `RdpInputSession::Mutter`.
+fn notify<A: dbus::arg::AppendAll>(
+    conn: &SyncConnection,
+    session: &dbus::Path<'static>,
+    method: &str,
+    args: A,
+) -> Result<(), dbus::Error> {
+    let proxy = conn.with_proxy(MUTTER_REMOTE_DESKTOP, session.clone(), DBUS_TIMEOUT);
+    proxy.method_call(MUTTER_REMOTE_DESKTOP_SESSION, method, args)
+}
+
+pub fn notify_keyboard_keycode(
+    conn: &SyncConnection,
+    session: &dbus::Path<'static>,
+    keycode: u32,
+    pressed: bool,
+) -> Result<(), dbus::Error> {
+    notify(conn, session, "NotifyKeyboardKeycode", (keycode, pressed))
+}
+
+pub fn notify_pointer_button(
+    conn: &SyncConnection,
+    session: &dbus::Path<'static>,
+    button: i32,
+    pressed: bool,
+) -> Result<(), dbus::Error> {
+    notify(conn, session, "NotifyPointerButton", (button, pressed))
+}
+
+// `axis` is 0 for vertical, 1 for horizontal.
+pub fn notify_pointer_axis_discrete(
+    conn: &SyncConnection,
+    session: &dbus::Path<'static>,
+    axis: u32,
+    steps: i32,
+) -> Result<(), dbus::Error> {
+    notify(conn, session, "NotifyPointerAxisDiscrete", (axis, steps))
+}
+
+pub fn notify_pointer_motion_relative(
+    conn: &SyncConnection,
+    session: &dbus::Path<'static>,
+    dx: f64,
+    dy: f64,
+) -> Result<(), dbus::Error> {
+    notify(conn, session, "NotifyPointerMotionRelative", (dx, dy))
+}
+
+pub fn notify_pointer_motion_absolute(
+    conn: &SyncConnection,
+    session: &dbus::Path<'static>,
+    stream: &dbus::Path<'static>,
+    x: f64,
+    y: f64,
+) -> Result<(), dbus::Error> {
+    notify(
+        conn,
+        session,
+        "NotifyPointerMotionAbsolute",
+        (stream.to_string(), x, y),
+    )
+}
@@ -17,10 +17,11 @@ use gstreamer as gst;
 use gstreamer::prelude::*;
 use gstreamer_app::AppSink;
 
-use hbb_common::config;
+use hbb_common::{config, serde_json};
 
 use super::capturable::PixelProvider;
 use super::capturable::{Capturable, Recorder};
+use super::mutter_screencast;
 use super::remote_desktop_portal::OrgFreedesktopPortalRemoteDesktop as remote_desktop_portal;
 use super::request_portal::OrgFreedesktopPortalRequestResponse;
 use super::screencast_portal::OrgFreedesktopPortalScreenCast as screencast_portal;
@@ -41,6 +42,14 @@ pub fn is_rdp_session_hold() -> bool {
     RDP_SESSION_INFO.lock().unwrap().is_some()
 }
 
+// The session is a remote desktop session of Mutter, see `RdpInputSession::Mutter`.
+pub fn is_mutter_session() -> bool {
+    match RDP_SESSION_INFO.lock().unwrap().as_ref() {
+        Some(info) => matches!(info.input, RdpInputSession::Mutter(_)),
+        None => false,
+    }
+}
+
 pub fn try_close_session() {
     let mut rdp_info = RDP_SESSION_INFO.lock().unwrap();
     let mut close = false;
@@ -58,23 +67,39 @@ pub fn try_close_session() {
 pub struct RdpSessionInfo {
     pub conn: Arc<SyncConnection>,
     pub streams: Vec<PwStreamInfo>,
-    pub fd: OwnedFd,
+    // None if the streams are from Mutter, they are on the default pipewire remote.
+    pub fd: Option<OwnedFd>,
     pub session: dbus::Path<'static>,
+    pub input: RdpInputSession,
     pub is_support_restore_token: bool,
     pub resolution: Arc<Mutex<Option<(usize, usize)>>>,
 }
+
+// The kind of `RdpSessionInfo::session`, which takes the input.
+#[derive(Debug, Clone)]
+pub enum RdpInputSession {
+    // A session of the remote desktop portal.
+    Portal,
+    // A remote desktop session of Mutter, the absolute pointer motion is on the screen cast
+    // stream objects, keyed by the pipewire nodes.
+    Mutter(HashMap<u64, dbus::Path<'static>>),
+}
 #[derive(Debug, Clone, Copy)]
 pub struct PwStreamInfo {
     pub path: u64,
-    source_type: u64,
-    position: (i32, i32),
-    size: (usize, usize),
+    pub(super) source_type: u64,
+    pub(super) position: (i32, i32),
+    pub(super) size: (usize, usize),
 }
 
 impl PwStreamInfo {
     pub fn get_size(&self) -> (usize, usize) {
         self.size
     }
+
+    pub fn get_position(&self) -> (i32, i32) {
+        self.position
+    }
 }
 
 #[derive(Debug)]
@@ -105,7 +130,7 @@ impl Error for GStreamerError {}
 pub struct PipeWireCapturable {
     // connection needs to be kept alive for recording
     dbus_conn: Arc<SyncConnection>,
-    fd: OwnedFd,
+    fd: Option<OwnedFd>,
     path: u64,
     source_type: u64,
     pub position: (i32, i32),
@@ -115,7 +140,7 @@ pub struct PipeWireCapturable {
 impl PipeWireCapturable {
     fn new(
         conn: Arc<SyncConnection>,
-        fd: OwnedFd,
+        fd: Option<OwnedFd>,
         resolution: Arc<Mutex<Option<(usize, usize)>>>,
         stream: PwStreamInfo,
     ) -> Self {
@@ -148,7 +173,7 @@ impl std::fmt::Debug for PipeWireCapturable {
             f,
             "PipeWireCapturable {{dbus: {}, fd: {}, path: {}, source_type: {}}}",
             self.dbus_conn.unique_name(),
-            self.fd.as_raw_fd(),
+            self.fd.as_ref().map_or(-1, |fd| fd.as_raw_fd()),
             self.path,
             self.source_type
         )
@@ -218,7 +243,9 @@ impl PipeWireRecorder {
         let pipeline = gst::Pipeline::new(None);
 
         let src = gst::ElementFactory::make("pipewiresrc", None)?;
-        src.set_property("fd", &capturable.fd.as_raw_fd())?;
+        if let Some(fd) = capturable.fd.as_ref() {
+            src.set_property("fd", &fd.as_raw_fd())?;
+        }
         src.set_property("path", &format!("{}", capturable.path))?;
         src.set_property("keepalive_time", &1_000.as_raw_fd())?;
 
@@ -502,6 +529,59 @@ fn streams_from_response(response: OrgFreedesktopPortalRequestResponse) -> Vec<P
 static mut INIT: bool = false;
 const RESTORE_TOKEN: &str = "restore_token";
 const RESTORE_TOKEN_CONF_KEY: &str = "wayland-restore-token";
+// The restore tokens of the display sets, `{display set: restore token}`.
+// A token restores the monitors selected before, it does not apply once monitors are added or removed.
+const RESTORE_TOKENS_CONF_KEY: &str = "wayland-restore-tokens";
+// Capture through Mutter or wlr-screencopy instead of the portal, no consent dialog is shown.
+pub const OPTION_WAYLAND_HEADLESS: &str = "wayland-headless";
+
+// The current monitors, from Xwayland, because the portal does not provide them before the selection.
+fn get_display_set() -> String {
+    let output = match Command::new("xrandr").arg("--listmonitors").output() {
+        Ok(output) if output.status.success() => output,
+        _ => return "".to_owned(),
+    };
+    // " 0: +*XWAYLAND0 1920/520x1080/290+0+0  XWAYLAND0"
+    let mut monitors = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .skip(1)
+        .filter_map(|line| line.split_whitespace().nth(2).map(|s| s.to_owned()))
+        .collect::<Vec<_>>();
+    monitors.sort();
+    monitors.join(";")
+}
+
+fn get_restore_tokens() -> HashMap<String, String> {
+    serde_json::from_str(&config::LocalConfig::get_option(RESTORE_TOKENS_CONF_KEY))
+        .unwrap_or_default()
+}
+
+fn get_restore_token(display_set: &str) -> String {
+    match get_restore_tokens().remove(display_set) {
+        Some(token) => token,
+        None => config::LocalConfig::get_option(RESTORE_TOKEN_CONF_KEY),
+    }
+}
+
+fn set_restore_token(display_set: &str, token: &str) {
+    let mut tokens = get_restore_tokens();
+    tokens.insert(display_set.to_owned(), token.to_owned());
+    config::LocalConfig::set_option(
+        RESTORE_TOKENS_CONF_KEY.to_owned(),
+        serde_json::to_string(&tokens).unwrap_or_default(),
+    );
+    // The latest token, it is also checked by the settings page.
+    config::LocalConfig::set_option(RESTORE_TOKEN_CONF_KEY.to_owned(), token.to_owned());
+}
+
+pub fn clear_restore_tokens() {
+    config::LocalConfig::set_option(RESTORE_TOKENS_CONF_KEY.to_owned(), "".to_owned());
+    config::LocalConfig::set_option(RESTORE_TOKEN_CONF_KEY.to_owned(), "".to_owned());
+}
+
+pub fn is_headless_capture() -> bool {
+    config::Config::get_option(OPTION_WAYLAND_HEADLESS) == "Y" && is_server_running()
+}
 
 pub fn get_available_cursor_modes() -> Result<u32, dbus::Error> {
     let conn = SyncConnection::new_session()?;
@@ -649,7 +729,7 @@ fn on_create_session_response(
         // See `is_server_running()` to understand the following code.
         if is_server_running() {
             if is_support_restore_token {
-                let restore_token = config::LocalConfig::get_option(RESTORE_TOKEN_CONF_KEY);
+                let restore_token = get_restore_token(&get_display_set());
                 if !restore_token.is_empty() {
                     args.insert(RESTORE_TOKEN.to_string(), Variant(Box::new(restore_token)));
                 }
@@ -661,7 +741,8 @@ fn on_create_session_response(
                 Variant(Box::new("u3".to_string())),
             );
             // https://flatpak.github.io/xdg-desktop-portal/docs/doc-org.freedesktop.portal.ScreenCast.html
-            // args.insert("multiple".into(), Variant(Box::new(true)));
+            // Each selected monitor is a display of the peer.
+            args.insert("multiple".into(), Variant(Box::new(true)));
             args.insert("types".into(), Variant(Box::new(1u32))); //| 2u32)));
 
             let path = portal.select_sources(ses.clone(), args)?;
@@ -725,7 +806,7 @@ fn on_select_devices_response(
             Variant(Box::new("u3".to_string())),
         );
         // https://flatpak.github.io/xdg-desktop-portal/docs/doc-org.freedesktop.portal.ScreenCast.html
-        // args.insert("multiple".into(), Variant(Box::new(true)));
+        args.insert("multiple".into(), Variant(Box::new(true)));
         args.insert("types".into(), Variant(Box::new(1u32))); //| 2u32)));
 
         let session = session.clone();
@@ -804,10 +885,7 @@ fn on_start_response(
             if is_support_restore_token {
                 if let Some(restore_token) = r.results.get(RESTORE_TOKEN) {
                     if let Some(restore_token) = restore_token.as_str() {
-                        config::LocalConfig::set_option(
-                            RESTORE_TOKEN_CONF_KEY.to_owned(),
-                            restore_token.to_owned(),
-                        );
+                        set_restore_token(&get_display_set(), restore_token);
                     }
                 }
             }
@@ -833,6 +911,27 @@ pub fn get_capturables() -> Result<Vec<PipeWireCapturable>, Box<dyn Error>> {
         Err(err) => return Err(Box::new(err)),
     };
 
+    if rdp_connection.is_none() && is_headless_capture() && mutter_screencast::is_available() {
+        match mutter_screencast::request_screen_cast() {
+            Ok((conn, session, streams)) => {
+                let objects = streams.iter().map(|(s, o)| (s.path, o.clone())).collect();
+                *rdp_connection = Some(RdpSessionInfo {
+                    conn: Arc::new(conn),
+                    streams: streams.into_iter().map(|(s, _)| s).collect(),
+                    fd: None,
+                    session,
+                    input: RdpInputSession::Mutter(objects),
+                    // No dialog to request again, the same as a restore token.
+                    is_support_restore_token: true,
+                    resolution: Arc::new(Mutex::new(None)),
+                });
+            }
+            Err(e) => {
+                warn!("Failed to capture through Mutter, use the portal: {}", e);
+            }
+        }
+    }
+
     if rdp_connection.is_none() {
         let (conn, fd, streams, session, is_support_restore_token) = request_remote_desktop()?;
         let conn = Arc::new(conn);
@@ -840,8 +939,9 @@ pub fn get_capturables() -> Result<Vec<PipeWireCapturable>, Box<dyn Error>> {
         let rdp_info = RdpSessionInfo {
             conn,
             streams,
-            fd,
+            fd: Some(fd),
             session,
+            input: RdpInputSession::Portal,
             is_support_restore_token,
             resolution: Arc::new(Mutex::new(None)),
         };
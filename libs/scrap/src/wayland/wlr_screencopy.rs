@@ -0,0 +1,473 @@
+// Capture through `wlr-screencopy-unstable-v1`, supported by wlroots compositors (Sway, Hyprland).
+//
+// The compositor copies the output into a shm buffer on request, no portal and no consent dialog
+// are involved. So it is used for unattended servers, see `pipewire::OPTION_WAYLAND_HEADLESS`.
+// `ext-image-copy-capture-v1` is not supported yet.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::File;
+use std::io;
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd};
+use std::os::unix::fs::FileExt;
+use std::time::{Duration, Instant};
+
+use hbb_common::libc;
+
+use tracing::debug;
+use wayland_client::{
+    delegate_noop,
+    globals::{registry_queue_init, GlobalList, GlobalListContents},
+    protocol::{
+        wl_buffer::WlBuffer,
+        wl_output::{self, WlOutput},
+        wl_registry::WlRegistry,
+        wl_shm::{self, WlShm},
+        wl_shm_pool::WlShmPool,
+    },
+    Connection, Dispatch, EventQueue, Proxy, QueueHandle, WEnum,
+};
+use wayland_protocols_wlr::screencopy::v1::client::{
+    zwlr_screencopy_frame_v1::{self, ZwlrScreencopyFrameV1},
+    zwlr_screencopy_manager_v1::ZwlrScreencopyManagerV1,
+};
+
+use super::capturable::{Capturable, PixelProvider, Recorder};
+use super::pipewire::DBusError as WaylandError;
+
+#[derive(Debug, Clone, Default)]
+struct OutputInfo {
+    name: String,
+    position: (i32, i32),
+    size: (usize, usize),
+    transform_swapped: bool,
+}
+
+#[derive(Default)]
+struct FrameState {
+    // (format, width, height, stride)
+    buffer: Option<(wl_shm::Format, u32, u32, u32)>,
+    y_invert: bool,
+    ready: bool,
+    failed: bool,
+}
+
+#[derive(Default)]
+struct State {
+    outputs: HashMap<u32, OutputInfo>,
+    frame: FrameState,
+}
+
+impl Dispatch<WlRegistry, GlobalListContents> for State {
+    fn event(
+        _: &mut Self,
+        _: &WlRegistry,
+        _: <WlRegistry as Proxy>::Event,
+        _: &GlobalListContents,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+// The user data is the global name of the output.
+impl Dispatch<WlOutput, u32> for State {
+    fn event(
+        state: &mut Self,
+        _: &WlOutput,
+        event: wl_output::Event,
+        global_name: &u32,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        let info = state.outputs.entry(*global_name).or_default();
+        match event {
+            wl_output::Event::Geometry {
+                x, y, transform, ..
+            } => {
+                info.position = (x, y);
+                info.transform_swapped = matches!(
+                    transform,
+                    WEnum::Value(
+                        wl_output::Transform::_90
+                            | wl_output::Transform::_270
+                            | wl_output::Transform::Flipped90
+                            | wl_output::Transform::Flipped270
+                    )
+                );
+            }
+            wl_output::Event::Mode {
+                flags,
+                width,
+                height,
+                ..
+            } => {
+                if let WEnum::Value(flags) = flags {
+                    if flags.contains(wl_output::Mode::Current) {
+                        info.size = (width as _, height as _);
+                    }
+                }
+            }
+            wl_output::Event::Name { name } => {
+                info.name = name;
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<ZwlrScreencopyFrameV1, ()> for State {
+    fn event(
+        state: &mut Self,
+        _: &ZwlrScreencopyFrameV1,
+        event: zwlr_screencopy_frame_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        match event {
+            zwlr_screencopy_frame_v1::Event::Buffer {
+                format: WEnum::Value(format),
+                width,
+                height,
+                stride,
+            } => {
+                state.frame.buffer = Some((format, width, height, stride));
+            }
+            zwlr_screencopy_frame_v1::Event::Flags { flags } => {
+                if let WEnum::Value(flags) = flags {
+                    state.frame.y_invert = flags.contains(zwlr_screencopy_frame_v1::Flags::YInvert);
+                }
+            }
+            zwlr_screencopy_frame_v1::Event::Ready { .. } => {
+                state.frame.ready = true;
+            }
+            zwlr_screencopy_frame_v1::Event::Failed => {
+                state.frame.failed = true;
+            }
+            _ => {}
+        }
+    }
+}
+
+delegate_noop!(State: ignore WlShm);
+delegate_noop!(State: ignore WlShmPool);
+delegate_noop!(State: ignore WlBuffer);
+delegate_noop!(State: ZwlrScreencopyManagerV1);
+
+fn err<E: ToString>(e: E) -> Box<dyn Error> {
+    Box::new(WaylandError(e.to_string()))
+}
+
+fn connect() -> Result<(Connection, GlobalList, EventQueue<State>), Box<dyn Error>> {
+    let conn = Connection::connect_to_env().map_err(err)?;
+    let (globals, queue) = registry_queue_init::<State>(&conn).map_err(err)?;
+    Ok((conn, globals, queue))
+}
+
+fn has_screencopy(globals: &GlobalList) -> bool {
+    let name = ZwlrScreencopyManagerV1::interface().name;
+    globals
+        .contents()
+        .with_list(|list| list.iter().any(|g| g.interface == name))
+}
+
+pub fn is_supported() -> bool {
+    match connect() {
+        Ok((_, globals, _)) => has_screencopy(&globals),
+        Err(_) => false,
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct WlrCapturable {
+    // The global name of the output in the compositor, it is the same for all clients.
+    global_name: u32,
+    output_name: String,
+    pub position: (i32, i32),
+    pub size: (usize, usize),
+}
+
+pub fn get_capturables() -> Result<Vec<WlrCapturable>, Box<dyn Error>> {
+    let (_conn, globals, mut queue) = connect()?;
+    if !has_screencopy(&globals) {
+        return Err(err("The compositor does not support wlr-screencopy"));
+    }
+    let qh = queue.handle();
+    let mut state = State::default();
+    for g in globals.contents().clone_list() {
+        if g.interface == WlOutput::interface().name {
+            let _: WlOutput = globals
+                .registry()
+                .bind(g.name, g.version.min(4), &qh, g.name);
+        }
+    }
+    queue.roundtrip(&mut state).map_err(err)?;
+    let mut capturables = state
+        .outputs
+        .into_iter()
+        .map(|(global_name, info)| WlrCapturable {
+            global_name,
+            output_name: info.name,
+            position: info.position,
+            size: if info.transform_swapped {
+                (info.size.1, info.size.0)
+            } else {
+                info.size
+            },
+        })
+        .collect::<Vec<_>>();
+    capturables.sort_by_key(|c| (c.position.1, c.position.0));
+    debug!("wlr-screencopy outputs: {:?}", capturables);
+    Ok(capturables)
+}
+
+impl Capturable for WlrCapturable {
+    fn name(&self) -> String {
+        format!("wlr-screencopy {}", self.output_name)
+    }
+
+    fn geometry_relative(&self) -> Result<(f64, f64, f64, f64), Box<dyn Error>> {
+        Ok((0.0, 0.0, 1.0, 1.0))
+    }
+
+    fn before_input(&mut self) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    fn recorder(&self, capture_cursor: bool) -> Result<Box<dyn Recorder>, Box<dyn Error>> {
+        Ok(Box::new(WlrRecorder::new(self, capture_cursor)?))
+    }
+}
+
+struct ShmBuffer {
+    file: File,
+    buffer: WlBuffer,
+    // (format, width, height, stride)
+    info: (wl_shm::Format, u32, u32, u32),
+}
+
+pub struct WlrRecorder {
+    _conn: Connection,
+    queue: EventQueue<State>,
+    state: State,
+    manager: ZwlrScreencopyManagerV1,
+    shm: WlShm,
+    output: WlOutput,
+    overlay_cursor: bool,
+    shm_buffer: Option<ShmBuffer>,
+    // The frame being copied and its buffer info, it is kept if the copy is not done in time.
+    pending: Option<(ZwlrScreencopyFrameV1, (wl_shm::Format, u32, u32, u32))>,
+    data: Vec<u8>,
+    saved_raw_data: Vec<u8>,
+}
+
+impl WlrRecorder {
+    fn new(capturable: &WlrCapturable, overlay_cursor: bool) -> Result<Self, Box<dyn Error>> {
+        let conn = Connection::connect_to_env().map_err(err)?;
+        let (globals, queue) = registry_queue_init::<State>(&conn).map_err(err)?;
+        let qh = queue.handle();
+        let manager: ZwlrScreencopyManagerV1 = globals.bind(&qh, 1..=3, ()).map_err(err)?;
+        let shm: WlShm = globals.bind(&qh, 1..=1, ()).map_err(err)?;
+        let output: WlOutput =
+            globals
+                .registry()
+                .bind(capturable.global_name, 1, &qh, capturable.global_name);
+        Ok(Self {
+            _conn: conn,
+            queue,
+            state: State::default(),
+            manager,
+            shm,
+            output,
+            overlay_cursor,
+            shm_buffer: None,
+            pending: None,
+            data: Vec::new(),
+            saved_raw_data: Vec::new(),
+        })
+    }
+
+    fn ensure_shm_buffer(
+        &mut self,
+        info: (wl_shm::Format, u32, u32, u32),
+    ) -> Result<&ShmBuffer, Box<dyn Error>> {
+        if self.shm_buffer.as_ref().map(|b| b.info) != Some(info) {
+            if let Some(old) = self.shm_buffer.take() {
+                old.buffer.destroy();
+            }
+            let (format, width, height, stride) = info;
+            let size = stride * height;
+            let file = create_shm_file(size as _)?;
+            let qh = self.queue.handle();
+            let pool = self.shm.create_pool(file.as_fd(), size as _, &qh, ());
+            let buffer =
+                pool.create_buffer(0, width as _, height as _, stride as _, format, &qh, ());
+            pool.destroy();
+            self.shm_buffer = Some(ShmBuffer { file, buffer, info });
+        }
+        self.shm_buffer.as_ref().ok_or_else(|| err("No shm buffer"))
+    }
+
+    // Returns false if the frame is neither ready nor failed before the deadline.
+    fn wait_frame(&mut self, deadline: Instant) -> Result<bool, Box<dyn Error>> {
+        loop {
+            self.queue.dispatch_pending(&mut self.state).map_err(err)?;
+            if self.state.frame.ready || self.state.frame.failed {
+                return Ok(true);
+            }
+            self.queue.flush().map_err(err)?;
+            let Some(guard) = self.queue.prepare_read() else {
+                continue;
+            };
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            if !poll_readable(guard.connection_fd(), timeout)? {
+                return Ok(false);
+            }
+            match guard.read() {
+                Ok(_) => {}
+                Err(wayland_client::backend::WaylandError::Io(e))
+                    if e.kind() == io::ErrorKind::WouldBlock => {}
+                Err(e) => return Err(err(e)),
+            }
+        }
+    }
+}
+
+fn poll_readable(fd: BorrowedFd, timeout: Duration) -> Result<bool, Box<dyn Error>> {
+    let mut pfd = libc::pollfd {
+        fd: fd.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    let timeout = timeout.as_millis().min(i32::MAX as _) as libc::c_int;
+    match unsafe { libc::poll(&mut pfd, 1, timeout) } {
+        n if n > 0 => Ok(true),
+        0 => Ok(false),
+        _ => {
+            let e = io::Error::last_os_error();
+            if e.kind() == io::ErrorKind::Interrupted {
+                Ok(false)
+            } else {
+                Err(e.into())
+            }
+        }
+    }
+}
+
+fn create_shm_file(size: u64) -> Result<File, Box<dyn Error>> {
+    let dir = std::env::var("XDG_RUNTIME_DIR").unwrap_or_else(|_| "/dev/shm".to_owned());
+    let path = std::path::Path::new(&dir).join(format!(
+        "scrap-screencopy-{}-{}",
+        std::process::id(),
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or_default()
+    ));
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(&path)?;
+    // The fd is kept by us and the compositor only.
+    std::fs::remove_file(&path)?;
+    file.set_len(size)?;
+    Ok(file)
+}
+
+impl Recorder for WlrRecorder {
+    fn capture(&mut self, timeout_ms: u64) -> Result<PixelProvider, Box<dyn Error>> {
+        let deadline = Instant::now() + Duration::from_millis(timeout_ms);
+        if self.pending.is_none() {
+            let qh = self.queue.handle();
+            self.state.frame = FrameState::default();
+            let frame =
+                self.manager
+                    .capture_output(self.overlay_cursor as _, &self.output, &qh, ());
+            // The buffer info is sent right after the request.
+            self.queue.roundtrip(&mut self.state).map_err(err)?;
+            let Some(info) = self.state.frame.buffer else {
+                frame.destroy();
+                return Err(err("wlr-screencopy failed, no buffer info"));
+            };
+            let buffer = self.ensure_shm_buffer(info)?.buffer.clone();
+            frame.copy(&buffer);
+            self.pending = Some((frame, info));
+        }
+        // The copy is finished by the compositor on its next repaint, wait for it in the next call.
+        if !self.wait_frame(deadline)? {
+            return Ok(PixelProvider::NONE);
+        }
+        let Some((frame, (format, width, height, stride))) = self.pending.take() else {
+            return Err(err("No pending frame"));
+        };
+        frame.destroy();
+        if self.state.frame.failed {
+            return Err(err("wlr-screencopy failed"));
+        }
+
+        let (width, height, stride) = (width as usize, height as usize, stride as usize);
+        let row = width * 4;
+        self.data.resize(row * height, 0);
+        let file = &self
+            .shm_buffer
+            .as_ref()
+            .ok_or_else(|| err("No shm buffer"))?
+            .file;
+        for y in 0..height {
+            let src_y = if self.state.frame.y_invert {
+                height - 1 - y
+            } else {
+                y
+            };
+            file.read_exact_at(
+                &mut self.data[y * row..(y + 1) * row],
+                (src_y * stride) as u64,
+            )?;
+        }
+        if crate::would_block_if_equal(&mut self.saved_raw_data, &self.data).is_err() {
+            return Ok(PixelProvider::NONE);
+        }
+        // Little endian, `Argb8888` is B, G, R, A in memory.
+        match format {
+            wl_shm::Format::Argb8888 | wl_shm::Format::Xrgb8888 => {
+                Ok(PixelProvider::BGR0(width, height, &self.data))
+            }
+            wl_shm::Format::Abgr8888 | wl_shm::Format::Xbgr8888 => {
+                Ok(PixelProvider::RGB0(width, height, &self.data))
+            }
+            _ => Err(err(format!("Unsupported shm format {:?}", format))),
+        }
+    }
+}
+
+impl Drop for WlrRecorder {
+    fn drop(&mut self) {
+        if let Some((frame, _)) = self.pending.take() {
+            frame.destroy();
+        }
+        if let Some(shm_buffer) = self.shm_buffer.take() {
+            shm_buffer.buffer.destroy();
+        }
+        self.manager.destroy();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::os::unix::net::UnixStream;
+
+    #[test]
+    fn test_poll_readable() {
+        let (mut a, b) = UnixStream::pair().unwrap();
+        let start = Instant::now();
+        assert!(!poll_readable(b.as_fd(), Duration::from_millis(50)).unwrap());
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert!(!poll_readable(b.as_fd(), Duration::ZERO).unwrap());
+        a.write_all(&[1]).unwrap();
+        assert!(poll_readable(b.as_fd(), Duration::from_secs(1)).unwrap());
+    }
+}
@@ -66,6 +66,14 @@ wm_start()
     export LANG LANGUAGE
   fi
 
+  # session template, see session-templates.toml
+  if [ -n "$DESKTOP_SESSION_COMMAND" ]; then
+    pre_start
+    sh -c "$DESKTOP_SESSION_COMMAND"
+    post_start
+    exit 0
+  fi
+
   # debian
   if [ -r /etc/X11/Xsession ]; then
     pre_start
@@ -2,9 +2,11 @@ use hbb_common::{
     anyhow::anyhow,
     bail,
     config::Config,
-    get_time,
+    get_time, log,
     password_security::{decrypt_vec_or_original, encrypt_vec_or_original},
-    ResultType,
+    rand::{self, Rng},
+    sha2::{Digest, Sha256},
+    tokio, ResultType,
 };
 use serde_derive::{Deserialize, Serialize};
 use std::sync::Mutex;
@@ -17,6 +19,28 @@ lazy_static::lazy_static! {
 const ISSUER: &str = "CloudyDesk";
 const TAG_LOGIN: &str = "Connection";
 
+// Comma separated second factors to use, `totp`, `webhook`, `email` and `backup-codes`.
+// Empty means all the configured ones.
+//
+// to-do: hardware keys (FIDO2/WebAuthn) are deferred to a follow-up. They are not a code typed
+// by the user, the assertion is signed by the key on the client side, so they need a new login
+// message carrying the challenge and the assertion, and the client to talk to the key by CTAP2.
+const OPTION_2FA_PROVIDERS: &str = "2fa-providers";
+const OPTION_2FA_WEBHOOK: &str = "2fa-webhook";
+const OPTION_2FA_SMTP: &str = "2fa-smtp";
+const OPTION_2FA_BACKUP_CODES: &str = "2fa-backup-codes";
+
+const PROVIDER_TOTP: &str = "totp";
+const PROVIDER_WEBHOOK: &str = "webhook";
+const PROVIDER_EMAIL: &str = "email";
+const PROVIDER_BACKUP_CODES: &str = "backup-codes";
+
+// The valid period of the codes delivered by the webhook and email.
+const ONE_TIME_CODE_VALID_MS: i64 = 5 * 60 * 1000;
+const ONE_TIME_CODE_DIGITS: usize = 6;
+const BACKUP_CODES_COUNT: usize = 10;
+const BACKUP_CODE_LEN: usize = 10;
+
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct TOTPInfo {
     pub name: String,
@@ -202,3 +226,524 @@ pub fn get_chatid_telegram(bot_token: &str) -> ResultType<Option<String>> {
 
     Ok(chat_id)
 }
+
+fn set_option(key: &str, value: &str) {
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    crate::ipc::set_option(key, value);
+    #[cfg(any(target_os = "android", target_os = "ios"))]
+    Config::set_option(key.to_owned(), value.to_owned());
+}
+
+fn encrypt_secret(secret: &str) -> Vec<u8> {
+    encrypt_vec_or_original(secret.as_bytes(), "00", 1024)
+}
+
+fn decrypt_secret(secret: &[u8]) -> ResultType<String> {
+    // An empty secret is not encrypted, e.g. the webhook without the header.
+    if secret.is_empty() {
+        return Ok("".to_owned());
+    }
+    let (secret, success, _) = decrypt_vec_or_original(secret, "00");
+    if !success {
+        bail!("decrypt_vec_or_original 2fa secret failed");
+    }
+    Ok(String::from_utf8(secret)?)
+}
+
+/// The login which requires the second factor.
+pub struct Challenge {
+    pub ip: String,
+    pub peer_id: String,
+    pub peer_name: String,
+}
+
+impl Challenge {
+    fn text(&self, code: &str) -> String {
+        format!(
+            "2FA code: {}\n\nA new connection has been established to your device with ID {}. The source IP address is {}.",
+            code,
+            Config::get_id(),
+            self.ip,
+        )
+    }
+}
+
+/// A second factor of the login, besides the password.
+pub trait SecondFactor: Send {
+    fn name(&self) -> &'static str;
+
+    /// Starts the challenge of a new login, e.g. delivers the code to the user.
+    fn challenge(&mut self, _challenge: &Challenge) {}
+
+    fn verify(&mut self, code: &str) -> bool;
+
+    /// Whether this second factor makes the 2FA required.
+    /// Backup codes only recover the other second factors.
+    fn is_primary(&self) -> bool {
+        true
+    }
+}
+
+/// The second factors enabled on this installation.
+pub struct Auth2fa {
+    factors: Vec<Box<dyn SecondFactor>>,
+}
+
+impl Auth2fa {
+    /// Returns `None` if the 2FA is not required.
+    pub fn get() -> Option<Self> {
+        Self::get_by(|k| Config::get_option(k))
+    }
+
+    /// The same as `get`, with the options got by `get_option`, e.g. by ipc in the ui process.
+    pub fn get_by(get_option: impl Fn(&str) -> String) -> Option<Self> {
+        let providers = get_option(OPTION_2FA_PROVIDERS);
+        let providers: Vec<&str> = providers
+            .split(',')
+            .map(|x| x.trim())
+            .filter(|x| !x.is_empty())
+            .collect();
+        let enabled = |name: &str| providers.is_empty() || providers.contains(&name);
+        let mut factors: Vec<Box<dyn SecondFactor>> = Vec::new();
+        if enabled(PROVIDER_TOTP) {
+            if let Some(totp) = get_2fa(Some(get_option("2fa"))) {
+                factors.push(Box::new(TotpFactor { totp }));
+            }
+        }
+        if enabled(PROVIDER_WEBHOOK) {
+            match Webhook::parse(&get_option(OPTION_2FA_WEBHOOK)) {
+                Ok(Some(webhook)) => factors.push(Box::new(OneTimeCodeFactor::new(webhook))),
+                Err(err) => log::error!("Failed to get 2fa webhook: {}", err),
+                _ => {}
+            }
+        }
+        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+        if enabled(PROVIDER_EMAIL) {
+            match Smtp::parse(&get_option(OPTION_2FA_SMTP)) {
+                Ok(Some(smtp)) => factors.push(Box::new(OneTimeCodeFactor::new(smtp))),
+                Err(err) => log::error!("Failed to get 2fa smtp: {}", err),
+                _ => {}
+            }
+        }
+        if !factors.iter().any(|f| f.is_primary()) {
+            return None;
+        }
+        if enabled(PROVIDER_BACKUP_CODES)
+            && BackupCodes::parse(&get_option(OPTION_2FA_BACKUP_CODES)).is_some()
+        {
+            factors.push(Box::new(BackupCodesFactor));
+        }
+        Some(Self { factors })
+    }
+
+    pub fn challenge(&mut self, challenge: &Challenge) {
+        for f in self.factors.iter_mut() {
+            f.challenge(challenge);
+        }
+    }
+
+    /// Returns the name of the second factor which accepts the code.
+    pub fn verify(&mut self, code: &str) -> Option<&'static str> {
+        let code = code.trim();
+        if code.is_empty() {
+            return None;
+        }
+        self.factors
+            .iter_mut()
+            .find_map(|f| if f.verify(code) { Some(f.name()) } else { None })
+    }
+}
+
+struct TotpFactor {
+    totp: TOTP,
+}
+
+impl SecondFactor for TotpFactor {
+    fn name(&self) -> &'static str {
+        PROVIDER_TOTP
+    }
+
+    fn challenge(&mut self, challenge: &Challenge) {
+        let bot = match TelegramBot::get() {
+            Ok(Some(bot)) => bot,
+            Err(err) => {
+                log::error!("Failed to get telegram bot: {}", err);
+                return;
+            }
+            _ => return,
+        };
+        if let Ok(code) = self.totp.generate_current() {
+            let text = challenge.text(&code);
+            tokio::spawn(async move {
+                if let Err(err) = send_2fa_code_to_telegram(&text, bot).await {
+                    log::error!("Failed to send 2fa code to telegram bot: {}", err);
+                }
+            });
+        }
+    }
+
+    fn verify(&mut self, code: &str) -> bool {
+        self.totp.check_current(code).unwrap_or(false)
+    }
+}
+
+/// Delivers the one time code of a login to the user.
+trait CodeDelivery: Send {
+    fn name(&self) -> &'static str;
+
+    fn deliver(&self, challenge: &Challenge, code: String);
+}
+
+// A random code is generated for each login, and delivered by `D`.
+struct OneTimeCodeFactor<D: CodeDelivery> {
+    delivery: D,
+    // (code, created_at)
+    code: Option<(String, i64)>,
+}
+
+impl<D: CodeDelivery> OneTimeCodeFactor<D> {
+    fn new(delivery: D) -> Self {
+        Self {
+            delivery,
+            code: None,
+        }
+    }
+}
+
+impl<D: CodeDelivery> SecondFactor for OneTimeCodeFactor<D> {
+    fn name(&self) -> &'static str {
+        self.delivery.name()
+    }
+
+    fn challenge(&mut self, challenge: &Challenge) {
+        let mut rng = rand::thread_rng();
+        let code: String = (0..ONE_TIME_CODE_DIGITS)
+            .map(|_| char::from(b'0' + rng.gen_range(0..10)))
+            .collect();
+        self.code = Some((code.clone(), get_time()));
+        self.delivery.deliver(challenge, code);
+    }
+
+    fn verify(&mut self, code: &str) -> bool {
+        let res = match self.code.as_ref() {
+            Some((c, created_at)) => c == code && get_time() - created_at < ONE_TIME_CODE_VALID_MS,
+            None => false,
+        };
+        if res {
+            self.code = None;
+        }
+        res
+    }
+}
+
+/// Posts the code to a http endpoint, e.g. Slack/Teams incoming webhooks or a SMS gateway.
+///
+/// The body is `{"text", "code", "id", "ip", "peer_id", "peer_name"}`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Webhook {
+    pub url: String,
+    // e.g. "Authorization: Bearer xxx", it is stored encrypted.
+    #[serde(skip)]
+    pub header_str: String,
+    #[serde(default)]
+    pub header: Vec<u8>,
+}
+
+impl Webhook {
+    pub fn save(&self) -> ResultType<()> {
+        let webhook = Webhook {
+            header: encrypt_secret(&self.header_str),
+            ..self.clone()
+        };
+        set_option(OPTION_2FA_WEBHOOK, &serde_json::to_string(&webhook)?);
+        Ok(())
+    }
+
+    fn parse(data: &str) -> ResultType<Option<Webhook>> {
+        if data.is_empty() {
+            return Ok(None);
+        }
+        let mut webhook = serde_json::from_str::<Webhook>(data)?;
+        if webhook.url.is_empty() {
+            return Ok(None);
+        }
+        webhook.header_str = decrypt_secret(&webhook.header)?;
+        Ok(Some(webhook))
+    }
+}
+
+impl CodeDelivery for Webhook {
+    fn name(&self) -> &'static str {
+        PROVIDER_WEBHOOK
+    }
+
+    fn deliver(&self, challenge: &Challenge, code: String) {
+        let body = serde_json::json!({
+            "text": challenge.text(&code),
+            "code": code,
+            "id": Config::get_id(),
+            "ip": challenge.ip,
+            "peer_id": challenge.peer_id,
+            "peer_name": challenge.peer_name,
+        });
+        let url = self.url.clone();
+        let header = self.header_str.clone();
+        tokio::spawn(async move {
+            if let Err(err) = crate::post_request(url, body.to_string(), &header).await {
+                log::error!("Failed to send 2fa code to webhook: {}", err);
+            }
+        });
+    }
+}
+
+/// Sends the code by email.
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Smtp {
+    pub server: String,
+    #[serde(default)]
+    pub port: u16,
+    // "tls", "starttls" or "none"
+    #[serde(default)]
+    pub security: String,
+    #[serde(default)]
+    pub username: String,
+    #[serde(skip)]
+    pub password_str: String,
+    #[serde(default)]
+    pub password: Vec<u8>,
+    pub from: String,
+    // Comma separated recipients.
+    pub to: String,
+}
+
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+impl Smtp {
+    pub fn save(&self) -> ResultType<()> {
+        let smtp = Smtp {
+            password: encrypt_secret(&self.password_str),
+            ..self.clone()
+        };
+        set_option(OPTION_2FA_SMTP, &serde_json::to_string(&smtp)?);
+        Ok(())
+    }
+
+    fn parse(data: &str) -> ResultType<Option<Smtp>> {
+        if data.is_empty() {
+            return Ok(None);
+        }
+        let mut smtp = serde_json::from_str::<Smtp>(data)?;
+        if smtp.server.is_empty() || smtp.to.is_empty() {
+            return Ok(None);
+        }
+        smtp.password_str = decrypt_secret(&smtp.password)?;
+        Ok(Some(smtp))
+    }
+
+    pub fn send(&self, subject: &str, text: String) -> ResultType<()> {
+        use lettre::{
+            message::Mailbox, transport::smtp::authentication::Credentials, SmtpTransport,
+            Transport,
+        };
+        let mut builder = lettre::Message::builder()
+            .from(self.from.parse::<Mailbox>()?)
+            .subject(subject);
+        for to in self
+            .to
+            .split(',')
+            .map(|x| x.trim())
+            .filter(|x| !x.is_empty())
+        {
+            builder = builder.to(to.parse::<Mailbox>()?);
+        }
+        let email = builder.body(text)?;
+        let mut transport = match self.security.as_str() {
+            "none" => SmtpTransport::builder_dangerous(&self.server),
+            "starttls" => SmtpTransport::starttls_relay(&self.server)?,
+            _ => SmtpTransport::relay(&self.server)?,
+        };
+        if self.port > 0 {
+            transport = transport.port(self.port);
+        }
+        if !self.username.is_empty() {
+            transport = transport.credentials(Credentials::new(
+                self.username.clone(),
+                self.password_str.clone(),
+            ));
+        }
+        transport
+            .timeout(Some(std::time::Duration::from_secs(12)))
+            .build()
+            .send(&email)?;
+        Ok(())
+    }
+}
+
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+impl CodeDelivery for Smtp {
+    fn name(&self) -> &'static str {
+        PROVIDER_EMAIL
+    }
+
+    fn deliver(&self, challenge: &Challenge, code: String) {
+        let smtp = self.clone();
+        let text = challenge.text(&code);
+        std::thread::spawn(move || {
+            if let Err(err) = smtp.send(&format!("{} {}", ISSUER, "2FA code"), text) {
+                log::error!("Failed to send 2fa code by email: {}", err);
+            }
+        });
+    }
+}
+
+/// One time recovery codes, only the salted hashes are stored.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct BackupCodes {
+    salt: String,
+    hashes: Vec<String>,
+}
+
+impl BackupCodes {
+    fn get() -> Option<BackupCodes> {
+        Self::parse(&Config::get_option(OPTION_2FA_BACKUP_CODES))
+    }
+
+    fn parse(data: &str) -> Option<BackupCodes> {
+        serde_json::from_str::<BackupCodes>(data)
+            .ok()
+            .filter(|x| !x.hashes.is_empty())
+    }
+
+    fn hash(&self, code: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(&self.salt);
+        hasher.update(normalize_backup_code(code));
+        hex::encode(hasher.finalize())
+    }
+}
+
+// The codes are displayed in groups, e.g. "abcde-fghij".
+fn normalize_backup_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+struct BackupCodesFactor;
+
+impl SecondFactor for BackupCodesFactor {
+    fn name(&self) -> &'static str {
+        PROVIDER_BACKUP_CODES
+    }
+
+    fn verify(&mut self, code: &str) -> bool {
+        let Some(mut codes) = BackupCodes::get() else {
+            return false;
+        };
+        let hash = codes.hash(code);
+        let Some(pos) = codes.hashes.iter().position(|x| *x == hash) else {
+            return false;
+        };
+        codes.hashes.remove(pos);
+        log::info!("2FA backup code used, {} left", codes.hashes.len());
+        // We are in the server process, no need to go through ipc.
+        Config::set_option(
+            OPTION_2FA_BACKUP_CODES.to_owned(),
+            serde_json::to_string(&codes).unwrap_or_default(),
+        );
+        true
+    }
+
+    fn is_primary(&self) -> bool {
+        false
+    }
+}
+
+/// Generates new backup codes, the old ones are invalidated.
+///
+/// The codes are returned to be shown to the user once, only their hashes are stored.
+pub fn generate_backup_codes() -> ResultType<Vec<String>> {
+    const CHARSET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+    let mut rng = rand::thread_rng();
+    let mut salt = [0u8; 16];
+    rng.fill(&mut salt);
+    let mut backup_codes = BackupCodes {
+        salt: hex::encode(salt),
+        hashes: Vec::new(),
+    };
+    let mut codes = Vec::new();
+    for _ in 0..BACKUP_CODES_COUNT {
+        let code: String = (0..BACKUP_CODE_LEN)
+            .map(|_| CHARSET[rng.gen_range(0..CHARSET.len())] as char)
+            .collect();
+        let code = format!(
+            "{}-{}",
+            &code[..BACKUP_CODE_LEN / 2],
+            &code[BACKUP_CODE_LEN / 2..]
+        );
+        backup_codes.hashes.push(backup_codes.hash(&code));
+        codes.push(code);
+    }
+    set_option(
+        OPTION_2FA_BACKUP_CODES,
+        &serde_json::to_string(&backup_codes)?,
+    );
+    Ok(codes)
+}
+
+pub fn get_backup_codes_left(get_option: impl Fn(&str) -> String) -> usize {
+    BackupCodes::parse(&get_option(OPTION_2FA_BACKUP_CODES))
+        .map(|x| x.hashes.len())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backup_code_hash() {
+        let codes = BackupCodes {
+            salt: "salt".to_owned(),
+            hashes: vec![],
+        };
+        assert_eq!(codes.hash("abcde-fghij"), codes.hash(" ABCDEFGHIJ"));
+        assert_ne!(codes.hash("abcde-fghij"), codes.hash("abcde-fghik"));
+    }
+
+    #[test]
+    fn test_one_time_code() {
+        struct Nop;
+        impl CodeDelivery for Nop {
+            fn name(&self) -> &'static str {
+                "nop"
+            }
+            fn deliver(&self, _challenge: &Challenge, _code: String) {}
+        }
+        let mut f = OneTimeCodeFactor::new(Nop);
+        assert!(!f.verify("000000"));
+        f.challenge(&Challenge {
+            ip: "".to_owned(),
+            peer_id: "".to_owned(),
+            peer_name: "".to_owned(),
+        });
+        let code = f.code.as_ref().unwrap().0.clone();
+        assert_eq!(code.len(), ONE_TIME_CODE_DIGITS);
+        assert!(f.verify(&code));
+        // The code can be used only once.
+        assert!(!f.verify(&code));
+    }
+
+    #[test]
+    fn test_webhook_only() {
+        let get_option = |k: &str| match k {
+            OPTION_2FA_WEBHOOK => r#"{"url":"https://example.com/hook"}"#.to_owned(),
+            _ => "".to_owned(),
+        };
+        let auth = Auth2fa::get_by(get_option).unwrap();
+        assert_eq!(auth.factors.len(), 1);
+        assert_eq!(auth.factors[0].name(), PROVIDER_WEBHOOK);
+        assert!(Auth2fa::get_by(|_| "".to_owned()).is_none());
+    }
+}
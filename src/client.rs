@@ -10,7 +10,6 @@ use cpal::{
     Device, Host, StreamConfig,
 };
 use crossbeam_queue::ArrayQueue;
-use magnum_opus::{Channels::*, Decoder as AudioDecoder};
 #[cfg(not(target_os = "linux"))]
 use ringbuf::{ring_buffer::RbBase, Rb};
 use serde::{Deserialize, Serialize};
@@ -29,9 +28,12 @@ use uuid::Uuid;
 
 use crate::{
     check_port,
+    client::transport_qos::TransportPreference,
     common::input::{MOUSE_BUTTON_LEFT, MOUSE_BUTTON_RIGHT, MOUSE_TYPE_DOWN, MOUSE_TYPE_UP},
-    create_symmetric_key_msg, decode_id_pk, get_rs_pk, is_keyboard_mode_supported,
-    kcp_stream::KcpStream,
+    create_symmetric_key_msg, decode_id_pk, get_rs_pk,
+    hifi_audio::AudioDecoder,
+    is_keyboard_mode_supported,
+    kcp_stream::{KcpProfile, KcpStream},
     secure_tcp,
     ui_interface::{get_builtin_option, use_texture_render},
     ui_session_interface::{InvokeUiSession, Session},
@@ -89,10 +91,15 @@ use crate::ui_session_interface::SessionPermissionConfig;
 
 pub use super::lang::*;
 
+pub mod diagnose;
 pub mod file_trait;
 pub mod helper;
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub mod input_macro;
 pub mod io_loop;
 pub mod screenshot;
+pub mod transfer_queue;
+pub mod transport_qos;
 
 pub const MILLI1: Duration = Duration::from_millis(1);
 pub const SEC30: Duration = Duration::from_secs(30);
@@ -104,6 +111,7 @@ pub const LOGIN_MSG_DESKTOP_NOT_INITED: &str = "Desktop env is not inited";
 pub const LOGIN_MSG_DESKTOP_SESSION_NOT_READY: &str = "Desktop session not ready";
 pub const LOGIN_MSG_DESKTOP_XSESSION_FAILED: &str = "Desktop xsession failed";
 pub const LOGIN_MSG_DESKTOP_SESSION_ANOTHER_USER: &str = "Desktop session another user login";
+pub const LOGIN_MSG_DESKTOP_SESSION_LIMIT: &str = "Desktop session limit reached";
 pub const LOGIN_MSG_DESKTOP_XORG_NOT_FOUND: &str = "Desktop xorg not found";
 // ls /usr/share/xsessions/
 pub const LOGIN_MSG_DESKTOP_NO_DESKTOP: &str = "Desktop none";
@@ -254,8 +262,12 @@ impl Client {
         if hbb_common::is_ip_str(peer) {
             return Ok((
                 (
-                    connect_tcp_local(check_port(peer, RELAY_PORT + 1), None, CONNECT_TIMEOUT)
-                        .await?,
+                    crate::direct_tls::connect(
+                        peer,
+                        &check_port(peer, RELAY_PORT + 1),
+                        CONNECT_TIMEOUT,
+                    )
+                    .await?,
                     true,
                     None,
                     None,
@@ -269,7 +281,7 @@ impl Client {
         if hbb_common::is_domain_port_str(peer) {
             return Ok((
                 (
-                    connect_tcp_local(peer, None, CONNECT_TIMEOUT).await?,
+                    crate::direct_tls::connect(peer, peer, CONNECT_TIMEOUT).await?,
                     true,
                     None,
                     None,
@@ -281,6 +293,7 @@ impl Client {
         }
 
         let other_server = interface.get_lch().read().unwrap().other_server.clone();
+        let direct_profile = other_server.is_none() && !interface.is_force_relay();
         let (peer, other_server, key, token) = if let Some((a, b, c)) = other_server.as_ref() {
             (a.as_ref(), b.as_ref(), c.as_ref(), "")
         } else {
@@ -307,11 +320,15 @@ impl Client {
             crate::test_ipv6().await;
         }
 
+        let transport_preference = interface.get_lch().read().unwrap().transport_preference;
         let (stop_udp_tx, stop_udp_rx) = oneshot::channel::<()>();
         let udp =
         // no need to care about multiple rendezvous servers case, since it is acutally not used any more.
         // Shared state for UDP NAT test result
-        if crate::get_udp_punch_enabled() && !interface.is_force_relay() {
+        if crate::get_udp_punch_enabled()
+            && !interface.is_force_relay()
+            && transport_preference != TransportPreference::Tcp
+        {
             if let Ok((socket, addr)) = new_direct_udp_for(&rendezvous_server).await {
                 let udp_port = Arc::new(Mutex::new(0));
                 let up_cloned = udp_port.clone();
@@ -339,28 +356,41 @@ impl Client {
             servers.clone(),
             contained,
         );
-        if udp.0.is_none() {
-            return fut.await;
-        }
-        let mut connect_futures = Vec::new();
-        connect_futures.push(fut.boxed());
-        let fut = Self::_start_inner(
-            peer.to_owned(),
-            key.to_owned(),
-            token.to_owned(),
-            conn_type,
-            interface,
-            (None, None),
-            None,
-            rendezvous_server,
-            servers,
-            contained,
-        );
-        connect_futures.push(fut.boxed());
-        match select_ok(connect_futures).await {
-            Ok(conn) => Ok((conn.0 .0, conn.0 .1, conn.0 .2)),
-            Err(e) => Err(e),
+        let res = if udp.0.is_none() || transport_preference == TransportPreference::Kcp {
+            fut.await
+        } else {
+            let mut connect_futures = Vec::new();
+            connect_futures.push(fut.boxed());
+            let fut = Self::_start_inner(
+                peer.to_owned(),
+                key.to_owned(),
+                token.to_owned(),
+                conn_type,
+                interface,
+                (None, None),
+                None,
+                rendezvous_server,
+                servers,
+                contained,
+            );
+            connect_futures.push(fut.boxed());
+            match select_ok(connect_futures).await {
+                Ok(conn) => Ok((conn.0 .0, conn.0 .1, conn.0 .2)),
+                Err(e) => Err(e),
+            }
+        };
+        // The peer found by the subnet scan is connected by its direct address only if the
+        // rendezvous server fails, the address may be stale.
+        if res.is_err() && direct_profile {
+            if let Some((conn, pk)) = crate::lan::connect_direct_profile(peer).await {
+                return Ok((
+                    (conn, true, Some(pk), None, "TCP"),
+                    (0, "".to_owned()),
+                    false,
+                ));
+            }
         }
+        res
     }
 
     async fn _start_inner(
@@ -557,7 +587,14 @@ impl Client {
                         connect_futures.push(
                             async move {
                                 let conn = fut.await?;
-                                Ok((conn, None, if use_ws() { "WebSocket" } else { "Relay" }))
+                                let typ = if use_ws() {
+                                    "WebSocket"
+                                } else if crate::quic_stream::is_quic(&conn.local_addr()) {
+                                    "QUIC Relay"
+                                } else {
+                                    "Relay"
+                                };
+                                Ok((conn, None, typ))
                             }
                             .boxed(),
                         );
@@ -573,7 +610,7 @@ impl Client {
                         let pk =
                             Self::secure_connection(&peer, signed_id_pk, &key, &mut conn).await?;
                         return Ok((
-                            (conn, typ == "IPv6", pk, kcp, typ),
+                            (conn, typ.ends_with("IPv6"), pk, kcp, typ),
                             (feedback, rendezvous_server),
                             false,
                         ));
@@ -688,14 +725,19 @@ impl Client {
         let start = std::time::Instant::now();
 
         let mut connect_futures = Vec::new();
-        let fut = connect_tcp_local(peer, Some(local_addr), connect_timeout);
-        connect_futures.push(
-            async move {
-                let conn = fut.await?;
-                Ok((conn, None, "TCP"))
-            }
-            .boxed(),
-        );
+        // Only the KCP is tried if switched to it, then the relay if it fails.
+        let prefer_kcp = udp_socket_nat.is_some()
+            && interface.get_lch().read().unwrap().transport_preference == TransportPreference::Kcp;
+        if !prefer_kcp {
+            let fut = connect_tcp_local(peer, Some(local_addr), connect_timeout);
+            connect_futures.push(
+                async move {
+                    let conn = fut.await?;
+                    Ok((conn, None, "TCP"))
+                }
+                .boxed(),
+            );
+        }
         if let Some(udp_socket_nat) = udp_socket_nat {
             connect_futures.push(udp_nat_connect(udp_socket_nat, "UDP", connect_timeout).boxed());
         }
@@ -726,7 +768,10 @@ impl Client {
                     interface.update_direct(Some(false));
                     bail!("Failed to connect via relay server: {}", e);
                 }
-                typ = "Relay";
+                typ = match &conn {
+                    Ok(conn) if crate::quic_stream::is_quic(&conn.local_addr()) => "QUIC Relay",
+                    _ => "Relay",
+                };
                 direct = false;
             } else {
                 bail!("Failed to make direct connection to remote desktop");
@@ -786,12 +831,40 @@ impl Client {
                 return Ok(option_pk);
             }
         };
+        Self::exchange_key(peer_id, &sign_pk, conn).await?;
+        Ok(option_pk)
+    }
+
+    /// Establish secure connection with the peer found by the subnet scan, without the rendezvous server.
+    ///
+    /// `pk` is the public key of the peer verified by the rendezvous server in a previous connection.
+    pub(crate) async fn secure_direct_connection(
+        peer_id: &str,
+        pk: &[u8],
+        conn: &mut Stream,
+    ) -> ResultType<()> {
+        let Ok(pk) = pk.try_into() else {
+            bail!("Invalid public key of {}", peer_id);
+        };
+        // No fallback to non-secure connection, the peer is not authenticated otherwise.
+        if !Self::exchange_key(peer_id, &sign::PublicKey(pk), conn).await? {
+            bail!("Handshake failed: {} is not authenticated", peer_id);
+        }
+        Ok(())
+    }
+
+    // Returns `true` if the symmetric key is set.
+    async fn exchange_key(
+        peer_id: &str,
+        sign_pk: &sign::PublicKey,
+        conn: &mut Stream,
+    ) -> ResultType<bool> {
         match timeout(READ_TIMEOUT, conn.next()).await? {
             Some(res) => {
                 let bytes = res?;
                 if let Ok(msg_in) = Message::parse_from_bytes(&bytes) {
                     if let Some(message::Union::SignedId(si)) = msg_in.union {
-                        if let Ok((id, their_pk_b)) = decode_id_pk(&si.id, &sign_pk) {
+                        if let Ok((id, their_pk_b)) = decode_id_pk(&si.id, sign_pk) {
                             if id == peer_id {
                                 let (asymmetric_value, symmetric_value, key) =
                                     create_symmetric_key_msg(their_pk_b);
@@ -802,7 +875,9 @@ impl Client {
                                     ..Default::default()
                                 });
                                 timeout(CONNECT_TIMEOUT, conn.send(&msg_out)).await??;
+                                crate::quic_stream::set_key(&conn.local_addr(), &key);
                                 conn.set_key(key);
+                                return Ok(true);
                             } else {
                                 log::error!("Handshake failed: sign failure");
                                 conn.send(&Message::new()).await?;
@@ -827,7 +902,7 @@ impl Client {
                 bail!("Reset by the peer");
             }
         }
-        Ok(option_pk)
+        Ok(false)
     }
 
     /// Request a relay connection to the server.
@@ -903,12 +978,13 @@ impl Client {
         conn_type: ConnType,
         ipv4: bool,
     ) -> ResultType<Stream> {
-        let mut conn = connect_tcp(
-            ipv4_to_ipv6(check_port(relay_server, RELAY_PORT), ipv4),
-            CONNECT_TIMEOUT,
-        )
-        .await
-        .with_context(|| "Failed to connect to relay server")?;
+        let relay_server = ipv4_to_ipv6(check_port(relay_server, RELAY_PORT), ipv4);
+        let mut conn = match crate::quic_stream::connect_relay(&relay_server, false).await {
+            Some(conn) => conn,
+            None => connect_tcp(relay_server, CONNECT_TIMEOUT)
+                .await
+                .with_context(|| "Failed to connect to relay server")?,
+        };
         let mut msg_out = RendezvousMessage::new();
         msg_out.set_request_relay(RequestRelay {
             licence_key: key.to_owned(),
@@ -1111,7 +1187,10 @@ impl ClientClipboardHandler {
                     if crate::clipboard::is_file_url_set_by_cloudydesk(&urls) {
                         return;
                     }
-                    if self.is_file_required() {
+                    crate::clipboard_history::record_files("", urls.clone());
+                    if self.is_file_required()
+                        && crate::clipboard_dlp::allow_files(ClipboardSide::Client, true)
+                    {
                         match clipboard::platform::unix::serv_files::sync_files(&urls) {
                             Ok(()) => {
                                 let msg = crate::clipboard_file::clip_2_msg(
@@ -1129,6 +1208,9 @@ impl ClientClipboardHandler {
             }
 
             if let Some(msg) = check_clipboard(&mut self.ctx, ClipboardSide::Client, false) {
+                if let Some(message::Union::MultiClipboards(multi_clipboards)) = &msg.union {
+                    crate::clipboard_history::record("", &multi_clipboards.clipboards);
+                }
                 if self.is_text_required() {
                     self.send_msg(msg, false);
                 }
@@ -1177,6 +1259,9 @@ pub struct AudioHandler {
     audio_decoder: Option<(AudioDecoder, Vec<f32>)>,
     #[cfg(target_os = "linux")]
     simple: Option<psimple::Simple>,
+    // The pulseaudio sink to play, the default sink if `None`.
+    #[cfg(target_os = "linux")]
+    device: Option<String>,
     #[cfg(not(target_os = "linux"))]
     audio_buffer: AudioBuffer,
     sample_rate: (u32, u32),
@@ -1187,6 +1272,8 @@ pub struct AudioHandler {
     device_channel: u16,
     #[cfg(not(target_os = "linux"))]
     ready: Arc<std::sync::Mutex<bool>>,
+    // The high fidelity audio mode, buffer more to play without gaps.
+    hifi: bool,
 }
 
 #[cfg(not(target_os = "linux"))]
@@ -1327,15 +1414,37 @@ impl AudioHandler {
             bail!("Invalid audio format");
         }
 
+        // The channel order of the server, like wasapi and opus.
+        let mut map = pulse::channelmap::Map::default();
+        let map = if format0.channels > 2 {
+            map.init_extend(spec.channels, pulse::channelmap::MapDef::WAVEEx)
+                .map(|m| *m)
+        } else {
+            None
+        };
+        let attr = if self.hifi {
+            let tlength = spec.usec_to_bytes(pulse::time::MicroSeconds(
+                crate::hifi_audio::HIFI_JITTER_BUFFER_MS as u64 * 1000,
+            ));
+            Some(pulse::def::BufferAttr {
+                maxlength: u32::MAX,
+                tlength: tlength as _,
+                prebuf: u32::MAX,
+                minreq: u32::MAX,
+                fragsize: u32::MAX,
+            })
+        } else {
+            None
+        };
         self.simple = Some(Simple::new(
             None,                   // Use the default server
             &crate::get_app_name(), // Our application’s name
             Direction::Playback,    // We want a playback stream
-            None,                   // Use the default device
+            self.device.as_deref(), // Use the default device if not set
             "playback",             // Description of our stream
             &spec,                  // Our sample format
-            None,                   // Use default channel map
-            None,                   // Use default buffering attributes
+            map.as_ref(),           // Use default channel map if not set
+            attr.as_ref(),          // Use default buffering attributes if not set
         )?);
         self.sample_rate = (format0.sample_rate, format0.sample_rate);
         Ok(())
@@ -1394,7 +1503,7 @@ impl AudioHandler {
 
     /// Handle audio format and create an audio decoder.
     pub fn handle_format(&mut self, f: AudioFormat) {
-        match AudioDecoder::new(f.sample_rate, if f.channels > 1 { Stereo } else { Mono }) {
+        match AudioDecoder::new(f.sample_rate, f.channels as _) {
             Ok(d) => {
                 let buffer = vec![0.; f.sample_rate as usize * f.channels as usize];
                 self.audio_decoder = Some((d, buffer));
@@ -1445,7 +1554,11 @@ impl AudioHandler {
                             self.device_channel,
                         );
                     }
-                    self.audio_buffer.append_pcm(&buffer);
+                    if self.hifi {
+                        self.audio_buffer.append_pcm2(&buffer);
+                    } else {
+                        self.audio_buffer.append_pcm(&buffer);
+                    }
                 }
                 #[cfg(target_os = "linux")]
                 {
@@ -1473,6 +1586,12 @@ impl AudioHandler {
             .resize(config.sample_rate.0 as _, config.channels as _);
         let audio_buffer = self.audio_buffer.0.clone();
         let ready = self.ready.clone();
+        let hifi = self.hifi;
+        let prefill = config.sample_rate.0 as usize
+            * config.channels as usize
+            * crate::hifi_audio::HIFI_JITTER_BUFFER_MS
+            / 1000;
+        let mut prefilling = true;
         let timeout = None;
         let stream = device.build_output_stream(
             config,
@@ -1484,9 +1603,21 @@ impl AudioHandler {
                 let mut n = data.len();
                 let mut lock = audio_buffer.lock().unwrap();
                 let mut having = lock.occupied_len();
+                // Wait for the jitter buffer to fill after an underrun, instead of playing the gaps.
+                if hifi {
+                    if prefilling && having >= prefill {
+                        prefilling = false;
+                    }
+                    if !prefilling && having < n {
+                        prefilling = true;
+                    }
+                    if prefilling {
+                        n = 0;
+                    }
+                }
                 // android two timestamps, one from zero, another not
                 #[cfg(not(target_os = "android"))]
-                if having < n {
+                if !hifi && having < n {
                     let tms = info.timestamp();
                     let how_long = tms
                         .playback
@@ -1740,6 +1871,8 @@ pub struct LoginConfigHandler {
     pub restarting_remote_device: bool,
     pub force_relay: bool,
     pub direct: Option<bool>,
+    // Switched by the transport QoS of the session, kept across reconnections.
+    pub transport_preference: TransportPreference,
     pub received: bool,
     switch_uuid: Option<String>,
     pub save_ab_password_to_recent: bool, // true: connected with ab password
@@ -2753,6 +2886,7 @@ pub enum MediaData {
     VideoFrame(Box<VideoFrame>),
     AudioFrame(Box<AudioFrame>),
     AudioFormat(AudioFormat),
+    AudioHifi(bool),
     Reset,
     RecordScreen(bool),
 }
@@ -2919,9 +3053,20 @@ pub fn start_video_thread<F, T>(
 /// Start an audio thread
 /// Return a audio [`MediaSender`]
 pub fn start_audio_thread() -> MediaSender {
+    start_audio_thread_with_device(None)
+}
+
+/// Start the audio thread playing to the given pulseaudio sink, used for the virtual microphone.
+pub fn start_audio_thread_with_device(
+    #[allow(unused_variables)] device: Option<String>,
+) -> MediaSender {
     let (audio_sender, audio_receiver) = mpsc::channel::<MediaData>();
     std::thread::spawn(move || {
         let mut audio_handler = AudioHandler::default();
+        #[cfg(target_os = "linux")]
+        {
+            audio_handler.device = device;
+        }
         loop {
             if let Ok(data) = audio_receiver.recv() {
                 match data {
@@ -2932,6 +3077,10 @@ pub fn start_audio_thread() -> MediaSender {
                         log::debug!("recved audio format, sample rate={}", f.sample_rate);
                         audio_handler.handle_format(f);
                     }
+                    // Applied to the next audio format, the server restarts the audio on change.
+                    MediaData::AudioHifi(hifi) => {
+                        audio_handler.hifi = hifi;
+                    }
                     _ => {}
                 }
             } else {
@@ -3263,6 +3412,12 @@ lazy_static::lazy_static! {
             text: "another_user_login_text_tip",
             link: "",
             try_again: false,
+        }), (LOGIN_MSG_DESKTOP_SESSION_LIMIT, LoginErrorMsgBox{
+            msgtype: "info-nocancel",
+            title: "session_limit_title_tip",
+            text: "session_limit_text_tip",
+            link: "",
+            try_again: false,
         }), (LOGIN_MSG_DESKTOP_XORG_NOT_FOUND, LoginErrorMsgBox{
             msgtype: "info-nocancel",
             title: "xorg_not_found_title_tip",
@@ -3678,6 +3833,7 @@ pub enum Data {
     ElevateWithLogon(String, String),
     NewVoiceCall,
     CloseVoiceCall,
+    MicPassthrough(bool),
     ResetDecoder(Option<usize>),
     RenameFile((i32, String, String, bool)),
     TakeScreenshot((i32, String)),
@@ -4152,11 +4308,31 @@ async fn udp_nat_connect(
             log::debug!("{err}");
             anyhow!(err)
         })?;
-    let res = KcpStream::connect(socket, Duration::from_millis(ms_timeout))
-        .await
-        .map_err(|err| {
-            log::debug!("Failed to connect KCP stream: {}", err);
-            anyhow!(err)
-        })?;
+    if crate::quic_stream::is_allowed(false) {
+        match crate::quic_stream::connect(socket.clone(), ms_timeout).await {
+            Ok(stream) => {
+                return Ok((
+                    stream,
+                    None,
+                    if typ == "IPv6" { "QUIC IPv6" } else { "QUIC" },
+                ))
+            }
+            Err(err) => {
+                log::info!("Failed to connect QUIC, fall back to KCP: {}", err);
+                // The socket is disconnected for QUIC.
+                socket.connect(socket.peer_addr()?).await?;
+            }
+        }
+    }
+    let res = KcpStream::connect(
+        socket,
+        Duration::from_millis(ms_timeout),
+        KcpProfile::get_local(),
+    )
+    .await
+    .map_err(|err| {
+        log::debug!("Failed to connect KCP stream: {}", err);
+        anyhow!(err)
+    })?;
     Ok((res.1, Some(res.0), typ))
 }
@@ -0,0 +1,483 @@
+// Connection path diagnostics, `--diagnose <peer-id> [--json]`.
+//
+// Every way of connecting to the peer is tried separately instead of racing them like
+// `Client::start`, and the result, the time used and the failure reason of each step are
+// reported, as text or as json.
+
+use super::{test_udp_uat, Client};
+use crate::kcp_stream::{KcpProfile, KcpStream};
+use hbb_common::{
+    bail,
+    config::{Config, LocalConfig, CONNECT_TIMEOUT, READ_TIMEOUT, RELAY_PORT},
+    log,
+    rendezvous_proto::*,
+    socket_client::{connect_tcp, connect_tcp_local, new_direct_udp_for},
+    tokio::{self, net::UdpSocket, sync::oneshot},
+    AddrMangle, ResultType, Stream,
+};
+use serde_derive::Serialize;
+use std::{
+    future::Future,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+const PUNCH_TIMEOUT: u64 = 3_000;
+const UDP_NAT_TIMEOUT: u64 = 3_000;
+
+#[derive(Debug, Serialize)]
+struct Step {
+    name: &'static str,
+    /// `ok`, `failed` or `skipped`
+    status: &'static str,
+    elapsed_ms: u64,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    detail: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    reason: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct Report {
+    peer: String,
+    version: String,
+    rendezvous_server: String,
+    steps: Vec<Step>,
+}
+
+enum Punched {
+    Hole(PunchHoleResponse),
+    Relay(RelayResponse),
+}
+
+async fn timed<T>(fut: impl Future<Output = ResultType<T>>) -> (ResultType<T>, u64) {
+    let start = Instant::now();
+    let res = fut.await;
+    (res, start.elapsed().as_millis() as _)
+}
+
+impl Report {
+    fn record<T>(
+        &mut self,
+        name: &'static str,
+        (res, elapsed_ms): (ResultType<(T, String)>, u64),
+    ) -> Option<T> {
+        let (status, detail, reason, value) = match res {
+            Ok((value, detail)) => ("ok", detail, "".to_owned(), Some(value)),
+            Err(err) => ("failed", "".to_owned(), err.to_string(), None),
+        };
+        log::info!(
+            "diagnose {}: {} in {}ms {}{}",
+            name,
+            status,
+            elapsed_ms,
+            detail,
+            reason
+        );
+        self.steps.push(Step {
+            name,
+            status,
+            elapsed_ms,
+            detail,
+            reason,
+        });
+        value
+    }
+
+    async fn run<T>(
+        &mut self,
+        name: &'static str,
+        fut: impl Future<Output = ResultType<(T, String)>>,
+    ) -> Option<T> {
+        let res = timed(fut).await;
+        self.record(name, res)
+    }
+
+    fn skip(&mut self, name: &'static str, reason: &str) {
+        self.steps.push(Step {
+            name,
+            status: "skipped",
+            elapsed_ms: 0,
+            detail: "".to_owned(),
+            reason: reason.to_owned(),
+        });
+    }
+
+    fn to_text(&self) -> String {
+        let mut text = format!(
+            "Diagnose {} (version {}), rendezvous server: {}\n",
+            self.peer, self.version, self.rendezvous_server
+        );
+        for step in self.steps.iter() {
+            text += &format!(
+                "{:<8} {:<20} {:>6}ms  {}\n",
+                step.status.to_uppercase(),
+                step.name,
+                step.elapsed_ms,
+                if step.reason.is_empty() {
+                    &step.detail
+                } else {
+                    &step.reason
+                }
+            );
+        }
+        text
+    }
+}
+
+pub fn handle_cli(args: &[String]) {
+    let Some(peer) = args.first() else {
+        println!("Usage: --diagnose <peer-id> [--json]");
+        return;
+    };
+    let report = diagnose(peer);
+    if args.iter().any(|x| x == "--json") {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&report).unwrap_or_default()
+        );
+    } else {
+        print!("{}", report.to_text());
+    }
+}
+
+#[tokio::main]
+async fn diagnose(peer: &str) -> Report {
+    let key = crate::get_key(false).await;
+    let token = LocalConfig::get_option("access_token");
+    let conn_type = ConnType::DEFAULT_CONN;
+    let (rendezvous_server, _, _) = crate::get_rendezvous_server(1_000).await;
+    let mut report = Report {
+        peer: peer.to_owned(),
+        version: crate::VERSION.to_owned(),
+        rendezvous_server: rendezvous_server.clone(),
+        ..Default::default()
+    };
+
+    let socket = report
+        .run("rendezvous", async {
+            let mut socket = connect_tcp(&*rendezvous_server, CONNECT_TIMEOUT).await?;
+            if !key.is_empty() && !token.is_empty() {
+                crate::secure_tcp(&mut socket, &key).await?;
+            }
+            let detail = format!("local address {}", socket.local_addr());
+            Ok((socket, detail))
+        })
+        .await;
+    let nat_type = report
+        .run("nat_type", test_nat_type(&rendezvous_server))
+        .await
+        .unwrap_or(NatType::UNKNOWN_NAT);
+    let ipv6 = report
+        .run("ipv6", async {
+            if let Some(handle) = crate::test_ipv6().await {
+                handle.await.ok();
+            }
+            let Some((socket, addr)) = crate::get_ipv6_socket().await else {
+                bail!("No public IPv6 address");
+            };
+            let detail = format!("public address {}", AddrMangle::decode(&addr));
+            Ok(((socket, addr), detail))
+        })
+        .await;
+    let udp = report
+        .run("udp_nat", test_udp_nat(&rendezvous_server))
+        .await;
+
+    let mut direct_addrs = crate::lan::get_direct_addrs(peer);
+    let Some(mut socket) = socket else {
+        for name in [
+            "punch_hole_request",
+            "tcp_punch",
+            "udp_punch",
+            "kcp",
+            "ipv6_punch",
+            "relay",
+        ] {
+            report.skip(name, "The rendezvous server is not reachable");
+        }
+        direct_port(&mut report, peer, direct_addrs).await;
+        return report;
+    };
+    let my_addr = socket.local_addr();
+    let mut msg_out = RendezvousMessage::new();
+    msg_out.set_punch_hole_request(PunchHoleRequest {
+        id: peer.to_owned(),
+        token: token.clone(),
+        nat_type: nat_type.into(),
+        licence_key: key.clone(),
+        conn_type: conn_type.into(),
+        version: crate::VERSION.to_owned(),
+        udp_port: udp.as_ref().map(|x| x.1).unwrap_or(0) as _,
+        socket_addr_v6: ipv6.as_ref().map(|x| x.1.clone()).unwrap_or_default(),
+        ..Default::default()
+    });
+    let punched = report
+        .run("punch_hole_request", async {
+            socket.send(&msg_out).await?;
+            match crate::get_next_nonkeyexchange_msg(&mut socket, Some(READ_TIMEOUT))
+                .await
+                .and_then(|x| x.union)
+            {
+                Some(rendezvous_message::Union::PunchHoleResponse(ph)) => {
+                    if ph.socket_addr.is_empty() {
+                        bail!(get_punch_failure(&ph));
+                    }
+                    let detail = format!(
+                        "peer address {}, nat type {:?}, local: {}, udp: {}, relay server: {}",
+                        AddrMangle::decode(&ph.socket_addr),
+                        ph.nat_type(),
+                        ph.is_local(),
+                        ph.is_udp,
+                        ph.relay_server
+                    );
+                    Ok((Punched::Hole(ph), detail))
+                }
+                Some(rendezvous_message::Union::RelayResponse(rr)) => {
+                    let detail = format!("relay requested by the peer via {}", rr.relay_server);
+                    Ok((Punched::Relay(rr), detail))
+                }
+                Some(other) => bail!("Unexpected response: {:?}", other),
+                None => bail!("No response from the rendezvous server"),
+            }
+        })
+        .await;
+    drop(socket);
+
+    match punched {
+        None => {
+            for name in ["tcp_punch", "udp_punch", "kcp", "ipv6_punch", "relay"] {
+                report.skip(name, "The punch hole request failed");
+            }
+        }
+        Some(Punched::Relay(rr)) => {
+            for name in ["tcp_punch", "udp_punch", "kcp", "ipv6_punch"] {
+                report.skip(name, "The peer requested a relay connection");
+            }
+            let signed_id_pk = rr.pk().to_vec();
+            report
+                .run("relay", async {
+                    let mut conn = Client::create_relay(
+                        peer,
+                        rr.uuid.clone(),
+                        rr.relay_server.clone(),
+                        &key,
+                        conn_type,
+                        my_addr.is_ipv4(),
+                    )
+                    .await?;
+                    Client::secure_connection(peer, signed_id_pk, &key, &mut conn).await?;
+                    Ok(((), relay_detail(&rr.relay_server, &conn)))
+                })
+                .await;
+        }
+        Some(Punched::Hole(ph)) => {
+            let peer_addr = AddrMangle::decode(&ph.socket_addr);
+            let signed_id_pk = ph.pk.to_vec();
+            let udp = if ph.is_udp { udp.map(|x| x.0) } else { None };
+            let ipv6_addr = AddrMangle::decode(&ph.socket_addr_v6);
+            let ipv6 = if ipv6_addr.port() > 0 {
+                ipv6.map(|x| x.0)
+            } else {
+                None
+            };
+            let tcp_punch = timed(async {
+                let mut conn = connect_tcp_local(peer_addr, Some(my_addr), PUNCH_TIMEOUT).await?;
+                Client::secure_connection(peer, signed_id_pk.clone(), &key, &mut conn).await?;
+                Ok(((), format!("connected to {}", peer_addr)))
+            });
+            let udp_punch = async {
+                let Some(socket) = udp.clone() else {
+                    return None;
+                };
+                let punch = timed(async {
+                    socket.connect(peer_addr).await?;
+                    crate::punch_udp(socket.clone(), false).await?;
+                    Ok(((), format!("punched to {}", peer_addr)))
+                })
+                .await;
+                if punch.0.is_err() {
+                    return Some((punch, None));
+                }
+                let kcp = timed(async {
+                    let (kcp, mut conn) = connect_kcp(socket).await?;
+                    Client::secure_connection(peer, signed_id_pk.clone(), &key, &mut conn).await?;
+                    let detail = format!(
+                        "connected to {} with the {} profile",
+                        peer_addr,
+                        kcp.profile().name()
+                    );
+                    Ok(((), detail))
+                })
+                .await;
+                Some((punch, Some(kcp)))
+            };
+            let ipv6_punch = async {
+                let socket = ipv6.clone()?;
+                Some(
+                    timed(async {
+                        socket.connect(ipv6_addr).await?;
+                        crate::punch_udp(socket.clone(), false).await?;
+                        let (_kcp, mut conn) = connect_kcp(socket).await?;
+                        Client::secure_connection(peer, signed_id_pk.clone(), &key, &mut conn)
+                            .await?;
+                        Ok(((), format!("connected to {}", ipv6_addr)))
+                    })
+                    .await,
+                )
+            };
+            let (tcp_punch, udp_punch, ipv6_punch) = tokio::join!(tcp_punch, udp_punch, ipv6_punch);
+            report.record("tcp_punch", tcp_punch);
+            match udp_punch {
+                Some((punch, kcp)) => {
+                    report.record("udp_punch", punch);
+                    match kcp {
+                        Some(kcp) => {
+                            report.record("kcp", kcp);
+                        }
+                        None => report.skip("kcp", "The UDP punch failed"),
+                    }
+                }
+                None => {
+                    let reason = if !ph.is_udp {
+                        "The peer does not support UDP punch"
+                    } else {
+                        "The UDP NAT test failed"
+                    };
+                    report.skip("udp_punch", reason);
+                    report.skip("kcp", reason);
+                }
+            }
+            match ipv6_punch {
+                Some(res) => {
+                    report.record("ipv6_punch", res);
+                }
+                None => report.skip("ipv6_punch", "No IPv6 address on both sides"),
+            }
+            if ph.relay_server.is_empty() {
+                report.skip("relay", "No relay server provided by the rendezvous server");
+            } else {
+                report
+                    .run("relay", async {
+                        let mut conn = Client::request_relay(
+                            peer,
+                            ph.relay_server.clone(),
+                            &rendezvous_server,
+                            !signed_id_pk.is_empty(),
+                            &key,
+                            &token,
+                            conn_type,
+                        )
+                        .await?;
+                        Client::secure_connection(peer, signed_id_pk.clone(), &key, &mut conn)
+                            .await?;
+                        Ok(((), relay_detail(&ph.relay_server, &conn)))
+                    })
+                    .await;
+            }
+            direct_addrs.push(SocketAddr::new(peer_addr.ip(), RELAY_PORT as u16 + 1).to_string());
+        }
+    }
+    direct_port(&mut report, peer, direct_addrs).await;
+    report
+}
+
+fn get_punch_failure(ph: &PunchHoleResponse) -> String {
+    if !ph.other_failure.is_empty() {
+        return ph.other_failure.clone();
+    }
+    match ph.failure.enum_value() {
+        Ok(punch_hole_response::Failure::ID_NOT_EXIST) => "ID does not exist",
+        Ok(punch_hole_response::Failure::OFFLINE) => "Remote desktop is offline",
+        Ok(punch_hole_response::Failure::LICENSE_MISMATCH) => "Key mismatch",
+        Ok(punch_hole_response::Failure::LICENSE_OVERUSE) => "Key overuse",
+        _ => "other punch hole failure",
+    }
+    .to_owned()
+}
+
+/// The same as `test_nat_type_` in common.rs, without updating the config.
+async fn test_nat_type(server: &str) -> ResultType<(NatType, String)> {
+    let mut msg_out = RendezvousMessage::new();
+    msg_out.set_test_nat_request(TestNatRequest {
+        serial: Config::get_serial(),
+        ..Default::default()
+    });
+    let mut ports = Vec::new();
+    let mut local_addr = None;
+    for server in [server.to_owned(), crate::increase_port(server, -1)] {
+        let mut socket = connect_tcp_local(&*server, local_addr, CONNECT_TIMEOUT).await?;
+        local_addr.get_or_insert(socket.local_addr());
+        socket.send(&msg_out).await?;
+        match crate::get_next_nonkeyexchange_msg(&mut socket, None)
+            .await
+            .and_then(|x| x.union)
+        {
+            Some(rendezvous_message::Union::TestNatResponse(tnr)) => ports.push(tnr.port),
+            _ => bail!("No nat test response from {}", server),
+        }
+    }
+    let nat_type = if ports[0] == ports[1] {
+        NatType::ASYMMETRIC
+    } else {
+        NatType::SYMMETRIC
+    };
+    let detail = format!("{:?}, mapped ports {} and {}", nat_type, ports[0], ports[1]);
+    Ok((nat_type, detail))
+}
+
+async fn test_udp_nat(server: &str) -> ResultType<((Arc<UdpSocket>, u16), String)> {
+    let (socket, addr) = new_direct_udp_for(server).await?;
+    let port = Arc::new(Mutex::new(0));
+    // the test stops once the sender is dropped
+    let (_tx, rx) = oneshot::channel();
+    hbb_common::timeout(
+        UDP_NAT_TIMEOUT,
+        test_udp_uat(socket.clone(), addr, port.clone(), rx),
+    )
+    .await
+    .ok();
+    let port = *port.lock().unwrap();
+    if port == 0 {
+        bail!("No UDP response from {}", addr);
+    }
+    let detail = format!("mapped port {}", port);
+    Ok(((socket, port), detail))
+}
+
+fn relay_detail(relay_server: &str, conn: &Stream) -> String {
+    if crate::quic_stream::is_quic(&conn.local_addr()) {
+        format!("connected via {} over QUIC", relay_server)
+    } else {
+        format!("connected via {}", relay_server)
+    }
+}
+
+async fn connect_kcp(socket: Arc<UdpSocket>) -> ResultType<(KcpStream, Stream)> {
+    KcpStream::connect(
+        socket,
+        Duration::from_millis(PUNCH_TIMEOUT),
+        KcpProfile::get_local(),
+    )
+    .await
+}
+
+async fn direct_port(report: &mut Report, peer: &str, addrs: Vec<String>) {
+    if addrs.is_empty() {
+        report.skip("direct_port", "No address of the peer known");
+        return;
+    }
+    report
+        .run("direct_port", async {
+            let mut errors = Vec::new();
+            for addr in addrs {
+                match crate::direct_tls::connect(peer, &addr, PUNCH_TIMEOUT).await {
+                    Ok(_) => return Ok(((), format!("connected to {}", addr))),
+                    Err(err) => errors.push(format!("{}: {}", addr, err)),
+                }
+            }
+            bail!(errors.join(", "))
+        })
+        .await;
+}
@@ -0,0 +1,782 @@
+// Input macros, scripts in the DSL of `enigo::dsl`, like `{CTRL+ALT+DEL}{WAIT:1000}user{TAB}`.
+//
+// A macro is recorded from the key and mouse events sent by the session, the short pauses are
+// dropped. It is played as legacy key events and mouse events, the peer does not need to support
+// anything new. The macros are saved per peer by the UI, in the peer option `input-macros`.
+
+use super::{send_mouse, Data, Interface};
+use crate::common::input::*;
+use enigo::{
+    dsl::{self, Token},
+    Key, MouseButton,
+};
+use hbb_common::{log, message_proto::*, ResultType};
+use std::{
+    collections::HashSet,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+// The shorter pauses are not recorded, they are played as `PLAY_INTERVAL`.
+const MIN_RECORDED_WAIT: Duration = Duration::from_millis(300);
+// Between the events, for the peer to keep up, like typing.
+const PLAY_INTERVAL: Duration = Duration::from_millis(20);
+
+const CONTROL_KEYS: &[(ControlKey, Key)] = &[
+    (ControlKey::Alt, Key::Alt),
+    (ControlKey::Shift, Key::Shift),
+    (ControlKey::Control, Key::Control),
+    (ControlKey::Meta, Key::Meta),
+    (ControlKey::RAlt, Key::RightAlt),
+    (ControlKey::RShift, Key::RightShift),
+    (ControlKey::RControl, Key::RightControl),
+    (ControlKey::Return, Key::Return),
+    (ControlKey::Tab, Key::Tab),
+    (ControlKey::Escape, Key::Escape),
+    (ControlKey::Space, Key::Space),
+    (ControlKey::Backspace, Key::Backspace),
+    (ControlKey::Delete, Key::Delete),
+    (ControlKey::Insert, Key::Insert),
+    (ControlKey::Home, Key::Home),
+    (ControlKey::End, Key::End),
+    (ControlKey::PageUp, Key::PageUp),
+    (ControlKey::PageDown, Key::PageDown),
+    (ControlKey::UpArrow, Key::UpArrow),
+    (ControlKey::DownArrow, Key::DownArrow),
+    (ControlKey::LeftArrow, Key::LeftArrow),
+    (ControlKey::RightArrow, Key::RightArrow),
+    (ControlKey::CapsLock, Key::CapsLock),
+    (ControlKey::NumLock, Key::NumLock),
+    (ControlKey::Snapshot, Key::Snapshot),
+    (ControlKey::Pause, Key::Pause),
+    (ControlKey::Apps, Key::Apps),
+    (ControlKey::F1, Key::F1),
+    (ControlKey::F2, Key::F2),
+    (ControlKey::F3, Key::F3),
+    (ControlKey::F4, Key::F4),
+    (ControlKey::F5, Key::F5),
+    (ControlKey::F6, Key::F6),
+    (ControlKey::F7, Key::F7),
+    (ControlKey::F8, Key::F8),
+    (ControlKey::F9, Key::F9),
+    (ControlKey::F10, Key::F10),
+    (ControlKey::F11, Key::F11),
+    (ControlKey::F12, Key::F12),
+];
+
+const MODIFIERS: &[ControlKey] = &[
+    ControlKey::Alt,
+    ControlKey::Shift,
+    ControlKey::Control,
+    ControlKey::Meta,
+    ControlKey::RAlt,
+    ControlKey::RShift,
+    ControlKey::RControl,
+];
+
+const MOUSE_BUTTONS: &[(i32, MouseButton)] = &[
+    (MOUSE_BUTTON_LEFT, MouseButton::Left),
+    (MOUSE_BUTTON_RIGHT, MouseButton::Right),
+    (MOUSE_BUTTON_WHEEL, MouseButton::Middle),
+    (MOUSE_BUTTON_BACK, MouseButton::Back),
+    (MOUSE_BUTTON_FORWARD, MouseButton::Forward),
+];
+
+fn control_key_to_key(ck: ControlKey) -> Option<Key> {
+    CONTROL_KEYS
+        .iter()
+        .find(|(c, _)| *c == ck)
+        .map(|(_, key)| *key)
+}
+
+fn key_to_control_key(key: Key) -> Option<ControlKey> {
+    CONTROL_KEYS
+        .iter()
+        .find(|(_, k)| *k == key)
+        .map(|(ck, _)| *ck)
+}
+
+// The keys of the map mode, by the position on the US layout.
+fn rdev_key_to_key(key: rdev::Key) -> Option<Key> {
+    use rdev::Key as R;
+    let chr = match key {
+        R::Alt => return Some(Key::Alt),
+        R::AltGr => return Some(Key::RightAlt),
+        R::ShiftLeft => return Some(Key::Shift),
+        R::ShiftRight => return Some(Key::RightShift),
+        R::ControlLeft => return Some(Key::Control),
+        R::ControlRight => return Some(Key::RightControl),
+        R::MetaLeft | R::MetaRight => return Some(Key::Meta),
+        R::Return | R::KpReturn => return Some(Key::Return),
+        R::Tab => return Some(Key::Tab),
+        R::Escape => return Some(Key::Escape),
+        R::Space => return Some(Key::Space),
+        R::Backspace => return Some(Key::Backspace),
+        R::Delete => return Some(Key::Delete),
+        R::Insert => return Some(Key::Insert),
+        R::Home => return Some(Key::Home),
+        R::End => return Some(Key::End),
+        R::PageUp => return Some(Key::PageUp),
+        R::PageDown => return Some(Key::PageDown),
+        R::UpArrow => return Some(Key::UpArrow),
+        R::DownArrow => return Some(Key::DownArrow),
+        R::LeftArrow => return Some(Key::LeftArrow),
+        R::RightArrow => return Some(Key::RightArrow),
+        R::CapsLock => return Some(Key::CapsLock),
+        R::NumLock => return Some(Key::NumLock),
+        R::PrintScreen => return Some(Key::Snapshot),
+        R::Pause => return Some(Key::Pause),
+        R::Apps => return Some(Key::Apps),
+        R::F1 => return Some(Key::F1),
+        R::F2 => return Some(Key::F2),
+        R::F3 => return Some(Key::F3),
+        R::F4 => return Some(Key::F4),
+        R::F5 => return Some(Key::F5),
+        R::F6 => return Some(Key::F6),
+        R::F7 => return Some(Key::F7),
+        R::F8 => return Some(Key::F8),
+        R::F9 => return Some(Key::F9),
+        R::F10 => return Some(Key::F10),
+        R::F11 => return Some(Key::F11),
+        R::F12 => return Some(Key::F12),
+        R::Num1 | R::Kp1 => '1',
+        R::Num2 | R::Kp2 => '2',
+        R::Num3 | R::Kp3 => '3',
+        R::Num4 | R::Kp4 => '4',
+        R::Num5 | R::Kp5 => '5',
+        R::Num6 | R::Kp6 => '6',
+        R::Num7 | R::Kp7 => '7',
+        R::Num8 | R::Kp8 => '8',
+        R::Num9 | R::Kp9 => '9',
+        R::Num0 | R::Kp0 => '0',
+        R::KeyA => 'a',
+        R::KeyB => 'b',
+        R::KeyC => 'c',
+        R::KeyD => 'd',
+        R::KeyE => 'e',
+        R::KeyF => 'f',
+        R::KeyG => 'g',
+        R::KeyH => 'h',
+        R::KeyI => 'i',
+        R::KeyJ => 'j',
+        R::KeyK => 'k',
+        R::KeyL => 'l',
+        R::KeyM => 'm',
+        R::KeyN => 'n',
+        R::KeyO => 'o',
+        R::KeyP => 'p',
+        R::KeyQ => 'q',
+        R::KeyR => 'r',
+        R::KeyS => 's',
+        R::KeyT => 't',
+        R::KeyU => 'u',
+        R::KeyV => 'v',
+        R::KeyW => 'w',
+        R::KeyX => 'x',
+        R::KeyY => 'y',
+        R::KeyZ => 'z',
+        R::Comma => ',',
+        R::Dot | R::KpDecimal => '.',
+        R::SemiColon => ';',
+        R::Quote => '\'',
+        R::LeftBracket => '[',
+        R::RightBracket => ']',
+        R::Slash | R::KpDivide => '/',
+        R::BackSlash => '\\',
+        R::Minus | R::KpMinus => '-',
+        R::Equal => '=',
+        R::KpPlus => '+',
+        R::KpMultiply => '*',
+        R::BackQuote => '`',
+        _ => return None,
+    };
+    Some(Key::Layout(chr))
+}
+
+// The key code of the map mode is the code of the peer platform.
+fn map_code_to_key(peer: &str, code: u32) -> Option<Key> {
+    let mut peer = peer.to_lowercase();
+    peer.retain(|c| !c.is_whitespace());
+    let key = match peer.as_str() {
+        "windows" => rdev::win_key_from_scancode(code),
+        "macos" => rdev::macos_key_from_code(code as _),
+        _ => rdev::linux_key_from_code(code),
+    };
+    rdev_key_to_key(key)
+}
+
+#[derive(Default)]
+pub struct InputMacro {
+    recorder: Option<Recorder>,
+    // The flag to stop the playing.
+    playing: Option<Arc<AtomicBool>>,
+}
+
+impl InputMacro {
+    #[inline]
+    pub fn is_recording(&self) -> bool {
+        self.recorder.is_some()
+    }
+
+    #[inline]
+    pub fn is_playing(&self) -> bool {
+        self.playing.is_some()
+    }
+
+    pub fn start_recording(&mut self) {
+        self.recorder = Some(Recorder::new());
+    }
+
+    // Returns the recorded script.
+    pub fn stop_recording(&mut self) -> String {
+        self.recorder.take().map(|r| r.finish()).unwrap_or_default()
+    }
+
+    pub fn record_key(&mut self, peer: &str, evt: &KeyEvent) {
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.record_key(peer, evt);
+        }
+    }
+
+    pub fn record_mouse(&mut self, mask: i32, x: i32, y: i32) {
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.record_mouse(mask, x, y);
+        }
+    }
+
+    // Returns `None` if a macro is playing.
+    pub fn start_playing(&mut self) -> Option<Arc<AtomicBool>> {
+        if self.playing.is_some() {
+            return None;
+        }
+        let stop = Arc::new(AtomicBool::new(false));
+        self.playing = Some(stop.clone());
+        Some(stop)
+    }
+
+    pub fn stop_playing(&mut self) {
+        if let Some(stop) = self.playing.take() {
+            stop.store(true, Ordering::SeqCst);
+        }
+    }
+}
+
+// The escaped `{` and `}`, see `dsl::key_name`. They are not recorded as held keys,
+// the tag of a held `}` before the text `}}` would be read as `{+}}}`.
+#[inline]
+fn is_bracket(name: &str) -> bool {
+    name == "{{" || name == "}}"
+}
+
+struct Recorder {
+    script: String,
+    last: Instant,
+    // The key or button just pressed, written as a click if it is released next.
+    pending: Option<String>,
+    pressed: HashSet<String>,
+    pos: (i32, i32),
+    recorded_pos: Option<(i32, i32)>,
+}
+
+impl Recorder {
+    fn new() -> Self {
+        Self {
+            script: String::new(),
+            last: Instant::now(),
+            pending: None,
+            pressed: HashSet::new(),
+            pos: (0, 0),
+            recorded_pos: None,
+        }
+    }
+
+    fn flush(&mut self) {
+        if let Some(name) = self.pending.take() {
+            if is_bracket(&name) {
+                // Typed at once, its key up is ignored.
+                self.pressed.remove(&name);
+                self.script.push_str(&name);
+            } else {
+                self.script.push_str(&format!("{{+{}}}", name));
+            }
+        }
+    }
+
+    fn wait(&mut self) {
+        let elapsed = self.last.elapsed();
+        self.last = Instant::now();
+        if elapsed >= MIN_RECORDED_WAIT {
+            self.flush();
+            self.script
+                .push_str(&format!("{{WAIT:{}}}", elapsed.as_millis()));
+        }
+    }
+
+    fn down(&mut self, name: String) {
+        // The repeated key down events.
+        if self.pressed.contains(&name) {
+            return;
+        }
+        self.wait();
+        self.flush();
+        self.pressed.insert(name.clone());
+        self.pending = Some(name);
+    }
+
+    fn up(&mut self, name: String) {
+        // The key up of the translate mode after the text.
+        if !self.pressed.remove(&name) {
+            return;
+        }
+        self.wait();
+        if self.pending.as_ref() == Some(&name) {
+            self.pending = None;
+            // A character is written as the text.
+            if name.chars().count() == 1 || is_bracket(&name) {
+                self.script.push_str(&name);
+            } else {
+                self.script.push_str(&format!("{{{}}}", name));
+            }
+        } else {
+            self.flush();
+            self.script.push_str(&format!("{{-{}}}", name));
+        }
+    }
+
+    fn text(&mut self, text: &str) {
+        self.wait();
+        self.flush();
+        self.script
+            .push_str(&text.replace('{', "{{").replace('}', "}}"));
+    }
+
+    fn record_key(&mut self, peer: &str, evt: &KeyEvent) {
+        let key = match &evt.union {
+            Some(key_event::Union::ControlKey(ck))
+                if ck.enum_value() == Ok(ControlKey::CtrlAltDel) =>
+            {
+                self.wait();
+                self.flush();
+                self.script.push_str("{CTRL+ALT+DEL}");
+                return;
+            }
+            Some(key_event::Union::ControlKey(ck)) => {
+                control_key_to_key(ck.enum_value_or_default())
+            }
+            Some(key_event::Union::Chr(chr)) => {
+                if evt.mode.enum_value() == Ok(KeyboardMode::Legacy) {
+                    char::from_u32(*chr).map(Key::Layout)
+                } else {
+                    map_code_to_key(peer, *chr)
+                }
+            }
+            Some(key_event::Union::Seq(seq)) => {
+                self.text(seq);
+                return;
+            }
+            Some(key_event::Union::Unicode(u)) => {
+                if let Some(c) = char::from_u32(*u) {
+                    self.text(&c.to_string());
+                }
+                return;
+            }
+            _ => None,
+        };
+        let Some(name) = key.and_then(dsl::key_name) else {
+            return;
+        };
+        if evt.press {
+            self.down(name.clone());
+            self.up(name);
+        } else if evt.down {
+            self.down(name);
+        } else {
+            self.up(name);
+        }
+    }
+
+    // The moves are written only before the buttons.
+    fn record_mouse(&mut self, mask: i32, x: i32, y: i32) {
+        let evt_type = mask & 0x7;
+        if evt_type == MOUSE_TYPE_MOVE {
+            self.pos = (x, y);
+            return;
+        }
+        if evt_type != MOUSE_TYPE_DOWN && evt_type != MOUSE_TYPE_UP {
+            return;
+        }
+        let buttons = mask >> 3;
+        let Some(name) = MOUSE_BUTTONS
+            .iter()
+            .find(|(b, _)| *b == buttons)
+            .and_then(|(_, button)| dsl::mouse_button_name(*button))
+        else {
+            return;
+        };
+        self.pos = (x, y);
+        if self.recorded_pos != Some(self.pos) {
+            self.wait();
+            self.flush();
+            self.script
+                .push_str(&format!("{{MOVE:{},{}}}", self.pos.0, self.pos.1));
+            self.recorded_pos = Some(self.pos);
+        }
+        if evt_type == MOUSE_TYPE_DOWN {
+            self.down(name.to_owned());
+        } else {
+            self.up(name.to_owned());
+        }
+    }
+
+    fn finish(mut self) -> String {
+        self.flush();
+        // Release what is still pressed, the stop of the recording is not recorded.
+        let mut pressed: Vec<_> = self.pressed.drain().collect();
+        pressed.sort();
+        for name in pressed {
+            self.script.push_str(&format!("{{-{}}}", name));
+        }
+        self.script
+    }
+}
+
+pub fn check(script: &str) -> ResultType<Vec<Token>> {
+    Ok(dsl::tokenize(script)?)
+}
+
+struct Player<'a, T: Interface> {
+    interface: &'a T,
+    // The pressed modifiers, sent with the key events.
+    modifiers: Vec<ControlKey>,
+    keys: Vec<Key>,
+    buttons: Vec<i32>,
+    pos: (i32, i32),
+}
+
+impl<'a, T: Interface> Player<'a, T> {
+    fn send_key(&mut self, key: Key, down: bool, press: bool) {
+        let mut evt = KeyEvent::new();
+        let has = |ck: &[ControlKey]| ck.iter().any(|ck| self.modifiers.contains(ck));
+        // The peer handles it specially, like the secure attention sequence on Windows.
+        let is_ctrl_alt_del = press
+            && key == Key::Delete
+            && has(&[ControlKey::Control, ControlKey::RControl])
+            && has(&[ControlKey::Alt, ControlKey::RAlt]);
+        match key {
+            _ if is_ctrl_alt_del => evt.set_control_key(ControlKey::CtrlAltDel),
+            Key::Layout(c) => evt.set_chr(c as _),
+            _ => match key_to_control_key(key) {
+                Some(ck) => evt.set_control_key(ck),
+                None => return,
+            },
+        }
+        let ck = key_to_control_key(key).filter(|ck| MODIFIERS.contains(ck));
+        // The peer releases the modifiers not in the list, and releases the ones in the list
+        // after the key if they are not pressed before.
+        evt.modifiers = self
+            .modifiers
+            .iter()
+            .filter(|m| Some(**m) != ck)
+            .map(|m| (*m).into())
+            .collect();
+        evt.down = down;
+        evt.press = press;
+        evt.mode = KeyboardMode::Legacy.into();
+        let mut msg = Message::new();
+        msg.set_key_event(evt);
+        self.interface.send(Data::Message(msg));
+        if press {
+            return;
+        }
+        if let Some(ck) = ck {
+            self.modifiers.retain(|m| *m != ck);
+            if down {
+                self.modifiers.push(ck);
+            }
+        }
+        self.keys.retain(|k| *k != key);
+        if down {
+            self.keys.push(key);
+        }
+    }
+
+    fn send_mouse(&mut self, button: Option<MouseButton>, evt_type: i32) {
+        let button = button
+            .and_then(|button| MOUSE_BUTTONS.iter().find(|(_, b)| *b == button))
+            .map(|(b, _)| *b)
+            .unwrap_or(0);
+        let has = |ck: ControlKey| self.modifiers.contains(&ck);
+        let alt = has(ControlKey::Alt) || has(ControlKey::RAlt);
+        let ctrl = has(ControlKey::Control) || has(ControlKey::RControl);
+        let shift = has(ControlKey::Shift) || has(ControlKey::RShift);
+        let command = has(ControlKey::Meta);
+        send_mouse(
+            button << 3 | evt_type,
+            self.pos.0,
+            self.pos.1,
+            alt,
+            ctrl,
+            shift,
+            command,
+            self.interface,
+        );
+        if evt_type == MOUSE_TYPE_DOWN {
+            self.buttons.push(button);
+        } else if evt_type == MOUSE_TYPE_UP {
+            self.buttons.retain(|b| *b != button);
+        }
+    }
+
+    fn play(&mut self, token: Token) {
+        match token {
+            Token::Sequence(text) => {
+                for c in text.chars() {
+                    self.send_key(Key::Layout(c), false, true);
+                }
+            }
+            Token::Unicode(text) => {
+                let mut evt = KeyEvent::new();
+                evt.set_seq(text);
+                let mut msg = Message::new();
+                msg.set_key_event(evt);
+                self.interface.send(Data::Message(msg));
+            }
+            Token::KeyDown(key) => self.send_key(key, true, false),
+            Token::KeyUp(key) => self.send_key(key, false, false),
+            Token::KeyClick(key) => self.send_key(key, false, true),
+            // Handled by the caller.
+            Token::Wait(_) => {}
+            Token::MouseMove(x, y) => {
+                self.pos = (x, y);
+                self.send_mouse(None, MOUSE_TYPE_MOVE);
+            }
+            Token::MouseDown(button) => self.send_mouse(Some(button), MOUSE_TYPE_DOWN),
+            Token::MouseUp(button) => self.send_mouse(Some(button), MOUSE_TYPE_UP),
+            Token::MouseClick(button) => {
+                self.send_mouse(Some(button), MOUSE_TYPE_DOWN);
+                self.send_mouse(Some(button), MOUSE_TYPE_UP);
+            }
+        }
+    }
+
+    // Not to leave anything pressed on the peer.
+    fn release_all(&mut self) {
+        for key in self.keys.clone().into_iter().rev() {
+            self.send_key(key, false, false);
+        }
+        for button in self.buttons.clone() {
+            self.buttons.retain(|b| *b != button);
+            send_mouse(
+                button << 3 | MOUSE_TYPE_UP,
+                self.pos.0,
+                self.pos.1,
+                false,
+                false,
+                false,
+                false,
+                self.interface,
+            );
+        }
+    }
+}
+
+fn sleep(duration: Duration, stop: &AtomicBool) {
+    let start = Instant::now();
+    while !stop.load(Ordering::SeqCst) {
+        let left = duration.saturating_sub(start.elapsed());
+        if left.is_zero() {
+            break;
+        }
+        std::thread::sleep(left.min(Duration::from_millis(100)));
+    }
+}
+
+// Blocks until the macro ends or `stop` is set.
+pub fn play(interface: &impl Interface, tokens: Vec<Token>, stop: &AtomicBool) {
+    let mut player = Player {
+        interface,
+        modifiers: vec![],
+        keys: vec![],
+        buttons: vec![],
+        pos: (0, 0),
+    };
+    for token in tokens {
+        if stop.load(Ordering::SeqCst) {
+            log::info!("Input macro is stopped");
+            break;
+        }
+        match token {
+            Token::Wait(ms) => sleep(Duration::from_millis(ms), stop),
+            token => {
+                player.play(token);
+                sleep(PLAY_INTERVAL, stop);
+            }
+        }
+    }
+    player.release_all();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::LoginConfigHandler;
+    use hbb_common::Stream;
+    use std::sync::{Mutex, RwLock};
+
+    #[derive(Clone, Default)]
+    struct Sent(Arc<Mutex<Vec<Message>>>);
+
+    #[async_trait::async_trait]
+    impl Interface for Sent {
+        fn send(&self, data: Data) {
+            if let Data::Message(msg) = data {
+                self.0.lock().unwrap().push(msg);
+            }
+        }
+        fn msgbox(&self, _: &str, _: &str, _: &str, _: &str) {}
+        fn handle_login_error(&self, _: &str) -> bool {
+            false
+        }
+        fn handle_peer_info(&self, _: PeerInfo) {}
+        fn set_multiple_windows_session(&self, _: Vec<WindowsSession>) {}
+        async fn handle_hash(&self, _: &str, _: Hash, _: &mut Stream) {}
+        async fn handle_login_from_ui(
+            &self,
+            _: String,
+            _: String,
+            _: String,
+            _: bool,
+            _: &mut Stream,
+        ) {
+        }
+        async fn handle_test_delay(&self, _: TestDelay, _: &mut Stream) {}
+        fn get_lch(&self) -> Arc<RwLock<LoginConfigHandler>> {
+            Default::default()
+        }
+    }
+
+    fn control_key(ck: ControlKey, down: bool) -> KeyEvent {
+        let mut evt = KeyEvent::new();
+        evt.set_control_key(ck);
+        evt.down = down;
+        evt
+    }
+
+    fn chr(c: char, down: bool, press: bool) -> KeyEvent {
+        let mut evt = KeyEvent::new();
+        evt.set_chr(c as _);
+        evt.down = down;
+        evt.press = press;
+        evt.mode = KeyboardMode::Legacy.into();
+        evt
+    }
+
+    fn seq(text: &str) -> KeyEvent {
+        let mut evt = KeyEvent::new();
+        evt.set_seq(text.to_owned());
+        evt
+    }
+
+    #[test]
+    fn test_record_roundtrip() {
+        let mut r = Recorder::new();
+        r.record_key("", &control_key(ControlKey::Control, true));
+        r.record_key("", &chr('c', false, true));
+        r.record_key("", &control_key(ControlKey::Control, false));
+        r.record_key("", &seq("a{b}"));
+        r.record_mouse(MOUSE_TYPE_MOVE, 10, 20);
+        r.record_mouse(MOUSE_BUTTON_LEFT << 3 | MOUSE_TYPE_DOWN, 10, 20);
+        r.record_mouse(MOUSE_BUTTON_LEFT << 3 | MOUSE_TYPE_UP, 10, 20);
+        let script = r.finish();
+        assert_eq!(script, "{+CTRL}c{-CTRL}a{{b}}{MOVE:10,20}{LBUTTON}");
+        assert_eq!(
+            check(&script).unwrap(),
+            vec![
+                Token::KeyDown(Key::Control),
+                Token::Sequence("c".to_owned()),
+                Token::KeyUp(Key::Control),
+                Token::Sequence("a{b}".to_owned()),
+                Token::MouseMove(10, 20),
+                Token::MouseClick(MouseButton::Left),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_record_brackets() {
+        let mut r = Recorder::new();
+        // Held while another key is typed.
+        r.record_key("", &chr('{', true, false));
+        r.record_key("", &chr('x', false, true));
+        r.record_key("", &chr('{', false, false));
+        r.record_key("", &chr('}', false, true));
+        // Held before the text `}`.
+        r.record_key("", &chr('}', true, false));
+        r.record_key("", &seq("}"));
+        r.record_key("", &chr('}', false, false));
+        // Still pressed at the end.
+        r.record_key("", &chr('{', true, false));
+        let script = r.finish();
+        assert_eq!(script, "{{x}}}}}}{{");
+        assert_eq!(
+            check(&script).unwrap(),
+            vec![Token::Sequence("{x}}}{".to_owned())]
+        );
+    }
+
+    #[test]
+    fn test_record_release_pressed() {
+        let mut r = Recorder::new();
+        r.record_key("", &control_key(ControlKey::Control, true));
+        r.record_key("", &chr('a', true, false));
+        r.record_mouse(MOUSE_BUTTON_RIGHT << 3 | MOUSE_TYPE_DOWN, 1, 2);
+        let script = r.finish();
+        assert_eq!(
+            script,
+            "{+CTRL}{+a}{MOVE:1,2}{+RBUTTON}{-CTRL}{-RBUTTON}{-a}"
+        );
+        let tokens = check(&script).unwrap();
+        assert_eq!(
+            tokens[tokens.len() - 3..],
+            [
+                Token::KeyUp(Key::Control),
+                Token::MouseUp(MouseButton::Right),
+                Token::KeyUp(Key::Layout('a')),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_play_release_pressed() {
+        let sent = Sent::default();
+        let tokens = check("{+CTRL}{+a}{MOVE:5,6}{+LBUTTON}").unwrap();
+        play(&sent, tokens, &AtomicBool::new(false));
+        let msgs = sent.0.lock().unwrap();
+        assert_eq!(msgs.len(), 7);
+        let key = |msg: &Message| match &msg.union {
+            Some(message::Union::KeyEvent(evt)) => Some((evt.union.clone(), evt.down)),
+            _ => None,
+        };
+        assert_eq!(
+            key(&msgs[4]),
+            Some((Some(key_event::Union::Chr('a' as _)), false))
+        );
+        assert_eq!(
+            key(&msgs[5]),
+            Some((
+                Some(key_event::Union::ControlKey(ControlKey::Control.into())),
+                false
+            ))
+        );
+        match &msgs[6].union {
+            Some(message::Union::MouseEvent(evt)) => {
+                assert_eq!(evt.mask, MOUSE_BUTTON_LEFT << 3 | MOUSE_TYPE_UP);
+                assert_eq!((evt.x, evt.y), (5, 6));
+            }
+            _ => panic!("Not a mouse event"),
+        }
+    }
+}
@@ -4,10 +4,16 @@ use crate::clipboard::{update_clipboard, ClipboardSide};
 use crate::{audio_service, clipboard::CLIPBOARD_INTERVAL, ConnInner, CLIENT_SERVER};
 use crate::{
     client::{
-        self, new_voice_call_request, Client, Data, Interface, MediaData, MediaSender,
-        QualityStatus, MILLI1, SEC30,
+        self, new_voice_call_request,
+        transfer_queue::TransferQueue,
+        transport_qos::{TransportPreference, TransportQoS},
+        Client, Data, Interface, MediaData, MediaSender, QualityStatus, MILLI1, SEC30,
     },
-    common::get_default_sound_input,
+    common::{
+        get_default_sound_input, make_audio_msg, AudioMessage, WindowShareMessage, WolRelayMessage,
+    },
+    kcp_stream::{KcpProfile, KcpStats},
+    quic_stream::{QuicLane, QuicLanes},
     ui_session_interface::{InvokeUiSession, Session},
 };
 #[cfg(feature = "unix-file-copy-paste")]
@@ -20,6 +26,8 @@ use clipboard::ContextSend;
 use crossbeam_queue::ArrayQueue;
 #[cfg(not(target_os = "ios"))]
 use hbb_common::tokio::sync::mpsc::error::TryRecvError;
+#[cfg(any(target_os = "windows", feature = "unix-file-copy-paste"))]
+use hbb_common::tokio::sync::Mutex as TokioMutex;
 use hbb_common::{
     allow_err,
     config::{self, LocalConfig, PeerConfig, TransferSerde},
@@ -37,10 +45,8 @@ use hbb_common::{
         sync::mpsc,
         time::{self, Duration, Instant},
     },
-    Stream,
+    ResultType, Stream,
 };
-#[cfg(any(target_os = "windows", feature = "unix-file-copy-paste"))]
-use hbb_common::{tokio::sync::Mutex as TokioMutex, ResultType};
 use scrap::CodecFormat;
 use std::{
     collections::HashMap,
@@ -60,10 +66,12 @@ pub struct Remote<T: InvokeUiSession> {
     sender: mpsc::UnboundedSender<Data>,
     // Stop sending local audio to remote client.
     stop_voice_call_sender: Option<std::sync::mpsc::Sender<()>>,
+    stop_mic_passthrough_sender: Option<std::sync::mpsc::Sender<()>>,
     voice_call_request_timestamp: Option<NonZeroI64>,
     read_jobs: Vec<fs::TransferJob>,
     write_jobs: Vec<fs::TransferJob>,
     remove_jobs: HashMap<i32, RemoveJob>,
+    transfer_queue: Option<TransferQueue>,
     timer: crate::CloudyDeskInterval,
     last_update_jobs_status: (Instant, HashMap<i32, u64>),
     is_connected: bool,
@@ -78,6 +86,9 @@ pub struct Remote<T: InvokeUiSession> {
     chroma: Arc<RwLock<Option<Chroma>>>,
     last_record_state: bool,
     sent_close_reason: bool,
+    transport_qos: Option<TransportQoS>,
+    // The video, file, clipboard and terminal lanes of the QUIC connection.
+    quic_lanes: Option<QuicLanes>,
 }
 
 #[derive(Default)]
@@ -111,6 +122,7 @@ impl<T: InvokeUiSession> Remote<T> {
             read_jobs: Vec::new(),
             write_jobs: Vec::new(),
             remove_jobs: Default::default(),
+            transfer_queue: None,
             timer: crate::cloudydesk_interval(time::interval(SEC30)),
             last_update_jobs_status: (Instant::now(), Default::default()),
             is_connected: false,
@@ -120,6 +132,7 @@ impl<T: InvokeUiSession> Remote<T> {
             data_count: Arc::new(AtomicUsize::new(0)),
             video_format: CodecFormat::Unknown,
             stop_voice_call_sender: None,
+            stop_mic_passthrough_sender: None,
             voice_call_request_timestamp: None,
             elevation_requested: false,
             peer_info: Default::default(),
@@ -127,6 +140,8 @@ impl<T: InvokeUiSession> Remote<T> {
             chroma: Default::default(),
             last_record_state: false,
             sent_close_reason: false,
+            transport_qos: None,
+            quic_lanes: None,
         }
     }
 
@@ -155,6 +170,11 @@ impl<T: InvokeUiSession> Remote<T> {
 
         let mut last_recv_time = Instant::now();
         let mut received = false;
+        if self.handler.is_file_transfer() {
+            let mut queue = TransferQueue::load(&self.handler.get_id());
+            queue.on_disconnected();
+            self.transfer_queue = Some(queue);
+        }
         let conn_type = if self.handler.is_file_transfer() {
             ConnType::FILE_TRANSFER
         } else if self.handler.is_view_camera() {
@@ -183,10 +203,20 @@ impl<T: InvokeUiSession> Remote<T> {
                 self.handler
                     .set_connection_type(peer.is_secured(), direct, stream_type); // flutter -> connection_ready
                 self.handler.update_direct(Some(direct));
+                if let Some(pk) = pk.as_ref() {
+                    crate::lan::set_direct_pk(&self.handler.get_id(), pk);
+                }
+                let (quic_lanes, mut rx_quic) = crate::quic_stream::take_lanes(&peer.local_addr());
+                self.quic_lanes = quic_lanes;
                 if conn_type == ConnType::DEFAULT_CONN || conn_type == ConnType::VIEW_CAMERA {
                     self.handler
                         .set_fingerprint(crate::common::pk_to_fingerprint(pk.unwrap_or_default()));
+                    // QUIC is not switched to TCP or KCP.
+                    if TransportQoS::is_enabled() && self.quic_lanes.is_none() {
+                        self.transport_qos = Some(TransportQoS::new(kcp.is_some()));
+                    }
                 }
+                let kcp_stats = kcp.as_ref().map(|x| (x.profile(), x.stats()));
 
                 // just build for now
                 #[cfg(not(any(target_os = "windows", feature = "unix-file-copy-paste")))]
@@ -252,6 +282,13 @@ impl<T: InvokeUiSession> Remote<T> {
                                 break;
                             }
                         }
+                        Some(bytes) = rx_quic.recv() => {
+                            last_recv_time = Instant::now();
+                            self.data_count.fetch_add(bytes.len(), Ordering::Relaxed);
+                            if !self.handle_msg_from_peer(&bytes, &mut peer).await {
+                                break
+                            }
+                        }
                         d = self.receiver.recv() => {
                             if let Some(d) = d {
                                 if !self.handle_msg_from_ui(d, &mut peer).await {
@@ -269,7 +306,11 @@ impl<T: InvokeUiSession> Remote<T> {
                                 break;
                             }
                             if !self.read_jobs.is_empty() {
-                                if let Err(err) = fs::handle_read_jobs(&mut self.read_jobs, &mut peer).await {
+                                let stream = match self.quic_lanes.as_mut() {
+                                    Some(lanes) => lanes.stream(QuicLane::File, &mut peer).await,
+                                    None => &mut peer,
+                                };
+                                if let Err(err) = fs::handle_read_jobs(&mut self.read_jobs, stream).await {
                                     self.handler.msgbox("error", "Connection Error", &err.to_string(), "");
                                     break;
                                 }
@@ -296,6 +337,7 @@ impl<T: InvokeUiSession> Remote<T> {
                                 *v.frame_count.write().unwrap() = 0;
                             });
                             self.fps_control(direct, fps.clone());
+                            self.dispatch_transfer_queue();
                             let chroma = self.chroma.read().unwrap().clone();
                             let chroma = match chroma {
                                 Some(Chroma::I444) => "4:4:4",
@@ -308,21 +350,31 @@ impl<T: InvokeUiSession> Remote<T> {
                             } else {
                                 Some(self.video_format.clone())
                             };
+                            let transport = self.check_transport(stream_type, kcp_stats.as_ref());
                             self.handler.update_quality_status(QualityStatus {
                                 speed: Some(speed),
                                 fps,
                                 chroma,
                                 codec_format,
+                                transport: Some(transport),
                                 ..Default::default()
                             });
                         }
                     }
                 }
                 log::debug!("Exit io_loop of id={}", self.handler.get_id());
+                crate::lan::remove_wol_relay_session(&self.handler.get_id(), &self.sender);
+                self.sync_transfer_queue_file_num();
+                if let Some(queue) = self.transfer_queue.as_mut() {
+                    queue.on_disconnected();
+                }
                 // Stop client audio server.
                 if let Some(s) = self.stop_voice_call_sender.take() {
                     s.send(()).ok();
                 }
+                if let Some(s) = self.stop_mic_passthrough_sender.take() {
+                    s.send(()).ok();
+                }
                 if kcp.is_some() {
                     // Send the close reason if it hasn't been sent yet, as KCP cannot detect the socket close event.
                     self.send_close_reason(&mut peer, "kcp").await;
@@ -356,7 +408,7 @@ impl<T: InvokeUiSession> Remote<T> {
 
     #[cfg(any(target_os = "windows", feature = "unix-file-copy-paste"))]
     async fn handle_local_clipboard_msg(
-        &self,
+        &mut self,
         peer: &mut Stream,
         msg: Option<clipboard::ClipboardFile>,
     ) {
@@ -397,7 +449,7 @@ impl<T: InvokeUiSession> Remote<T> {
                         };
                         log::debug!("Send system clipboard message to remote");
                         let msg = crate::clipboard_file::clip_2_msg(clip);
-                        allow_err!(peer.send(&msg).await);
+                        allow_err!(send_on_lane(&mut self.quic_lanes, peer, &msg).await);
                     }
                 }
             },
@@ -427,6 +479,16 @@ impl<T: InvokeUiSession> Remote<T> {
                 }
             }
         }
+        if let Some(queue) = self.transfer_queue.as_mut() {
+            if queue.contains(id) {
+                queue.set_file_num(id, file_num);
+                match err.as_ref() {
+                    Some(err) => queue.on_job_error(id, err.clone()),
+                    None => queue.on_job_done(id),
+                }
+                self.update_transfer_queue();
+            }
+        }
         if let Some(err) = err {
             self.handler.job_error(id, err, file_num);
         } else {
@@ -434,6 +496,47 @@ impl<T: InvokeUiSession> Remote<T> {
         }
     }
 
+    // Starts the next queued job if no queued job is running.
+    fn dispatch_transfer_queue(&mut self) {
+        if !self.is_connected {
+            return;
+        }
+        let Some(queue) = self.transfer_queue.as_mut() else {
+            return;
+        };
+        let reloaded = queue.reload();
+        if let Some((id, data)) = queue.dispatch() {
+            // The failed attempt of a retried job may still be in the lists, with the same id.
+            let _ = fs::remove_job(id, &mut self.read_jobs);
+            let _ = fs::remove_job(id, &mut self.write_jobs);
+            for d in data {
+                self.sender.send(d).ok();
+            }
+            self.update_transfer_queue();
+        } else if reloaded {
+            self.update_transfer_queue();
+        }
+    }
+
+    // Keeps the file reached by the running queued jobs, a retry then resumes from it.
+    fn sync_transfer_queue_file_num(&mut self) {
+        let Some(queue) = self.transfer_queue.as_mut() else {
+            return;
+        };
+        for job in self.read_jobs.iter().chain(self.write_jobs.iter()) {
+            if queue.contains(job.id()) {
+                queue.set_file_num(job.id(), job.file_num());
+            }
+        }
+    }
+
+    fn update_transfer_queue(&self) {
+        if let Some(queue) = self.transfer_queue.as_ref() {
+            let json = serde_json::to_string(queue.jobs()).unwrap_or_default();
+            self.handler.update_transfer_queue(&json);
+        }
+    }
+
     fn stop_voice_call(&mut self) {
         let voice_call_sender = std::mem::replace(&mut self.stop_voice_call_sender, None);
         if let Some(stopper) = voice_call_sender {
@@ -441,7 +544,15 @@ impl<T: InvokeUiSession> Remote<T> {
         }
     }
 
+    fn stop_mic_passthrough(&mut self) {
+        if let Some(stopper) = self.stop_mic_passthrough_sender.take() {
+            let _ = stopper.send(());
+            self.handler.update_mic_passthrough(false);
+        }
+    }
+
     // Start a voice call recorder, records audio and send to remote
+    // Also used by the microphone passthrough, the same audio frames without a voice call.
     fn start_voice_call(&mut self) -> Option<std::sync::mpsc::Sender<()>> {
         if self.handler.is_file_transfer()
             || self.handler.is_port_forward()
@@ -533,6 +644,52 @@ impl<T: InvokeUiSession> Remote<T> {
         self.sent_close_reason = true;
     }
 
+    /// Switches the transport if it keeps performing badly, and returns the status of it.
+    fn check_transport(
+        &mut self,
+        stream_type: &str,
+        kcp_stats: Option<&(KcpProfile, Arc<KcpStats>)>,
+    ) -> String {
+        let mut transport = match kcp_stats {
+            Some((profile, _)) => format!("KCP {} ({})", profile.name(), stream_type),
+            None => stream_type.to_owned(),
+        };
+        if let Some(lanes) = self.quic_lanes.as_ref() {
+            transport += &format!(", {}ms", lanes.rtt().as_millis());
+        }
+        // The delay is not measured before logging in.
+        let is_connected = self.is_connected;
+        if let Some(qos) = self.transport_qos.as_mut().filter(|_| is_connected) {
+            if let Some(rtt) = qos.rtt() {
+                transport += &format!(", {}ms", rtt);
+            }
+            transport += &format!(", {:.0}% spikes", qos.spikes() * 100.);
+            if qos.is_stalled() {
+                transport += ", stalled";
+            }
+            if let Some(target) = qos.check_switch() {
+                let lc = self.handler.lc.read().unwrap();
+                let allowed = lc.transport_preference == TransportPreference::Auto
+                    && !(target == TransportPreference::Kcp
+                        && (lc.force_relay || !crate::get_udp_punch_enabled()));
+                drop(lc);
+                if allowed {
+                    log::info!("Switch transport from {} to {:?}", transport, target);
+                    self.transport_qos = None;
+                    self.handler.switch_transport(target);
+                }
+            }
+        }
+        if let Some((_, stats)) = kcp_stats {
+            transport += &format!(
+                ", {}/{} pkts",
+                stats.sent_packets.load(Ordering::Relaxed),
+                stats.received_packets.load(Ordering::Relaxed)
+            );
+        }
+        transport
+    }
+
     async fn handle_msg_from_ui(&mut self, data: Data, peer: &mut Stream) -> bool {
         match data {
             Data::Close => {
@@ -565,7 +722,7 @@ impl<T: InvokeUiSession> Remote<T> {
                     },
                     _ => {}
                 }
-                allow_err!(peer.send(&msg).await);
+                allow_err!(send_on_lane(&mut self.quic_lanes, peer, &msg).await);
             }
             Data::SendFiles((id, r#type, path, to, file_num, include_hidden, is_remote)) => {
                 log::info!("send files, is remote {}", is_remote);
@@ -590,8 +747,12 @@ impl<T: InvokeUiSession> Remote<T> {
                         od,
                     ));
                     allow_err!(
-                        peer.send(&fs::new_send(id, r#type, path, file_num, include_hidden))
-                            .await
+                        send_on_lane(
+                            &mut self.quic_lanes,
+                            peer,
+                            &fs::new_send(id, r#type, path, file_num, include_hidden)
+                        )
+                        .await
                     );
                 } else {
                     match fs::TransferJob::new_read(
@@ -635,8 +796,12 @@ impl<T: InvokeUiSession> Remote<T> {
                             self.read_jobs.push(job);
                             self.timer = crate::cloudydesk_interval(time::interval(MILLI1));
                             allow_err!(
-                                peer.send(&fs::new_receive(id, to, file_num, files, total_size))
-                                    .await
+                                send_on_lane(
+                                    &mut self.quic_lanes,
+                                    peer,
+                                    &fs::new_receive(id, to, file_num, files, total_size)
+                                )
+                                .await
                             );
                         }
                     }
@@ -706,13 +871,17 @@ impl<T: InvokeUiSession> Remote<T> {
                         job.is_last_job = false;
                         job.is_resume = true;
                         allow_err!(
-                            peer.send(&fs::new_send(
-                                id,
-                                fs::JobType::Generic,
-                                job.remote.clone(),
-                                job.file_num,
-                                job.show_hidden
-                            ))
+                            send_on_lane(
+                                &mut self.quic_lanes,
+                                peer,
+                                &fs::new_send(
+                                    id,
+                                    fs::JobType::Generic,
+                                    job.remote.clone(),
+                                    job.file_num,
+                                    job.show_hidden
+                                )
+                            )
                             .await
                         );
                     }
@@ -733,13 +902,17 @@ impl<T: InvokeUiSession> Remote<T> {
                                     fs::transform_windows_path(&mut files);
                                 }
                                 allow_err!(
-                                    peer.send(&fs::new_receive(
-                                        id,
-                                        job.remote.clone(),
-                                        job.file_num,
-                                        files,
-                                        job.total_size(),
-                                    ))
+                                    send_on_lane(
+                                        &mut self.quic_lanes,
+                                        peer,
+                                        &fs::new_receive(
+                                            id,
+                                            job.remote.clone(),
+                                            job.file_num,
+                                            files,
+                                            job.total_size(),
+                                        )
+                                    )
                                     .await
                                 );
                             }
@@ -806,7 +979,7 @@ impl<T: InvokeUiSession> Remote<T> {
                         job.confirm(&req).await;
                         file_action.set_send_confirm(req);
                         msg.set_file_action(file_action);
-                        allow_err!(peer.send(&msg).await);
+                        allow_err!(send_on_lane(&mut self.quic_lanes, peer, &msg).await);
                     }
                 }
             }
@@ -822,7 +995,7 @@ impl<T: InvokeUiSession> Remote<T> {
                         ..Default::default()
                     });
                     msg_out.set_file_action(file_action);
-                    allow_err!(peer.send(&msg_out).await);
+                    allow_err!(send_on_lane(&mut self.quic_lanes, peer, &msg_out).await);
                     self.remove_jobs
                         .insert(id, RemoveJob::new(Vec::new(), path, sep, is_remote));
                 } else {
@@ -852,12 +1025,18 @@ impl<T: InvokeUiSession> Remote<T> {
                     ..Default::default()
                 });
                 msg_out.set_file_action(file_action);
-                allow_err!(peer.send(&msg_out).await);
+                allow_err!(send_on_lane(&mut self.quic_lanes, peer, &msg_out).await);
                 if let Some(job) = fs::remove_job(id, &mut self.write_jobs) {
                     job.remove_download_file();
                 }
                 let _ = fs::remove_job(id, &mut self.read_jobs);
                 self.remove_jobs.remove(&id);
+                if let Some(queue) = self.transfer_queue.as_mut() {
+                    if queue.contains(id) {
+                        allow_err!(queue.remove(id));
+                        self.update_transfer_queue();
+                    }
+                }
             }
             Data::RemoveDir((id, path)) => {
                 let mut msg_out = Message::new();
@@ -869,7 +1048,7 @@ impl<T: InvokeUiSession> Remote<T> {
                     ..Default::default()
                 });
                 msg_out.set_file_action(file_action);
-                allow_err!(peer.send(&msg_out).await);
+                allow_err!(send_on_lane(&mut self.quic_lanes, peer, &msg_out).await);
             }
             Data::RemoveFile((id, path, file_num, is_remote)) => {
                 if is_remote {
@@ -882,7 +1061,7 @@ impl<T: InvokeUiSession> Remote<T> {
                         ..Default::default()
                     });
                     msg_out.set_file_action(file_action);
-                    allow_err!(peer.send(&msg_out).await);
+                    allow_err!(send_on_lane(&mut self.quic_lanes, peer, &msg_out).await);
                 } else {
                     match fs::remove_file(&path) {
                         Err(err) => {
@@ -904,7 +1083,7 @@ impl<T: InvokeUiSession> Remote<T> {
                         ..Default::default()
                     });
                     msg_out.set_file_action(file_action);
-                    allow_err!(peer.send(&msg_out).await);
+                    allow_err!(send_on_lane(&mut self.quic_lanes, peer, &msg_out).await);
                 } else {
                     match fs::create_dir(&path) {
                         Err(err) => {
@@ -927,7 +1106,7 @@ impl<T: InvokeUiSession> Remote<T> {
                         ..Default::default()
                     });
                     msg_out.set_file_action(file_action);
-                    allow_err!(peer.send(&msg_out).await);
+                    allow_err!(send_on_lane(&mut self.quic_lanes, peer, &msg_out).await);
                 } else {
                     let err = fs::rename_file(&path, &new_name)
                         .err()
@@ -946,7 +1125,7 @@ impl<T: InvokeUiSession> Remote<T> {
                 misc.set_elevation_request(request);
                 let mut msg = Message::new();
                 msg.set_misc(misc);
-                allow_err!(peer.send(&msg).await);
+                allow_err!(send_on_lane(&mut self.quic_lanes, peer, &msg).await);
                 self.elevation_requested = true;
             }
             Data::ElevateWithLogon(username, password) => {
@@ -960,7 +1139,7 @@ impl<T: InvokeUiSession> Remote<T> {
                 misc.set_elevation_request(request);
                 let mut msg = Message::new();
                 msg.set_misc(misc);
-                allow_err!(peer.send(&msg).await);
+                allow_err!(send_on_lane(&mut self.quic_lanes, peer, &msg).await);
                 self.elevation_requested = true;
             }
             Data::NewVoiceCall => {
@@ -970,7 +1149,7 @@ impl<T: InvokeUiSession> Remote<T> {
                     NonZeroI64::new(msg.voice_call_request().req_timestamp)
                         .unwrap_or(NonZeroI64::new(get_time()).unwrap()),
                 );
-                allow_err!(peer.send(&msg).await);
+                allow_err!(send_on_lane(&mut self.quic_lanes, peer, &msg).await);
                 self.handler.on_voice_call_waiting();
             }
             Data::CloseVoiceCall => {
@@ -978,7 +1157,25 @@ impl<T: InvokeUiSession> Remote<T> {
                 let msg = new_voice_call_request(false);
                 self.handler
                     .on_voice_call_closed("Closed manually by the peer");
-                allow_err!(peer.send(&msg).await);
+                allow_err!(send_on_lane(&mut self.quic_lanes, peer, &msg).await);
+            }
+            Data::MicPassthrough(enable) => {
+                if enable && self.stop_voice_call_sender.is_some() {
+                    self.handler.msgbox(
+                        "custom-nocancel-error",
+                        "Microphone passthrough",
+                        "Microphone passthrough is not available during a voice call",
+                        "",
+                    );
+                    self.handler.update_mic_passthrough(false);
+                    return true;
+                }
+                if !enable {
+                    self.stop_mic_passthrough();
+                }
+                // The recorder starts after the peer confirms.
+                let msg = make_audio_msg(&AudioMessage::MicPassthrough { enable });
+                allow_err!(send_on_lane(&mut self.quic_lanes, peer, &msg).await);
             }
             Data::ResetDecoder(display) => match display {
                 Some(display) => {
@@ -999,7 +1196,7 @@ impl<T: InvokeUiSession> Remote<T> {
                     sid,
                     ..Default::default()
                 });
-                allow_err!(peer.send(&msg).await);
+                allow_err!(send_on_lane(&mut self.quic_lanes, peer, &msg).await);
             }
             _ => {}
         }
@@ -1050,6 +1247,7 @@ impl<T: InvokeUiSession> Remote<T> {
                 );
             }
             self.last_update_jobs_status.0 = Instant::now();
+            self.sync_transfer_queue_file_num();
         }
     }
 
@@ -1345,6 +1543,19 @@ impl<T: InvokeUiSession> Remote<T> {
                             }
                         }
                         self.handler.handle_peer_info(pi);
+                        crate::lan::add_wol_relay_session(
+                            &self.handler.get_id(),
+                            self.sender.clone(),
+                        );
+                        if self.handler.is_default() || self.handler.is_view_camera() {
+                            let capabilities = crate::common::Capabilities {
+                                lossless_tiles: true,
+                            };
+                            allow_err!(
+                                peer.send(&crate::common::make_capabilities_msg(&capabilities))
+                                    .await
+                            );
+                        }
                         #[cfg(all(target_os = "windows", not(feature = "flutter")))]
                         self.check_clipboard_file_context();
                         if self.handler.is_default() {
@@ -1409,6 +1620,8 @@ impl<T: InvokeUiSession> Remote<T> {
                         }
 
                         self.is_connected = true;
+                        self.update_transfer_queue();
+                        self.dispatch_transfer_queue();
                     }
                     _ => {}
                 },
@@ -1423,6 +1636,11 @@ impl<T: InvokeUiSession> Remote<T> {
                 }
                 Some(message::Union::Clipboard(cb)) => {
                     if !self.handler.lc.read().unwrap().disable_clipboard.v {
+                        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                        crate::clipboard_history::record(
+                            &self.handler.get_id(),
+                            std::slice::from_ref(&cb),
+                        );
                         #[cfg(not(any(target_os = "android", target_os = "ios")))]
                         update_clipboard(vec![cb], ClipboardSide::Client);
                         #[cfg(target_os = "ios")]
@@ -1442,6 +1660,8 @@ impl<T: InvokeUiSession> Remote<T> {
                 }
                 Some(message::Union::MultiClipboards(_mcb)) => {
                     if !self.handler.lc.read().unwrap().disable_clipboard.v {
+                        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                        crate::clipboard_history::record(&self.handler.get_id(), &_mcb.clipboards);
                         #[cfg(not(any(target_os = "android", target_os = "ios")))]
                         update_clipboard(_mcb.clipboards, ClipboardSide::Client);
                         #[cfg(target_os = "android")]
@@ -1509,7 +1729,10 @@ impl<T: InvokeUiSession> Remote<T> {
                                                 };
                                                 job.confirm(&req).await;
                                                 let msg = new_send_confirm(req);
-                                                allow_err!(peer.send(&msg).await);
+                                                allow_err!(
+                                                    send_on_lane(&mut self.quic_lanes, peer, &msg)
+                                                        .await
+                                                );
                                             } else {
                                                 self.handler.override_file_confirm(
                                                     digest.id,
@@ -1549,7 +1772,14 @@ impl<T: InvokeUiSession> Remote<T> {
                                                         };
                                                         job.confirm(&req).await;
                                                         let msg = new_send_confirm(req);
-                                                        allow_err!(peer.send(&msg).await);
+                                                        allow_err!(
+                                                            send_on_lane(
+                                                                &mut self.quic_lanes,
+                                                                peer,
+                                                                &msg
+                                                            )
+                                                            .await
+                                                        );
                                                     }
                                                     DigestCheckResult::NeedConfirm(digest) => {
                                                         let mut overwrite_strategy =
@@ -1577,7 +1807,14 @@ impl<T: InvokeUiSession> Remote<T> {
                                                                 };
                                                             job.confirm(&req).await;
                                                             let msg = new_send_confirm(req);
-                                                            allow_err!(peer.send(&msg).await);
+                                                            allow_err!(
+                                                                send_on_lane(
+                                                                    &mut self.quic_lanes,
+                                                                    peer,
+                                                                    &msg
+                                                                )
+                                                                .await
+                                                            );
                                                         } else {
                                                             self.handler.override_file_confirm(
                                                                 digest.id,
@@ -1597,7 +1834,14 @@ impl<T: InvokeUiSession> Remote<T> {
                                                     };
                                                         job.confirm(&req).await;
                                                         let msg = new_send_confirm(req);
-                                                        allow_err!(peer.send(&msg).await);
+                                                        allow_err!(
+                                                            send_on_lane(
+                                                                &mut self.quic_lanes,
+                                                                peer,
+                                                                &msg
+                                                            )
+                                                            .await
+                                                        );
                                                     }
                                                 },
                                                 Err(err) => {
@@ -1865,6 +2109,84 @@ impl<T: InvokeUiSession> Remote<T> {
                         #[cfg(feature = "flutter")]
                         self.handler.switch_back(&self.handler.get_id());
                     }
+                    Some(misc::Union::PluginRequest(p))
+                        if p.id == crate::common::WOL_RELAY_REQUEST_ID =>
+                    {
+                        match serde_json::from_slice::<WolRelayMessage>(&p.content) {
+                            Ok(WolRelayMessage::Result { id, error }) => {
+                                crate::lan::on_wol_relay_result(&self.handler.get_id(), &id, error);
+                            }
+                            Ok(_) => {}
+                            Err(e) => log::error!("Invalid wol relay message: {}", e),
+                        }
+                    }
+                    Some(misc::Union::PluginRequest(p))
+                        if p.id == crate::common::SESSION_TEMPLATES_REQUEST_ID =>
+                    {
+                        match serde_json::from_slice::<Vec<String>>(&p.content) {
+                            Ok(templates) => {
+                                self.handler.update_session_templates(
+                                    &serde_json::to_string(&templates).unwrap_or_default(),
+                                );
+                            }
+                            Err(e) => log::error!("Invalid session templates: {}", e),
+                        }
+                    }
+                    Some(misc::Union::PluginRequest(p))
+                        if p.id == crate::common::WINDOW_SHARE_REQUEST_ID =>
+                    {
+                        match serde_json::from_slice::<WindowShareMessage>(&p.content) {
+                            Ok(WindowShareMessage::Windows { windows }) => {
+                                self.handler.update_shareable_windows(
+                                    &serde_json::to_string(&windows).unwrap_or_default(),
+                                );
+                            }
+                            Ok(WindowShareMessage::Error { msg }) => {
+                                self.handler.msgbox(
+                                    "custom-nocancel-error",
+                                    "Share window",
+                                    &msg,
+                                    "",
+                                );
+                            }
+                            Ok(_) => {}
+                            Err(e) => log::error!("Invalid window share message: {}", e),
+                        }
+                    }
+                    Some(misc::Union::PluginRequest(p))
+                        if p.id == crate::common::AUDIO_REQUEST_ID =>
+                    {
+                        match serde_json::from_slice::<AudioMessage>(&p.content) {
+                            Ok(AudioMessage::Apps { apps, current }) => {
+                                self.handler.update_audio_apps(
+                                    &serde_json::json!({ "apps": apps, "current": current })
+                                        .to_string(),
+                                );
+                            }
+                            Ok(AudioMessage::MicPassthrough { enable }) => {
+                                if !enable {
+                                    self.stop_mic_passthrough();
+                                } else if self.stop_mic_passthrough_sender.is_none() {
+                                    self.stop_mic_passthrough_sender = self.start_voice_call();
+                                    self.handler.update_mic_passthrough(
+                                        self.stop_mic_passthrough_sender.is_some(),
+                                    );
+                                }
+                            }
+                            Ok(AudioMessage::Quality { hifi }) => {
+                                self.audio_sender.send(MediaData::AudioHifi(hifi)).ok();
+                            }
+                            Ok(AudioMessage::Error { msg }) => {
+                                self.handler.update_mic_passthrough(
+                                    self.stop_mic_passthrough_sender.is_some(),
+                                );
+                                self.handler
+                                    .msgbox("custom-nocancel-error", "Audio", &msg, "");
+                            }
+                            Ok(_) => {}
+                            Err(e) => log::error!("Invalid audio message: {}", e),
+                        }
+                    }
                     #[cfg(all(feature = "flutter", feature = "plugin_framework"))]
                     #[cfg(not(any(target_os = "android", target_os = "ios")))]
                     Some(misc::Union::PluginRequest(p)) => {
@@ -1895,6 +2217,9 @@ impl<T: InvokeUiSession> Remote<T> {
                     _ => {}
                 },
                 Some(message::Union::TestDelay(t)) => {
+                    if let Some(qos) = self.transport_qos.as_mut() {
+                        qos.on_network_delay(t.last_delay);
+                    }
                     self.handler.handle_test_delay(t, peer).await;
                 }
                 Some(message::Union::AudioFrame(frame)) => {
@@ -1978,6 +2303,8 @@ impl<T: InvokeUiSession> Remote<T> {
                             if response.accepted {
                                 // The peer accepted the voice call.
                                 self.handler.on_voice_call_started();
+                                // The peer stops the microphone passthrough when accepting.
+                                self.stop_mic_passthrough();
                                 self.stop_voice_call_sender = self.start_voice_call();
                             } else {
                                 // The peer refused the voice call.
@@ -2230,6 +2557,9 @@ impl<T: InvokeUiSession> Remote<T> {
         _peer: &mut Stream,
     ) {
         log::debug!("handling cliprdr msg from server peer");
+        if !crate::clipboard_dlp::allow_cliprdr(&clip, ClipboardSide::Client) {
+            return;
+        }
         #[cfg(feature = "flutter")]
         if let Some(hbb_common::message_proto::cliprdr::Union::FormatList(_)) = &clip.union {
             if self.client_conn_id
@@ -2297,7 +2627,7 @@ impl<T: InvokeUiSession> Remote<T> {
                 }
 
                 for msg in out_msgs.into_iter() {
-                    allow_err!(_peer.send(&msg).await);
+                    allow_err!(send_on_lane(&mut self.quic_lanes, _peer, &msg).await);
                 }
             }
         }
@@ -2428,3 +2758,15 @@ impl Drop for VideoThread {
         *self.discard_queue.write().unwrap() = true;
     }
 }
+
+/// Sends the message on its QUIC lane if any, or the stream.
+async fn send_on_lane(
+    lanes: &mut Option<QuicLanes>,
+    peer: &mut Stream,
+    msg: &Message,
+) -> ResultType<()> {
+    match lanes.as_mut() {
+        Some(lanes) => lanes.send(peer, msg).await,
+        None => peer.send(msg).await,
+    }
+}
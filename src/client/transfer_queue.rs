@@ -0,0 +1,452 @@
+use hbb_common::{
+    allow_err, bail,
+    config::{Config, LocalConfig},
+    fs, get_time, log, ResultType,
+};
+use serde_derive::{Deserialize, Serialize};
+use std::{path::PathBuf, time::SystemTime};
+
+use super::Data;
+
+// Ids of queued jobs are allocated above this base, so they never collide with ids generated by the UI.
+const QUEUE_JOB_ID_BASE: i32 = 1 << 24;
+const DEFAULT_MAX_ATTEMPTS: u32 = 5;
+const RETRY_BASE_DELAY_MS: i64 = 5_000;
+const RETRY_MAX_DELAY_MS: i64 = 300_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QueuedJobStatus {
+    Pending,
+    Running,
+    Failed,
+    Done,
+}
+
+impl Default for QueuedJobStatus {
+    fn default() -> Self {
+        Self::Pending
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct QueuedJob {
+    #[serde(default)]
+    pub id: i32,
+    /// Remote path for downloads, local path for uploads.
+    #[serde(default)]
+    pub path: String,
+    /// Local path for downloads, remote path for uploads.
+    #[serde(default)]
+    pub to: String,
+    #[serde(default)]
+    pub file_num: i32,
+    #[serde(default)]
+    pub include_hidden: bool,
+    /// `true` if the job downloads from the peer, the same meaning as `TransferJob::is_remote`.
+    #[serde(default)]
+    pub is_remote: bool,
+    #[serde(default)]
+    pub priority: i32,
+    #[serde(default)]
+    pub status: QueuedJobStatus,
+    #[serde(default)]
+    pub attempts: u32,
+    #[serde(default)]
+    pub next_attempt_at: i64,
+    #[serde(default)]
+    pub last_error: String,
+    #[serde(default)]
+    pub created_at: i64,
+}
+
+impl QueuedJob {
+    #[inline]
+    fn is_due(&self, now: i64) -> bool {
+        self.status == QueuedJobStatus::Pending && self.next_attempt_at <= now
+    }
+}
+
+/// Persistent transfer queue of one peer.
+///
+/// The queue is stored in `transfer_queue/<peer>.toml` in the config directory,
+/// so it survives reconnects and restarts of the app.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct TransferQueue {
+    #[serde(skip)]
+    peer: String,
+    #[serde(default)]
+    jobs: Vec<QueuedJob>,
+    // The modification time of the file when it was last loaded or stored.
+    #[serde(skip)]
+    modified: Option<SystemTime>,
+}
+
+impl TransferQueue {
+    fn path(peer: &str) -> PathBuf {
+        let name: String = peer
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        Config::path("transfer_queue").join(format!("{}.toml", name))
+    }
+
+    #[inline]
+    fn get_modified(peer: &str) -> Option<SystemTime> {
+        std::fs::metadata(Self::path(peer))
+            .and_then(|m| m.modified())
+            .ok()
+    }
+
+    pub fn load(peer: &str) -> Self {
+        let modified = Self::get_modified(peer);
+        let mut queue: TransferQueue = hbb_common::config::load_path(Self::path(peer));
+        queue.peer = peer.to_owned();
+        queue.modified = modified;
+        queue
+    }
+
+    /// Picks up the changes made by the CLI or the UI if the file has been modified since it was
+    /// last loaded or stored, keeping the state of the running job. Returns `true` if reloaded.
+    pub fn reload(&mut self) -> bool {
+        if Self::get_modified(&self.peer) == self.modified {
+            return false;
+        }
+        let running = self
+            .jobs
+            .iter()
+            .find(|j| j.status == QueuedJobStatus::Running)
+            .cloned();
+        let queue = Self::load(&self.peer);
+        self.jobs = queue.jobs;
+        self.modified = queue.modified;
+        if let Some(running) = running {
+            if let Some(job) = self.jobs.iter_mut().find(|j| j.id == running.id) {
+                *job = running;
+            }
+        }
+        true
+    }
+
+    pub fn store(&mut self) {
+        let path = Self::path(&self.peer);
+        allow_err!(hbb_common::config::store_path(path, &*self));
+        self.modified = Self::get_modified(&self.peer);
+    }
+
+    #[inline]
+    pub fn jobs(&self) -> &Vec<QueuedJob> {
+        &self.jobs
+    }
+
+    #[inline]
+    pub fn contains(&self, id: i32) -> bool {
+        self.jobs.iter().any(|j| j.id == id)
+    }
+
+    #[inline]
+    pub fn has_running(&self) -> bool {
+        self.jobs
+            .iter()
+            .any(|j| j.status == QueuedJobStatus::Running)
+    }
+
+    fn next_id(&self) -> i32 {
+        self.jobs
+            .iter()
+            .map(|j| j.id)
+            .max()
+            .map(|id| id + 1)
+            .unwrap_or(QUEUE_JOB_ID_BASE)
+            .max(QUEUE_JOB_ID_BASE)
+    }
+
+    pub fn push(
+        &mut self,
+        path: String,
+        to: String,
+        include_hidden: bool,
+        is_remote: bool,
+        priority: i32,
+    ) -> i32 {
+        let id = self.next_id();
+        self.jobs.push(QueuedJob {
+            id,
+            path,
+            to,
+            include_hidden,
+            is_remote,
+            priority,
+            created_at: get_time(),
+            ..Default::default()
+        });
+        self.store();
+        id
+    }
+
+    pub fn remove(&mut self, id: i32) -> ResultType<()> {
+        let Some(pos) = self.jobs.iter().position(|j| j.id == id) else {
+            bail!("No such queued job {}", id);
+        };
+        self.jobs.remove(pos);
+        self.store();
+        Ok(())
+    }
+
+    pub fn set_priority(&mut self, id: i32, priority: i32) -> ResultType<()> {
+        let Some(job) = self.jobs.iter_mut().find(|j| j.id == id) else {
+            bail!("No such queued job {}", id);
+        };
+        job.priority = priority;
+        self.store();
+        Ok(())
+    }
+
+    /// Moves a failed job back to pending, resetting its attempts.
+    pub fn retry(&mut self, id: i32) -> ResultType<()> {
+        let Some(job) = self.jobs.iter_mut().find(|j| j.id == id) else {
+            bail!("No such queued job {}", id);
+        };
+        job.status = QueuedJobStatus::Pending;
+        job.attempts = 0;
+        job.next_attempt_at = 0;
+        self.store();
+        Ok(())
+    }
+
+    /// Removes finished jobs, or all jobs if `all` is set.
+    pub fn clear(&mut self, all: bool) {
+        if all {
+            self.jobs.clear();
+        } else {
+            self.jobs.retain(|j| j.status != QueuedJobStatus::Done);
+        }
+        self.store();
+    }
+
+    /// Picks the due job with the highest priority, the older one first if priorities are equal.
+    fn take_next(&mut self) -> Option<&mut QueuedJob> {
+        let now = get_time();
+        self.jobs
+            .iter_mut()
+            .filter(|j| j.is_due(now))
+            .max_by(|a, b| {
+                a.priority
+                    .cmp(&b.priority)
+                    .then_with(|| b.created_at.cmp(&a.created_at))
+            })
+    }
+
+    /// Returns the id and the messages of the next job, `None` if a job is running or nothing is due.
+    ///
+    /// A job that has been attempted before is resumed, the peer then continues from the
+    /// offset of the partially transferred file.
+    pub fn dispatch(&mut self) -> Option<(i32, Vec<Data>)> {
+        if self.has_running() {
+            return None;
+        }
+        let job = self.take_next()?;
+        job.status = QueuedJobStatus::Running;
+        let resume = job.attempts > 0;
+        job.attempts += 1;
+        let mut data = Vec::new();
+        if resume {
+            data.push(Data::AddJob((
+                job.id,
+                fs::JobType::Generic,
+                job.path.clone(),
+                job.to.clone(),
+                job.file_num,
+                job.include_hidden,
+                job.is_remote,
+            )));
+            data.push(Data::ResumeJob((job.id, job.is_remote)));
+        } else {
+            data.push(Data::SendFiles((
+                job.id,
+                fs::JobType::Generic,
+                job.path.clone(),
+                job.to.clone(),
+                job.file_num,
+                job.include_hidden,
+                job.is_remote,
+            )));
+        }
+        log::info!(
+            "dispatch queued transfer job {}, attempt {}, resume: {}",
+            job.id,
+            job.attempts,
+            resume
+        );
+        let id = job.id;
+        self.store();
+        Some((id, data))
+    }
+
+    /// Records the file the job has reached, a resumed job continues from it.
+    pub fn set_file_num(&mut self, id: i32, file_num: i32) {
+        if let Some(job) = self.jobs.iter_mut().find(|j| j.id == id) {
+            if job.file_num != file_num && file_num >= 0 {
+                job.file_num = file_num;
+                self.store();
+            }
+        }
+    }
+
+    pub fn on_job_done(&mut self, id: i32) {
+        if let Some(job) = self.jobs.iter_mut().find(|j| j.id == id) {
+            job.status = QueuedJobStatus::Done;
+            job.last_error.clear();
+            self.store();
+        }
+    }
+
+    /// Records a failed attempt and schedules a retry with exponential backoff.
+    pub fn on_job_error(&mut self, id: i32, err: String) {
+        let max_attempts = get_max_attempts();
+        if let Some(job) = self.jobs.iter_mut().find(|j| j.id == id) {
+            if job.attempts >= max_attempts {
+                log::warn!(
+                    "queued transfer job {} failed {} times: {}",
+                    id,
+                    job.attempts,
+                    err
+                );
+                job.status = QueuedJobStatus::Failed;
+            } else {
+                let delay = (RETRY_BASE_DELAY_MS << job.attempts.min(16)).min(RETRY_MAX_DELAY_MS);
+                job.status = QueuedJobStatus::Pending;
+                job.next_attempt_at = get_time() + delay;
+            }
+            job.last_error = err;
+            self.store();
+        }
+    }
+
+    /// Marks the running job as interrupted, it will be resumed on the next dispatch.
+    ///
+    /// Also called before the first dispatch, for the job that was running when the app exited.
+    pub fn on_disconnected(&mut self) {
+        let mut changed = false;
+        for job in self.jobs.iter_mut() {
+            if job.status == QueuedJobStatus::Running {
+                job.status = QueuedJobStatus::Pending;
+                changed = true;
+            }
+        }
+        if changed {
+            self.store();
+        }
+    }
+}
+
+#[inline]
+fn get_max_attempts() -> u32 {
+    LocalConfig::get_option("transfer-queue-max-attempts")
+        .parse()
+        .unwrap_or(DEFAULT_MAX_ATTEMPTS)
+}
+
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub fn handle_cli(args: &[String]) {
+    let usage = "Usage:
+    --transfer-queue list <peer-id>
+    --transfer-queue upload <peer-id> <local-path> <remote-path> [priority]
+    --transfer-queue download <peer-id> <remote-path> <local-path> [priority]
+    --transfer-queue remove <peer-id> <job-id>
+    --transfer-queue priority <peer-id> <job-id> <priority>
+    --transfer-queue retry <peer-id> <job-id>
+    --transfer-queue clear <peer-id> [all]";
+    if args.len() < 2 {
+        println!("{}", usage);
+        return;
+    }
+    let mut queue = TransferQueue::load(&args[1]);
+    let parse_id = |i: usize| args.get(i).and_then(|s| s.parse::<i32>().ok());
+    let res = match (args[0].as_str(), args.len()) {
+        ("list", 2) => {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(queue.jobs()).unwrap_or_default()
+            );
+            Ok(())
+        }
+        ("upload" | "download", 4 | 5) => {
+            let priority = args.get(4).and_then(|s| s.parse().ok()).unwrap_or(0);
+            let id = queue.push(
+                args[2].clone(),
+                args[3].clone(),
+                false,
+                args[0] == "download",
+                priority,
+            );
+            println!("{}", id);
+            Ok(())
+        }
+        ("remove", 3) => match parse_id(2) {
+            Some(id) => queue.remove(id),
+            None => Err(hbb_common::anyhow::anyhow!("Invalid job id")),
+        },
+        ("priority", 4) => match (parse_id(2), parse_id(3)) {
+            (Some(id), Some(priority)) => queue.set_priority(id, priority),
+            _ => Err(hbb_common::anyhow::anyhow!("Invalid job id or priority")),
+        },
+        ("retry", 3) => match parse_id(2) {
+            Some(id) => queue.retry(id),
+            None => Err(hbb_common::anyhow::anyhow!("Invalid job id")),
+        },
+        ("clear", 2 | 3) => {
+            queue.clear(args.get(2).map(|s| s == "all").unwrap_or(false));
+            Ok(())
+        }
+        _ => {
+            println!("{}", usage);
+            return;
+        }
+    };
+    if let Err(err) = res {
+        println!("{}", err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_queue() -> TransferQueue {
+        TransferQueue {
+            peer: "test".to_owned(),
+            ..Default::default()
+        }
+    }
+
+    fn add(queue: &mut TransferQueue, priority: i32, created_at: i64) -> i32 {
+        let id = queue.next_id();
+        queue.jobs.push(QueuedJob {
+            id,
+            priority,
+            created_at,
+            ..Default::default()
+        });
+        id
+    }
+
+    #[test]
+    fn test_take_next_by_priority() {
+        let mut queue = new_queue();
+        let a = add(&mut queue, 0, 1);
+        let b = add(&mut queue, 5, 2);
+        let c = add(&mut queue, 5, 3);
+        assert!(a >= QUEUE_JOB_ID_BASE);
+        assert_eq!(queue.take_next().map(|j| j.id), Some(b));
+        queue.jobs.iter_mut().find(|j| j.id == b).unwrap().status = QueuedJobStatus::Done;
+        assert_eq!(queue.take_next().map(|j| j.id), Some(c));
+        queue
+            .jobs
+            .iter_mut()
+            .find(|j| j.id == c)
+            .unwrap()
+            .next_attempt_at = i64::MAX;
+        assert_eq!(queue.take_next().map(|j| j.id), Some(a));
+    }
+}
@@ -0,0 +1,155 @@
+use hbb_common::config::LocalConfig;
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+/*
+Transport switch (client side):
+    The peer measures the network delay for its video QoS (`video_qos::user_network_delay`) about every
+    second, by the TestDelay round trip under the video load, and sends the last one in `last_delay`.
+    The decision is made on the last TRANSPORT_WINDOW samples of it.
+    A sample missing for TRANSPORT_STALL_TIMEOUT is a stall, the TestDelay or its echo is stuck.
+    KCP => TCP/relay: RTT stays high or the transport stalls, UDP is throttled or the KCP profile does not fit the link.
+    TCP/relay => KCP: TCP hides the loss as the retransmission delay, so the loss is seen as the spikes of the
+        delay above the lowest one by TCP_SPIKE_MS (the minimum retransmission timeout), and the stalls
+        of the head-of-line blocking.
+    The session reconnects with the other transport at most once.
+*/
+
+pub const OPTION_TRANSPORT_AUTO_SWITCH: &str = "transport-auto-switch";
+
+const TRANSPORT_WINDOW: usize = 20; // Number of delay samples the decision is made on
+const TRANSPORT_STALL_TIMEOUT: Duration = Duration::from_secs(3);
+const TRANSPORT_MIN_SESSION: Duration = Duration::from_secs(30); // Do not switch before the session settles
+const TRANSPORT_BAD_COUNT: usize = 10; // Consecutive bad evaluations before switching
+const KCP_MAX_RTT: u32 = 400;
+const TCP_SPIKE_MS: u32 = 200;
+const TCP_MAX_SPIKES: f32 = 0.2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TransportPreference {
+    #[default]
+    Auto,
+    Tcp,
+    Kcp,
+}
+
+pub struct TransportQoS {
+    kcp: bool,
+    delays: VecDeque<u32>,
+    last_sample: Instant,
+    bad_count: usize,
+    start: Instant,
+}
+
+impl TransportQoS {
+    pub fn new(kcp: bool) -> Self {
+        Self {
+            kcp,
+            delays: Default::default(),
+            last_sample: Instant::now(),
+            bad_count: 0,
+            start: Instant::now(),
+        }
+    }
+
+    pub fn is_enabled() -> bool {
+        LocalConfig::get_option(OPTION_TRANSPORT_AUTO_SWITCH) != "N"
+    }
+
+    /// The `last_delay` of the TestDelay from the peer.
+    pub fn on_network_delay(&mut self, delay: u32) {
+        self.last_sample = Instant::now();
+        // Not measured yet.
+        if delay == 0 {
+            return;
+        }
+        if self.delays.len() >= TRANSPORT_WINDOW {
+            self.delays.pop_front();
+        }
+        self.delays.push_back(delay);
+    }
+
+    pub fn rtt(&self) -> Option<u32> {
+        if self.delays.is_empty() {
+            return None;
+        }
+        Some(self.delays.iter().sum::<u32>() / self.delays.len() as u32)
+    }
+
+    /// The ratio of the delay samples with a retransmission spike.
+    pub fn spikes(&self) -> f32 {
+        let Some(min) = self.delays.iter().min() else {
+            return 0.;
+        };
+        let spikes = self
+            .delays
+            .iter()
+            .filter(|d| **d >= min + TCP_SPIKE_MS)
+            .count();
+        spikes as f32 / self.delays.len() as f32
+    }
+
+    pub fn is_stalled(&self) -> bool {
+        self.last_sample.elapsed() >= TRANSPORT_STALL_TIMEOUT
+    }
+
+    /// Returns the transport to switch to, if the current one keeps performing badly.
+    /// Called every second.
+    pub fn check_switch(&mut self) -> Option<TransportPreference> {
+        if self.delays.len() < TRANSPORT_WINDOW || self.start.elapsed() < TRANSPORT_MIN_SESSION {
+            return None;
+        }
+        let bad = self.is_stalled()
+            || if self.kcp {
+                self.rtt().map(|x| x > KCP_MAX_RTT).unwrap_or(false)
+            } else {
+                self.spikes() > TCP_MAX_SPIKES
+            };
+        if !bad {
+            self.bad_count = 0;
+            return None;
+        }
+        self.bad_count += 1;
+        if self.bad_count < TRANSPORT_BAD_COUNT {
+            return None;
+        }
+        self.bad_count = 0;
+        Some(if self.kcp {
+            TransportPreference::Tcp
+        } else {
+            TransportPreference::Kcp
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tcp_spikes() {
+        let mut qos = TransportQoS::new(false);
+        qos.start -= TRANSPORT_MIN_SESSION;
+        for i in 0..TRANSPORT_WINDOW {
+            qos.on_network_delay(if i % 4 == 0 { 320 } else { 60 + i as u32 });
+        }
+        assert_eq!(qos.spikes(), 0.25);
+        for _ in 1..TRANSPORT_BAD_COUNT {
+            assert_eq!(qos.check_switch(), None);
+        }
+        assert_eq!(qos.check_switch(), Some(TransportPreference::Kcp));
+
+        // A high but steady delay is not the loss.
+        let mut qos = TransportQoS::new(false);
+        qos.start -= TRANSPORT_MIN_SESSION;
+        for i in 0..TRANSPORT_WINDOW {
+            qos.on_network_delay(500 + i as u32);
+        }
+        assert_eq!(qos.spikes(), 0.);
+        for _ in 0..TRANSPORT_BAD_COUNT {
+            assert_eq!(qos.check_switch(), None);
+        }
+    }
+}
@@ -62,7 +62,12 @@ pub fn check_clipboard(
         Ok(content) => {
             if !content.is_empty() {
                 let mut msg = Message::new();
-                let clipboards = proto::create_multi_clipboards(content);
+                let mut clipboards = proto::create_multi_clipboards(content);
+                clipboards.clipboards =
+                    crate::clipboard_dlp::filter(clipboards.clipboards, side, true);
+                if clipboards.clipboards.is_empty() {
+                    return None;
+                }
                 msg.set_multi_clipboards(clipboards.clone());
                 *LAST_MULTI_CLIPBOARDS.lock().unwrap() = clipboards;
                 return Some(msg);
@@ -197,6 +202,7 @@ pub fn check_clipboard_cm() -> ResultType<MultiClipboards> {
 
 #[cfg(not(target_os = "android"))]
 fn update_clipboard_(multi_clipboards: Vec<Clipboard>, side: ClipboardSide) {
+    let multi_clipboards = crate::clipboard_dlp::filter(multi_clipboards, side, false);
     let to_update_data = proto::from_multi_clipbards(multi_clipboards);
     if to_update_data.is_empty() {
         return;
@@ -484,7 +490,9 @@ pub fn get_current_clipboard_msg(
     let mut multi_clipboards = LAST_MULTI_CLIPBOARDS.lock().unwrap();
     if multi_clipboards.clipboards.is_empty() {
         let mut ctx = ClipboardContext::new().ok()?;
-        *multi_clipboards = proto::create_multi_clipboards(ctx.get(side, true).ok()?);
+        let mut clipboards = proto::create_multi_clipboards(ctx.get(side, true).ok()?);
+        clipboards.clipboards = crate::clipboard_dlp::filter(clipboards.clipboards, side, true);
+        *multi_clipboards = clipboards;
     }
     if multi_clipboards.clipboards.is_empty() {
         return None;
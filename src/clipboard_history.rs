@@ -0,0 +1,503 @@
+// Client side clipboard history.
+//
+// Every clipboard content copied locally or received from a peer is recorded here,
+// so that it can be browsed from the session UI and pushed to the peer on demand.
+// Local contents are recorded with an empty peer id.
+
+use hbb_common::{
+    allow_err,
+    compress::decompress,
+    config::{Config, LocalConfig},
+    get_time, log,
+    message_proto::{Clipboard, ClipboardFormat, Message, MultiClipboards},
+    password_security::{decrypt_vec_or_original, encrypt_vec_or_original},
+    protobuf::Message as _,
+};
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+const OPTION_HISTORY_SIZE: &str = "clipboard-history-size";
+const OPTION_HISTORY_FORMATS: &str = "clipboard-history-formats";
+const OPTION_HISTORY_PERSIST: &str = "clipboard-history-persist";
+
+const DEFAULT_HISTORY_SIZE: usize = 30;
+const DEFAULT_HISTORY_FORMATS: &str = "text,html,rtf,image,files";
+// Larger contents are not recorded, mostly big images.
+const MAX_ENTRY_SIZE: usize = 8 * 1024 * 1024;
+const PREVIEW_LEN: usize = 100;
+const LOCAL_HISTORY_NAME: &str = "local";
+
+lazy_static::lazy_static! {
+    static ref HISTORY: Arc<Mutex<ClipboardHistory>> = Default::default();
+    // Serializes the writes of the history files, which are done without holding `HISTORY`.
+    static ref SAVE_LOCK: Arc<Mutex<()>> = Default::default();
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct HistoryEntry {
+    pub id: i64,
+    pub peer: String,
+    pub time: i64,
+    pub formats: Vec<String>,
+    pub preview: String,
+    pub files: Vec<String>,
+    #[serde(skip)]
+    data: Vec<u8>,
+    // base64 of the encrypted `data`, empty until the entry is persisted.
+    #[serde(skip)]
+    encrypted: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct PersistedEntry {
+    #[serde(default)]
+    id: i64,
+    #[serde(default)]
+    time: i64,
+    #[serde(default)]
+    formats: Vec<String>,
+    #[serde(default)]
+    preview: String,
+    #[serde(default)]
+    files: Vec<String>,
+    // base64 of the encrypted `MultiClipboards`.
+    #[serde(default)]
+    data: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct PersistedHistory {
+    #[serde(default)]
+    entries: Vec<PersistedEntry>,
+}
+
+#[derive(Default)]
+struct ClipboardHistory {
+    entries: HashMap<String, VecDeque<HistoryEntry>>,
+    loaded: HashSet<String>,
+    last_id: i64,
+}
+
+impl ClipboardHistory {
+    fn next_id(&mut self) -> i64 {
+        self.last_id = get_time().max(self.last_id + 1);
+        self.last_id
+    }
+
+    // Loads the persisted history of `peer` once persistence is on, keeping the entries
+    // recorded before.
+    fn ensure_loaded(&mut self, peer: &str) {
+        if !is_persist_enabled() || !self.loaded.insert(peer.to_owned()) {
+            return;
+        }
+        let persisted: PersistedHistory = hbb_common::config::load_path(history_path(peer));
+        let mut entries = VecDeque::new();
+        for e in persisted.entries {
+            let Ok(encrypted) = crate::decode64(&e.data) else {
+                continue;
+            };
+            let (data, success, _) = decrypt_vec_or_original(&encrypted, "00");
+            if !success {
+                log::warn!("Failed to decrypt clipboard history entry {}", e.id);
+                continue;
+            }
+            self.last_id = self.last_id.max(e.id);
+            entries.push_back(HistoryEntry {
+                id: e.id,
+                peer: peer.to_owned(),
+                time: e.time,
+                formats: e.formats,
+                preview: e.preview,
+                files: e.files,
+                data,
+                encrypted: e.data,
+            });
+        }
+        for e in self.entries.remove(peer).unwrap_or_default() {
+            if !entries.iter().any(|x| x.id == e.id) {
+                entries.push_back(e);
+            }
+        }
+        entries.make_contiguous().sort_by_key(|e| e.id);
+        truncate(&mut entries);
+        self.entries.insert(peer.to_owned(), entries);
+    }
+
+    fn persisted(&mut self, peer: &str) -> PersistedHistory {
+        let entries = self
+            .entries
+            .get_mut(peer)
+            .map(|entries| {
+                entries
+                    .iter_mut()
+                    .map(|e| {
+                        // Entries recorded before persistence was turned on.
+                        if e.encrypted.is_empty() {
+                            e.encrypted = encrypt(&e.data);
+                        }
+                        PersistedEntry {
+                            id: e.id,
+                            time: e.time,
+                            formats: e.formats.clone(),
+                            preview: e.preview.clone(),
+                            files: e.files.clone(),
+                            data: e.encrypted.clone(),
+                        }
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        PersistedHistory { entries }
+    }
+
+    // Returns `false` if the entry is the same as the last one.
+    fn push(&mut self, mut entry: HistoryEntry) -> bool {
+        let peer = entry.peer.clone();
+        self.ensure_loaded(&peer);
+        let entries = self.entries.entry(peer.clone()).or_default();
+        if let Some(last) = entries.back() {
+            if last.data == entry.data && last.files == entry.files {
+                return false;
+            }
+        }
+        entry.id = self.next_id();
+        let entries = self.entries.entry(peer).or_default();
+        entries.push_back(entry);
+        truncate(entries);
+        true
+    }
+
+    fn find(&self, id: i64) -> Option<&HistoryEntry> {
+        self.entries
+            .values()
+            .flat_map(|entries| entries.iter())
+            .find(|e| e.id == id)
+    }
+}
+
+fn truncate(entries: &mut VecDeque<HistoryEntry>) {
+    let size = get_history_size();
+    while entries.len() > size {
+        entries.pop_front();
+    }
+}
+
+fn encrypt(data: &[u8]) -> String {
+    crate::encode64(encrypt_vec_or_original(data, "00", MAX_ENTRY_SIZE * 2))
+}
+
+// Writes the history of `peer`. The writes are serialized and each one takes the latest
+// entries, so an older history never overwrites a newer one.
+fn save(peer: &str) {
+    if !is_persist_enabled() {
+        return;
+    }
+    let _lock = SAVE_LOCK.lock().unwrap();
+    let persisted = HISTORY.lock().unwrap().persisted(peer);
+    allow_err!(hbb_common::config::store_path(
+        history_path(peer),
+        persisted
+    ));
+}
+
+fn history_path(peer: &str) -> PathBuf {
+    let name = if peer.is_empty() {
+        LOCAL_HISTORY_NAME.to_owned()
+    } else {
+        // Prefix peer ids, so a peer can never be mixed up with the local history.
+        let id: String = peer
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        format!("peer_{}", id)
+    };
+    Config::path("clipboard_history").join(format!("{}.toml", name))
+}
+
+#[inline]
+fn is_persist_enabled() -> bool {
+    LocalConfig::get_option(OPTION_HISTORY_PERSIST) == "Y"
+}
+
+#[inline]
+fn get_history_size() -> usize {
+    LocalConfig::get_option(OPTION_HISTORY_SIZE)
+        .parse()
+        .unwrap_or(DEFAULT_HISTORY_SIZE)
+}
+
+fn get_allowed_formats() -> Vec<String> {
+    let mut formats = LocalConfig::get_option(OPTION_HISTORY_FORMATS);
+    if formats.is_empty() {
+        formats = DEFAULT_HISTORY_FORMATS.to_owned();
+    }
+    formats
+        .split(',')
+        .map(|s| s.trim().to_lowercase())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+fn format_name(c: &Clipboard) -> &'static str {
+    match c.format.enum_value() {
+        Ok(ClipboardFormat::Text) => "text",
+        Ok(ClipboardFormat::Rtf) => "rtf",
+        Ok(ClipboardFormat::Html) => "html",
+        Ok(ClipboardFormat::ImageRgba)
+        | Ok(ClipboardFormat::ImagePng)
+        | Ok(ClipboardFormat::ImageSvg) => "image",
+        _ => "special",
+    }
+}
+
+// The formats of the clipboards, in order and without duplicates.
+fn entry_formats(clipboards: &[Clipboard]) -> Vec<String> {
+    let mut seen = HashSet::new();
+    clipboards
+        .iter()
+        .map(format_name)
+        .filter(|f| *f != "special" && seen.insert(*f))
+        .map(|f| f.to_owned())
+        .collect()
+}
+
+fn get_preview(clipboards: &[Clipboard]) -> String {
+    if let Some(c) = clipboards
+        .iter()
+        .find(|c| c.format.enum_value() == Ok(ClipboardFormat::Text))
+    {
+        let content = if c.compress {
+            decompress(&c.content)
+        } else {
+            c.content.to_vec()
+        };
+        let text = String::from_utf8_lossy(&content);
+        let mut preview: String = text.chars().take(PREVIEW_LEN).collect();
+        if text.chars().count() > PREVIEW_LEN {
+            preview.push_str("...");
+        }
+        return preview;
+    }
+    if let Some(c) = clipboards.iter().find(|c| format_name(c) == "image") {
+        return format!("image {}x{}", c.width, c.height);
+    }
+    "".to_owned()
+}
+
+/// Records the clipboards copied locally (`peer` is empty) or received from `peer`.
+pub fn record(peer: &str, clipboards: &[Clipboard]) {
+    let allowed = get_allowed_formats();
+    let clipboards: Vec<Clipboard> = clipboards
+        .iter()
+        .filter(|c| {
+            let name = format_name(c);
+            // Special formats are kept along with the formats they belong to, e.g. Excel XML with html.
+            (name == "special" && allowed.iter().any(|f| f == "html"))
+                || allowed.iter().any(|f| f == name)
+        })
+        .cloned()
+        .collect();
+    if clipboards.iter().all(|c| format_name(c) == "special") {
+        return;
+    }
+    let multi_clipboards = MultiClipboards {
+        clipboards,
+        ..Default::default()
+    };
+    let data = match multi_clipboards.write_to_bytes() {
+        Ok(data) => data,
+        Err(e) => {
+            log::error!("Failed to serialize clipboard history entry, {}", e);
+            return;
+        }
+    };
+    if data.len() > MAX_ENTRY_SIZE {
+        log::debug!("Skip clipboard history entry of {} bytes", data.len());
+        return;
+    }
+    // Encrypted before locking the history, it is the slow part of saving.
+    let encrypted = if is_persist_enabled() {
+        encrypt(&data)
+    } else {
+        "".to_owned()
+    };
+    let pushed = HISTORY.lock().unwrap().push(HistoryEntry {
+        peer: peer.to_owned(),
+        time: get_time(),
+        formats: entry_formats(&multi_clipboards.clipboards),
+        preview: get_preview(&multi_clipboards.clipboards),
+        data,
+        encrypted,
+        ..Default::default()
+    });
+    if pushed {
+        save(peer);
+    }
+}
+
+/// Records the file list copied locally.
+pub fn record_files(peer: &str, files: Vec<String>) {
+    if files.is_empty() || !get_allowed_formats().iter().any(|f| f == "files") {
+        return;
+    }
+    let pushed = HISTORY.lock().unwrap().push(HistoryEntry {
+        peer: peer.to_owned(),
+        time: get_time(),
+        formats: vec!["files".to_owned()],
+        preview: files.join("\n").chars().take(PREVIEW_LEN).collect(),
+        files,
+        ..Default::default()
+    });
+    if pushed {
+        save(peer);
+    }
+}
+
+/// Returns the local history and the history of `peer`, the newest first.
+pub fn get_history(peer: &str) -> Vec<HistoryEntry> {
+    let mut lock = HISTORY.lock().unwrap();
+    lock.ensure_loaded("");
+    lock.ensure_loaded(peer);
+    let mut entries = lock
+        .entries
+        .iter()
+        .filter(|(p, _)| p.is_empty() || *p == peer)
+        .flat_map(|(_, entries)| entries.iter().cloned())
+        .collect::<Vec<_>>();
+    entries.sort_by(|a, b| b.id.cmp(&a.id));
+    entries
+}
+
+pub fn remove(id: i64) {
+    let mut peer = None;
+    for (p, entries) in HISTORY.lock().unwrap().entries.iter_mut() {
+        if let Some(pos) = entries.iter().position(|e| e.id == id) {
+            entries.remove(pos);
+            peer = Some(p.clone());
+            break;
+        }
+    }
+    if let Some(peer) = peer {
+        save(&peer);
+    }
+}
+
+pub fn clear(peer: &str) {
+    let _lock = SAVE_LOCK.lock().unwrap();
+    let mut lock = HISTORY.lock().unwrap();
+    lock.entries.remove(peer);
+    lock.loaded.insert(peer.to_owned());
+    std::fs::remove_file(history_path(peer)).ok();
+}
+
+fn get_multi_clipboards(id: i64) -> Option<MultiClipboards> {
+    let lock = HISTORY.lock().unwrap();
+    let entry = lock.find(id)?;
+    if entry.data.is_empty() {
+        return None;
+    }
+    MultiClipboards::parse_from_bytes(&entry.data).ok()
+}
+
+/// Builds the message to push the history entry to the peer.
+pub fn get_entry_msg(id: i64, peer_version: &str, peer_platform: &str) -> Option<Message> {
+    let multi_clipboards = get_multi_clipboards(id)?;
+    if let Some(msg) = crate::clipboard::get_msg_if_not_support_multi_clip(
+        peer_version,
+        peer_platform,
+        &multi_clipboards,
+    ) {
+        return Some(msg);
+    }
+    let mut msg = Message::new();
+    msg.set_multi_clipboards(multi_clipboards);
+    Some(msg)
+}
+
+/// Builds the message to offer the files of the history entry to the peer, as if they were
+/// copied again.
+#[cfg(feature = "unix-file-copy-paste")]
+pub fn get_files_msg(id: i64) -> Option<Message> {
+    let files = HISTORY.lock().unwrap().find(id)?.files.clone();
+    if files.is_empty() {
+        return None;
+    }
+    if let Err(e) = clipboard::platform::unix::serv_files::sync_files(&files) {
+        log::error!("Failed to sync clipboard history files: {}", e);
+        return None;
+    }
+    Some(crate::clipboard_file::clip_2_msg(
+        crate::clipboard_file::unix_file_clip::get_format_list(),
+    ))
+}
+
+/// Sets the local clipboard to the history entry.
+pub fn set_local_clipboard(id: i64) {
+    if let Some(multi_clipboards) = get_multi_clipboards(id) {
+        crate::clipboard::update_clipboard(
+            multi_clipboards.clipboards,
+            crate::clipboard::ClipboardSide::Client,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clipboard(format: ClipboardFormat, content: &str) -> Clipboard {
+        Clipboard {
+            format: format.into(),
+            content: content.as_bytes().to_vec().into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_entry_formats() {
+        let clipboards = vec![
+            clipboard(ClipboardFormat::Text, "a"),
+            clipboard(ClipboardFormat::Html, "<b>a</b>"),
+            clipboard(ClipboardFormat::Special, "x"),
+            clipboard(ClipboardFormat::Text, "b"),
+        ];
+        assert_eq!(entry_formats(&clipboards), vec!["text", "html"]);
+    }
+
+    #[test]
+    fn test_push() {
+        let mut history = ClipboardHistory::default();
+        let entry = |data: &[u8]| HistoryEntry {
+            peer: "test".to_owned(),
+            data: data.to_vec(),
+            ..Default::default()
+        };
+        assert!(history.push(entry(b"a")));
+        assert!(!history.push(entry(b"a")));
+        assert!(history.push(entry(b"b")));
+        for i in 0..DEFAULT_HISTORY_SIZE {
+            history.push(entry(&i.to_le_bytes()));
+        }
+        let entries = &history.entries["test"];
+        assert!(entries.len() <= get_history_size());
+        assert!(entries
+            .iter()
+            .zip(entries.iter().skip(1))
+            .all(|(a, b)| a.id < b.id));
+        if !is_persist_enabled() {
+            // Loaded as soon as persistence is turned on.
+            assert!(history.loaded.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_preview() {
+        let text = "x".repeat(PREVIEW_LEN + 1);
+        let preview = get_preview(&[clipboard(ClipboardFormat::Text, &text)]);
+        assert_eq!(preview, format!("{}...", "x".repeat(PREVIEW_LEN)));
+    }
+}
@@ -63,6 +63,122 @@ pub const DEFAULT_KEEP_ALIVE: i32 = 60_000;
 
 const MIN_VER_MULTI_UI_SESSION: &str = "1.2.4";
 
+// The window sharing messages are carried in json by `PluginRequest` with this id.
+pub const WINDOW_SHARE_REQUEST_ID: &str = "window-share";
+
+#[derive(Debug, Clone, serde_derive::Serialize, serde_derive::Deserialize)]
+pub struct ShareableWindow {
+    pub id: u64,
+    pub title: String,
+    pub display: usize,
+}
+
+#[derive(Debug, Clone, serde_derive::Serialize, serde_derive::Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum WindowShareMessage {
+    // Client to server
+    List,
+    Share { display: usize, window: u64 },
+    Stop { display: usize },
+    // Server to client
+    Windows { windows: Vec<ShareableWindow> },
+    Error { msg: String },
+}
+
+pub fn make_window_share_msg(msg: &WindowShareMessage) -> Message {
+    make_plugin_request_msg(WINDOW_SHARE_REQUEST_ID, msg)
+}
+
+// The custom messages between the peers, carried in json by `PluginRequest` with the id.
+pub fn make_plugin_request_msg<T: serde::Serialize + ?Sized>(id: &str, content: &T) -> Message {
+    let mut misc = Misc::new();
+    misc.set_plugin_request(PluginRequest {
+        id: id.to_owned(),
+        content: serde_json::to_vec(content).unwrap_or_default().into(),
+        ..Default::default()
+    });
+    let mut msg_out = Message::new();
+    msg_out.set_misc(misc);
+    msg_out
+}
+
+// Wake-on-LAN through an online peer, carried in json by `PluginRequest` with this id.
+pub const WOL_RELAY_REQUEST_ID: &str = "wol-relay";
+
+#[derive(Debug, Clone, serde_derive::Serialize, serde_derive::Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum WolRelayMessage {
+    // Client to server, the mac and ip addresses of the sleeping peer known by the client.
+    Request {
+        id: String,
+        macs: Vec<String>,
+        ips: Vec<String>,
+    },
+    // Server to client, `error` is empty if the magic packet is sent.
+    Result {
+        id: String,
+        error: String,
+    },
+}
+
+pub fn make_wol_relay_msg(msg: &WolRelayMessage) -> Message {
+    make_plugin_request_msg(WOL_RELAY_REQUEST_ID, msg)
+}
+
+// The optional features understood by the client, sent in json by `PluginRequest` with this id
+// once logged in. The missing fields are the features of the older clients.
+pub const CAPABILITIES_REQUEST_ID: &str = "capabilities";
+
+#[derive(Debug, Clone, Default, serde_derive::Serialize, serde_derive::Deserialize)]
+pub struct Capabilities {
+    #[serde(default)]
+    pub lossless_tiles: bool,
+}
+
+pub fn make_capabilities_msg(capabilities: &Capabilities) -> Message {
+    make_plugin_request_msg(CAPABILITIES_REQUEST_ID, capabilities)
+}
+
+// The session templates of the headless desktops, sent in json by `PluginRequest` with this id
+// before the client is asked for the os login.
+pub const SESSION_TEMPLATES_REQUEST_ID: &str = "session-templates";
+
+pub fn make_session_templates_msg(templates: &[String]) -> Message {
+    make_plugin_request_msg(SESSION_TEMPLATES_REQUEST_ID, templates)
+}
+
+pub const AUDIO_REQUEST_ID: &str = "audio";
+
+#[derive(Debug, Clone, serde_derive::Serialize, serde_derive::Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AudioMessage {
+    // Client to server
+    ListApps,
+    // `None` to capture the whole audio again.
+    CaptureApp {
+        app: Option<String>,
+    },
+    // Both directions, the server confirms with the same message.
+    MicPassthrough {
+        enable: bool,
+    },
+    Quality {
+        hifi: bool,
+    },
+    // Server to client
+    Apps {
+        apps: Vec<String>,
+        current: Option<String>,
+    },
+    Error {
+        msg: String,
+    },
+}
+
+pub fn make_audio_msg(msg: &AudioMessage) -> Message {
+    make_plugin_request_msg(AUDIO_REQUEST_ID, msg)
+}
+
 pub mod input {
     pub const MOUSE_TYPE_MOVE: i32 = 0;
     pub const MOUSE_TYPE_DOWN: i32 = 1;
@@ -881,6 +997,17 @@ pub fn increase_port<T: std::string::ToString>(host: T, offset: i32) -> String {
     hbb_common::socket_client::increase_port(host, offset)
 }
 
+#[inline]
+pub fn get_direct_port() -> i32 {
+    let mut port = Config::get_option("direct-access-port")
+        .parse::<i32>()
+        .unwrap_or(0);
+    if port <= 0 {
+        port = RENDEZVOUS_PORT + 2;
+    }
+    port
+}
+
 pub const POSTFIX_SERVICE: &'static str = "_service";
 
 #[inline]
@@ -535,6 +535,13 @@ pub fn core_main() -> Option<Vec<String>> {
         } else if args[0] == "--server" {
             log::info!("start --server with user {}", crate::username());
             #[cfg(target_os = "linux")]
+            if let Some((display_num, _)) =
+                crate::platform::linux_desktop_manager::get_session_server()
+            {
+                crate::server::start_headless_session_server(display_num);
+                return None;
+            }
+            #[cfg(target_os = "linux")]
             {
                 hbb_common::allow_err!(crate::platform::check_autostart_config());
                 std::process::Command::new("pkill")
@@ -759,6 +766,22 @@ pub fn core_main() -> Option<Vec<String>> {
                 println!("Installation and administrative privileges required!");
             }
             return None;
+        } else if args[0] == "--rendezvous-server" {
+            let port = crate::rendezvous_server::parse_port(&args[1..]);
+            if let Err(err) = crate::rendezvous_server::start(port) {
+                log::error!("Failed to start the rendezvous server: {}", err);
+                println!("{}", err);
+            }
+            return None;
+        } else if args[0] == "--diagnose" {
+            crate::client::diagnose::handle_cli(&args[1..]);
+            return None;
+        } else if args[0] == "--bwe-replay" {
+            crate::server::bandwidth_estimator::handle_cli(&args[1..]);
+            return None;
+        } else if args[0] == "--transfer-queue" {
+            crate::client::transfer_queue::handle_cli(&args[1..]);
+            return None;
         } else if args[0] == "--check-hwcodec-config" {
             #[cfg(feature = "hwcodec")]
             crate::ipc::hwcodec_process();
@@ -0,0 +1,178 @@
+// TLS with mutual certificate authentication of the direct access.
+//
+// If `direct-access-tls` is enabled, the direct server only accepts the TLS connections with
+// a client certificate issued by the configured CA, so the other clients can not even reach
+// the login stage. The identity of the client certificate is recorded in the connection audit.
+//
+// Server options: `direct-access-tls`, `direct-access-tls-cert`, `direct-access-tls-key`
+// and `direct-access-tls-ca`, the paths of the PEM files.
+// Client (local) options: `direct-access-tls-client-cert`, `direct-access-tls-client-key` and
+// `direct-access-tls-ca`, the identity used for the peers requiring TLS.
+// Peer options: `direct-access-tls` to connect to the peer with TLS, and optionally
+// `direct-access-tls-server-name` if the server certificate is not issued for the ip address.
+
+use hbb_common::{
+    anyhow::anyhow,
+    bail,
+    bytes_codec::BytesCodec,
+    config::{self, Config, LocalConfig, PeerConfig},
+    log,
+    sha2::{Digest, Sha256},
+    socket_client::connect_tcp_local,
+    tcp::{DynTcpStream, FramedStream},
+    timeout,
+    tokio::{
+        io::{AsyncRead, AsyncWrite},
+        net::TcpStream,
+    },
+    tokio_util, ResultType, Stream,
+};
+use std::{net::SocketAddr, sync::Arc};
+use tokio_rustls::{
+    rustls::{
+        pki_types::{CertificateDer, PrivateKeyDer, ServerName},
+        server::WebPkiClientVerifier,
+        ClientConfig, RootCertStore, ServerConfig,
+    },
+    TlsAcceptor, TlsConnector,
+};
+
+const OPTION_TLS: &str = "direct-access-tls";
+const OPTION_CERT: &str = "direct-access-tls-cert";
+const OPTION_KEY: &str = "direct-access-tls-key";
+const OPTION_CA: &str = "direct-access-tls-ca";
+const OPTION_CLIENT_CERT: &str = "direct-access-tls-client-cert";
+const OPTION_CLIENT_KEY: &str = "direct-access-tls-client-key";
+const OPTION_SERVER_NAME: &str = "direct-access-tls-server-name";
+
+const HANDSHAKE_TIMEOUT: u64 = 5_000;
+
+fn load_certs(path: &str) -> ResultType<Vec<CertificateDer<'static>>> {
+    let mut reader = std::io::BufReader::new(std::fs::File::open(path)?);
+    let certs = rustls_pemfile::certs(&mut reader).collect::<Result<Vec<_>, _>>()?;
+    if certs.is_empty() {
+        bail!("No certificate found in {}", path);
+    }
+    Ok(certs)
+}
+
+fn load_key(path: &str) -> ResultType<PrivateKeyDer<'static>> {
+    let mut reader = std::io::BufReader::new(std::fs::File::open(path)?);
+    rustls_pemfile::private_key(&mut reader)?.ok_or(anyhow!("No private key found in {}", path))
+}
+
+fn load_roots(path: &str) -> ResultType<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(path)? {
+        roots.add(cert)?;
+    }
+    Ok(roots)
+}
+
+/// Returns the identity of the certificate, `CN=<common name>, sha256=<fingerprint>`.
+fn get_identity(cert: &CertificateDer) -> String {
+    let cn = x509_parser::parse_x509_certificate(cert.as_ref())
+        .ok()
+        .and_then(|(_, c)| {
+            c.subject()
+                .iter_common_name()
+                .next()
+                .and_then(|cn| cn.as_str().ok().map(|s| s.to_owned()))
+        })
+        .unwrap_or_default();
+    let fingerprint = hex::encode(Sha256::digest(cert.as_ref()));
+    format!("CN={}, sha256={}", cn, fingerprint)
+}
+
+pub(crate) fn create_framed(
+    stream: impl AsyncRead + AsyncWrite + Unpin + Send + Sync + 'static,
+    local_addr: SocketAddr,
+) -> Stream {
+    Stream::Tcp(FramedStream(
+        tokio_util::codec::Framed::new(DynTcpStream(Box::new(stream)), BytesCodec::new()),
+        local_addr,
+        None,
+        0,
+    ))
+}
+
+#[inline]
+pub fn is_server_enabled() -> bool {
+    config::option2bool(OPTION_TLS, &Config::get_option(OPTION_TLS))
+}
+
+/// The options the acceptor is created from, to check if the acceptor should be recreated.
+pub fn get_server_options() -> String {
+    if !is_server_enabled() {
+        return "".to_owned();
+    }
+    [OPTION_CERT, OPTION_KEY, OPTION_CA]
+        .iter()
+        .map(|k| Config::get_option(k))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+pub fn new_acceptor() -> ResultType<TlsAcceptor> {
+    let roots = load_roots(&Config::get_option(OPTION_CA))?;
+    let verifier = WebPkiClientVerifier::builder(Arc::new(roots)).build()?;
+    let config = ServerConfig::builder()
+        .with_client_cert_verifier(verifier)
+        .with_single_cert(
+            load_certs(&Config::get_option(OPTION_CERT))?,
+            load_key(&Config::get_option(OPTION_KEY))?,
+        )?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// Completes the TLS handshake of the direct access, the client certificate is verified.
+///
+/// Returns the stream and the identity of the client certificate.
+pub async fn accept(
+    acceptor: &TlsAcceptor,
+    stream: TcpStream,
+    local_addr: SocketAddr,
+    addr: SocketAddr,
+) -> ResultType<(Stream, String)> {
+    let stream = timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await??;
+    let identity = stream
+        .get_ref()
+        .1
+        .peer_certificates()
+        .and_then(|certs| certs.first())
+        .map(get_identity)
+        .unwrap_or_default();
+    log::info!("direct access tls client {}: {}", addr, identity);
+    Ok((create_framed(stream, local_addr), identity))
+}
+
+fn new_connector() -> ResultType<TlsConnector> {
+    let roots = load_roots(&LocalConfig::get_option(OPTION_CA))?;
+    let config = ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_client_auth_cert(
+            load_certs(&LocalConfig::get_option(OPTION_CLIENT_CERT))?,
+            load_key(&LocalConfig::get_option(OPTION_CLIENT_KEY))?,
+        )?;
+    Ok(TlsConnector::from(Arc::new(config)))
+}
+
+/// Connects to the direct access of the peer `id`, with TLS if it is enabled in the peer options.
+pub async fn connect(id: &str, addr: &str, ms_timeout: u64) -> ResultType<Stream> {
+    let options = PeerConfig::load(id).options;
+    let get = |k: &str| options.get(k).cloned().unwrap_or_default();
+    if !config::option2bool(OPTION_TLS, &get(OPTION_TLS)) {
+        return connect_tcp_local(addr, None, ms_timeout).await;
+    }
+    let connector = new_connector()?;
+    let stream = timeout(ms_timeout, TcpStream::connect(addr)).await??;
+    stream.set_nodelay(true).ok();
+    let local_addr = stream.local_addr()?;
+    let mut server_name = get(OPTION_SERVER_NAME);
+    if server_name.is_empty() {
+        server_name = stream.peer_addr()?.ip().to_string();
+    }
+    let server_name = ServerName::try_from(server_name)?;
+    let stream = timeout(HANDSHAKE_TIMEOUT, connector.connect(server_name, stream)).await??;
+    Ok(create_framed(stream, local_addr))
+}
@@ -712,6 +712,7 @@ impl InvokeUiSession for FlutterHandler {
                     &status.codec_format.map_or(NULL, |it| it.to_string()),
                 ),
                 ("chroma", &status.chroma.map_or(NULL, |it| it.to_string())),
+                ("transport", &status.transport.map_or(NULL, |it| it)),
             ],
             &[],
         );
@@ -760,6 +761,38 @@ impl InvokeUiSession for FlutterHandler {
         self.push_event("load_last_job", &[("value", job_json)], &[]);
     }
 
+    fn update_transfer_queue(&self, queue_json: &str) {
+        self.push_event("update_transfer_queue", &[("value", queue_json)], &[]);
+    }
+
+    fn update_session_templates(&self, templates_json: &str) {
+        self.push_event(
+            "update_session_templates",
+            &[("value", templates_json)],
+            &[],
+        );
+    }
+
+    fn update_shareable_windows(&self, windows_json: &str) {
+        self.push_event("update_shareable_windows", &[("value", windows_json)], &[]);
+    }
+
+    fn update_audio_apps(&self, apps_json: &str) {
+        self.push_event("update_audio_apps", &[("value", apps_json)], &[]);
+    }
+
+    fn update_mic_passthrough(&self, enabled: bool) {
+        self.push_event("update_mic_passthrough", &[("value", enabled)], &[]);
+    }
+
+    fn update_input_macro(&self, recording: bool, playing: bool) {
+        self.push_event(
+            "update_input_macro",
+            &[("recording", recording), ("playing", playing)],
+            &[],
+        );
+    }
+
     fn update_folder_files(
         &self,
         id: i32,
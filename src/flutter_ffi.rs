@@ -348,6 +348,79 @@ pub fn session_take_screenshot(session_id: SessionID, display: usize) {
     }
 }
 
+pub fn session_request_shareable_windows(session_id: SessionID) {
+    if let Some(s) = sessions::get_session_by_session_id(&session_id) {
+        s.request_shareable_windows();
+    }
+}
+
+pub fn session_share_window(session_id: SessionID, display: usize, window: u64) {
+    if let Some(s) = sessions::get_session_by_session_id(&session_id) {
+        s.share_window(display, window);
+    }
+}
+
+pub fn session_stop_sharing_window(session_id: SessionID, display: usize) {
+    if let Some(s) = sessions::get_session_by_session_id(&session_id) {
+        s.stop_sharing_window(display);
+    }
+}
+
+pub fn session_request_audio_apps(session_id: SessionID) {
+    if let Some(s) = sessions::get_session_by_session_id(&session_id) {
+        s.request_audio_apps();
+    }
+}
+
+pub fn session_capture_app_audio(session_id: SessionID, app: String) {
+    if let Some(s) = sessions::get_session_by_session_id(&session_id) {
+        s.capture_app_audio(app);
+    }
+}
+
+pub fn session_set_mic_passthrough(session_id: SessionID, enable: bool) {
+    if let Some(s) = sessions::get_session_by_session_id(&session_id) {
+        s.set_mic_passthrough(enable);
+    }
+}
+
+pub fn session_start_input_macro_recording(_session_id: SessionID) {
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    if let Some(session) = sessions::get_session_by_session_id(&_session_id) {
+        session.start_input_macro_recording();
+    }
+}
+
+pub fn session_stop_input_macro_recording(_session_id: SessionID) -> String {
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    if let Some(session) = sessions::get_session_by_session_id(&_session_id) {
+        return session.stop_input_macro_recording();
+    }
+    "".to_owned()
+}
+
+pub fn session_check_input_macro(_session_id: SessionID, _script: String) -> String {
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    if let Some(session) = sessions::get_session_by_session_id(&_session_id) {
+        return session.check_input_macro(&_script);
+    }
+    "".to_owned()
+}
+
+pub fn session_play_input_macro(_session_id: SessionID, _script: String) {
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    if let Some(session) = sessions::get_session_by_session_id(&_session_id) {
+        session.play_input_macro(_script);
+    }
+}
+
+pub fn session_stop_input_macro(_session_id: SessionID) {
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    if let Some(session) = sessions::get_session_by_session_id(&_session_id) {
+        session.stop_input_macro();
+    }
+}
+
 pub fn session_handle_screenshot(#[allow(unused_variables)] session_id: SessionID, action: String) -> String {
     crate::client::screenshot::handle_screenshot(action)
 }
@@ -704,6 +777,36 @@ pub fn session_input_string(session_id: SessionID, value: String) {
     }
 }
 
+pub fn session_get_clipboard_history(_session_id: SessionID) -> String {
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    if let Some(session) = sessions::get_session_by_session_id(&_session_id) {
+        return session.get_clipboard_history();
+    }
+    "".to_owned()
+}
+
+pub fn session_push_clipboard_history(_session_id: SessionID, _entry_id: i64) {
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    if let Some(session) = sessions::get_session_by_session_id(&_session_id) {
+        session.push_clipboard_history(_entry_id);
+    }
+}
+
+pub fn main_set_clipboard_from_history(_entry_id: i64) {
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    crate::clipboard_history::set_local_clipboard(_entry_id);
+}
+
+pub fn main_remove_clipboard_history(_entry_id: i64) {
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    crate::clipboard_history::remove(_entry_id);
+}
+
+pub fn main_clear_clipboard_history(_peer_id: String) {
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    crate::clipboard_history::clear(&_peer_id);
+}
+
 // chat_client_mode
 pub fn session_send_chat(session_id: SessionID, text: String) {
     if let Some(session) = sessions::get_session_by_session_id(&session_id) {
@@ -929,6 +1032,47 @@ pub fn session_resume_job(session_id: SessionID, act_id: i32, is_remote: bool) {
     }
 }
 
+pub fn session_get_transfer_queue(session_id: SessionID) -> String {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.get_transfer_queue()
+    } else {
+        "".to_owned()
+    }
+}
+
+pub fn session_enqueue_transfer(
+    session_id: SessionID,
+    path: String,
+    to: String,
+    include_hidden: bool,
+    is_remote: bool,
+    priority: i32,
+) -> SyncReturn<i32> {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        SyncReturn(session.enqueue_transfer(path, to, include_hidden, is_remote, priority))
+    } else {
+        SyncReturn(-1)
+    }
+}
+
+pub fn session_remove_queued_transfer(session_id: SessionID, id: i32) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.remove_queued_transfer(id);
+    }
+}
+
+pub fn session_set_queued_transfer_priority(session_id: SessionID, id: i32, priority: i32) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.set_queued_transfer_priority(id, priority);
+    }
+}
+
+pub fn session_retry_queued_transfer(session_id: SessionID, id: i32) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.retry_queued_transfer(id);
+    }
+}
+
 pub fn session_rename_file(
     session_id: SessionID,
     act_id: i32,
@@ -1015,6 +1159,10 @@ pub fn main_show_option(_key: String) -> SyncReturn<bool> {
     if _key.eq(config::keys::OPTION_ALLOW_LINUX_HEADLESS) {
         return SyncReturn(true);
     }
+    #[cfg(target_os = "linux")]
+    if _key.eq(scrap::wayland::pipewire::OPTION_WAYLAND_HEADLESS) {
+        return SyncReturn(crate::platform::linux::current_is_wayland());
+    }
     SyncReturn(false)
 }
 
@@ -1957,6 +2105,10 @@ pub fn main_wol(id: String) {
     crate::lan::send_wol(id)
 }
 
+pub fn main_get_wol_status(id: String) -> SyncReturn<String> {
+    SyncReturn(crate::lan::get_wol_status(&id))
+}
+
 pub fn main_create_shortcut(_id: String) {
     #[cfg(windows)]
     create_shortcut(_id);
@@ -2524,6 +2676,28 @@ pub fn main_has_valid_2fa_sync() -> SyncReturn<bool> {
     SyncReturn(has_valid_2fa())
 }
 
+pub fn main_generate_2fa_backup_codes() -> String {
+    generate_2fa_backup_codes()
+}
+
+pub fn main_get_2fa_backup_codes_left() -> SyncReturn<usize> {
+    SyncReturn(get_2fa_backup_codes_left())
+}
+
+pub fn main_set_2fa_webhook(url: String, header: String) -> String {
+    set_2fa_webhook(url, header)
+}
+
+pub fn main_set_2fa_smtp(config: String) -> String {
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    return set_2fa_smtp(config);
+    #[cfg(any(target_os = "android", target_os = "ios"))]
+    {
+        let _ = config;
+        "".to_owned()
+    }
+}
+
 pub fn main_verify_bot(token: String) -> String {
     verify_bot(token)
 }
@@ -0,0 +1,274 @@
+// The high fidelity audio mode, chosen per session by the option `hifi-audio`.
+//
+// Standard: opus `LowDelay` with the default bitrate, mono or stereo, the sample rate follows the device.
+// Hi-fi: opus `Audio` at 48 kHz with a high bitrate, and up to 5.1 channels.
+//
+// magnum-opus does not expose the multistream api, so more than 2 channels are encoded as
+// independent stereo (and a last mono) streams, like what opus multistream does internally.
+// A frame is `[u16 le length][packet]` of each stream in the channel order.
+
+use hbb_common::{anyhow::anyhow, bail, ResultType};
+use magnum_opus::{Application, Bitrate, Channels, Decoder, Encoder};
+
+pub const OPTION_HIFI_AUDIO: &str = "hifi-audio";
+pub const HIFI_SAMPLE_RATE: u32 = 48000;
+pub const HIFI_MAX_CHANNELS: u16 = 6;
+// 256 kbps for stereo, 768 kbps for 5.1, transparent for music with opus.
+const HIFI_BITRATE_PER_CHANNEL: i32 = 128_000;
+// The playback waits for this much audio after an underrun, instead of playing the gaps.
+pub const HIFI_JITTER_BUFFER_MS: usize = 150;
+
+// The channels of each stream.
+fn stream_channels(channels: u16) -> Vec<u16> {
+    let mut v = vec![2; channels as usize / 2];
+    if channels % 2 == 1 {
+        v.push(1);
+    }
+    v
+}
+
+fn opus_channels(channels: u16) -> Channels {
+    if channels > 1 {
+        Channels::Stereo
+    } else {
+        Channels::Mono
+    }
+}
+
+pub enum AudioEncoder {
+    Opus(Encoder),
+    MultiStream(MultiStreamEncoder),
+}
+
+impl AudioEncoder {
+    pub fn new(sample_rate: u32, channels: u16, hifi: bool) -> ResultType<Self> {
+        if channels > 2 {
+            return Ok(Self::MultiStream(MultiStreamEncoder::new(
+                sample_rate,
+                channels,
+            )?));
+        }
+        let application = if hifi {
+            Application::Audio
+        } else {
+            Application::LowDelay
+        };
+        let mut encoder = Encoder::new(sample_rate, opus_channels(channels), application)?;
+        if hifi {
+            encoder.set_bitrate(Bitrate::Bits(HIFI_BITRATE_PER_CHANNEL * channels as i32))?;
+        }
+        Ok(Self::Opus(encoder))
+    }
+
+    pub fn encode_vec_float(&mut self, input: &[f32], max_size: usize) -> ResultType<Vec<u8>> {
+        match self {
+            Self::Opus(encoder) => Ok(encoder.encode_vec_float(input, max_size)?),
+            Self::MultiStream(encoder) => encoder.encode(input, max_size),
+        }
+    }
+}
+
+pub struct MultiStreamEncoder {
+    encoders: Vec<(Encoder, u16)>,
+    channels: usize,
+    buf: Vec<f32>,
+}
+
+impl MultiStreamEncoder {
+    fn new(sample_rate: u32, channels: u16) -> ResultType<Self> {
+        if channels > HIFI_MAX_CHANNELS {
+            bail!("Unsupported audio channels: {}", channels);
+        }
+        let mut encoders = vec![];
+        for ch in stream_channels(channels) {
+            let mut encoder = Encoder::new(sample_rate, opus_channels(ch), Application::Audio)?;
+            encoder.set_bitrate(Bitrate::Bits(HIFI_BITRATE_PER_CHANNEL * ch as i32))?;
+            encoders.push((encoder, ch));
+        }
+        Ok(Self {
+            encoders,
+            channels: channels as _,
+            buf: vec![],
+        })
+    }
+
+    fn encode(&mut self, input: &[f32], max_size: usize) -> ResultType<Vec<u8>> {
+        let frames = input.len() / self.channels;
+        let mut out = vec![];
+        let mut offset = 0;
+        for (encoder, ch) in self.encoders.iter_mut() {
+            let ch = *ch as usize;
+            self.buf.clear();
+            for frame in input.chunks_exact(self.channels).take(frames) {
+                self.buf.extend_from_slice(&frame[offset..offset + ch]);
+            }
+            let packet = encoder.encode_vec_float(&self.buf, max_size)?;
+            out.extend_from_slice(&(packet.len() as u16).to_le_bytes());
+            out.extend_from_slice(&packet);
+            offset += ch;
+        }
+        Ok(out)
+    }
+}
+
+pub enum AudioDecoder {
+    Opus(Decoder),
+    MultiStream(MultiStreamDecoder),
+}
+
+impl AudioDecoder {
+    pub fn new(sample_rate: u32, channels: u16) -> ResultType<Self> {
+        if channels > 2 {
+            return Ok(Self::MultiStream(MultiStreamDecoder::new(
+                sample_rate,
+                channels,
+            )?));
+        }
+        Ok(Self::Opus(Decoder::new(
+            sample_rate,
+            opus_channels(channels),
+        )?))
+    }
+
+    // Returns the number of samples per channel.
+    pub fn decode_float(
+        &mut self,
+        input: &[u8],
+        output: &mut [f32],
+        fec: bool,
+    ) -> ResultType<usize> {
+        match self {
+            Self::Opus(decoder) => Ok(decoder.decode_float(input, output, fec)?),
+            Self::MultiStream(decoder) => decoder.decode(input, output, fec),
+        }
+    }
+}
+
+pub struct MultiStreamDecoder {
+    decoders: Vec<(Decoder, u16)>,
+    channels: usize,
+    buf: Vec<f32>,
+}
+
+impl MultiStreamDecoder {
+    fn new(sample_rate: u32, channels: u16) -> ResultType<Self> {
+        if channels > HIFI_MAX_CHANNELS {
+            bail!("Unsupported audio channels: {}", channels);
+        }
+        let mut decoders = vec![];
+        for ch in stream_channels(channels) {
+            decoders.push((Decoder::new(sample_rate, opus_channels(ch))?, ch));
+        }
+        Ok(Self {
+            decoders,
+            channels: channels as _,
+            buf: vec![0.; sample_rate as usize * 2],
+        })
+    }
+
+    fn decode(&mut self, input: &[u8], output: &mut [f32], fec: bool) -> ResultType<usize> {
+        let mut input = input;
+        let mut offset = 0;
+        let mut frames = usize::MAX;
+        for (decoder, ch) in self.decoders.iter_mut() {
+            let ch = *ch as usize;
+            if input.len() < 2 {
+                bail!("Invalid multistream audio frame");
+            }
+            let len = u16::from_le_bytes([input[0], input[1]]) as usize;
+            let packet = input
+                .get(2..2 + len)
+                .ok_or_else(|| anyhow!("Invalid multistream audio frame"))?;
+            input = &input[2 + len..];
+            let n = decoder.decode_float(packet, &mut self.buf, fec)?;
+            // All streams have the same frame size, in case not.
+            frames = frames.min(n).min(output.len() / self.channels);
+            for i in 0..frames {
+                output[i * self.channels + offset..i * self.channels + offset + ch]
+                    .copy_from_slice(&self.buf[i * ch..(i + 1) * ch]);
+            }
+            offset += ch;
+        }
+        Ok(if frames == usize::MAX { 0 } else { frames })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 10 ms, channel `c` is a sine of amplitude `0.1 * (c + 1)`.
+    fn make_frame(index: usize, channels: u16) -> Vec<f32> {
+        let frames = HIFI_SAMPLE_RATE as usize / 100;
+        let mut v = Vec::with_capacity(frames * channels as usize);
+        for i in 0..frames {
+            let t = (index * frames + i) as f32 / HIFI_SAMPLE_RATE as f32;
+            for c in 0..channels {
+                v.push(0.1 * (c + 1) as f32 * (2. * std::f32::consts::PI * 440. * t).sin());
+            }
+        }
+        v
+    }
+
+    fn rms(data: &[f32], channels: usize, channel: usize) -> f32 {
+        let samples: Vec<f32> = data
+            .iter()
+            .skip(channel)
+            .step_by(channels)
+            .copied()
+            .collect();
+        (samples.iter().map(|x| x * x).sum::<f32>() / samples.len() as f32).sqrt()
+    }
+
+    #[test]
+    fn test_multistream_roundtrip() {
+        let channels = HIFI_MAX_CHANNELS;
+        let mut encoder = AudioEncoder::new(HIFI_SAMPLE_RATE, channels, true).unwrap();
+        let mut decoder = AudioDecoder::new(HIFI_SAMPLE_RATE, channels).unwrap();
+        assert!(matches!(encoder, AudioEncoder::MultiStream(_)));
+        let mut output = vec![0.; HIFI_SAMPLE_RATE as usize / 100 * channels as usize];
+        // The first frames are the delay of the codec.
+        for i in 0..20 {
+            let input = make_frame(i, channels);
+            let packet = encoder.encode_vec_float(&input, 4000).unwrap();
+            let n = decoder.decode_float(&packet, &mut output, false).unwrap();
+            assert_eq!(n * channels as usize, input.len());
+        }
+        // The channels stay in order.
+        for c in 0..channels as usize {
+            let expected = 0.1 * (c + 1) as f32 / std::f32::consts::SQRT_2;
+            let actual = rms(&output, channels as _, c);
+            assert!(
+                (actual - expected).abs() < expected * 0.2,
+                "channel {}: {} != {}",
+                c,
+                actual,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_multistream_malformed() {
+        let channels = 5;
+        let mut encoder = AudioEncoder::new(HIFI_SAMPLE_RATE, channels, true).unwrap();
+        let mut decoder = AudioDecoder::new(HIFI_SAMPLE_RATE, channels).unwrap();
+        let mut output = vec![0.; HIFI_SAMPLE_RATE as usize / 100 * channels as usize];
+        assert!(decoder.decode_float(&[], &mut output, false).is_err());
+        assert!(decoder.decode_float(&[1], &mut output, false).is_err());
+        // The length is beyond the frame.
+        assert!(decoder
+            .decode_float(&[0xff, 0xff, 0, 0], &mut output, false)
+            .is_err());
+        // The first stream only.
+        let packet = encoder
+            .encode_vec_float(&make_frame(0, channels), 4000)
+            .unwrap();
+        let len = u16::from_le_bytes([packet[0], packet[1]]) as usize;
+        assert!(decoder
+            .decode_float(&packet[..2 + len], &mut output, false)
+            .is_err());
+        assert!(decoder.decode_float(&packet, &mut output, false).is_ok());
+        assert!(AudioDecoder::new(HIFI_SAMPLE_RATE, HIFI_MAX_CHANNELS + 1).is_err());
+    }
+}
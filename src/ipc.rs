@@ -40,6 +40,16 @@ use crate::{common::is_server, privacy_mode, rendezvous_mediator::RendezvousMedi
 // IPC actions here.
 pub const IPC_ACTION_CLOSE: &str = "close";
 pub static EXIT_RECV_CLOSE: AtomicBool = AtomicBool::new(true);
+// The cm of each headless session has its own ipc, the postfix is inherited from the session server.
+pub const ENV_CM_POSTFIX: &str = "CM_IPC_POSTFIX";
+
+#[inline]
+pub fn cm_postfix() -> String {
+    std::env::var(ENV_CM_POSTFIX)
+        .ok()
+        .filter(|postfix| postfix.starts_with("_cm"))
+        .unwrap_or_else(|| "_cm".to_owned())
+}
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(tag = "t", content = "c")]
@@ -169,6 +179,8 @@ pub enum DataControl {
         miny: i32,
         maxy: i32,
     },
+    // Grab the physical input devices while the ipc connection is alive.
+    BlockInput(bool),
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -698,7 +710,10 @@ async fn handle(data: Data, stream: &mut Connection) {
             } else if value == "clear" {
                 set_local_option(key.clone(), "".to_owned());
                 #[cfg(target_os = "linux")]
-                scrap::wayland::pipewire::close_session();
+                {
+                    scrap::wayland::pipewire::clear_restore_tokens();
+                    scrap::wayland::pipewire::close_session();
+                }
                 Some("".to_owned())
             } else {
                 None
@@ -766,7 +781,7 @@ pub async fn connect(ms_timeout: u64, postfix: &str) -> ResultType<ConnectionTmp
 #[cfg(target_os = "linux")]
 #[tokio::main(flavor = "current_thread")]
 pub async fn start_pa() {
-    use crate::audio_service::AUDIO_DATA_SIZE_U8;
+    use crate::audio_service::{APP_AUDIO_PREFIX, AUDIO_DATA_SIZE_U8};
 
     match new_listener("_pa").await {
         Ok(mut incoming) => {
@@ -781,7 +796,23 @@ pub async fn start_pa() {
                             {
                                 device = x;
                             }
-                            if !device.is_empty() {
+                            let mut capture_app = None;
+                            if let Some(app) = device.strip_prefix(APP_AUDIO_PREFIX) {
+                                match crate::platform::linux::start_pa_app_capture(app) {
+                                    Ok(monitor) => {
+                                        capture_app = Some(app.to_owned());
+                                        device = monitor;
+                                    }
+                                    Err(err) => {
+                                        log::error!(
+                                            "Failed to capture the audio of {}, capture the whole audio: {}",
+                                            app,
+                                            err
+                                        );
+                                        device = "".to_owned();
+                                    }
+                                }
+                            } else if !device.is_empty() {
                                 device = crate::platform::linux::get_pa_source_name(&device);
                             }
                             if device.is_empty() {
@@ -798,6 +829,7 @@ pub async fn start_pa() {
                             log::info!("pa monitor: {:?}", device);
                             // systemctl --user status pulseaudio.service
                             let mut buf: Vec<u8> = vec![0; AUDIO_DATA_SIZE_U8];
+                            let mut read_count: usize = 0;
                             match psimple::Simple::new(
                                 None,                             // Use the default server
                                 &crate::get_app_name(),           // Our application’s name
@@ -809,6 +841,15 @@ pub async fn start_pa() {
                                 None, // Use default buffering attributes
                             ) {
                                 Ok(s) => loop {
+                                    // The new streams of the application, about once a second.
+                                    read_count += 1;
+                                    if read_count % 100 == 0 {
+                                        if let Some(app) = capture_app.as_ref() {
+                                            allow_err!(
+                                                crate::platform::linux::move_pa_app_streams(app)
+                                            );
+                                        }
+                                    }
                                     if let Ok(_) = s.read(&mut buf) {
                                         let out =
                                             if buf.iter().filter(|x| **x != 0).next().is_none() {
@@ -826,6 +867,9 @@ pub async fn start_pa() {
                                     log::error!("Could not create simple pulse: {}", err);
                                 }
                             }
+                            if capture_app.is_some() {
+                                crate::platform::linux::stop_pa_app_capture();
+                            }
                         }
                         Err(err) => {
                             log::error!("Couldn't get pa client: {:?}", err);
@@ -9,17 +9,117 @@ use hbb_common::{
 };
 use kcp_sys::{
     endpoint::KcpEndpoint,
+    ffi_safe::KcpConfig,
     packet_def::{KcpPacket, KcpPacketHeader},
     stream,
 };
-use std::{net::SocketAddr, sync::Arc};
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+pub const OPTION_KCP_PROFILE: &str = "kcp-profile";
+
+/// The tuning of the KCP endpoint, selected by the `kcp-profile` option.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum KcpProfile {
+    /// Retransmits early and flushes often, the default.
+    #[default]
+    LowLatency,
+    /// Retransmits on the first duplicate ack with larger windows, for the links dropping packets.
+    LossyLink,
+    /// Normal retransmission and congestion control with a slower flush, for metered links.
+    BandwidthSaver,
+}
+
+impl KcpProfile {
+    pub fn from_option(v: &str) -> Self {
+        match v {
+            "lossy-link" => Self::LossyLink,
+            "bandwidth-saver" => Self::BandwidthSaver,
+            _ => Self::LowLatency,
+        }
+    }
+
+    /// The profile of the client side.
+    pub fn get_local() -> Self {
+        Self::from_option(&config::LocalConfig::get_option(OPTION_KCP_PROFILE))
+    }
+
+    /// The profile of the controlled side.
+    pub fn get_server() -> Self {
+        Self::from_option(&config::Config::get_option(OPTION_KCP_PROFILE))
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::LowLatency => "low-latency",
+            Self::LossyLink => "lossy-link",
+            Self::BandwidthSaver => "bandwidth-saver",
+        }
+    }
+
+    fn config(&self, conv: u32) -> KcpConfig {
+        // nodelay, interval(ms), fast resend, no congestion control, send window, receive window
+        let (nodelay, interval, resend, nc, snd_wnd, rcv_wnd) = match self {
+            Self::LowLatency => (true, 10, 2, true, 1024, 1024),
+            Self::LossyLink => (true, 10, 1, true, 2048, 2048),
+            Self::BandwidthSaver => (false, 40, 0, false, 256, 256),
+        };
+        let mut cfg = KcpConfig::new(conv);
+        cfg.configure_nodelay(nodelay, interval, resend, nc);
+        cfg.configure_window(snd_wnd, rcv_wnd);
+        cfg
+    }
+
+    fn new_endpoint(&self) -> KcpEndpoint {
+        let profile = *self;
+        let mut endpoint = KcpEndpoint::new();
+        endpoint.set_kcp_config_factory(Box::new(move |conv| profile.config(conv)));
+        endpoint
+    }
+}
+
+/// The UDP packets and bytes carried by the KCP stream.
+#[derive(Debug, Default)]
+pub struct KcpStats {
+    pub sent_packets: AtomicU64,
+    pub sent_bytes: AtomicU64,
+    pub received_packets: AtomicU64,
+    pub received_bytes: AtomicU64,
+}
+
+impl KcpStats {
+    fn on_sent(&self, len: usize) {
+        self.sent_packets.fetch_add(1, Ordering::Relaxed);
+        self.sent_bytes.fetch_add(len as _, Ordering::Relaxed);
+    }
+
+    fn on_received(&self, len: usize) {
+        self.received_packets.fetch_add(1, Ordering::Relaxed);
+        self.received_bytes.fetch_add(len as _, Ordering::Relaxed);
+    }
+}
 
 pub struct KcpStream {
     _endpoint: KcpEndpoint,
     stop_sender: Option<oneshot::Sender<()>>,
+    profile: KcpProfile,
+    stats: Arc<KcpStats>,
 }
 
 impl KcpStream {
+    pub fn profile(&self) -> KcpProfile {
+        self.profile
+    }
+
+    pub fn stats(&self) -> Arc<KcpStats> {
+        self.stats.clone()
+    }
+
     fn create_framed(stream: stream::KcpStream, local_addr: Option<SocketAddr>) -> Stream {
         Stream::Tcp(FramedStream(
             tokio_util::codec::Framed::new(DynTcpStream(Box::new(stream)), BytesCodec::new()),
@@ -33,8 +133,9 @@ impl KcpStream {
         udp_socket: Arc<UdpSocket>,
         timeout: std::time::Duration,
         init_packet: Option<BytesMut>,
+        profile: KcpProfile,
     ) -> ResultType<(Self, Stream)> {
-        let mut endpoint = KcpEndpoint::new();
+        let mut endpoint = profile.new_endpoint();
         endpoint.run().await;
 
         let (input, output) = (
@@ -44,12 +145,20 @@ impl KcpStream {
                 .ok_or_else(|| anyhow::anyhow!("Failed to get output receiver"))?,
         );
         let (stop_sender, stop_receiver) = oneshot::channel();
+        let stats = Arc::new(KcpStats::default());
         if let Some(packet) = init_packet {
             if packet.len() >= std::mem::size_of::<KcpPacketHeader>() {
                 input.send(packet.into()).await?;
             }
         }
-        Self::kcp_io(udp_socket.clone(), input, output, stop_receiver).await;
+        Self::kcp_io(
+            udp_socket.clone(),
+            input,
+            output,
+            stop_receiver,
+            stats.clone(),
+        )
+        .await;
 
         let conn_id = tokio::time::timeout(timeout, endpoint.accept()).await??;
         if let Some(stream) = stream::KcpStream::new(&endpoint, conn_id) {
@@ -57,6 +166,8 @@ impl KcpStream {
                 Self {
                     _endpoint: endpoint,
                     stop_sender: Some(stop_sender),
+                    profile,
+                    stats,
                 },
                 Self::create_framed(stream, udp_socket.local_addr().ok()),
             ))
@@ -68,8 +179,9 @@ impl KcpStream {
     pub async fn connect(
         udp_socket: Arc<UdpSocket>,
         timeout: std::time::Duration,
+        profile: KcpProfile,
     ) -> ResultType<(Self, Stream)> {
-        let mut endpoint = KcpEndpoint::new();
+        let mut endpoint = profile.new_endpoint();
         endpoint.run().await;
 
         let (input, output) = (
@@ -79,7 +191,15 @@ impl KcpStream {
                 .ok_or_else(|| anyhow::anyhow!("Failed to get output receiver"))?,
         );
         let (stop_sender, stop_receiver) = oneshot::channel();
-        Self::kcp_io(udp_socket.clone(), input, output, stop_receiver).await;
+        let stats = Arc::new(KcpStats::default());
+        Self::kcp_io(
+            udp_socket.clone(),
+            input,
+            output,
+            stop_receiver,
+            stats.clone(),
+        )
+        .await;
 
         let conn_id = endpoint.connect(timeout, 0, 0, Bytes::new()).await?;
         if let Some(stream) = stream::KcpStream::new(&endpoint, conn_id) {
@@ -87,6 +207,8 @@ impl KcpStream {
                 Self {
                     _endpoint: endpoint,
                     stop_sender: Some(stop_sender),
+                    profile,
+                    stats,
                 },
                 Self::create_framed(stream, udp_socket.local_addr().ok()),
             ))
@@ -100,6 +222,7 @@ impl KcpStream {
         input: mpsc::Sender<KcpPacket>,
         mut output: mpsc::Receiver<KcpPacket>,
         mut stop_receiver: oneshot::Receiver<()>,
+        stats: Arc<KcpStats>,
     ) {
         let udp = udp_socket.clone();
         tokio::spawn(async move {
@@ -111,10 +234,12 @@ impl KcpStream {
                         break;
                     }
                     Some(data) = output.recv() => {
-                        if let Err(e) = udp.send(&data.inner()).await {
+                        let data = data.inner();
+                        if let Err(e) = udp.send(&data).await {
                             log::debug!("KCP send error: {:?}", e);
                             break;
                         }
+                        stats.on_sent(data.len());
                     }
                     result = udp.recv_from(&mut buf) => {
                         match result {
@@ -122,6 +247,7 @@ impl KcpStream {
                                 if size < std::mem::size_of::<KcpPacketHeader>() {
                                     continue;
                                 }
+                                stats.on_received(size);
                                 input
                                     .send(BytesMut::from(&buf[..size]).into())
                                     .await.ok();
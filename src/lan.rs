@@ -1,34 +1,102 @@
+use crate::client::Data;
 #[cfg(not(target_os = "ios"))]
 use hbb_common::whoami;
 use hbb_common::{
     allow_err,
     anyhow::bail,
     config::Config,
-    config::{self, RENDEZVOUS_PORT},
+    config::{self, LocalConfig, RENDEZVOUS_PORT},
+    futures::{self, StreamExt as _},
     log,
     protobuf::Message as _,
     rendezvous_proto::*,
+    socket_client::connect_tcp_local,
+    sodiumoxide::base64,
     tokio::{
         self,
         sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
     },
-    ResultType,
+    ResultType, Stream,
 };
+use serde_derive::{Deserialize, Serialize};
 
+use serde_json::json;
 use std::{
     collections::{HashMap, HashSet},
     net::{IpAddr, Ipv4Addr, SocketAddr, ToSocketAddrs, UdpSocket},
-    time::Instant,
+    sync::Mutex,
+    time::{Duration, Instant},
 };
 
 type Message = RendezvousMessage;
 
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+const MDNS_SERVICE_TYPE: &str = "_cloudydesk._tcp.local.";
+
+// Comma separated ipv4 CIDR ranges or addresses to scan, e.g. `10.1.0.0/24,10.2.3.4`.
+const OPTION_SCAN_RANGES: &str = "discovery-scan-ranges";
+// The direct access port of the peers to scan, the same as ours if empty.
+const OPTION_SCAN_PORT: &str = "discovery-scan-port";
+const MAX_SCAN_HOSTS: usize = 4096;
+const MAX_SCAN_CONCURRENCY: usize = 256;
+const SCAN_TIMEOUT: u64 = 500;
+// The first frame sent by us on the direct access port, before the peer speaks. It asks the peer
+// to secure the connection with its key, or `ping` asks the peer to identify itself.
+// The other clients send nothing first, see `accept_direct_request`.
+const CMD_DIRECT_SECURE: &str = "direct-secure";
+// The plain direct connections are delayed by it.
+#[cfg(not(target_os = "ios"))]
+const DIRECT_FIRST_FRAME_TIMEOUT: u64 = 300;
+
+// Wake-on-LAN through an online peer, which is on the same subnet as the sleeping peer.
+// The request is sent over a session to the relaying peer, which must have `allow-wol-relay` enabled.
+#[cfg(not(target_os = "ios"))]
+const OPTION_ALLOW_WOL_RELAY: &str = "allow-wol-relay";
+const WOL_RELAY_TIMEOUT: u64 = 2_000;
+const MAX_WOL_RELAY_TRIES: usize = 3;
+
+lazy_static::lazy_static! {
+    // id -> json of the last wol status
+    static ref WOL_STATUS: Mutex<HashMap<String, String>> = Default::default();
+    // id -> the sender of the logged in session to the peer, which can relay wol for us
+    static ref WOL_RELAY_SESSIONS: Mutex<HashMap<String, UnboundedSender<Data>>> = Default::default();
+    // id of the sleeping peer -> the sender of the relay result
+    static ref WOL_RELAY_RESULTS: Mutex<HashMap<String, std::sync::mpsc::Sender<(String, String)>>> = Default::default();
+}
+
+// The direct addresses of the peers found by the subnet scan, used to connect to the peers
+// without the rendezvous server.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct DirectProfiles {
+    #[serde(default)]
+    peers: HashMap<String, Vec<String>>,
+    // id -> base64 of the public key verified by the rendezvous server
+    #[serde(default)]
+    pks: HashMap<String, String>,
+}
+
+impl DirectProfiles {
+    fn path() -> std::path::PathBuf {
+        Config::path("direct_profiles.toml")
+    }
+
+    fn load() -> Self {
+        hbb_common::config::load_path(Self::path())
+    }
+
+    fn store(&self) {
+        allow_err!(hbb_common::config::store_path(Self::path(), self));
+    }
+}
+
 #[cfg(not(target_os = "ios"))]
 pub(super) fn start_listening() -> ResultType<()> {
     let addr = SocketAddr::from(([0, 0, 0, 0], get_broadcast_port()));
     let socket = std::net::UdpSocket::bind(addr)?;
     socket.set_read_timeout(Some(std::time::Duration::from_millis(1000)))?;
     log::info!("lan discovery listener started");
+    #[cfg(not(target_os = "android"))]
+    std::thread::spawn(start_mdns_advertising);
     loop {
         let mut buf = [0; 2048];
         if let Ok((len, addr)) = socket.recv_from(&mut buf) {
@@ -36,32 +104,15 @@ pub(super) fn start_listening() -> ResultType<()> {
                 match msg_in.union {
                     Some(rendezvous_message::Union::PeerDiscovery(p)) => {
                         if p.cmd == "ping"
-                            && config::option2bool(
-                                "enable-lan-discovery",
-                                &Config::get_option("enable-lan-discovery"),
-                            )
+                            && is_lan_discovery_enabled()
+                            && is_ip_whitelisted(&addr.ip())
                         {
                             let id = Config::get_id();
                             if p.id == id {
                                 continue;
                             }
                             if let Some(self_addr) = get_ipaddr_by_peer(&addr) {
-                                let mut msg_out = Message::new();
-                                let mut hostname = crate::whoami_hostname();
-                                // The default hostname is "localhost" which is a bit confusing
-                                if hostname == "localhost" {
-                                    hostname = "unknown".to_owned();
-                                }
-                                let peer = PeerDiscovery {
-                                    cmd: "pong".to_owned(),
-                                    mac: get_mac(&self_addr),
-                                    id,
-                                    hostname,
-                                    username: crate::platform::get_active_username(),
-                                    platform: whoami::platform().to_string(),
-                                    ..Default::default()
-                                };
-                                msg_out.set_peer_discovery(peer);
+                                let msg_out = new_pong(id, &self_addr);
                                 socket.send_to(&msg_out.write_to_bytes()?, addr).ok();
                             }
                         }
@@ -73,10 +124,434 @@ pub(super) fn start_listening() -> ResultType<()> {
     }
 }
 
+#[cfg(not(target_os = "ios"))]
+fn new_pong(id: String, self_addr: &IpAddr) -> Message {
+    let mut msg_out = Message::new();
+    let mut hostname = crate::whoami_hostname();
+    // The default hostname is "localhost" which is a bit confusing
+    if hostname == "localhost" {
+        hostname = "unknown".to_owned();
+    }
+    let peer = PeerDiscovery {
+        cmd: "pong".to_owned(),
+        mac: get_mac(self_addr),
+        id,
+        hostname,
+        username: crate::platform::get_active_username(),
+        platform: whoami::platform().to_string(),
+        ..Default::default()
+    };
+    msg_out.set_peer_discovery(peer);
+    msg_out
+}
+
+#[cfg(not(target_os = "ios"))]
+#[inline]
+fn is_lan_discovery_enabled() -> bool {
+    config::option2bool(
+        "enable-lan-discovery",
+        &Config::get_option("enable-lan-discovery"),
+    )
+}
+
+// The port advertised by mDNS, the direct access port if enabled, so that the peer can be
+// connected directly by ip. Otherwise the broadcast port, which is only used to identify us.
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+fn get_mdns_port() -> u16 {
+    use config::keys::OPTION_DIRECT_SERVER;
+    if config::option2bool(
+        OPTION_DIRECT_SERVER,
+        &Config::get_option(OPTION_DIRECT_SERVER),
+    ) {
+        crate::get_direct_port() as _
+    } else {
+        get_broadcast_port()
+    }
+}
+
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+fn new_mdns_service(id: &str, port: u16) -> ResultType<mdns_sd::ServiceInfo> {
+    let mut hostname = crate::whoami_hostname();
+    if hostname == "localhost" {
+        hostname = "unknown".to_owned();
+    }
+    // The mDNS host name must be a valid dns label.
+    let host_label: String = hostname
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect();
+    let properties = [
+        ("id", id.to_owned()),
+        ("hostname", hostname),
+        ("platform", whoami::platform().to_string()),
+        ("version", crate::VERSION.to_owned()),
+    ];
+    let service = mdns_sd::ServiceInfo::new(
+        MDNS_SERVICE_TYPE,
+        id,
+        &format!("{}.local.", host_label),
+        "",
+        port,
+        &properties[..],
+    )?
+    .enable_addr_auto();
+    Ok(service)
+}
+
+// Advertises ourselves via mDNS/DNS-SD, which can be seen across the broadcast domains
+// with an mDNS reflector, and by the standard tools like `avahi-browse` or `dns-sd`.
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+fn start_mdns_advertising() {
+    let daemon = match mdns_sd::ServiceDaemon::new() {
+        Ok(daemon) => daemon,
+        Err(e) => {
+            log::error!("Failed to start mDNS daemon: {}", e);
+            return;
+        }
+    };
+    // (id, port, full name) of the registered service.
+    let mut registered: Option<(String, u16, String)> = None;
+    loop {
+        let wanted = if is_lan_discovery_enabled() {
+            Some((Config::get_id(), get_mdns_port()))
+        } else {
+            None
+        };
+        let changed = match (&registered, &wanted) {
+            (Some((id, port, _)), Some((id2, port2))) => id != id2 || port != port2,
+            (None, None) => false,
+            _ => true,
+        };
+        if changed {
+            if let Some((_, _, fullname)) = registered.take() {
+                allow_err!(daemon.unregister(&fullname));
+                log::info!("mDNS service unregistered: {}", fullname);
+            }
+            if let Some((id, port)) = wanted {
+                match new_mdns_service(&id, port) {
+                    Ok(service) => {
+                        let fullname = service.get_fullname().to_owned();
+                        match daemon.register(service) {
+                            Ok(_) => {
+                                log::info!("mDNS service registered: {}, port: {}", fullname, port);
+                                registered = Some((id, port, fullname));
+                            }
+                            Err(e) => log::error!("Failed to register mDNS service: {}", e),
+                        }
+                    }
+                    Err(e) => log::error!("Failed to create mDNS service: {}", e),
+                }
+            }
+        }
+        std::thread::sleep(Duration::from_secs(3));
+    }
+}
+
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+fn mdns_browse(tx: UnboundedSender<config::DiscoveryPeer>) -> ResultType<()> {
+    let daemon = mdns_sd::ServiceDaemon::new()?;
+    let receiver = daemon.browse(MDNS_SERVICE_TYPE)?;
+    let my_id = Config::get_id();
+    let deadline = Instant::now() + Duration::from_millis(3_000);
+    while let Some(timeout) = deadline.checked_duration_since(Instant::now()) {
+        match receiver.recv_timeout(timeout) {
+            Ok(mdns_sd::ServiceEvent::ServiceResolved(info)) => {
+                let id = info
+                    .get_property_val_str("id")
+                    .unwrap_or_default()
+                    .to_owned();
+                if id.is_empty() || id == my_id {
+                    continue;
+                }
+                // The mac address is unknown, it is kept if we have got it by the broadcast.
+                let ip_mac = info
+                    .get_addresses()
+                    .iter()
+                    .filter(|ip| ip.is_ipv4())
+                    .map(|ip| (ip.to_string(), "".to_owned()))
+                    .collect::<HashMap<_, _>>();
+                allow_err!(tx.send(config::DiscoveryPeer {
+                    id,
+                    ip_mac,
+                    username: "".to_owned(),
+                    hostname: info
+                        .get_property_val_str("hostname")
+                        .unwrap_or_default()
+                        .to_owned(),
+                    platform: info
+                        .get_property_val_str("platform")
+                        .unwrap_or_default()
+                        .to_owned(),
+                    online: true,
+                }));
+            }
+            Ok(_) => {}
+            Err(_) => break,
+        }
+    }
+    allow_err!(daemon.stop_browse(MDNS_SERVICE_TYPE));
+    allow_err!(daemon.shutdown());
+    log::info!("mDNS browse done");
+    Ok(())
+}
+
+/// Reads the first frame of a connection on the direct access port, see `CMD_DIRECT_SECURE`.
+///
+/// Returns whether the connection is to be secured, or `None` if it is only a ping of the subnet
+/// scan, which is answered here, the connection should be closed then.
+#[cfg(not(target_os = "ios"))]
+pub(crate) async fn accept_direct_request(stream: &mut Stream, addr: SocketAddr) -> Option<bool> {
+    let Ok(Some(Ok(bytes))) = hbb_common::timeout(DIRECT_FIRST_FRAME_TIMEOUT, stream.next()).await
+    else {
+        return Some(false);
+    };
+    let msg_in = Message::parse_from_bytes(&bytes).ok();
+    match msg_in.and_then(|m| m.union) {
+        Some(rendezvous_message::Union::PeerDiscovery(p)) if p.cmd == CMD_DIRECT_SECURE => {
+            // The peer is not the one expected, the client fails to authenticate us.
+            Some(p.id == Config::get_id())
+        }
+        Some(rendezvous_message::Union::PeerDiscovery(p)) if p.cmd == "ping" => {
+            if is_lan_discovery_enabled() && is_ip_whitelisted(&canonical_ip(addr.ip())) {
+                let self_addr = canonical_ip(stream.local_addr().ip());
+                let msg_out = new_pong(Config::get_id(), &self_addr);
+                allow_err!(stream.send(&msg_out).await);
+            }
+            None
+        }
+        _ => {
+            log::warn!(
+                "Unexpected first message on the direct access from {}",
+                addr
+            );
+            Some(false)
+        }
+    }
+}
+
+// The direct access listener accepts both ipv4 and ipv6, ipv4 peers are seen as mapped addresses.
+#[cfg(not(target_os = "ios"))]
+#[inline]
+fn canonical_ip(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+        _ => ip,
+    }
+}
+
+// Same as the whitelist check of the connection, we should not reveal ourselves to blocked ips.
+#[cfg(not(target_os = "ios"))]
+fn is_ip_whitelisted(ip: &IpAddr) -> bool {
+    use cidr_utils::cidr::IpCidr;
+    let whitelist = Config::get_option("whitelist");
+    let whitelist: Vec<&str> = whitelist.split(',').filter(|x| !x.is_empty()).collect();
+    whitelist.is_empty()
+        || whitelist.iter().any(|x| *x == "0.0.0.0")
+        || whitelist
+            .iter()
+            .any(|x| IpCidr::from_str(x).map_or(false, |y| y.contains(*ip)))
+}
+
+// Returns the first and the last host of an ipv4 CIDR range or a single address.
+fn parse_scan_range(range: &str) -> Option<(u32, u32)> {
+    let (ip, bits) = match range.split_once('/') {
+        Some((ip, bits)) => (ip, bits.trim().parse::<u32>().ok()?),
+        None => (range, 32),
+    };
+    let ip = u32::from(ip.trim().parse::<Ipv4Addr>().ok()?);
+    if bits > 32 {
+        return None;
+    }
+    let mask = if bits == 0 {
+        0
+    } else {
+        u32::MAX << (32 - bits)
+    };
+    let first = ip & mask;
+    let last = first | !mask;
+    if bits >= 31 {
+        Some((first, last))
+    } else {
+        // Skip the network and broadcast addresses.
+        Some((first + 1, last - 1))
+    }
+}
+
+fn get_scan_addrs() -> Vec<SocketAddr> {
+    let port = LocalConfig::get_option(OPTION_SCAN_PORT)
+        .parse::<u16>()
+        .unwrap_or(crate::get_direct_port() as _);
+    let mut addrs = Vec::new();
+    for range in LocalConfig::get_option(OPTION_SCAN_RANGES)
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|x| !x.is_empty())
+    {
+        let Some((first, last)) = parse_scan_range(range) else {
+            log::warn!("Invalid scan range: {}", range);
+            continue;
+        };
+        for ip in first..=last {
+            if addrs.len() >= MAX_SCAN_HOSTS {
+                log::warn!(
+                    "Too many hosts to scan, only the first {} are scanned",
+                    MAX_SCAN_HOSTS
+                );
+                return addrs;
+            }
+            addrs.push(SocketAddr::from((Ipv4Addr::from(ip), port)));
+        }
+    }
+    addrs
+}
+
+// Identifies the peers by a ping on their direct access port, answered by the direct server,
+// so only the peers accepting the direct connections are found. The peers requiring TLS are not.
+#[tokio::main(flavor = "current_thread")]
+async fn scan(
+    addrs: Vec<SocketAddr>,
+    tx: UnboundedSender<config::DiscoveryPeer>,
+) -> ResultType<()> {
+    log::info!("scan {} hosts", addrs.len());
+    let my_id = Config::get_id();
+    let mut probes = futures::stream::iter(addrs)
+        .map(|addr| async move { (addr, probe_direct_port(addr).await) })
+        .buffer_unordered(MAX_SCAN_CONCURRENCY);
+    let mut updated: HashMap<String, Vec<String>> = HashMap::new();
+    while let Some((addr, res)) = probes.next().await {
+        let Ok(p) = res else {
+            continue;
+        };
+        if p.id.is_empty() || p.id == my_id {
+            continue;
+        }
+        let addrs = updated.entry(p.id.clone()).or_default();
+        if addrs.contains(&addr.to_string()) {
+            continue;
+        }
+        addrs.push(addr.to_string());
+        allow_err!(tx.send(config::DiscoveryPeer {
+            id: p.id,
+            ip_mac: HashMap::from([(addr.ip().to_string(), p.mac)]),
+            username: p.username,
+            hostname: p.hostname,
+            platform: p.platform,
+            online: true,
+        }));
+    }
+    log::info!("scan done, found {} peers", updated.len());
+    let mut profiles = DirectProfiles::load();
+    profiles.peers.extend(updated);
+    profiles.store();
+    Ok(())
+}
+
+async fn probe_direct_port(addr: SocketAddr) -> ResultType<PeerDiscovery> {
+    let mut stream = connect_tcp_local(addr, None, SCAN_TIMEOUT).await?;
+    let mut msg_out = Message::new();
+    msg_out.set_peer_discovery(PeerDiscovery {
+        cmd: "ping".to_owned(),
+        ..Default::default()
+    });
+    stream.send(&msg_out).await?;
+    if let Some(res) = hbb_common::timeout(SCAN_TIMEOUT, stream.next()).await? {
+        let msg_in = Message::parse_from_bytes(&res?)?;
+        if let Some(rendezvous_message::Union::PeerDiscovery(p)) = msg_in.union {
+            if p.cmd == "pong" {
+                return Ok(p);
+            }
+        }
+    }
+    bail!("No pong from {}", addr);
+}
+
+/// Returns the direct addresses of the peer found by the subnet scan.
+pub fn get_direct_addrs(id: &str) -> Vec<String> {
+    DirectProfiles::load().peers.remove(id).unwrap_or_default()
+}
+
+pub fn remove_direct_profile(id: &str) {
+    let mut profiles = DirectProfiles::load();
+    let removed = profiles.peers.remove(id).is_some();
+    if profiles.pks.remove(id).is_some() || removed {
+        profiles.store();
+    }
+}
+
+/// Remembers the public key of the peer verified by the rendezvous server,
+/// which is required to connect to the peer by the direct addresses.
+pub fn set_direct_pk(id: &str, pk: &[u8]) {
+    let pk = base64::encode(pk, base64::Variant::Original);
+    let mut profiles = DirectProfiles::load();
+    if profiles.pks.get(id) != Some(&pk) {
+        profiles.pks.insert(id.to_owned(), pk);
+        profiles.store();
+    }
+}
+
+/// Connects to the peer by the direct addresses found by the subnet scan, returns the stream and
+/// the public key of the peer. The address may have been taken by another peer, and the scan is
+/// not authenticated, so the peer is authenticated by the key exchange with its known public key.
+pub async fn connect_direct_profile(id: &str) -> Option<(Stream, Vec<u8>)> {
+    let mut profiles = DirectProfiles::load();
+    let addrs = profiles.peers.remove(id)?;
+    let Some(pk) = profiles
+        .pks
+        .remove(id)
+        .and_then(|pk| base64::decode(pk, base64::Variant::Original).ok())
+    else {
+        log::info!("The public key of {} is unknown, not connect directly", id);
+        return None;
+    };
+    for addr in addrs {
+        let Ok(addr) = addr.parse::<SocketAddr>() else {
+            continue;
+        };
+        match connect_direct_secure(id, &pk, addr).await {
+            Ok(stream) => {
+                log::info!("connect {} directly by {}", id, addr);
+                return Some((stream, pk));
+            }
+            Err(e) => log::warn!("Failed to connect {} directly by {}: {}", id, addr, e),
+        }
+    }
+    None
+}
+
+async fn connect_direct_secure(id: &str, pk: &[u8], addr: SocketAddr) -> ResultType<Stream> {
+    let mut stream =
+        crate::direct_tls::connect(id, &addr.to_string(), config::CONNECT_TIMEOUT).await?;
+    let mut msg_out = Message::new();
+    msg_out.set_peer_discovery(PeerDiscovery {
+        cmd: CMD_DIRECT_SECURE.to_owned(),
+        id: id.to_owned(),
+        ..Default::default()
+    });
+    hbb_common::timeout(config::CONNECT_TIMEOUT, stream.send(&msg_out)).await??;
+    crate::client::Client::secure_direct_connection(id, pk, &mut stream).await?;
+    Ok(stream)
+}
+
 #[tokio::main(flavor = "current_thread")]
 pub async fn discover() -> ResultType<()> {
-    let sockets = send_query()?;
-    let rx = spawn_wait_responses(sockets);
+    let (tx, rx) = unbounded_channel::<_>();
+    let scan_addrs = get_scan_addrs();
+    if !scan_addrs.is_empty() {
+        let tx_scan = tx.clone();
+        std::thread::spawn(move || {
+            allow_err!(scan(scan_addrs, tx_scan));
+        });
+    }
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    {
+        let tx_mdns = tx.clone();
+        std::thread::spawn(move || {
+            allow_err!(mdns_browse(tx_mdns));
+        });
+    }
+    match send_query() {
+        Ok(sockets) => spawn_wait_responses(sockets, tx),
+        Err(e) => log::error!("Failed to send discover ping: {}", e),
+    }
     handle_received_peers(rx).await?;
 
     log::info!("discover ping done");
@@ -85,9 +560,15 @@ pub async fn discover() -> ResultType<()> {
 
 pub fn send_wol(id: String) {
     let interfaces = default_net::get_interfaces();
+    let mut is_local = false;
     for peer in &config::LanPeers::load().peers {
         if peer.id == id {
-            for (_, mac) in peer.ip_mac.iter() {
+            for (ip, mac) in peer.ip_mac.iter() {
+                if let Ok(ip) = ip.parse::<Ipv4Addr>() {
+                    is_local |= interfaces
+                        .iter()
+                        .any(|i| i.ipv4.iter().any(|x| in_subnet(ip, x)));
+                }
                 if let Ok(mac_addr) = mac.parse() {
                     for interface in &interfaces {
                         for ipv4 in &interface.ipv4 {
@@ -102,6 +583,226 @@ pub fn send_wol(id: String) {
             break;
         }
     }
+    if is_local {
+        set_wol_status(&id, false, "", "");
+    } else {
+        // We are not on the same subnet, the magic packet above can not reach the peer.
+        set_wol_status(&id, true, "", "");
+        std::thread::spawn(move || match send_wol_relay(&id) {
+            Ok(relay) => set_wol_status(&id, false, &relay, ""),
+            Err(e) => {
+                log::error!("Failed to relay wol to {}: {}", id, e);
+                set_wol_status(&id, false, "", &e.to_string());
+            }
+        });
+    }
+}
+
+#[inline]
+fn in_subnet(ip: Ipv4Addr, net: &default_net::ip::Ipv4Net) -> bool {
+    (u32::from(ip) & u32::from(net.netmask)) == (u32::from(net.addr) & u32::from(net.netmask))
+}
+
+fn set_wol_status(id: &str, pending: bool, relay: &str, error: &str) {
+    let status = json!({
+        "id": id,
+        "pending": pending,
+        "relay": relay,
+        "error": error,
+    })
+    .to_string();
+    WOL_STATUS
+        .lock()
+        .unwrap()
+        .insert(id.to_owned(), status.clone());
+    #[cfg(feature = "flutter")]
+    {
+        let data = HashMap::from([("name", "wol_status".to_owned()), ("status", status)]);
+        let _res = crate::flutter::push_global_event(
+            crate::flutter::APP_TYPE_MAIN,
+            serde_json::ser::to_string(&data).unwrap_or("".to_owned()),
+        );
+    }
+}
+
+/// Returns the json of the last wol status of the peer, `{id, pending, relay, error}`.
+///
+/// `pending` is true while the relaying peers are being tried,
+/// `relay` is the id of the peer which has sent the magic packet on our behalf.
+pub fn get_wol_status(id: &str) -> String {
+    WOL_STATUS
+        .lock()
+        .unwrap()
+        .get(id)
+        .cloned()
+        .unwrap_or_default()
+}
+
+#[inline]
+fn common_prefix_len(a: Ipv4Addr, b: Ipv4Addr) -> u32 {
+    (u32::from(a) ^ u32::from(b)).leading_zeros()
+}
+
+/// Makes the logged in session to the peer available to relay wol.
+pub(crate) fn add_wol_relay_session(id: &str, sender: UnboundedSender<Data>) {
+    WOL_RELAY_SESSIONS
+        .lock()
+        .unwrap()
+        .insert(id.to_owned(), sender);
+}
+
+pub(crate) fn remove_wol_relay_session(id: &str, sender: &UnboundedSender<Data>) {
+    let mut sessions = WOL_RELAY_SESSIONS.lock().unwrap();
+    if sessions.get(id).map_or(false, |s| s.same_channel(sender)) {
+        sessions.remove(id);
+    }
+}
+
+/// Handles the wol relay result from the session to `relay`.
+pub(crate) fn on_wol_relay_result(relay: &str, id: &str, error: String) {
+    if let Some(tx) = WOL_RELAY_RESULTS.lock().unwrap().get(id) {
+        tx.send((relay.to_owned(), error)).ok();
+    }
+}
+
+// Asks an online peer to send the magic packet, over the sessions of this process, so that the
+// peer can be reached wherever we are. We do not know the subnets of the relaying peers, the
+// peers with the addresses closest to the sleeping peer's are tried first, see `LanPeers`.
+// The relaying peer checks its own subnets.
+fn send_wol_relay(id: &str) -> ResultType<String> {
+    let peers = config::LanPeers::load().peers;
+    let target = peers.iter().find(|p| p.id == id);
+    let mut ips: Vec<String> = target
+        .map(|p| p.ip_mac.keys().cloned().collect())
+        .unwrap_or_default();
+    for addr in get_direct_addrs(id) {
+        if let Ok(addr) = addr.parse::<SocketAddr>() {
+            if !ips.contains(&addr.ip().to_string()) {
+                ips.push(addr.ip().to_string());
+            }
+        }
+    }
+    let macs: Vec<String> = target
+        .map(|p| {
+            p.ip_mac
+                .values()
+                .filter(|mac| !mac.is_empty())
+                .cloned()
+                .collect()
+        })
+        .unwrap_or_default();
+    let target_ips: Vec<Ipv4Addr> = ips.iter().filter_map(|ip| ip.parse().ok()).collect();
+    let mut candidates: Vec<(u32, String, UnboundedSender<Data>)> = WOL_RELAY_SESSIONS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|(relay, _)| *relay != id)
+        .map(|(relay, sender)| {
+            let len = peers
+                .iter()
+                .filter(|p| p.id == *relay)
+                .flat_map(|p| p.ip_mac.keys())
+                .filter_map(|ip| ip.parse::<Ipv4Addr>().ok())
+                .flat_map(|ip| target_ips.iter().map(move |t| common_prefix_len(*t, ip)))
+                .max()
+                .unwrap_or(0);
+            (len, relay.clone(), sender.clone())
+        })
+        .collect();
+    candidates.sort_by(|a, b| b.0.cmp(&a.0));
+    if candidates.is_empty() {
+        bail!("No connected peer to relay the magic packet");
+    }
+
+    let msg_out = crate::common::make_wol_relay_msg(&crate::common::WolRelayMessage::Request {
+        id: id.to_owned(),
+        macs,
+        ips,
+    });
+    let (tx, rx) = std::sync::mpsc::channel();
+    WOL_RELAY_RESULTS.lock().unwrap().insert(id.to_owned(), tx);
+    let mut last_error = "".to_owned();
+    for (_, relay, sender) in candidates.into_iter().take(MAX_WOL_RELAY_TRIES) {
+        log::info!("Ask {} to send wol to {}", relay, id);
+        if sender.send(Data::Message(msg_out.clone())).is_err() {
+            last_error = "Session closed".to_owned();
+            continue;
+        }
+        last_error = "Timeout".to_owned();
+        let deadline = Instant::now() + Duration::from_millis(WOL_RELAY_TIMEOUT);
+        while let Ok((from, error)) =
+            rx.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+        {
+            if from != relay {
+                continue;
+            }
+            if error.is_empty() {
+                WOL_RELAY_RESULTS.lock().unwrap().remove(id);
+                return Ok(relay);
+            }
+            last_error = error;
+            break;
+        }
+        log::warn!("{} failed to relay wol: {}", relay, last_error);
+    }
+    WOL_RELAY_RESULTS.lock().unwrap().remove(id);
+    bail!("{}", last_error);
+}
+
+/// Sends the magic packet on behalf of the peer of the session, only on the interfaces on the
+/// same subnet as the sleeping peer `id`.
+#[cfg(not(target_os = "ios"))]
+pub(crate) fn handle_wol_relay(id: &str, macs: Vec<String>, ips: Vec<String>) -> ResultType<()> {
+    if !config::option2bool(
+        OPTION_ALLOW_WOL_RELAY,
+        &Config::get_option(OPTION_ALLOW_WOL_RELAY),
+    ) {
+        log::warn!("Reject wol relay to {}", id);
+        bail!("Not allowed");
+    }
+    let mut macs: HashSet<String> = macs.into_iter().filter(|x| !x.is_empty()).collect();
+    let mut ips: HashSet<Ipv4Addr> = ips.iter().filter_map(|x| x.parse().ok()).collect();
+    if let Some(peer) = config::LanPeers::load()
+        .peers
+        .into_iter()
+        .find(|x| x.id == id)
+    {
+        for (ip, mac) in peer.ip_mac {
+            if let Ok(ip) = ip.parse() {
+                ips.insert(ip);
+            }
+            if !mac.is_empty() {
+                macs.insert(mac);
+            }
+        }
+    }
+    if macs.is_empty() {
+        bail!("No mac address of the peer");
+    }
+    let local_ips: Vec<Ipv4Addr> = default_net::get_interfaces()
+        .iter()
+        .flat_map(|i| i.ipv4.iter())
+        .filter(|net| ips.iter().any(|ip| in_subnet(*ip, net)))
+        .map(|net| net.addr)
+        .collect();
+    if local_ips.is_empty() {
+        bail!("Not on the same subnet");
+    }
+    let mut sent = false;
+    for mac in macs {
+        if let Ok(mac_addr) = mac.parse() {
+            for ip in &local_ips {
+                log::info!("Relay wol to {mac_addr} of {} for {}", ip, id);
+                if wol::send_wol(mac_addr, None, Some(IpAddr::V4(*ip))).is_ok() {
+                    sent = true;
+                }
+            }
+        }
+    }
+    if !sent {
+        bail!("Failed to send the magic packet");
+    }
+    Ok(())
 }
 
 #[inline]
@@ -287,8 +988,7 @@ fn wait_response(
     Ok(())
 }
 
-fn spawn_wait_responses(sockets: Vec<UdpSocket>) -> UnboundedReceiver<config::DiscoveryPeer> {
-    let (tx, rx) = unbounded_channel::<_>();
+fn spawn_wait_responses(sockets: Vec<UdpSocket>, tx: UnboundedSender<config::DiscoveryPeer>) {
     for socket in sockets {
         let tx_clone = tx.clone();
         std::thread::spawn(move || {
@@ -299,7 +999,6 @@ fn spawn_wait_responses(sockets: Vec<UdpSocket>) -> UnboundedReceiver<config::Di
             ));
         });
     }
-    rx
 }
 
 async fn handle_received_peers(mut rx: UnboundedReceiver<config::DiscoveryPeer>) -> ResultType<()> {
@@ -318,7 +1017,13 @@ async fn handle_received_peers(mut rx: UnboundedReceiver<config::DiscoveryPeer>)
                     if let Some(pos) = peers.iter().position(|x| x.is_same_peer(&peer) ) {
                         let peer1 = peers.remove(pos);
                         if in_response_set {
-                            peer.ip_mac.extend(peer1.ip_mac);
+                            // Both the broadcast and mDNS may respond, keep the known mac addresses.
+                            for (ip, mac) in peer1.ip_mac {
+                                let v = peer.ip_mac.entry(ip).or_default();
+                                if v.is_empty() {
+                                    *v = mac;
+                                }
+                            }
                             peer.online = true;
                         }
                     }
@@ -342,3 +1047,35 @@ async fn handle_received_peers(mut rx: UnboundedReceiver<config::DiscoveryPeer>)
     crate::flutter_ffi::main_load_lan_peers();
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_scan_range() {
+        let ip = |s: &str| u32::from(s.parse::<Ipv4Addr>().unwrap());
+        assert_eq!(
+            parse_scan_range("192.168.1.77/24"),
+            Some((ip("192.168.1.1"), ip("192.168.1.254")))
+        );
+        assert_eq!(
+            parse_scan_range("10.0.0.5"),
+            Some((ip("10.0.0.5"), ip("10.0.0.5")))
+        );
+        assert_eq!(
+            parse_scan_range("10.0.0.4/31"),
+            Some((ip("10.0.0.4"), ip("10.0.0.5")))
+        );
+        assert_eq!(parse_scan_range("10.0.0.0/33"), None);
+        assert_eq!(parse_scan_range("fe80::1/64"), None);
+    }
+
+    #[test]
+    fn test_canonical_ip() {
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        assert_eq!(canonical_ip(ip("::ffff:10.0.0.5")), ip("10.0.0.5"));
+        assert_eq!(canonical_ip(ip("10.0.0.5")), ip("10.0.0.5"));
+        assert_eq!(canonical_ip(ip("fe80::1")), ip("fe80::1"));
+    }
+}
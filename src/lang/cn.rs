@@ -710,5 +710,40 @@ pub static ref T: std::collections::HashMap<&'static str, &'static str> =
         ("elevation_username_tip", "输入用户名或域名\\用户名"),
         ("Preparing for installation ...", "准备安装..."),
         ("Show my cursor", "显示我的光标"),
+        ("Clipboard history", ""),
+        ("clipboard-history-tip", ""),
+        ("2FA webhook", ""),
+        ("2FA email", ""),
+        ("enable-2fa-webhook-tip", ""),
+        ("enable-2fa-email-tip", ""),
+        ("Webhook URL", ""),
+        ("HTTP header", ""),
+        ("SMTP server", ""),
+        ("From", ""),
+        ("To", ""),
+        ("Backup codes", ""),
+        ("Backup codes left", ""),
+        ("Generate backup codes", ""),
+        ("backup-codes-tip", ""),
+        ("regenerate-backup-codes-tip", ""),
+        ("virtual_output_not_support_tip", ""),
+        ("session_limit_title_tip", ""),
+        ("session_limit_text_tip", ""),
+        ("Session template", ""),
+        ("privacy_mode_impl_linux_blank_tip", ""),
+        ("Allow unattended Wayland capture", ""),
+        ("Share window", ""),
+        ("Stop sharing window", ""),
+        ("Capture application audio", ""),
+        ("All audio", ""),
+        ("Microphone passthrough", ""),
+        ("Audio", ""),
+        ("High fidelity audio", ""),
+        ("Input macros", ""),
+        ("Record", ""),
+        ("Script", ""),
+        ("Stop recording macro", ""),
+        ("Stop input macro", ""),
+        ("input-macros-tip", ""),
     ].iter().cloned().collect();
 }
@@ -260,5 +260,15 @@ pub static ref T: std::collections::HashMap<&'static str, &'static str> =
         ("websocket_tip", "When using WebSocket, only relay connections are supported."),
         ("terminal-admin-login-tip", "Please input the administrator username and password of the controlled side."),
         ("elevation_username_tip", "Input username or domain\\username"),
+        ("clipboard-history-tip", "The contents copied locally or received from the remote device are listed here. Click an entry to send it to the remote device."),
+        ("enable-2fa-webhook-tip", "Post a one-time 2FA code to an HTTP endpoint for each login, e.g. a Slack or Teams incoming webhook or an SMS gateway."),
+        ("enable-2fa-email-tip", "Send a one-time 2FA code by email for each login. A test email is sent when saving."),
+        ("backup-codes-tip", "Keep these codes in a safe place. Each code can be used once instead of a 2FA code, they will not be shown again."),
+        ("regenerate-backup-codes-tip", "The current backup codes will no longer work. Do you want to continue?"),
+        ("virtual_output_not_support_tip", "No free virtual output. X11 needs a disconnected RandR output, Wayland needs a wlroots compositor (sway)."),
+        ("session_limit_title_tip", "Too many desktop sessions"),
+        ("session_limit_text_tip", "The maximum count of the concurrent desktop sessions is reached. Please try again later."),
+        ("privacy_mode_impl_linux_blank_tip", "Blank screen and block local input"),
+        ("input-macros-tip", "Text is typed as is. Tags: {ENTER}, {TAB}, {CTRL+ALT+DEL}, {+SHIFT}...{-SHIFT}, {WAIT:500} in milliseconds, {MOVE:x,y}, {LBUTTON}, {RBUTTON}. Use {{ and }} for the brackets."),
     ].iter().cloned().collect();
 }
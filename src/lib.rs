@@ -3,7 +3,8 @@ mod keyboard;
 pub mod platform;
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 pub use platform::{
-    get_cursor, get_cursor_data, get_cursor_pos, get_focused_display, start_os_service,
+    get_active_window_rect, get_cursor, get_cursor_data, get_cursor_pos, get_focused_display,
+    start_os_service,
 };
 #[cfg(not(any(target_os = "ios")))]
 /// cbindgen:ignore
@@ -37,16 +38,24 @@ pub mod flutter;
 pub mod flutter_ffi;
 use common::*;
 mod auth_2fa;
+mod direct_tls;
+mod hifi_audio;
 #[cfg(feature = "cli")]
 pub mod cli;
 #[cfg(not(target_os = "ios"))]
 mod clipboard;
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+mod clipboard_dlp;
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+mod clipboard_history;
 #[cfg(not(any(target_os = "android", target_os = "ios", feature = "cli")))]
 pub mod core_main;
 mod custom_server;
 mod lang;
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 mod port_forward;
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+mod rendezvous_server;
 
 #[cfg(all(feature = "flutter", feature = "plugin_framework"))]
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
@@ -74,7 +83,8 @@ pub mod clipboard_file;
 
 pub mod privacy_mode;
 
-#[cfg(windows)]
+#[cfg(any(windows, target_os = "linux"))]
 pub mod virtual_display_manager;
 
 mod kcp_stream;
+mod quic_stream;
@@ -63,6 +63,7 @@ extern "C" {
     ) -> c_int;
     fn xdo_new(display: *const c_char) -> Xdo;
     fn xdo_get_active_window(xdo: Xdo, window: *mut *mut c_void) -> c_int;
+    fn xdo_activate_window(xdo: Xdo, window: *mut c_void) -> c_int;
     fn xdo_get_window_location(
         xdo: Xdo,
         window: *mut c_void,
@@ -106,7 +107,9 @@ pub struct xcb_xfixes_get_cursor_image {
 
 #[inline]
 pub fn is_headless_allowed() -> bool {
+    // The server of a headless session only captures its own session.
     Config::get_option(OPTION_ALLOW_LINUX_HEADLESS) == "Y"
+        && super::linux_desktop_manager::get_session_server().is_none()
 }
 
 #[inline]
@@ -141,6 +144,16 @@ pub fn get_cursor_pos() -> Option<(i32, i32)> {
 pub fn reset_input_cache() {}
 
 pub fn get_focused_display(displays: Vec<DisplayInfo>) -> Option<usize> {
+    let (x, y, width, height) = get_active_window_rect()?;
+    let center_x = x + width / 2;
+    let center_y = y + height / 2;
+    displays.iter().position(|d| {
+        center_x >= d.x && center_x < d.x + d.width && center_y >= d.y && center_y < d.y + d.height
+    })
+}
+
+/// (x, y, width, height) of the active window.
+pub fn get_active_window_rect() -> Option<(i32, i32, i32, i32)> {
     let mut res = None;
     XDO.with(|xdo| {
         if let Ok(xdo) = xdo.try_borrow_mut() {
@@ -170,20 +183,184 @@ pub fn get_focused_display(displays: Vec<DisplayInfo>) -> Option<usize> {
                 if xdo_get_window_size(*xdo, window, &mut width as _, &mut height as _) != 0 {
                     return;
                 }
-                let center_x = x + width / 2;
-                let center_y = y + height / 2;
-                res = displays.iter().position(|d| {
-                    center_x >= d.x
-                        && center_x < d.x + d.width
-                        && center_y >= d.y
-                        && center_y < d.y + d.height
-                });
+                res = Some((x, y, width, height));
             }
         }
     });
     res
 }
 
+/// The top-level windows with a title, (id, title), X11 only.
+/// The windows of the Wayland compositors are not exposed to other clients.
+pub fn get_windows() -> Vec<(u64, String)> {
+    if !is_x11() {
+        return vec![];
+    }
+    // "_NET_CLIENT_LIST(WINDOW): window id # 0x1e00003, 0x2200007"
+    let ids = run_cmds("xprop -root _NET_CLIENT_LIST").unwrap_or_default();
+    let Some((_, ids)) = ids.split_once('#') else {
+        return vec![];
+    };
+    ids.split(',')
+        .filter_map(|id| u64::from_str_radix(id.trim().trim_start_matches("0x"), 16).ok())
+        .filter_map(|id| {
+            // "_NET_WM_NAME(UTF8_STRING) = "Title""
+            let name = run_cmds(&format!("xprop -id {} _NET_WM_NAME", id)).ok()?;
+            let (_, title) = name.split_once(" = ")?;
+            let title = title.trim().trim_matches('"').to_owned();
+            if title.is_empty() {
+                None
+            } else {
+                Some((id, title))
+            }
+        })
+        .collect()
+}
+
+/// (x, y, width, height) of the window, `None` if it is closed.
+pub fn get_window_rect(id: u64) -> Option<(i32, i32, i32, i32)> {
+    let mut res = None;
+    XDO.with(|xdo| {
+        if let Ok(xdo) = xdo.try_borrow_mut() {
+            if xdo.is_null() {
+                return;
+            }
+            let mut x: c_int = 0;
+            let mut y: c_int = 0;
+            let mut width: c_int = 0;
+            let mut height: c_int = 0;
+            let window = id as usize as *mut c_void;
+            unsafe {
+                if xdo_get_window_location(
+                    *xdo,
+                    window,
+                    &mut x as _,
+                    &mut y as _,
+                    std::ptr::null_mut(),
+                ) != 0
+                {
+                    return;
+                }
+                if xdo_get_window_size(*xdo, window, &mut width as _, &mut height as _) != 0 {
+                    return;
+                }
+            }
+            if width > 0 && height > 0 {
+                res = Some((x, y, width, height));
+            }
+        }
+    });
+    res
+}
+
+pub fn get_active_window_id() -> Option<u64> {
+    let mut res = None;
+    XDO.with(|xdo| {
+        if let Ok(xdo) = xdo.try_borrow_mut() {
+            if xdo.is_null() {
+                return;
+            }
+            let mut window: *mut c_void = std::ptr::null_mut();
+            unsafe {
+                if xdo_get_active_window(*xdo, &mut window) == 0 && !window.is_null() {
+                    res = Some(window as usize as u64);
+                }
+            }
+        }
+    });
+    res
+}
+
+pub fn activate_window(id: u64) -> bool {
+    let mut res = false;
+    XDO.with(|xdo| {
+        if let Ok(xdo) = xdo.try_borrow_mut() {
+            if !xdo.is_null() {
+                res = unsafe { xdo_activate_window(*xdo, id as usize as *mut c_void) } == 0;
+            }
+        }
+    });
+    res
+}
+
+/// The content of a window through X Composite, the windows above it are not included.
+/// The X errors are returned by x11rb, they do not abort the process as the default Xlib handler does.
+pub struct WindowCapture {
+    conn: x11rb::rust_connection::RustConnection,
+    window: u32,
+}
+
+impl WindowCapture {
+    pub fn new(id: u64) -> ResultType<Self> {
+        use x11rb::protocol::composite::{ConnectionExt as _, Redirect};
+        let (conn, _) = x11rb::connect(None)?;
+        conn.composite_query_version(0, 4)?.reply()?;
+        let window = id as u32;
+        // Automatic, the X server still draws the window on the screen.
+        conn.composite_redirect_window(window, Redirect::AUTOMATIC)?
+            .check()?;
+        Ok(Self { conn, window })
+    }
+
+    /// BGRA pixels without padding, returns (width, height).
+    pub fn frame(&mut self, data: &mut Vec<u8>) -> ResultType<(usize, usize)> {
+        use x11rb::{
+            connection::Connection,
+            protocol::{
+                composite::ConnectionExt as _,
+                xproto::{ConnectionExt as _, ImageFormat},
+            },
+        };
+        let geometry = self.conn.get_geometry(self.window)?.reply()?;
+        if geometry.depth != 24 && geometry.depth != 32 {
+            bail!("Unsupported window depth {}", geometry.depth);
+        }
+        // The pixmap is replaced when the window is resized, name it for each frame.
+        let pixmap = self.conn.generate_id()?;
+        self.conn
+            .composite_name_window_pixmap(self.window, pixmap)?
+            .check()?;
+        let border = geometry.border_width as i16;
+        let image = self.conn.get_image(
+            ImageFormat::Z_PIXMAP,
+            pixmap,
+            border,
+            border,
+            geometry.width,
+            geometry.height,
+            !0,
+        );
+        self.conn.free_pixmap(pixmap)?;
+        let image = image?.reply()?;
+        let (width, height) = (geometry.width as usize, geometry.height as usize);
+        let len = width * height * 4;
+        if image.data.len() < len {
+            bail!(
+                "Invalid window image, {} bytes for {}x{}",
+                image.data.len(),
+                width,
+                height
+            );
+        }
+        data.clear();
+        data.extend_from_slice(&image.data[..len]);
+        Ok((width, height))
+    }
+}
+
+impl Drop for WindowCapture {
+    fn drop(&mut self) {
+        use x11rb::{
+            connection::Connection,
+            protocol::composite::{ConnectionExt as _, Redirect},
+        };
+        self.conn
+            .composite_unredirect_window(self.window, Redirect::AUTOMATIC)
+            .ok();
+        self.conn.flush().ok();
+    }
+}
+
 pub fn get_cursor() -> ResultType<Option<u64>> {
     let mut res = None;
     DISPLAY.with(|conn| {
@@ -837,6 +1014,175 @@ pub fn get_default_pa_source() -> Option<(String, String)> {
     None
 }
 
+// The null sink that the streams of the captured application are moved to.
+pub const PA_APP_CAPTURE_SINK: &str = "cloudydesk_app_capture";
+// The null sink played by the peer's microphone, and the source remapped from its monitor.
+pub const PA_VIRTUAL_MIC_SINK: &str = "cloudydesk_mic";
+const PA_VIRTUAL_MIC_SOURCE: &str = "cloudydesk_mic_source";
+
+lazy_static::lazy_static! {
+    // sink name -> the indexes of the loaded modules, in the loading order
+    static ref PA_MODULES: std::sync::Mutex<std::collections::HashMap<&'static str, Vec<String>>> = Default::default();
+}
+
+// The modules of the sink, by the arguments in `pactl list short modules`,
+// "<index>\t<name>\t<arguments>\t<used by>".
+fn find_pa_modules(list: &str, sink: &str) -> Vec<String> {
+    let sink_name = format!("sink_name={}", sink);
+    let monitor = format!("={}.monitor", sink);
+    list.lines()
+        .filter_map(|line| {
+            let mut fields = line.split('\t');
+            let id = fields.next()?.trim();
+            let args = fields.nth(1)?;
+            args.split_whitespace()
+                .any(|arg| arg == sink_name || arg.ends_with(&monitor))
+                .then(|| id.to_owned())
+        })
+        .collect()
+}
+
+// The modules left by a crashed or killed process, the new sink would get another name.
+fn unload_stale_pa_modules(key: &str) {
+    let Ok(list) = run_cmds("pactl list short modules") else {
+        return;
+    };
+    let ids = find_pa_modules(&list, key);
+    if ids.is_empty() {
+        return;
+    }
+    // The loopback and remap modules before the sink.
+    for id in ids.iter().rev() {
+        allow_err!(run_cmds(&format!("pactl unload-module {}", id)));
+    }
+    log::info!("Unloaded stale pulseaudio modules of {}: {:?}", key, ids);
+}
+
+fn load_pa_modules(key: &'static str, modules: &[String]) -> ResultType<()> {
+    let mut lock = PA_MODULES.lock().unwrap();
+    if lock.contains_key(key) {
+        return Ok(());
+    }
+    unload_stale_pa_modules(key);
+    let mut loaded: Vec<String> = vec![];
+    for module in modules {
+        match run_cmds(&format!("pactl load-module {}", module)) {
+            Ok(id) if id.trim().parse::<u32>().is_ok() => loaded.push(id.trim().to_owned()),
+            res => {
+                for id in loaded.iter().rev() {
+                    allow_err!(run_cmds(&format!("pactl unload-module {}", id)));
+                }
+                bail!("Failed to load pulseaudio module \"{}\": {:?}", module, res);
+            }
+        }
+    }
+    log::info!("Loaded pulseaudio modules of {}: {:?}", key, loaded);
+    lock.insert(key, loaded);
+    Ok(())
+}
+
+fn unload_pa_modules(key: &'static str) {
+    if let Some(ids) = PA_MODULES.lock().unwrap().remove(key) {
+        for id in ids.iter().rev() {
+            allow_err!(run_cmds(&format!("pactl unload-module {}", id)));
+        }
+        log::info!("Unloaded pulseaudio modules of {}: {:?}", key, ids);
+    }
+}
+
+// The application names of the playback streams, except ours.
+pub fn get_pa_applications() -> Vec<String> {
+    use pulsectl::controllers::*;
+    let mut out: Vec<String> = Vec::new();
+    match SinkController::create() {
+        Ok(mut handler) => {
+            if let Ok(apps) = handler.list_applications() {
+                let me = crate::get_app_name();
+                for app in apps {
+                    if let Some(name) = app.proplist.get_str("application.name") {
+                        if name != me && !out.contains(&name) {
+                            out.push(name);
+                        }
+                    }
+                }
+            }
+        }
+        Err(err) => {
+            log::error!("Failed to get_pa_applications: {:?}", err);
+        }
+    }
+    out
+}
+
+// Move the streams of `app` to the capture sink, the new streams need to be moved again.
+pub fn move_pa_app_streams(app: &str) -> ResultType<()> {
+    use pulsectl::controllers::*;
+    let mut handler = SinkController::create().map_err(|e| anyhow!("{:?}", e))?;
+    let sink = handler
+        .get_device_by_name(PA_APP_CAPTURE_SINK)
+        .map_err(|e| anyhow!("{:?}", e))?;
+    let apps = handler
+        .list_applications()
+        .map_err(|e| anyhow!("{:?}", e))?;
+    for a in apps {
+        if a.sink != sink.index && a.proplist.get_str("application.name").as_deref() == Some(app) {
+            allow_err!(handler
+                .move_app_by_name(a.index, PA_APP_CAPTURE_SINK)
+                .map_err(|e| anyhow!("{:?}", e)));
+        }
+    }
+    Ok(())
+}
+
+// Returns the monitor source to record.
+// The capture sink is looped back to the default sink, so the application is still heard locally.
+pub fn start_pa_app_capture(app: &str) -> ResultType<String> {
+    load_pa_modules(
+        PA_APP_CAPTURE_SINK,
+        &[
+            format!(
+                "module-null-sink sink_name={} sink_properties=device.description={}-App-Capture",
+                PA_APP_CAPTURE_SINK,
+                crate::get_app_name()
+            ),
+            format!(
+                "module-loopback source={}.monitor latency_msec=30",
+                PA_APP_CAPTURE_SINK
+            ),
+        ],
+    )?;
+    move_pa_app_streams(app)?;
+    Ok(format!("{}.monitor", PA_APP_CAPTURE_SINK))
+}
+
+// The moved streams go back to the default sink when the capture sink is unloaded.
+pub fn stop_pa_app_capture() {
+    unload_pa_modules(PA_APP_CAPTURE_SINK);
+}
+
+pub fn load_pa_virtual_mic() -> ResultType<()> {
+    load_pa_modules(
+        PA_VIRTUAL_MIC_SINK,
+        &[
+            format!(
+                "module-null-sink sink_name={} sink_properties=device.description={}-Microphone-Sink",
+                PA_VIRTUAL_MIC_SINK,
+                crate::get_app_name()
+            ),
+            format!(
+                "module-remap-source master={}.monitor source_name={} source_properties=device.description={}-Microphone",
+                PA_VIRTUAL_MIC_SINK,
+                PA_VIRTUAL_MIC_SOURCE,
+                crate::get_app_name()
+            ),
+        ],
+    )
+}
+
+pub fn unload_pa_virtual_mic() {
+    unload_pa_modules(PA_VIRTUAL_MIC_SINK);
+}
+
 pub fn lock_screen() {
     Command::new("xdg-screensaver").arg("lock").spawn().ok();
 }
@@ -859,7 +1205,7 @@ pub fn is_installed() -> bool {
 }
 
 #[inline]
-fn get_env(name: &str, uid: &str, process: &str) -> String {
+pub fn get_env(name: &str, uid: &str, process: &str) -> String {
     let cmd = format!("ps -u {} -f | grep -E '{}' | grep -v 'grep' | tail -1 | awk '{{print $2}}' | xargs -I__ cat /proc/__/environ 2>/dev/null | tr '\\0' '\\n' | grep '^{}=' | tail -1 | sed 's/{}=//g'", uid, process, name, name);
     if let Ok(x) = run_cmds(&cmd) {
         x.trim_end().to_string()
@@ -1612,3 +1958,23 @@ pub fn is_selinux_enforcing() -> bool {
         },
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_pa_modules() {
+        let list = "\
+0\tmodule-device-restore\t\t
+21\tmodule-null-sink\tsink_name=cloudydesk_mic sink_properties=device.description=CloudyDesk-Microphone-Sink\t
+22\tmodule-remap-source\tmaster=cloudydesk_mic.monitor source_name=cloudydesk_mic_source\t
+23\tmodule-null-sink\tsink_name=cloudydesk_app_capture\t
+24\tmodule-loopback\tsource=cloudydesk_app_capture.monitor latency_msec=30\t
+25\tmodule-null-sink\tsink_name=cloudydesk_mic_2\t
+";
+        assert_eq!(find_pa_modules(list, PA_VIRTUAL_MIC_SINK), vec!["21", "22"]);
+        assert_eq!(find_pa_modules(list, PA_APP_CAPTURE_SINK), vec!["23", "24"]);
+        assert!(find_pa_modules("", PA_APP_CAPTURE_SINK).is_empty());
+    }
+}
@@ -1,14 +1,29 @@
 use super::{linux::*, ResultType};
 use crate::client::{
     LOGIN_MSG_DESKTOP_NO_DESKTOP, LOGIN_MSG_DESKTOP_SESSION_ANOTHER_USER,
-    LOGIN_MSG_DESKTOP_SESSION_NOT_READY, LOGIN_MSG_DESKTOP_XORG_NOT_FOUND,
-    LOGIN_MSG_DESKTOP_XSESSION_FAILED,
+    LOGIN_MSG_DESKTOP_SESSION_LIMIT, LOGIN_MSG_DESKTOP_SESSION_NOT_READY,
+    LOGIN_MSG_DESKTOP_XORG_NOT_FOUND, LOGIN_MSG_DESKTOP_XSESSION_FAILED,
+};
+use hbb_common::{
+    allow_err, bail,
+    config::{Config, LocalConfig},
+    log,
+    rand::prelude::*,
+    tokio::{
+        net::{UnixListener, UnixStream},
+        time,
+    },
+    toml,
 };
-use hbb_common::{allow_err, bail, log, rand::prelude::*, tokio::time};
 use pam;
+use serde_derive::Deserialize;
 use std::{
     collections::HashMap,
-    os::unix::process::CommandExt,
+    fs::{File, OpenOptions, Permissions},
+    os::unix::{
+        fs::{OpenOptionsExt, PermissionsExt},
+        process::CommandExt,
+    },
     path::Path,
     process::{Child, Command},
     sync::{
@@ -20,27 +35,92 @@ use std::{
 };
 use users::{get_user_by_name, os::unix::UserExt, User};
 
+// The max count of the concurrent headless sessions, one session per user.
+const OPTION_HEADLESS_MAX_SESSIONS: &str = "headless-max-sessions";
+// Minutes. A headless session without connections is closed after this time, "0" means never.
+const OPTION_HEADLESS_IDLE_TIMEOUT: &str = "headless-idle-timeout";
+const DEFAULT_MAX_SESSIONS: usize = 4;
+// The session template last picked by a user, the key is suffixed with the username.
+const OPTION_SESSION_TEMPLATE_PREFIX: &str = "headless-session-template-";
+// "username:template" in the os login picks a session template. ':' is not allowed in usernames.
+const SESSION_TEMPLATE_SEPARATOR: char = ':';
+// Read by `startwm.sh`, the system session is started if it is empty.
+const ENV_DESKTOP_SESSION_COMMAND: &str = "DESKTOP_SESSION_COMMAND";
+// "--server --headless-session <display_num> <username>", the server of a headless session.
+// The main server checks the os login and hands the connection off to the server of the session.
+pub const ARG_HEADLESS_SESSION: &str = "--headless-session";
+const SESSION_SERVER_RESTART_INTERVAL: Duration = Duration::from_secs(3);
+const SESSION_SERVER_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
 lazy_static::lazy_static! {
     static ref DESKTOP_RUNNING: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
     static ref DESKTOP_MANAGER: Arc<Mutex<Option<DesktopManager>>> = Arc::new(Mutex::new(None));
+    static ref SESSION_SERVER: Option<(u32, String)> =
+        parse_session_server_args(&std::env::args().collect::<Vec<_>>());
 }
 
 #[derive(Debug)]
 struct DesktopManager {
     seat0_username: String,
     seat0_display_server: String,
-    child_username: String,
+    // The headless sessions, keyed by username.
+    // Each session has its own server, connections are routed to the session of their os login user.
+    sessions: HashMap<String, XSession>,
+    // The sessions being started and their display numbers, keyed by username.
+    starting: HashMap<String, u32>,
+}
+
+#[derive(Debug)]
+struct XSession {
+    display_num: u32,
     child_exit: Arc<AtomicBool>,
     is_child_running: Arc<AtomicBool>,
+    conn_count: usize,
+    idle_since: Instant,
+}
+
+impl XSession {
+    #[inline]
+    fn is_running(&self) -> bool {
+        self.is_child_running.load(Ordering::SeqCst)
+    }
+
+    fn stop(&self) {
+        self.child_exit.store(true, Ordering::SeqCst);
+    }
+}
+
+fn get_max_sessions() -> usize {
+    Config::get_option(OPTION_HEADLESS_MAX_SESSIONS)
+        .parse::<usize>()
+        .ok()
+        .filter(|n| *n > 0)
+        .unwrap_or(DEFAULT_MAX_SESSIONS)
+}
+
+fn get_idle_timeout() -> Option<Duration> {
+    Config::get_option(OPTION_HEADLESS_IDLE_TIMEOUT)
+        .parse::<u64>()
+        .ok()
+        .filter(|m| *m > 0)
+        .map(|m| Duration::from_secs(m * 60))
 }
 
 fn check_desktop_manager() {
     let mut desktop_manager = DESKTOP_MANAGER.lock().unwrap();
     if let Some(desktop_manager) = &mut (*desktop_manager) {
-        if desktop_manager.is_child_running.load(Ordering::SeqCst) {
-            return;
+        let idle_timeout = get_idle_timeout();
+        for (username, session) in desktop_manager.sessions.iter() {
+            if !session.is_running() {
+                session.stop();
+            } else if let Some(timeout) = idle_timeout {
+                if session.conn_count == 0 && session.idle_since.elapsed() > timeout {
+                    log::info!("Close idle headless session of {}", username);
+                    session.stop();
+                }
+            }
         }
-        desktop_manager.child_exit.store(true, Ordering::SeqCst);
+        desktop_manager.sessions.retain(|_, s| s.is_running());
     }
 }
 
@@ -92,6 +172,7 @@ fn detect_headless() -> Option<&'static str> {
 
 pub fn try_start_desktop(_username: &str, _passsword: &str) -> String {
     debug_assert!(crate::is_server());
+    let (_username, template) = split_session_template(_username);
     if _username.is_empty() {
         let username = get_username();
         if username.is_empty() {
@@ -110,7 +191,7 @@ pub fn try_start_desktop(_username: &str, _passsword: &str) -> String {
             // No need to verify password here.
             return "".to_owned();
         }
-        if !username.is_empty() {
+        if !username.is_empty() && !is_headless() {
             // Another user is logged in. No need to start a new xsession.
             return "".to_owned();
         }
@@ -119,7 +200,7 @@ pub fn try_start_desktop(_username: &str, _passsword: &str) -> String {
             return msg.to_owned();
         }
 
-        match try_start_x_session(_username, _passsword) {
+        match try_start_x_session(_username, _passsword, template) {
             Ok((username, x11_ready)) => {
                 if x11_ready {
                     if _username != username {
@@ -133,32 +214,67 @@ pub fn try_start_desktop(_username: &str, _passsword: &str) -> String {
             }
             Err(e) => {
                 log::error!("Failed to start xsession {}", e);
-                LOGIN_MSG_DESKTOP_XSESSION_FAILED.to_owned()
+                if e.to_string() == LOGIN_MSG_DESKTOP_SESSION_LIMIT {
+                    LOGIN_MSG_DESKTOP_SESSION_LIMIT.to_owned()
+                } else {
+                    LOGIN_MSG_DESKTOP_XSESSION_FAILED.to_owned()
+                }
             }
         }
     }
 }
 
-fn try_start_x_session(username: &str, password: &str) -> ResultType<(String, bool)> {
+fn try_start_x_session(
+    username: &str,
+    password: &str,
+    template: Option<&str>,
+) -> ResultType<(String, bool)> {
+    match &*DESKTOP_MANAGER.lock().unwrap() {
+        Some(desktop_manager) => {
+            if let Some(seat0_username) = desktop_manager.get_supported_display_seat0_username() {
+                return Ok((seat0_username, true));
+            }
+        }
+        None => bail!(crate::client::LOGIN_MSG_DESKTOP_NOT_INITED),
+    }
+    // The manager is not locked while authenticating and starting the session, which blocks
+    // for seconds, the other connections and the idle check go on meanwhile.
+    let userinfo = DesktopManager::authenticate(username, password)?;
+    let display_num = match DESKTOP_MANAGER.lock().unwrap().as_mut() {
+        Some(desktop_manager) => desktop_manager.reserve_x_session(username, template)?,
+        None => bail!(crate::client::LOGIN_MSG_DESKTOP_NOT_INITED),
+    };
+    let Some(display_num) = display_num else {
+        return Ok((username.to_owned(), true));
+    };
+    let template = pick_session_template(username, template);
+    let res = DesktopManager::start_x_session(&userinfo, username, password, template, display_num);
     let mut desktop_manager = DESKTOP_MANAGER.lock().unwrap();
-    if let Some(desktop_manager) = &mut (*desktop_manager) {
-        if let Some(seat0_username) = desktop_manager.get_supported_display_seat0_username() {
-            return Ok((seat0_username, true));
+    let Some(desktop_manager) = desktop_manager.as_mut() else {
+        if let Ok(session) = res {
+            session.stop();
         }
-
-        let _ = desktop_manager.try_start_x_session(username, password)?;
-        log::debug!(
-            "try_start_x_session, username: {}, {:?}",
-            &username,
-            &desktop_manager
-        );
-        Ok((
-            desktop_manager.child_username.clone(),
-            desktop_manager.is_running(),
-        ))
-    } else {
         bail!(crate::client::LOGIN_MSG_DESKTOP_NOT_INITED);
+    };
+    desktop_manager.starting.remove(username);
+    match res {
+        Ok(session) => {
+            log::info!("Succeeded to start x11");
+            desktop_manager
+                .sessions
+                .insert(username.to_string(), session);
+        }
+        Err(e) => bail!("failed to start x session, {}", e),
     }
+    log::debug!(
+        "try_start_x_session, username: {}, {:?}",
+        &username,
+        &desktop_manager
+    );
+    Ok((
+        username.to_owned(),
+        desktop_manager.is_session_running(username),
+    ))
 }
 
 #[inline]
@@ -172,20 +288,105 @@ pub fn is_headless() -> bool {
         })
 }
 
+// The user of the desktop captured by this server, the headless sessions are captured by their own servers.
 pub fn get_username() -> String {
     match &*DESKTOP_MANAGER.lock().unwrap() {
-        Some(manager) => {
-            if let Some(seat0_username) = manager.get_supported_display_seat0_username() {
-                seat0_username
-            } else {
-                if manager.is_running() && !manager.child_username.is_empty() {
-                    manager.child_username.clone()
-                } else {
-                    "".to_owned()
+        Some(manager) => manager
+            .get_supported_display_seat0_username()
+            .unwrap_or_default(),
+        None => "".to_owned(),
+    }
+}
+
+// The display number and the user of the session if this process is the server of a headless session.
+#[inline]
+pub fn get_session_server() -> Option<(u32, String)> {
+    SESSION_SERVER.clone()
+}
+
+fn parse_session_server_args(args: &[String]) -> Option<(u32, String)> {
+    match args {
+        [_, server, arg, display_num, username, ..]
+            if server == "--server" && arg == ARG_HEADLESS_SESSION && !username.is_empty() =>
+        {
+            Some((display_num.parse().ok()?, username.clone()))
+        }
+        _ => None,
+    }
+}
+
+// Only root can access the sockets of the session servers.
+#[inline]
+fn get_session_server_dir() -> String {
+    format!("/run/{}", crate::get_app_name().to_lowercase())
+}
+
+#[inline]
+fn get_session_server_path(display_num: u32) -> String {
+    format!("{}/headless-{}.sock", get_session_server_dir(), display_num)
+}
+
+// Called by the server of a headless session.
+pub fn bind_session_server(display_num: u32) -> ResultType<UnixListener> {
+    let dir = get_session_server_dir();
+    std::fs::create_dir_all(&dir)?;
+    std::fs::set_permissions(&dir, Permissions::from_mode(0o700))?;
+    let path = get_session_server_path(display_num);
+    std::fs::remove_file(&path).ok();
+    let listener = UnixListener::bind(&path)?;
+    std::fs::set_permissions(&path, Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+// Connects to the server of the headless session of the user, it may be still starting.
+pub async fn connect_session_server(username: &str) -> ResultType<UnixStream> {
+    let display_num = DESKTOP_MANAGER
+        .lock()
+        .unwrap()
+        .as_ref()
+        .and_then(|manager| manager.sessions.get(username))
+        .filter(|session| session.is_running())
+        .map(|session| session.display_num);
+    let Some(display_num) = display_num else {
+        bail!("No headless session of {}", username);
+    };
+    let path = get_session_server_path(display_num);
+    let begin = Instant::now();
+    loop {
+        match UnixStream::connect(&path).await {
+            Ok(stream) => return Ok(stream),
+            Err(e) => {
+                if begin.elapsed() > SESSION_SERVER_CONNECT_TIMEOUT {
+                    bail!("Failed to connect the server of {}, {}", username, e);
                 }
             }
         }
-        None => "".to_owned(),
+        time::sleep(Duration::from_millis(300)).await;
+    }
+}
+
+// Called when a connection is handed off to the headless session of the user.
+// `remove_connection()` is called on close.
+pub fn add_connection(username: &str) -> bool {
+    let mut desktop_manager = DESKTOP_MANAGER.lock().unwrap();
+    let Some(session) = desktop_manager
+        .as_mut()
+        .and_then(|manager| manager.sessions.get_mut(username))
+    else {
+        return false;
+    };
+    session.conn_count += 1;
+    true
+}
+
+pub fn remove_connection(username: &str) {
+    if let Some(desktop_manager) = DESKTOP_MANAGER.lock().unwrap().as_mut() {
+        if let Some(session) = desktop_manager.sessions.get_mut(username) {
+            session.conn_count = session.conn_count.saturating_sub(1);
+            if session.conn_count == 0 {
+                session.idle_since = Instant::now();
+            }
+        }
     }
 }
 
@@ -211,9 +412,8 @@ impl DesktopManager {
         Self {
             seat0_username,
             seat0_display_server,
-            child_username: "".to_owned(),
-            child_exit: Arc::new(AtomicBool::new(true)),
-            is_child_running: Arc::new(AtomicBool::new(false)),
+            sessions: HashMap::new(),
+            starting: HashMap::new(),
         }
     }
 
@@ -228,61 +428,71 @@ impl DesktopManager {
         }
     }
 
-    #[inline]
-    fn get_xauth() -> String {
-        let xauth = get_env_var("XAUTHORITY");
-        if xauth.is_empty() {
-            "/tmp/.Xauthority".to_owned()
-        } else {
-            xauth
+    // Each session has its own cookie file in the runtime dir of the user, only readable by the user.
+    fn create_xauth(uid: u32, gid: u32, display_num: u32) -> ResultType<String> {
+        let dir = format!("/run/user/{}", uid);
+        if !Path::new(&dir).is_dir() {
+            bail!("No runtime dir {}", dir);
         }
+        let randstr = (0..8)
+            .map(|_| format!("{:02x}", random::<u8>()))
+            .collect::<String>();
+        let path = format!("{}/.Xauthority-{}-{}", dir, display_num, randstr);
+        create_private_file(Path::new(&path), uid, gid)?;
+        Ok(path)
     }
 
     #[inline]
-    fn is_running(&self) -> bool {
-        self.is_child_running.load(Ordering::SeqCst)
+    fn is_session_running(&self, username: &str) -> bool {
+        self.sessions
+            .get(username)
+            .map_or(false, |s| s.is_running())
     }
 
-    fn try_start_x_session(&mut self, username: &str, password: &str) -> ResultType<()> {
-        match get_user_by_name(username) {
-            Some(userinfo) => {
-                let mut client = pam::Client::with_password(&pam_get_service_name())?;
-                client
-                    .conversation_mut()
-                    .set_credentials(username, password);
-                match client.authenticate() {
-                    Ok(_) => {
-                        if self.is_running() {
-                            return Ok(());
-                        }
-
-                        match self.start_x_session(&userinfo, username, password) {
-                            Ok(_) => {
-                                log::info!("Succeeded to start x11");
-                                self.child_username = username.to_string();
-                                Ok(())
-                            }
-                            Err(e) => {
-                                bail!("failed to start x session, {}", e);
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        bail!("failed to check user pass for {}, {}", username, e);
-                    }
-                }
-            }
-            None => {
-                bail!("failed to get userinfo of {}", username);
+    fn authenticate(username: &str, password: &str) -> ResultType<User> {
+        let Some(userinfo) = get_user_by_name(username) else {
+            bail!("failed to get userinfo of {}", username);
+        };
+        let mut client = pam::Client::with_password(&pam_get_service_name())?;
+        client
+            .conversation_mut()
+            .set_credentials(username, password);
+        if let Err(e) = client.authenticate() {
+            bail!("failed to check user pass for {}, {}", username, e);
+        }
+        Ok(userinfo)
+    }
+
+    // Returns the display number for the new session of the user, `None` if it is running.
+    fn reserve_x_session(
+        &mut self,
+        username: &str,
+        template: Option<&str>,
+    ) -> ResultType<Option<u32>> {
+        if self.is_session_running(username) {
+            if template.is_some() {
+                log::info!("The session of {} is running, template ignored", username);
             }
+            return Ok(None);
         }
+        if self.starting.contains_key(username) {
+            bail!("The session of {} is starting", username);
+        }
+        let count = self.sessions.values().filter(|s| s.is_running()).count() + self.starting.len();
+        if count >= get_max_sessions() {
+            bail!(LOGIN_MSG_DESKTOP_SESSION_LIMIT);
+        }
+        let display_num = self.get_avail_display()?;
+        self.starting.insert(username.to_owned(), display_num);
+        Ok(Some(display_num))
     }
 
     // The logic mainly from https://github.com/neutrinolabs/xrdp/blob/34fe9b60ebaea59e8814bbc3ca5383cabaa1b869/sesman/session.c#L334.
-    fn get_avail_display() -> ResultType<u32> {
+    // The displays of the sessions being started are skipped, their X servers may not be up yet.
+    fn get_avail_display(&self) -> ResultType<u32> {
         let display_range = 0..51;
         for i in display_range.clone() {
-            if Self::is_x_server_running(i) {
+            if Self::is_x_server_running(i) || self.starting.values().any(|n| *n == i) {
                 continue;
             }
             return Ok(i);
@@ -297,14 +507,12 @@ impl DesktopManager {
     }
 
     fn start_x_session(
-        &mut self,
         userinfo: &User,
         username: &str,
         password: &str,
-    ) -> ResultType<()> {
-        self.stop_children();
-
-        let display_num = Self::get_avail_display()?;
+        template: Option<SessionTemplate>,
+        display_num: u32,
+    ) -> ResultType<XSession> {
         // "xServer_ip:display_num.screen_num"
 
         let uid = userinfo.uid();
@@ -319,12 +527,17 @@ impl DesktopManager {
                 "XDG_RUNTIME_DIR",
                 format!("/run/user/{}", userinfo.uid().to_string()),
             ),
-            // ("DISPLAY", self.display.clone()),
-            // ("XAUTHORITY", self.xauth.clone()),
             // (ENV_DESKTOP_PROTOCAL, XProtocal::X11.to_string()),
         ]);
-        self.child_exit.store(false, Ordering::SeqCst);
-        let is_child_running = self.is_child_running.clone();
+        let child_exit = Arc::new(AtomicBool::new(false));
+        let is_child_running = Arc::new(AtomicBool::new(false));
+        let session = XSession {
+            display_num,
+            child_exit: child_exit.clone(),
+            is_child_running: is_child_running.clone(),
+            conn_count: 0,
+            idle_since: Instant::now(),
+        };
 
         let (tx_res, rx_res) = sync_channel(1);
         let password = password.to_string();
@@ -333,6 +546,7 @@ impl DesktopManager {
         std::thread::spawn(move || {
             match Self::start_x_session_thread(
                 tx_res.clone(),
+                child_exit,
                 is_child_running,
                 uid,
                 gid,
@@ -340,6 +554,7 @@ impl DesktopManager {
                 username,
                 password,
                 envs,
+                template,
             ) {
                 Ok(_) => {}
                 Err(e) => {
@@ -353,12 +568,13 @@ impl DesktopManager {
         match rx_res.recv_timeout(Duration::from_millis(10_000)) {
             Ok(res) => {
                 if res == "" {
-                    Ok(())
+                    Ok(session)
                 } else {
                     bail!(res)
                 }
             }
             Err(e) => {
+                session.stop();
                 bail!("Failed to recv x11 result {}", e)
             }
         }
@@ -371,6 +587,7 @@ impl DesktopManager {
 
     fn start_x_session_thread(
         tx_res: SyncSender<String>,
+        child_exit: Arc<AtomicBool>,
         is_child_running: Arc<AtomicBool>,
         uid: u32,
         gid: u32,
@@ -378,6 +595,7 @@ impl DesktopManager {
         username: String,
         password: String,
         envs: HashMap<&str, String>,
+        template: Option<SessionTemplate>,
     ) -> ResultType<()> {
         let mut client = pam::Client::with_password(&pam_get_service_name())?;
         client
@@ -391,13 +609,38 @@ impl DesktopManager {
         // fixme: FreeBSD kernel needs to login here.
         // see: https://github.com/neutrinolabs/xrdp/blob/a64573b596b5fb07ca3a51590c5308d621f7214e/sesman/session.c#L556
 
-        let (child_xorg, child_wm) = Self::start_x11(uid, gid, username, display_num, &envs)?;
+        let xauth = Self::create_xauth(uid, gid, display_num)?;
+        let res = Self::start_x11(
+            uid,
+            gid,
+            username.clone(),
+            display_num,
+            &xauth,
+            &envs,
+            template.as_ref(),
+        );
+        let (child_xorg, child_wm) = match res {
+            Ok(children) => children,
+            Err(e) => {
+                std::fs::remove_file(&xauth).ok();
+                bail!(e);
+            }
+        };
         is_child_running.store(true, Ordering::SeqCst);
 
         log::info!("Start xorg and wm done, notify and wait xtop x11");
         allow_err!(tx_res.send("".to_owned()));
 
-        Self::wait_stop_x11(child_xorg, child_wm);
+        Self::wait_stop_x11(
+            child_xorg,
+            child_wm,
+            &child_exit,
+            &is_child_running,
+            display_num,
+            &username,
+            &xauth,
+        );
+        std::fs::remove_file(&xauth).ok();
         log::info!("Wait x11 stop done");
         Ok(())
     }
@@ -472,17 +715,22 @@ impl DesktopManager {
         gid: u32,
         username: String,
         display_num: u32,
+        xauth: &str,
         envs: &HashMap<&str, String>,
+        template: Option<&SessionTemplate>,
     ) -> ResultType<(Child, Child)> {
         log::debug!("envs of user {}: {:?}", &username, &envs);
+        if let Some(template) = template {
+            log::info!("Use session template {:?}", template);
+        }
 
-        let xauth = Self::get_xauth();
         let display = Self::display_from_num(display_num);
 
-        Self::add_xauth_cookie(&xauth, &display, uid, gid, &envs)?;
+        Self::add_xauth_cookie(xauth, &display, uid, gid, &envs)?;
 
         // Start Xorg
-        let mut child_xorg = Self::start_x_server(&xauth, &display, uid, gid, &envs)?;
+        let dpi = template.map_or(0, |t| t.dpi);
+        let mut child_xorg = Self::start_x_server(xauth, &display, uid, gid, &envs, dpi)?;
 
         log::info!("xorg started, wait 10 secs to ensuer x server is running");
 
@@ -502,13 +750,23 @@ impl DesktopManager {
         log::info!(
             "xorg is running, start x window manager with DISPLAY: {}, XAUTHORITY: {}",
             &display,
-            &xauth
+            xauth
         );
 
-        std::env::set_var("DISPLAY", &display);
-        std::env::set_var("XAUTHORITY", &xauth);
+        let mut wm_envs = envs.clone();
+        wm_envs.insert("DISPLAY", display.clone());
+        wm_envs.insert("XAUTHORITY", xauth.to_owned());
+        if let Some(template) = template {
+            for (k, v) in template.env.iter() {
+                wm_envs.insert(k.as_str(), v.clone());
+            }
+            if !template.command.is_empty() {
+                wm_envs.insert(ENV_DESKTOP_SESSION_COMMAND, template.command.clone());
+            }
+            template.prepare(uid, gid, &wm_envs);
+        }
         // start window manager (startwm.sh)
-        let child_wm = match Self::start_x_window_manager(uid, gid, &envs) {
+        let child_wm = match Self::start_x_window_manager(uid, gid, &wm_envs) {
             Ok(c) => c,
             Err(e) => {
                 match Self::wait_xorg_exit(&mut child_xorg) {
@@ -597,34 +855,78 @@ impl DesktopManager {
         }
     }
 
-    fn try_wait_stop_x11(child_xorg: &mut Child, child_wm: &mut Child) -> bool {
-        let mut desktop_manager = DESKTOP_MANAGER.lock().unwrap();
-        let mut exited = true;
-        if let Some(desktop_manager) = &mut (*desktop_manager) {
-            if desktop_manager.child_exit.load(Ordering::SeqCst) {
-                exited = true;
-            } else {
-                exited = Self::try_wait_x11_child_exit(child_xorg, child_wm);
-            }
-            if exited {
-                log::debug!("Wait x11 children exiting");
-                Self::wait_x11_children_exit(child_xorg, child_wm);
-                desktop_manager
-                    .is_child_running
-                    .store(false, Ordering::SeqCst);
-                desktop_manager.child_exit.store(true, Ordering::SeqCst);
-            }
+    fn try_wait_stop_x11(
+        child_xorg: &mut Child,
+        child_wm: &mut Child,
+        child_exit: &AtomicBool,
+        is_child_running: &AtomicBool,
+    ) -> bool {
+        let exited = if child_exit.load(Ordering::SeqCst) {
+            true
+        } else {
+            Self::try_wait_x11_child_exit(child_xorg, child_wm)
+        };
+        if exited {
+            log::debug!("Wait x11 children exiting");
+            Self::wait_x11_children_exit(child_xorg, child_wm);
+            is_child_running.store(false, Ordering::SeqCst);
+            child_exit.store(true, Ordering::SeqCst);
         }
         exited
     }
 
-    fn wait_stop_x11(mut child_xorg: Child, mut child_wm: Child) {
+    fn wait_stop_x11(
+        mut child_xorg: Child,
+        mut child_wm: Child,
+        child_exit: &AtomicBool,
+        is_child_running: &AtomicBool,
+        display_num: u32,
+        username: &str,
+        xauth: &str,
+    ) {
+        let mut child_server: Option<Child> = None;
+        let mut server_started: Option<Instant> = None;
         loop {
-            if Self::try_wait_stop_x11(&mut child_xorg, &mut child_wm) {
+            if Self::try_wait_stop_x11(&mut child_xorg, &mut child_wm, child_exit, is_child_running)
+            {
                 break;
             }
+            // The server of the session is restarted if it exits.
+            let server_running = child_server
+                .as_mut()
+                .map_or(false, |c| matches!(c.try_wait(), Ok(None)));
+            if !server_running
+                && server_started.map_or(true, |t| t.elapsed() > SESSION_SERVER_RESTART_INTERVAL)
+            {
+                server_started = Some(Instant::now());
+                match Self::start_session_server(display_num, username, xauth) {
+                    Ok(c) => child_server = Some(c),
+                    Err(e) => log::error!("Failed to start the server of {}, {}", username, e),
+                }
+            }
             std::thread::sleep(Duration::from_millis(super::SERVICE_INTERVAL));
         }
+        if let Some(mut child_server) = child_server {
+            allow_err!(child_server.kill());
+            allow_err!(child_server.wait());
+        }
+    }
+
+    // The server of the session runs as root like this server, with the display of the session.
+    fn start_session_server(display_num: u32, username: &str, xauth: &str) -> ResultType<Child> {
+        let child = Command::new(std::env::current_exe()?)
+            .args([
+                "--server",
+                ARG_HEADLESS_SESSION,
+                &display_num.to_string(),
+                username,
+            ])
+            .env("DISPLAY", Self::display_from_num(display_num))
+            .env("XAUTHORITY", xauth)
+            .env(crate::ipc::ENV_CM_POSTFIX, format!("_cm{}", display_num))
+            .spawn()?;
+        log::info!("The server of {} is started, pid: {}", username, child.id());
+        Ok(child)
     }
 
     fn get_xorg() -> &'static str {
@@ -665,12 +967,17 @@ impl DesktopManager {
         uid: u32,
         gid: u32,
         envs: &HashMap<&str, String>,
+        dpi: u32,
     ) -> ResultType<Child> {
         let xorg = Self::get_xorg();
         log::info!("Use xorg: {}", &xorg);
         let app_name = crate::get_app_name().to_lowercase();
         let conf = format!("/etc/{app_name}/xorg.conf");
-        match Command::new(xorg)
+        let mut cmd = Command::new(xorg);
+        if dpi > 0 {
+            cmd.arg("-dpi").arg(dpi.to_string());
+        }
+        match cmd
             .envs(envs)
             .uid(uid)
             .gid(gid)
@@ -717,19 +1024,32 @@ impl DesktopManager {
     }
 
     fn stop_children(&mut self) {
-        self.child_exit.store(true, Ordering::SeqCst);
+        for session in self.sessions.values() {
+            session.stop();
+        }
         for _i in 1..10 {
-            if !self.is_child_running.load(Ordering::SeqCst) {
+            if !self.sessions.values().any(|s| s.is_running()) {
                 break;
             }
             std::thread::sleep(Duration::from_millis(super::SERVICE_INTERVAL));
         }
-        if self.is_child_running.load(Ordering::SeqCst) {
+        if self.sessions.values().any(|s| s.is_running()) {
             log::warn!("xdesktop child is still running!");
         }
     }
 }
 
+// `O_EXCL` fails if the path exists, even as a symlink.
+fn create_private_file(path: &Path, uid: u32, gid: u32) -> ResultType<File> {
+    let file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)?;
+    std::os::unix::fs::fchown(&file, Some(uid), Some(gid))?;
+    Ok(file)
+}
+
 fn pam_get_service_name() -> String {
     let app_name = crate::get_app_name().to_lowercase();
     if Path::new(&format!("/etc/pam.d/{app_name}")).is_file() {
@@ -738,3 +1058,240 @@ fn pam_get_service_name() -> String {
         "gdm".to_owned()
     }
 }
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct SessionTemplates {
+    // The template used if the user has not picked one.
+    default: String,
+    template: Vec<SessionTemplate>,
+}
+
+// Admin defined in `/etc/{app_name}/session-templates.toml`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+struct SessionTemplate {
+    name: String,
+    // The desktop environment or window manager, e.g. "startxfce4".
+    command: String,
+    env: HashMap<String, String>,
+    // "1920x1080", the mode must be known by the X server.
+    resolution: String,
+    dpi: u32,
+    // Run as the user before the desktop is started, not waited.
+    startup: Vec<String>,
+}
+
+impl SessionTemplates {
+    fn load() -> Self {
+        let app_name = crate::get_app_name().to_lowercase();
+        let path = format!("/etc/{app_name}/session-templates.toml");
+        let Ok(text) = std::fs::read_to_string(&path) else {
+            return Self::default();
+        };
+        match toml::from_str::<Self>(&text) {
+            Ok(templates) => templates,
+            Err(e) => {
+                log::error!("Failed to parse {}, {}", &path, e);
+                Self::default()
+            }
+        }
+    }
+
+    fn get(&self, name: &str) -> Option<SessionTemplate> {
+        if name.is_empty() {
+            return None;
+        }
+        self.template.iter().find(|t| t.name == name).cloned()
+    }
+
+    // The requested template, or the one picked before by the user, or the default.
+    fn pick(&self, requested: Option<&str>, picked: &str) -> Option<SessionTemplate> {
+        if let Some(template) = requested.and_then(|name| self.get(name)) {
+            return Some(template);
+        }
+        self.get(picked).or_else(|| self.get(&self.default))
+    }
+
+    fn names(&self) -> Vec<String> {
+        self.template
+            .iter()
+            .filter(|t| !t.name.is_empty())
+            .map(|t| t.name.clone())
+            .collect()
+    }
+}
+
+impl SessionTemplate {
+    fn prepare(&self, uid: u32, gid: u32, envs: &HashMap<&str, String>) {
+        if !self.resolution.is_empty() {
+            match Command::new("xrandr")
+                .envs(envs)
+                .uid(uid)
+                .gid(gid)
+                .args(["-s", &self.resolution])
+                .output()
+            {
+                Ok(output) => {
+                    if !output.status.success() {
+                        log::error!(
+                            "Failed to set resolution {}, {}",
+                            &self.resolution,
+                            String::from_utf8_lossy(&output.stderr)
+                        );
+                    }
+                }
+                Err(e) => log::error!("Failed to run xrandr, {}", e),
+            }
+        }
+        for cmd in self.startup.iter() {
+            if let Err(e) = Command::new("sh")
+                .envs(envs)
+                .uid(uid)
+                .gid(gid)
+                .args(["-c", cmd])
+                .spawn()
+            {
+                log::error!("Failed to run startup command '{}', {}", cmd, e);
+            }
+        }
+    }
+}
+
+// The os username in the login, without the session template.
+#[inline]
+pub fn get_login_username(login: &str) -> &str {
+    split_session_template(login).0
+}
+
+fn split_session_template(login: &str) -> (&str, Option<&str>) {
+    match login.split_once(SESSION_TEMPLATE_SEPARATOR) {
+        Some((username, template)) => (username, Some(template).filter(|t| !t.is_empty())),
+        None => (login, None),
+    }
+}
+
+// The names of the templates shown in the login dialog of the client.
+pub fn get_session_template_names() -> Vec<String> {
+    SessionTemplates::load().names()
+}
+
+// The picked template is kept for the user, and used for the next sessions without a template.
+fn pick_session_template(username: &str, requested: Option<&str>) -> Option<SessionTemplate> {
+    let templates = SessionTemplates::load();
+    let key = format!("{OPTION_SESSION_TEMPLATE_PREFIX}{username}");
+    if let Some(name) = requested {
+        if templates.get(name).is_some() {
+            LocalConfig::set_option(key.clone(), name.to_owned());
+        } else {
+            log::warn!("Session template {} is not found", name);
+        }
+    }
+    templates.pick(requested, &LocalConfig::get_option(&key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::MetadataExt;
+
+    fn args(v: &[&str]) -> Vec<String> {
+        v.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_session_server_args() {
+        assert_eq!(
+            parse_session_server_args(&args(&["app", "--server", ARG_HEADLESS_SESSION, "3", "u"])),
+            Some((3, "u".to_owned()))
+        );
+        assert_eq!(parse_session_server_args(&args(&["app", "--server"])), None);
+        assert_eq!(
+            parse_session_server_args(&args(&["app", "--server", ARG_HEADLESS_SESSION, "x", "u"])),
+            None
+        );
+        assert_eq!(
+            parse_session_server_args(&args(&["app", "--server", ARG_HEADLESS_SESSION, "3", ""])),
+            None
+        );
+        assert_eq!(
+            parse_session_server_args(&args(&["app", "--cm", ARG_HEADLESS_SESSION, "3", "u"])),
+            None
+        );
+    }
+
+    #[test]
+    fn test_create_private_file() {
+        let dir = std::env::temp_dir().join(format!("xauth-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let meta = std::fs::metadata(&dir).unwrap();
+        let (uid, gid) = (meta.uid(), meta.gid());
+
+        let path = dir.join(".Xauthority");
+        create_private_file(&path, uid, gid).unwrap();
+        let meta = std::fs::metadata(&path).unwrap();
+        assert_eq!(meta.mode() & 0o777, 0o600);
+        assert_eq!((meta.uid(), meta.gid()), (uid, gid));
+        // The file is never reused.
+        assert!(create_private_file(&path, uid, gid).is_err());
+
+        let target = dir.join("target");
+        let link = dir.join("link");
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+        assert!(create_private_file(&link, uid, gid).is_err());
+        assert!(!target.exists());
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_split_session_template() {
+        assert_eq!(split_session_template("user"), ("user", None));
+        assert_eq!(split_session_template("user:xfce"), ("user", Some("xfce")));
+        assert_eq!(split_session_template("user:"), ("user", None));
+        assert_eq!(split_session_template(""), ("", None));
+    }
+
+    #[test]
+    fn test_pick_session_template() {
+        let templates: SessionTemplates = toml::from_str(
+            r#"
+            default = "xfce"
+            [[template]]
+            name = "xfce"
+            command = "startxfce4"
+            [[template]]
+            name = "i3"
+            command = "i3"
+            resolution = "1280x720"
+            [template.env]
+            GDK_SCALE = "2"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(templates.names(), vec!["xfce", "i3"]);
+        let pick = |requested, picked| templates.pick(requested, picked).map(|t| t.name);
+        assert_eq!(pick(Some("i3"), "xfce").as_deref(), Some("i3"));
+        // Unknown or missing requests fall back to the picked template, then the default.
+        assert_eq!(pick(Some("kde"), "i3").as_deref(), Some("i3"));
+        assert_eq!(pick(None, "i3").as_deref(), Some("i3"));
+        assert_eq!(pick(None, "").as_deref(), Some("xfce"));
+        assert_eq!(pick(None, "kde").as_deref(), Some("xfce"));
+        let i3 = templates.get("i3").unwrap();
+        assert_eq!(i3.resolution, "1280x720");
+        assert_eq!(i3.env.get("GDK_SCALE").map(|s| s.as_str()), Some("2"));
+
+        let templates: SessionTemplates = toml::from_str("").unwrap();
+        assert!(templates.names().is_empty());
+        assert!(templates.pick(Some("xfce"), "xfce").is_none());
+    }
+
+    #[test]
+    fn test_get_login_username() {
+        assert_eq!(get_login_username("user"), "user");
+        assert_eq!(
+            get_login_username(&format!("user{SESSION_TEMPLATE_SEPARATOR}xfce")),
+            "user"
+        );
+    }
+}
@@ -403,6 +403,11 @@ fn unsafe_get_focused_display(displays: Vec<DisplayInfo>) -> Option<usize> {
     }
 }
 
+/// Not available, the windows of other apps need the screen recording permission to be listed.
+pub fn get_active_window_rect() -> Option<(i32, i32, i32, i32)> {
+    None
+}
+
 pub fn get_cursor() -> ResultType<Option<u64>> {
     autoreleasepool(|| unsafe_get_cursor())
 }
@@ -97,20 +97,158 @@ const REG_NAME_INSTALL_STARTMENUSHORTCUTS: &str = "STARTMENUSHORTCUTS";
 pub const REG_NAME_INSTALL_PRINTER: &str = "PRINTER";
 
 pub fn get_focused_display(displays: Vec<DisplayInfo>) -> Option<usize> {
+    let (x, y, width, height) = get_active_window_rect()?;
+    displays.iter().position(|display| {
+        let center_x = x + width / 2;
+        let center_y = y + height / 2;
+        center_x >= display.x
+            && center_x <= display.x + display.width
+            && center_y >= display.y
+            && center_y <= display.y + display.height
+    })
+}
+
+/// (x, y, width, height) of the foreground window.
+pub fn get_active_window_rect() -> Option<(i32, i32, i32, i32)> {
     unsafe {
         let hwnd = GetForegroundWindow();
         let mut rect: RECT = mem::zeroed();
         if GetWindowRect(hwnd, &mut rect as *mut RECT) == 0 {
             return None;
         }
-        displays.iter().position(|display| {
-            let center_x = rect.left + (rect.right - rect.left) / 2;
-            let center_y = rect.top + (rect.bottom - rect.top) / 2;
-            center_x >= display.x
-                && center_x <= display.x + display.width
-                && center_y >= display.y
-                && center_y <= display.y + display.height
-        })
+        Some((
+            rect.left,
+            rect.top,
+            rect.right - rect.left,
+            rect.bottom - rect.top,
+        ))
+    }
+}
+
+/// The visible top-level windows with a title, (id, title), in z-order.
+pub fn get_windows() -> Vec<(u64, String)> {
+    unsafe extern "system" fn enum_proc(hwnd: HWND, lparam: LPARAM) -> BOOL {
+        let windows = &mut *(lparam as *mut Vec<(u64, String)>);
+        if IsWindowVisible(hwnd) == 0 || IsIconic(hwnd) != 0 {
+            return TRUE;
+        }
+        if GetWindowLongW(hwnd, GWL_EXSTYLE) as DWORD & WS_EX_TOOLWINDOW != 0 {
+            return TRUE;
+        }
+        let len = GetWindowTextLengthW(hwnd);
+        if len <= 0 {
+            return TRUE;
+        }
+        let mut buf = vec![0u16; len as usize + 1];
+        let len = GetWindowTextW(hwnd, buf.as_mut_ptr(), buf.len() as _);
+        if len > 0 {
+            windows.push((hwnd as u64, String::from_utf16_lossy(&buf[..len as usize])));
+        }
+        TRUE
+    }
+    let mut windows: Vec<(u64, String)> = Vec::new();
+    unsafe {
+        EnumWindows(Some(enum_proc), &mut windows as *mut _ as LPARAM);
+    }
+    windows
+}
+
+/// (x, y, width, height) of the window, `None` if it is closed or minimized.
+pub fn get_window_rect(id: u64) -> Option<(i32, i32, i32, i32)> {
+    unsafe {
+        let hwnd = id as HWND;
+        if IsWindow(hwnd) == 0 || IsIconic(hwnd) != 0 {
+            return None;
+        }
+        let mut rect: RECT = mem::zeroed();
+        if GetWindowRect(hwnd, &mut rect as *mut RECT) == 0 {
+            return None;
+        }
+        Some((
+            rect.left,
+            rect.top,
+            rect.right - rect.left,
+            rect.bottom - rect.top,
+        ))
+    }
+}
+
+pub fn get_active_window_id() -> Option<u64> {
+    let hwnd = unsafe { GetForegroundWindow() };
+    if hwnd.is_null() {
+        None
+    } else {
+        Some(hwnd as u64)
+    }
+}
+
+pub fn activate_window(id: u64) -> bool {
+    unsafe {
+        let hwnd = id as HWND;
+        if IsIconic(hwnd) != 0 {
+            ShowWindow(hwnd, SW_RESTORE);
+        }
+        SetForegroundWindow(hwnd) != 0
+    }
+}
+
+// Not in winapi, renders the DirectX and DWM composed content too, Windows 8.1 and later.
+const PW_RENDERFULLCONTENT: UINT = 0x2;
+
+/// The content of a window through `PrintWindow`, the windows above it are not included.
+pub struct WindowCapture {
+    // The HWND, it is not `Send`.
+    id: u64,
+}
+
+impl WindowCapture {
+    pub fn new(id: u64) -> ResultType<Self> {
+        if unsafe { IsWindow(id as HWND) } == 0 {
+            bail!("The window {} is closed", id);
+        }
+        Ok(Self { id })
+    }
+
+    /// BGRA pixels without padding, returns (width, height).
+    pub fn frame(&mut self, data: &mut Vec<u8>) -> ResultType<(usize, usize)> {
+        let Some((_, _, width, height)) = get_window_rect(self.id) else {
+            bail!("The window {} is closed or minimized", self.id);
+        };
+        unsafe {
+            let dc = DC::new()?;
+            let bitmap = Bitmap::new(dc.0, width, height)?;
+            {
+                let bitmap_dc = BitmapDC::new(dc.0, bitmap.0)?;
+                if PrintWindow(self.id as HWND, bitmap_dc.dc(), PW_RENDERFULLCONTENT) == 0 {
+                    bail!("Failed to print the window: {}", io::Error::last_os_error());
+                }
+            }
+            let mut bmi: BITMAPINFO = mem::zeroed();
+            bmi.bmiHeader.biSize = mem::size_of::<BITMAPINFOHEADER>() as _;
+            bmi.bmiHeader.biWidth = width;
+            // Top-down.
+            bmi.bmiHeader.biHeight = -height;
+            bmi.bmiHeader.biPlanes = 1;
+            bmi.bmiHeader.biBitCount = 32;
+            bmi.bmiHeader.biCompression = BI_RGB;
+            data.resize(width as usize * height as usize * 4, 0);
+            if GetDIBits(
+                dc.0,
+                bitmap.0,
+                0,
+                height as _,
+                data.as_mut_ptr() as _,
+                &mut bmi,
+                DIB_RGB_COLORS,
+            ) == 0
+            {
+                bail!(
+                    "Failed to get the window bits: {}",
+                    io::Error::last_os_error()
+                );
+            }
+        }
+        Ok((width as usize, height as usize))
     }
 }
 
@@ -330,6 +468,28 @@ impl Drop for CompatibleDC {
     }
 }
 
+struct Bitmap(HBITMAP);
+
+impl Bitmap {
+    fn new(hdc: HDC, width: i32, height: i32) -> ResultType<Self> {
+        unsafe {
+            let bitmap = CreateCompatibleBitmap(hdc, width, height);
+            if bitmap.is_null() {
+                bail!("Failed to create a compatible bitmap");
+            }
+            Ok(Self(bitmap))
+        }
+    }
+}
+
+impl Drop for Bitmap {
+    fn drop(&mut self) {
+        unsafe {
+            DeleteObject(self.0 as _);
+        }
+    }
+}
+
 struct BitmapDC(CompatibleDC, HBITMAP);
 
 impl BitmapDC {
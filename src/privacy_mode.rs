@@ -34,6 +34,9 @@ mod win_virtual_display;
 #[cfg(windows)]
 pub use win_virtual_display::restore_reg_connectivity;
 
+#[cfg(target_os = "linux")]
+pub mod linux_blank;
+
 pub const INVALID_PRIVACY_MODE_CONN_ID: i32 = 0;
 pub const OCCUPIED: &'static str = "Privacy occupied by another one.";
 pub const TURN_OFF_OTHER_ID: &'static str =
@@ -47,6 +50,7 @@ pub const PRIVACY_MODE_IMPL_WIN_VIRTUAL_DISPLAY: &str = "privacy_mode_impl_virtu
 pub const PRIVACY_MODE_IMPL_WIN_DIRECT_OVERLAY: &str = "privacy_mode_impl_direct_overlay";
 pub const PRIVACY_MODE_IMPL_WIN_GIF_OVERLAY: &str = "privacy_mode_impl_gif_overlay";
 pub const PRIVACY_MODE_IMPL_WIN_SEPARATE_DESKTOP: &str = "privacy_mode_impl_separate_desktop";
+pub const PRIVACY_MODE_IMPL_LINUX_BLANK: &str = "privacy_mode_impl_linux_blank";
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(tag = "t", content = "c")]
@@ -115,7 +119,15 @@ lazy_static::lazy_static! {
                 }
             }.to_owned()
         }
-        #[cfg(not(windows))]
+        #[cfg(target_os = "linux")]
+        {
+            if linux_blank::is_supported() {
+                PRIVACY_MODE_IMPL_LINUX_BLANK
+            } else {
+                ""
+            }.to_owned()
+        }
+        #[cfg(not(any(windows, target_os = "linux")))]
         {
             "".to_owned()
         }
@@ -138,9 +150,9 @@ lazy_static::lazy_static! {
 pub type PrivacyModeCreator = fn(impl_key: &str) -> Box<dyn PrivacyMode>;
 lazy_static::lazy_static! {
     static ref PRIVACY_MODE_CREATOR: Arc<Mutex<HashMap<&'static str, PrivacyModeCreator>>> = {
-        #[cfg(not(windows))]
+        #[cfg(not(any(windows, target_os = "linux")))]
         let map: HashMap<&'static str, PrivacyModeCreator> = HashMap::new();
-        #[cfg(windows)]
+        #[cfg(any(windows, target_os = "linux"))]
         let mut map: HashMap<&'static str, PrivacyModeCreator> = HashMap::new();
         #[cfg(windows)]
         {
@@ -179,6 +191,12 @@ lazy_static::lazy_static! {
                     Box::new(win_virtual_display::PrivacyModeImpl::new(impl_key))
                 });
         }
+        #[cfg(target_os = "linux")]
+        {
+            map.insert(linux_blank::PRIVACY_MODE_IMPL, |impl_key: &str| {
+                Box::new(linux_blank::PrivacyModeImpl::new(impl_key))
+            });
+        }
         Arc::new(Mutex::new(map))
     };
 }
@@ -347,7 +365,7 @@ async fn set_privacy_mode_state(
     impl_key: String,
     ms_timeout: u64,
 ) -> ResultType<()> {
-    let mut c = connect(ms_timeout, "_cm").await?;
+    let mut c = connect(ms_timeout, &crate::ipc::cm_postfix()).await?;
     c.send(&Data::PrivacyModeState((conn_id, state, impl_key)))
         .await
 }
@@ -410,7 +428,18 @@ pub fn get_supported_privacy_mode_impl() -> Vec<(&'static str, &'static str)> {
 
         vec_impls
     }
-    #[cfg(not(target_os = "windows"))]
+    #[cfg(target_os = "linux")]
+    {
+        if linux_blank::is_supported() {
+            vec![(
+                PRIVACY_MODE_IMPL_LINUX_BLANK,
+                "privacy_mode_impl_linux_blank_tip",
+            )]
+        } else {
+            Vec::new()
+        }
+    }
+    #[cfg(not(any(target_os = "windows", target_os = "linux")))]
     {
         Vec::new()
     }
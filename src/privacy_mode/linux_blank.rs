@@ -0,0 +1,419 @@
+// Privacy mode on Linux.
+//
+// The physical outputs are blacked out, and the local keyboards and mice are grabbed by the root
+// service (`uinput::client::UInputBlocker`).
+// A black override-redirect window is not used, because the X11 capturer reads the root window,
+// the peer would receive the black window too.
+//
+// The outputs are blacked out by the gamma ramps (brightness), which are applied by the CRTCs,
+// the captured frames are not affected. On X11 the outputs are put into power saving mode (DPMS)
+// too, the capturer reads the root window whether they are on or not.
+// On Wayland the outputs are not powered off, the compositors stop painting the powered off
+// outputs and the captured frames would pause. Sway is not supported, wlroots only exposes the
+// gamma ramps to the Wayland clients.
+//
+// The grabs are bound to the ipc connection with the root service, they are released if this
+// process exits unexpectedly. The gamma ramps are kept then, until the outputs are reconfigured.
+
+use super::{PrivacyMode, PrivacyModeState, INVALID_PRIVACY_MODE_CONN_ID};
+use crate::{
+    platform::linux::{get_active_userid, get_env, is_x11},
+    uinput::client::UInputBlocker,
+};
+use dbus::{
+    arg::ArgType,
+    blocking::{Connection, Proxy},
+    channel::Channel,
+    Message,
+};
+use hbb_common::{allow_err, anyhow::anyhow, bail, log, ResultType};
+use std::{process::Command, time::Duration};
+
+pub const PRIVACY_MODE_IMPL: &str = super::PRIVACY_MODE_IMPL_LINUX_BLANK;
+
+const MUTTER_DISPLAY_CONFIG: &str = "org.gnome.Mutter.DisplayConfig";
+const MUTTER_DISPLAY_CONFIG_PATH: &str = "/org/gnome/Mutter/DisplayConfig";
+const DBUS_TIMEOUT: Duration = Duration::from_secs(3);
+
+enum Blanker {
+    X11,
+    // The session bus address of GNOME Shell.
+    Mutter(String),
+}
+
+// The settings of the user, restored when the privacy mode is turned off.
+enum Saved {
+    X11 {
+        dpms_enabled: bool,
+        // The outputs and their brightness.
+        brightness: Vec<(String, String)>,
+    },
+    Mutter {
+        serial: u32,
+        // The crtcs and their red, green and blue ramps.
+        gammas: Vec<(u32, (Vec<u16>, Vec<u16>, Vec<u16>))>,
+    },
+}
+
+impl Blanker {
+    fn detect() -> Option<Self> {
+        if is_x11() {
+            return Some(Self::X11);
+        }
+        let uid = get_active_userid();
+        // Sway has no command to set the gamma ramps.
+        if !std::env::var("SWAYSOCK").unwrap_or_default().is_empty()
+            || !get_env("SWAYSOCK", &uid, "sway").is_empty()
+        {
+            return None;
+        }
+        let bus = get_env("DBUS_SESSION_BUS_ADDRESS", &uid, "gnome-shell");
+        if !bus.is_empty() {
+            return Some(Self::Mutter(bus));
+        }
+        None
+    }
+
+    fn blank(&self) -> ResultType<Saved> {
+        Ok(match self {
+            Self::X11 => {
+                let dpms_enabled = x11_is_dpms_enabled();
+                let brightness = x11_get_brightness()?;
+                let saved = Saved::X11 {
+                    dpms_enabled,
+                    brightness: brightness.clone(),
+                };
+                for (output, _) in brightness.iter() {
+                    if let Err(e) = x11_set_brightness(output, "0") {
+                        self.unblank(saved);
+                        return Err(e);
+                    }
+                }
+                if let Err(e) = x11_set_power(false) {
+                    self.unblank(saved);
+                    return Err(e);
+                }
+                saved
+            }
+            Self::Mutter(bus) => {
+                let conn = mutter_connect(bus)?;
+                let proxy = conn.with_proxy(
+                    MUTTER_DISPLAY_CONFIG,
+                    MUTTER_DISPLAY_CONFIG_PATH,
+                    DBUS_TIMEOUT,
+                );
+                let (serial, crtcs) = mutter_get_active_crtcs(&conn)?;
+                let mut gammas = Vec::new();
+                for crtc in crtcs {
+                    let gamma: (Vec<u16>, Vec<u16>, Vec<u16>) =
+                        proxy.method_call(MUTTER_DISPLAY_CONFIG, "GetCrtcGamma", (serial, crtc))?;
+                    let zero = vec![0u16; gamma.0.len()];
+                    gammas.push((crtc, gamma));
+                    if let Err(e) = mutter_set_gamma(&proxy, serial, crtc, &zero, &zero, &zero) {
+                        self.unblank(Saved::Mutter { serial, gammas });
+                        return Err(e);
+                    }
+                }
+                Saved::Mutter { serial, gammas }
+            }
+        })
+    }
+
+    fn unblank(&self, saved: Saved) {
+        match (self, saved) {
+            (
+                Self::X11,
+                Saved::X11 {
+                    dpms_enabled,
+                    brightness,
+                },
+            ) => {
+                allow_err!(x11_set_power(true));
+                for (output, value) in brightness.iter() {
+                    allow_err!(x11_set_brightness(output, value));
+                }
+                // `xset dpms force` enables DPMS implicitly, restore the setting of the user.
+                if !dpms_enabled {
+                    allow_err!(Command::new("xset").arg("-dpms").status());
+                }
+            }
+            (Self::Mutter(bus), Saved::Mutter { serial, gammas }) => match mutter_connect(bus) {
+                Ok(conn) => {
+                    let proxy = conn.with_proxy(
+                        MUTTER_DISPLAY_CONFIG,
+                        MUTTER_DISPLAY_CONFIG_PATH,
+                        DBUS_TIMEOUT,
+                    );
+                    for (crtc, (red, green, blue)) in gammas.iter() {
+                        allow_err!(mutter_set_gamma(&proxy, serial, *crtc, red, green, blue));
+                    }
+                }
+                Err(e) => log::error!("Failed to restore the gamma of the outputs: {}", e),
+            },
+            _ => {}
+        }
+    }
+}
+
+fn x11_set_power(on: bool) -> ResultType<()> {
+    let output = Command::new("xset")
+        .args(["dpms", "force", if on { "on" } else { "off" }])
+        .output()?;
+    if !output.status.success() {
+        bail!(
+            "Failed to turn {} the outputs: {}",
+            if on { "on" } else { "off" },
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+fn x11_is_dpms_enabled() -> bool {
+    match Command::new("xset").arg("q").output() {
+        Ok(output) => !String::from_utf8_lossy(&output.stdout).contains("DPMS is Disabled"),
+        Err(_) => true,
+    }
+}
+
+fn x11_get_brightness() -> ResultType<Vec<(String, String)>> {
+    let output = Command::new("xrandr").arg("--verbose").output()?;
+    if !output.status.success() {
+        bail!(
+            "Failed to query the outputs: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    let brightness = parse_xrandr_brightness(&String::from_utf8_lossy(&output.stdout));
+    if brightness.is_empty() {
+        bail!("No connected outputs");
+    }
+    Ok(brightness)
+}
+
+// The brightness of the enabled outputs in the output of `xrandr --verbose`.
+fn parse_xrandr_brightness(text: &str) -> Vec<(String, String)> {
+    let mut brightness = Vec::new();
+    let mut output = None;
+    for line in text.lines() {
+        if !line.starts_with(char::is_whitespace) {
+            // The enabled outputs have the geometry, e.g. "1920x1080+0+0".
+            let words: Vec<&str> = line.split_whitespace().collect();
+            output = match words.as_slice() {
+                [name, "connected", rest @ ..] if rest.iter().any(|w| w.contains('+')) => {
+                    Some(name.to_string())
+                }
+                _ => None,
+            };
+        } else if let Some(value) = line.trim().strip_prefix("Brightness:") {
+            if let Some(name) = output.take() {
+                brightness.push((name, value.trim().to_owned()));
+            }
+        }
+    }
+    brightness
+}
+
+fn x11_set_brightness(output: &str, value: &str) -> ResultType<()> {
+    let output = Command::new("xrandr")
+        .args(["--output", output, "--brightness", value])
+        .output()?;
+    if !output.status.success() {
+        bail!(
+            "Failed to set the brightness: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+fn mutter_connect(bus: &str) -> ResultType<Connection> {
+    let mut channel = Channel::open_private(bus)?;
+    channel.register()?;
+    Ok(channel.into())
+}
+
+// The serial of the configuration and the crtcs driving the outputs.
+fn mutter_get_active_crtcs(conn: &Connection) -> ResultType<(u32, Vec<u32>)> {
+    let msg = Message::new_method_call(
+        MUTTER_DISPLAY_CONFIG,
+        MUTTER_DISPLAY_CONFIG_PATH,
+        MUTTER_DISPLAY_CONFIG,
+        "GetResources",
+    )
+    .map_err(|e| anyhow!(e))?;
+    let reply = conn
+        .channel()
+        .send_with_reply_and_block(msg, DBUS_TIMEOUT)?;
+    let mut iter = reply.iter_init();
+    let serial: u32 = iter.read()?;
+    let Some(mut array) = iter.recurse(ArgType::Array) else {
+        bail!("No crtcs in the display resources");
+    };
+    let mut crtcs = Vec::new();
+    while let Some(mut crtc) = array.recurse(ArgType::Struct) {
+        // (id, winsys_id, x, y, width, height, current_mode, ..)
+        let id: u32 = crtc.read()?;
+        let _winsys_id: i64 = crtc.read()?;
+        for _ in 0..4 {
+            let _: i32 = crtc.read()?;
+        }
+        let current_mode: i32 = crtc.read()?;
+        if current_mode >= 0 {
+            crtcs.push(id);
+        }
+        if !array.next() {
+            break;
+        }
+    }
+    Ok((serial, crtcs))
+}
+
+fn mutter_set_gamma(
+    proxy: &Proxy<&Connection>,
+    serial: u32,
+    crtc: u32,
+    red: &[u16],
+    green: &[u16],
+    blue: &[u16],
+) -> ResultType<()> {
+    proxy.method_call::<(), _, _, _>(
+        MUTTER_DISPLAY_CONFIG,
+        "SetCrtcGamma",
+        (serial, crtc, red, green, blue),
+    )?;
+    Ok(())
+}
+
+// The input devices are grabbed by the root service, which only runs if installed.
+pub fn is_supported() -> bool {
+    crate::platform::is_installed() && Blanker::detect().is_some()
+}
+
+struct Blanking {
+    blanker: Blanker,
+    saved: Saved,
+    // The input devices are grabbed as long as the blocker lives.
+    _blocker: UInputBlocker,
+}
+
+pub struct PrivacyModeImpl {
+    impl_key: String,
+    conn_id: i32,
+    blanking: Option<Blanking>,
+}
+
+impl PrivacyModeImpl {
+    pub fn new(impl_key: &str) -> Self {
+        Self {
+            impl_key: impl_key.to_owned(),
+            conn_id: INVALID_PRIVACY_MODE_CONN_ID,
+            blanking: None,
+        }
+    }
+
+    fn stop_blanking(&mut self) {
+        if let Some(blanking) = self.blanking.take() {
+            blanking.blanker.unblank(blanking.saved);
+        }
+    }
+}
+
+impl PrivacyMode for PrivacyModeImpl {
+    fn is_async_privacy_mode(&self) -> bool {
+        false
+    }
+
+    fn init(&self) -> ResultType<()> {
+        Ok(())
+    }
+
+    fn clear(&mut self) {
+        allow_err!(self.turn_off_privacy(self.conn_id, None));
+    }
+
+    fn turn_on_privacy(&mut self, conn_id: i32) -> ResultType<bool> {
+        if self.check_on_conn_id(conn_id)? {
+            log::debug!("Privacy mode of conn {} is already on", conn_id);
+            return Ok(true);
+        }
+        let Some(blanker) = Blanker::detect() else {
+            bail!("No supported display server to blank the outputs");
+        };
+        // Never blank the outputs if the local input can not be blocked.
+        let blocker = UInputBlocker::new()?;
+        let saved = blanker.blank()?;
+        self.blanking = Some(Blanking {
+            blanker,
+            saved,
+            _blocker: blocker,
+        });
+        self.conn_id = conn_id;
+        log::info!("Linux privacy mode is turned on by conn {}", conn_id);
+        Ok(true)
+    }
+
+    fn turn_off_privacy(
+        &mut self,
+        conn_id: i32,
+        state: Option<PrivacyModeState>,
+    ) -> ResultType<()> {
+        self.check_off_conn_id(conn_id)?;
+        self.stop_blanking();
+        if self.conn_id != INVALID_PRIVACY_MODE_CONN_ID {
+            log::info!(
+                "Linux privacy mode of conn {} is turned off, {:?}",
+                self.conn_id,
+                state
+            );
+        }
+        self.conn_id = INVALID_PRIVACY_MODE_CONN_ID;
+        Ok(())
+    }
+
+    #[inline]
+    fn pre_conn_id(&self) -> i32 {
+        self.conn_id
+    }
+
+    #[inline]
+    fn get_impl_key(&self) -> &str {
+        &self.impl_key
+    }
+}
+
+impl Drop for PrivacyModeImpl {
+    fn drop(&mut self) {
+        self.stop_blanking();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_xrandr_brightness() {
+        let text = "Screen 0: minimum 8 x 8, current 3840 x 1080, maximum 32767 x 32767
+eDP-1 connected primary 1920x1080+0+0 (0x47) normal (normal left inverted right x axis y axis) 309mm x 174mm
+\tIdentifier: 0x42
+\tGamma:      1.0:1.0:1.0
+\tBrightness: 0.80
+  1920x1080 (0x47) 138.700MHz +HSync -VSync *current +preferred
+HDMI-1 disconnected (normal left inverted right x axis y axis)
+\tBrightness: 0.0
+DP-2 connected (normal left inverted right x axis y axis)
+\tBrightness: 1.0
+DP-1 connected 1920x1080+1920+0 (0x48) normal (normal left inverted right x axis y axis) 527mm x 296mm
+\tBrightness: 1.0
+";
+        assert_eq!(
+            parse_xrandr_brightness(text),
+            vec![
+                ("eDP-1".to_owned(), "0.80".to_owned()),
+                ("DP-1".to_owned(), "1.0".to_owned())
+            ]
+        );
+        assert!(parse_xrandr_brightness("").is_empty());
+    }
+}
@@ -0,0 +1,624 @@
+// QUIC transport of the peer and the relay connections.
+//
+// If `allow-quic` is enabled, the client tries QUIC over the punched UDP socket before KCP, and
+// the controlled side accepts it if it is enabled there too, recognizing the QUIC Initial packet.
+// The relay connections are tried over QUIC before TCP, which only the embedded relay of
+// `--rendezvous-server` supports. Both fall back to the existing transports if QUIC is blocked.
+//
+// Besides the main stream, which carries the handshake and everything else, the video, file,
+// clipboard and terminal messages are sent on their own QUIC streams (lanes), so that a file
+// transfer does not delay the video. A lane is opened by the sender on the first message and
+// acknowledged by the receiver, the main stream is used if it is not acknowledged, e.g. a relay
+// bridging QUIC and TCP. The lanes are encrypted with the keys derived from the key of the main
+// stream, the peer is authenticated by the handshake of the main stream, not the certificate.
+//
+// The punched socket is disconnected so that the client can migrate to another address.
+
+use hbb_common::{
+    anyhow::anyhow,
+    bail,
+    bytes::BytesMut,
+    bytes_codec::BytesCodec,
+    config::{self, Config, LocalConfig},
+    log,
+    message_proto::*,
+    sha2::{Digest, Sha256},
+    sodiumoxide::crypto::secretbox,
+    tcp::{DynTcpStream, FramedStream},
+    timeout,
+    tokio::{
+        self,
+        io::{AsyncRead, AsyncWrite, ReadBuf},
+        net::UdpSocket,
+        sync::mpsc,
+    },
+    tokio_util, ResultType, Stream,
+};
+use quinn::{
+    crypto::rustls::QuicClientConfig, ClientConfig, Connection, Endpoint, EndpointConfig,
+    IdleTimeout, Incoming, RecvStream, SendStream, ServerConfig, TokioRuntime, TransportConfig,
+    VarInt,
+};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio_rustls::rustls::{
+    self,
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::{verify_tls12_signature, verify_tls13_signature, CryptoProvider},
+    pki_types::{CertificateDer, PrivatePkcs8KeyDer, ServerName, UnixTime},
+    DigitallySignedStruct, SignatureScheme,
+};
+
+pub const OPTION_ALLOW_QUIC: &str = "allow-quic";
+
+const SERVER_NAME: &str = "cloudydesk";
+// The QUIC attempt is given up within this time, to leave the time for the fallback.
+const CONNECT_TIMEOUT: u64 = 3_000;
+const LANE_TIMEOUT: u64 = 3_000;
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(5);
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+const LANE_ACK: u8 = 1;
+
+lazy_static::lazy_static! {
+    // local address of the main stream -> the connection, taken by the session for the lanes.
+    static ref CONNECTIONS: Mutex<HashMap<SocketAddr, Entry>> = Default::default();
+}
+
+struct Entry {
+    connection: Connection,
+    controlled: bool,
+    key: Option<secretbox::Key>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum QuicLane {
+    Main = 0,
+    Video = 1,
+    File = 2,
+    Clipboard = 3,
+    Terminal = 4,
+}
+
+impl QuicLane {
+    fn from_u8(v: u8) -> Option<Self> {
+        match v {
+            0 => Some(Self::Main),
+            1 => Some(Self::Video),
+            2 => Some(Self::File),
+            3 => Some(Self::Clipboard),
+            4 => Some(Self::Terminal),
+            _ => None,
+        }
+    }
+
+    /// The lane the message is sent on.
+    pub fn of(msg: &Message) -> Self {
+        match &msg.union {
+            // SwitchDisplay goes with the video frames to keep the order.
+            Some(message::Union::VideoFrame(_)) => Self::Video,
+            Some(message::Union::Misc(misc)) => match &misc.union {
+                Some(misc::Union::SwitchDisplay(_)) => Self::Video,
+                _ => Self::Main,
+            },
+            Some(message::Union::FileAction(_)) | Some(message::Union::FileResponse(_)) => {
+                Self::File
+            }
+            Some(message::Union::Clipboard(_))
+            | Some(message::Union::MultiClipboards(_))
+            | Some(message::Union::Cliprdr(_)) => Self::Clipboard,
+            Some(message::Union::TerminalAction(_)) | Some(message::Union::TerminalResponse(_)) => {
+                Self::Terminal
+            }
+            _ => Self::Main,
+        }
+    }
+}
+
+#[inline]
+pub fn is_allowed(controlled: bool) -> bool {
+    let v = if controlled {
+        Config::get_option(OPTION_ALLOW_QUIC)
+    } else {
+        LocalConfig::get_option(OPTION_ALLOW_QUIC)
+    };
+    config::option2bool(OPTION_ALLOW_QUIC, &v)
+}
+
+/// If the packet is the Initial packet of QUIC v1, which is padded to 1200 bytes at least.
+pub fn is_initial_packet(packet: &[u8]) -> bool {
+    packet.len() >= 1200 && packet[0] & 0xf0 == 0xc0 && packet[1..5] == [0, 0, 0, 1]
+}
+
+/// The QUIC server does not have a certificate of a trusted CA, the peer is authenticated by the
+/// handshake of the main stream instead.
+#[derive(Debug)]
+struct SkipServerVerification(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for SkipServerVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+fn transport_config() -> Arc<TransportConfig> {
+    let mut config = TransportConfig::default();
+    config.keep_alive_interval(Some(KEEP_ALIVE_INTERVAL));
+    config.max_idle_timeout(IdleTimeout::try_from(IDLE_TIMEOUT).ok());
+    Arc::new(config)
+}
+
+fn server_config() -> ResultType<ServerConfig> {
+    let cert = rcgen::generate_simple_self_signed(vec![SERVER_NAME.to_owned()])?;
+    let key = PrivatePkcs8KeyDer::from(cert.key_pair.serialize_der());
+    let mut config = ServerConfig::with_single_cert(vec![cert.cert.der().clone()], key.into())?;
+    config.transport_config(transport_config());
+    config.migration(true);
+    Ok(config)
+}
+
+fn client_config() -> ResultType<ClientConfig> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let crypto = rustls::ClientConfig::builder()
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(SkipServerVerification(provider)))
+        .with_no_client_auth();
+    let mut config = ClientConfig::new(Arc::new(QuicClientConfig::try_from(crypto)?));
+    config.transport_config(transport_config());
+    Ok(config)
+}
+
+fn new_endpoint(
+    socket: std::net::UdpSocket,
+    server_config: Option<ServerConfig>,
+) -> ResultType<Endpoint> {
+    socket.set_nonblocking(true)?;
+    let mut endpoint = Endpoint::new(
+        EndpointConfig::default(),
+        server_config,
+        socket,
+        Arc::new(TokioRuntime),
+    )?;
+    endpoint.set_default_client_config(client_config()?);
+    Ok(endpoint)
+}
+
+/// Duplicates the punched socket for the QUIC endpoint, and disconnects it so that the peer can
+/// migrate to another address.
+fn dup_socket(socket: &UdpSocket) -> ResultType<std::net::UdpSocket> {
+    #[cfg(unix)]
+    let socket = std::net::UdpSocket::from(std::os::fd::AsFd::as_fd(socket).try_clone_to_owned()?);
+    #[cfg(windows)]
+    let socket = std::net::UdpSocket::from(
+        std::os::windows::io::AsSocket::as_socket(socket).try_clone_to_owned()?,
+    );
+    disconnect(&socket);
+    Ok(socket)
+}
+
+#[cfg(unix)]
+fn disconnect(socket: &std::net::UdpSocket) {
+    use std::os::fd::AsRawFd;
+    let mut addr: libc::sockaddr = unsafe { std::mem::zeroed() };
+    addr.sa_family = libc::AF_UNSPEC as _;
+    // macOS returns EAFNOSUPPORT though the socket is disconnected.
+    unsafe {
+        libc::connect(
+            socket.as_raw_fd(),
+            &addr,
+            std::mem::size_of::<libc::sockaddr>() as _,
+        )
+    };
+}
+
+#[cfg(windows)]
+fn disconnect(socket: &std::net::UdpSocket) {
+    use std::os::windows::io::AsRawSocket;
+    use winapi::shared::ws2def::{AF_INET, AF_INET6, SOCKADDR, SOCKADDR_STORAGE};
+    let is_ipv6 = socket.local_addr().map(|x| x.is_ipv6()).unwrap_or(false);
+    // Connecting to the zero address disconnects the socket.
+    let mut addr: SOCKADDR_STORAGE = unsafe { std::mem::zeroed() };
+    addr.ss_family = if is_ipv6 { AF_INET6 } else { AF_INET } as _;
+    let len = if is_ipv6 { 28 } else { 16 };
+    unsafe {
+        winapi::um::winsock2::connect(
+            socket.as_raw_socket() as _,
+            &addr as *const _ as *const SOCKADDR,
+            len,
+        )
+    };
+}
+
+/// The two directions of a QUIC stream.
+struct BiStream {
+    send: SendStream,
+    recv: RecvStream,
+}
+
+impl AsyncRead for BiStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        AsyncRead::poll_read(Pin::new(&mut self.recv), cx, buf)
+    }
+}
+
+impl AsyncWrite for BiStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        AsyncWrite::poll_write(Pin::new(&mut self.send), cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        AsyncWrite::poll_flush(Pin::new(&mut self.send), cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        AsyncWrite::poll_shutdown(Pin::new(&mut self.send), cx)
+    }
+}
+
+fn create_framed(send: SendStream, recv: RecvStream, local_addr: SocketAddr) -> Stream {
+    Stream::Tcp(FramedStream(
+        tokio_util::codec::Framed::new(
+            DynTcpStream(Box::new(BiStream { send, recv })),
+            BytesCodec::new(),
+        ),
+        local_addr,
+        None,
+        0,
+    ))
+}
+
+fn register(local_addr: SocketAddr, connection: Connection, controlled: bool) {
+    let mut connections = CONNECTIONS.lock().unwrap();
+    connections.retain(|_, x| x.connection.close_reason().is_none());
+    connections.insert(
+        local_addr,
+        Entry {
+            connection,
+            controlled,
+            key: None,
+        },
+    );
+}
+
+async fn open_main(
+    endpoint: &Endpoint,
+    addr: SocketAddr,
+    local_addr: SocketAddr,
+    ms_timeout: u64,
+    controlled: bool,
+) -> ResultType<Stream> {
+    let connection = timeout(ms_timeout, endpoint.connect(addr, SERVER_NAME)?).await??;
+    let (mut send, recv) = connection.open_bi().await?;
+    send.write_all(&[QuicLane::Main as u8]).await?;
+    register(local_addr, connection, controlled);
+    Ok(create_framed(send, recv, local_addr))
+}
+
+async fn accept_main(
+    incoming: Incoming,
+    local_addr: SocketAddr,
+) -> ResultType<(Stream, Connection)> {
+    let connection = incoming.await?;
+    let (send, mut recv) = connection.accept_bi().await?;
+    let mut lane = [0u8; 1];
+    recv.read_exact(&mut lane).await?;
+    if lane[0] != QuicLane::Main as u8 {
+        bail!("Invalid QUIC main stream");
+    }
+    Ok((create_framed(send, recv, local_addr), connection))
+}
+
+/// Connects to the peer over the punched UDP socket.
+pub async fn connect(socket: Arc<UdpSocket>, ms_timeout: u64) -> ResultType<Stream> {
+    let addr = socket.peer_addr()?;
+    let local_addr = socket.local_addr()?;
+    let endpoint = new_endpoint(dup_socket(&socket)?, None)?;
+    open_main(
+        &endpoint,
+        addr,
+        local_addr,
+        ms_timeout.min(CONNECT_TIMEOUT),
+        false,
+    )
+    .await
+}
+
+/// Accepts the connection of the peer over the punched UDP socket. The Initial packet received
+/// by the punch is dropped, the client sends it again.
+pub async fn accept(socket: Arc<UdpSocket>, ms_timeout: u64) -> ResultType<Stream> {
+    let local_addr = socket.local_addr()?;
+    let endpoint = new_endpoint(dup_socket(&socket)?, Some(server_config()?))?;
+    let (stream, connection) = timeout(ms_timeout, async {
+        let incoming = endpoint
+            .accept()
+            .await
+            .ok_or_else(|| anyhow!("QUIC endpoint closed"))?;
+        accept_main(incoming, local_addr).await
+    })
+    .await??;
+    register(local_addr, connection, true);
+    Ok(stream)
+}
+
+/// Connects to the relay server over QUIC if allowed, `RequestRelay` is sent by the caller as
+/// over TCP. Returns `None` to fall back to TCP.
+pub async fn connect_relay(addr: &str, controlled: bool) -> Option<Stream> {
+    if !is_allowed(controlled) {
+        return None;
+    }
+    let res = async {
+        let addr = tokio::net::lookup_host(addr)
+            .await?
+            .next()
+            .ok_or_else(|| anyhow!("Failed to resolve {}", addr))?;
+        let socket = std::net::UdpSocket::bind(if addr.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        })?;
+        let local_addr = socket.local_addr()?;
+        let endpoint = new_endpoint(socket, None)?;
+        open_main(&endpoint, addr, local_addr, CONNECT_TIMEOUT, controlled).await
+    }
+    .await;
+    match res {
+        Ok(stream) => Some(stream),
+        Err(err) => {
+            log::info!("Failed to connect relay {} over QUIC: {}", addr, err);
+            None
+        }
+    }
+}
+
+/// The endpoint of the embedded relay, on the UDP port of the relay.
+pub fn new_relay_endpoint(addr: SocketAddr) -> ResultType<Endpoint> {
+    new_endpoint(std::net::UdpSocket::bind(addr)?, Some(server_config()?))
+}
+
+/// Accepts a relay connection, returns the main stream and the connection for the lanes.
+pub async fn accept_relay(
+    incoming: Incoming,
+    local_addr: SocketAddr,
+) -> ResultType<(Stream, Connection)> {
+    accept_main(incoming, local_addr).await
+}
+
+/// Pipes the lanes of the two relayed connections.
+pub async fn pipe_lanes(a: Connection, b: Connection) {
+    async fn copy(mut recv: RecvStream, mut send: SendStream) {
+        tokio::io::copy(&mut recv, &mut send).await.ok();
+        send.finish().ok();
+    }
+    async fn pipe(from: Connection, to: Connection) {
+        while let Ok((send, recv)) = from.accept_bi().await {
+            let to = to.clone();
+            tokio::spawn(async move {
+                if let Ok((to_send, to_recv)) = to.open_bi().await {
+                    tokio::join!(copy(recv, to_send), copy(to_recv, send));
+                }
+            });
+        }
+    }
+    tokio::join!(pipe(a.clone(), b.clone()), pipe(b, a));
+}
+
+/// Resets the lanes of the connection relayed to a TCP one, the sender falls back to the main
+/// stream.
+pub async fn reject_lanes(connection: Connection) {
+    while let Ok((mut send, mut recv)) = connection.accept_bi().await {
+        send.reset(VarInt::from_u32(0)).ok();
+        recv.stop(VarInt::from_u32(0)).ok();
+    }
+}
+
+/// Keeps the key of the main stream for the lanes.
+pub fn set_key(local_addr: &SocketAddr, key: &secretbox::Key) {
+    if let Some(entry) = CONNECTIONS.lock().unwrap().get_mut(local_addr) {
+        entry.key = Some(key.clone());
+    }
+}
+
+#[inline]
+pub fn is_quic(local_addr: &SocketAddr) -> bool {
+    CONNECTIONS.lock().unwrap().contains_key(local_addr)
+}
+
+/// The key of the lane sent by the controlled side or the controlling side.
+fn lane_key(
+    key: Option<&secretbox::Key>,
+    lane: QuicLane,
+    controlled: bool,
+) -> Option<secretbox::Key> {
+    let mut hasher = Sha256::new();
+    hasher.update(&key?.0);
+    hasher.update(format!("quic-lane-{}-{}", lane as u8, controlled));
+    secretbox::Key::from_slice(&hasher.finalize())
+}
+
+/// Takes the connection of the main stream, the messages received on the lanes are forwarded to
+/// the returned receiver.
+pub fn take_lanes(
+    local_addr: &SocketAddr,
+) -> (Option<QuicLanes>, mpsc::UnboundedReceiver<BytesMut>) {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let Some(entry) = CONNECTIONS.lock().unwrap().remove(local_addr) else {
+        return (None, rx);
+    };
+    tokio::spawn(accept_lanes(
+        entry.connection.clone(),
+        *local_addr,
+        entry.key.clone(),
+        !entry.controlled,
+        tx,
+    ));
+    (
+        Some(QuicLanes {
+            connection: entry.connection,
+            local_addr: *local_addr,
+            controlled: entry.controlled,
+            key: entry.key,
+            streams: Default::default(),
+            last_path: (0, 0),
+        }),
+        rx,
+    )
+}
+
+async fn accept_lanes(
+    connection: Connection,
+    local_addr: SocketAddr,
+    key: Option<secretbox::Key>,
+    peer_controlled: bool,
+    tx: mpsc::UnboundedSender<BytesMut>,
+) {
+    while let Ok((mut send, mut recv)) = connection.accept_bi().await {
+        let key = key.clone();
+        let tx = tx.clone();
+        tokio::spawn(async move {
+            let mut lane = [0u8; 1];
+            if recv.read_exact(&mut lane).await.is_err() {
+                return;
+            }
+            let Some(lane) = QuicLane::from_u8(lane[0]).filter(|x| *x != QuicLane::Main) else {
+                return;
+            };
+            if send.write_all(&[LANE_ACK]).await.is_err() {
+                return;
+            }
+            let mut stream = create_framed(send, recv, local_addr);
+            if let Some(key) = lane_key(key.as_ref(), lane, peer_controlled) {
+                stream.set_key(key);
+            }
+            while let Some(Ok(bytes)) = stream.next().await {
+                if tx.send(bytes).is_err() {
+                    break;
+                }
+            }
+            log::debug!("QUIC lane {:?} closed", lane);
+        });
+    }
+}
+
+/// The lanes of the QUIC connection, opened on the first message of each.
+pub struct QuicLanes {
+    connection: Connection,
+    local_addr: SocketAddr,
+    controlled: bool,
+    key: Option<secretbox::Key>,
+    // `None` if the lane is not available.
+    streams: HashMap<QuicLane, Option<Stream>>,
+    // (lost, sent) packets at the last `loss`
+    last_path: (u64, u64),
+}
+
+impl QuicLanes {
+    #[inline]
+    pub fn rtt(&self) -> Duration {
+        self.connection.rtt()
+    }
+
+    /// The packet loss ratio since the last call, `None` if nothing is sent.
+    pub fn loss(&mut self) -> Option<f32> {
+        let path = self.connection.stats().path;
+        let (lost, sent) = (path.lost_packets, path.sent_packets);
+        let (last_lost, last_sent) = std::mem::replace(&mut self.last_path, (lost, sent));
+        let sent = sent.saturating_sub(last_sent);
+        if sent == 0 {
+            return None;
+        }
+        Some((lost.saturating_sub(last_lost) as f32 / sent as f32).min(1.))
+    }
+
+    /// Returns the stream of the lane, or `main` if the lane is not available.
+    pub async fn stream<'a>(&'a mut self, lane: QuicLane, main: &'a mut Stream) -> &'a mut Stream {
+        if lane == QuicLane::Main {
+            return main;
+        }
+        if !self.streams.contains_key(&lane) {
+            let stream = match self.open(lane).await {
+                Ok(stream) => Some(stream),
+                Err(err) => {
+                    log::info!("QUIC lane {:?} is not available: {}", lane, err);
+                    None
+                }
+            };
+            self.streams.insert(lane, stream);
+        }
+        match self.streams.get_mut(&lane) {
+            Some(Some(stream)) => stream,
+            _ => main,
+        }
+    }
+
+    /// Sends the message on its lane.
+    pub async fn send(&mut self, main: &mut Stream, msg: &Message) -> ResultType<()> {
+        self.stream(QuicLane::of(msg), main).await.send(msg).await
+    }
+
+    async fn open(&self, lane: QuicLane) -> ResultType<Stream> {
+        let (mut send, mut recv) = self.connection.open_bi().await?;
+        send.write_all(&[lane as u8]).await?;
+        let mut ack = [0u8; 1];
+        timeout(LANE_TIMEOUT, recv.read_exact(&mut ack)).await??;
+        if ack[0] != LANE_ACK {
+            bail!("Invalid ack");
+        }
+        let mut stream = create_framed(send, recv, self.local_addr);
+        if let Some(key) = lane_key(self.key.as_ref(), lane, self.controlled) {
+            stream.set_key(key);
+        }
+        Ok(stream)
+    }
+}
@@ -714,26 +714,36 @@ impl RendezvousMediator {
     }
 }
 
-fn get_direct_port() -> i32 {
-    let mut port = Config::get_option("direct-access-port")
-        .parse::<i32>()
-        .unwrap_or(0);
-    if port <= 0 {
-        port = RENDEZVOUS_PORT + 2;
-    }
-    port
-}
-
 async fn direct_server(server: ServerPtr) {
     let mut listener = None;
     let mut port = 0;
+    let mut tls_options = "".to_owned();
+    let mut tls_acceptor = None;
     loop {
         let disabled = !option2bool(
             OPTION_DIRECT_SERVER,
             &Config::get_option(OPTION_DIRECT_SERVER),
         ) || option2bool("stop-service", &Config::get_option("stop-service"));
         if !disabled && listener.is_none() {
-            port = get_direct_port();
+            port = crate::get_direct_port();
+            tls_options = crate::direct_tls::get_server_options();
+            tls_acceptor = None;
+            if crate::direct_tls::is_server_enabled() {
+                match crate::direct_tls::new_acceptor() {
+                    Ok(acceptor) => tls_acceptor = Some(acceptor),
+                    Err(err) => {
+                        // Never fall back to the plain tcp, if tls is required.
+                        log::error!("Failed to load direct server tls config: {}", err);
+                        loop {
+                            if tls_options != crate::direct_tls::get_server_options() {
+                                break;
+                            }
+                            sleep(1.).await;
+                        }
+                        continue;
+                    }
+                }
+            }
             match hbb_common::tcp::listen_any(port as _).await {
                 Ok(l) => {
                     listener = Some(l);
@@ -750,7 +760,7 @@ async fn direct_server(server: ServerPtr) {
                         err
                     );
                     loop {
-                        if port != get_direct_port() {
+                        if port != crate::get_direct_port() {
                             break;
                         }
                         sleep(1.).await;
@@ -759,7 +769,10 @@ async fn direct_server(server: ServerPtr) {
             }
         }
         if let Some(l) = listener.as_mut() {
-            if disabled || port != get_direct_port() {
+            if disabled
+                || port != crate::get_direct_port()
+                || tls_options != crate::direct_tls::get_server_options()
+            {
                 log::info!("Exit direct access listen");
                 listener = None;
                 continue;
@@ -771,13 +784,34 @@ async fn direct_server(server: ServerPtr) {
                     .local_addr()
                     .unwrap_or(Config::get_any_listen_addr(true));
                 let server = server.clone();
+                let tls_acceptor = tls_acceptor.clone();
                 tokio::spawn(async move {
+                    let (mut stream, tls_identity) = match tls_acceptor {
+                        Some(acceptor) => {
+                            match crate::direct_tls::accept(&acceptor, stream, local_addr, addr)
+                                .await
+                            {
+                                Ok((stream, identity)) => (stream, Some(identity)),
+                                Err(err) => {
+                                    log::warn!("direct access tls from {} failed: {}", addr, err);
+                                    return;
+                                }
+                            }
+                        }
+                        None => (hbb_common::Stream::from(stream, local_addr), None),
+                    };
+                    // The peer connecting by the direct profile asks to secure the connection.
+                    let Some(secure) = super::lan::accept_direct_request(&mut stream, addr).await
+                    else {
+                        return;
+                    };
                     allow_err!(
                         crate::server::create_tcp_connection(
                             server,
-                            hbb_common::Stream::from(stream, local_addr),
+                            stream,
                             addr,
-                            false,
+                            secure,
+                            tls_identity
                         )
                         .await
                     );
@@ -831,14 +865,28 @@ async fn udp_nat_listen(
     let socket_cloned = socket.clone();
     let func = async {
         socket.connect(peer_addr).await?;
-        let res = crate::punch_udp(socket.clone(), true).await?;
+        let mut res = crate::punch_udp(socket.clone(), true).await?;
+        if res
+            .as_ref()
+            .map_or(false, |x| crate::quic_stream::is_initial_packet(x))
+        {
+            if crate::quic_stream::is_allowed(true) {
+                let stream = crate::quic_stream::accept(socket, CONNECT_TIMEOUT as _).await?;
+                crate::server::create_tcp_connection(server, stream, peer_addr_v4, true, None)
+                    .await?;
+                return Ok(());
+            }
+            // The client falls back to KCP.
+            res = None;
+        }
         let stream = crate::kcp_stream::KcpStream::accept(
             socket,
             Duration::from_millis(CONNECT_TIMEOUT as _),
             res,
+            crate::kcp_stream::KcpProfile::get_server(),
         )
         .await?;
-        crate::server::create_tcp_connection(server, stream.1, peer_addr_v4, true).await?;
+        crate::server::create_tcp_connection(server, stream.1, peer_addr_v4, true, None).await?;
         Ok(())
     };
     func.await.map_err(|e: anyhow::Error| {
@@ -0,0 +1,641 @@
+// A minimal rendezvous and relay server, `--rendezvous-server [port]`, for small deployments
+// without hbbs/hbbr.
+//
+// It speaks the same protocol as hbbs/hbbr, so the existing clients register to it and connect
+// through it with the address of this machine as the ID server and the printed public key as
+// the key. Only the relayed connections are supported, there is no hole punching.
+//
+// Ports: `port` (21116 by default) UDP/TCP for the registration and the connection requests,
+// `port - 1` TCP for the NAT test, `port + 1` TCP and UDP (QUIC) for the relay. The QUIC lanes
+// are piped too if both sides connect over QUIC.
+// The signing key and the registered peers are kept in `rendezvous_server.toml`.
+// Like hbbs/hbbr with `-k`, the connection and relay requests with another key are rejected.
+
+use hbb_common::{
+    allow_err, bail,
+    bytes::Bytes,
+    config::{self, Config, RENDEZVOUS_PORT},
+    log,
+    protobuf::Message as _,
+    rendezvous_proto::*,
+    sodiumoxide::crypto::{box_, sign},
+    tcp::{self, new_listener},
+    timeout,
+    tokio::{
+        self,
+        net::{TcpStream, UdpSocket},
+        select,
+        sync::mpsc,
+        time::interval,
+    },
+    AddrMangle, ResultType, Stream,
+};
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+type Message = RendezvousMessage;
+type Sender = mpsc::UnboundedSender<Message>;
+
+// A peer is offline if not registered within this time.
+const REG_TIMEOUT: u64 = 30_000;
+// Seconds, told to the peers in `RegisterPkResponse`.
+const KEEP_ALIVE: i32 = 60;
+// Heartbeat of the peers registered over tcp.
+const HEARTBEAT_INTERVAL: u64 = 10_000;
+// How long a relay connection or a connection request waits for the other side.
+const RELAY_TIMEOUT: u64 = 30_000;
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+struct StoredPeer {
+    #[serde(default)]
+    uuid: String,
+    #[serde(default)]
+    pk: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Store {
+    #[serde(default)]
+    sk: String,
+    #[serde(default)]
+    peers: HashMap<String, StoredPeer>,
+}
+
+impl Store {
+    fn path() -> std::path::PathBuf {
+        Config::path("rendezvous_server.toml")
+    }
+
+    fn load() -> Self {
+        config::load_path(Self::path())
+    }
+
+    fn store(&self) {
+        allow_err!(config::store_path(Self::path(), self));
+    }
+
+    fn get_key_pair(&mut self) -> (sign::PublicKey, sign::SecretKey) {
+        if let Some(sk) = crate::decode64(&self.sk)
+            .ok()
+            .and_then(|sk| sign::SecretKey::from_slice(&sk))
+        {
+            if let Some(pk) = sign::PublicKey::from_slice(&sk.0[sign::SECRETKEYBYTES / 2..]) {
+                return (pk, sk);
+            }
+        }
+        let (pk, sk) = sign::gen_keypair();
+        self.sk = crate::encode64(&sk.0);
+        self.store();
+        (pk, sk)
+    }
+}
+
+#[derive(Default)]
+struct Peer {
+    uuid: Bytes,
+    pk: Bytes,
+    udp_addr: Option<SocketAddr>,
+    tcp: Option<Sender>,
+    last_seen: Option<Instant>,
+}
+
+impl Peer {
+    fn is_online(&self) -> bool {
+        self.last_seen
+            .map(|x| x.elapsed().as_millis() < REG_TIMEOUT as u128)
+            .unwrap_or(false)
+    }
+
+    fn update(&mut self, addr: SocketAddr, tcp: Option<&Sender>) {
+        self.last_seen = Some(Instant::now());
+        if let Some(tcp) = tcp {
+            self.tcp = Some(tcp.clone());
+            self.udp_addr = None;
+        } else {
+            self.tcp = None;
+            self.udp_addr = Some(addr);
+        }
+    }
+}
+
+#[derive(Default)]
+struct State {
+    store: Store,
+    peers: HashMap<String, Peer>,
+    // client address -> the connection waiting for the relay response of the peer
+    waiting: HashMap<SocketAddr, Sender>,
+    // uuid -> the first connection of the relay, with the QUIC connection of the lanes
+    relays: HashMap<String, (Stream, Option<quinn::Connection>, Instant)>,
+}
+
+#[derive(Clone)]
+struct Server {
+    state: Arc<Mutex<State>>,
+    sk: sign::SecretKey,
+    // The printed public key, the clients must be configured with.
+    key: String,
+    udp: Arc<UdpSocket>,
+}
+
+pub fn parse_port(args: &[String]) -> u16 {
+    args.first()
+        .and_then(|x| x.parse().ok())
+        .unwrap_or(RENDEZVOUS_PORT as u16)
+}
+
+// Returns the nat test port and the relay port.
+fn get_ports(port: u16) -> ResultType<(u16, u16)> {
+    match (port.checked_sub(1), port.checked_add(1)) {
+        (Some(nat_port), Some(relay_port)) if nat_port > 0 => Ok((nat_port, relay_port)),
+        _ => bail!("Invalid port {}, port - 1 and port + 1 are used too", port),
+    }
+}
+
+impl State {
+    // Drops the relay connections whose peer has not come in time.
+    fn prune_relays(&mut self) {
+        self.relays
+            .retain(|_, (_, _, t)| t.elapsed().as_millis() < RELAY_TIMEOUT as u128);
+    }
+}
+
+#[tokio::main]
+pub async fn start(port: u16) -> ResultType<()> {
+    let (nat_port, relay_port) = get_ports(port)?;
+    let mut store = Store::load();
+    let (pk, sk) = store.get_key_pair();
+    let peers = store
+        .peers
+        .iter()
+        .map(|(id, p)| {
+            let peer = Peer {
+                uuid: crate::decode64(&p.uuid).unwrap_or_default().into(),
+                pk: crate::decode64(&p.pk).unwrap_or_default().into(),
+                ..Default::default()
+            };
+            (id.clone(), peer)
+        })
+        .collect();
+    let addr = |port: u16| SocketAddr::from(([0, 0, 0, 0], port));
+    let udp = Arc::new(UdpSocket::bind(addr(port)).await?);
+    let listener = new_listener(addr(port), false).await?;
+    let nat_listener = new_listener(addr(nat_port), false).await?;
+    let relay_listener = new_listener(addr(relay_port), false).await?;
+    let relay_endpoint = crate::quic_stream::new_relay_endpoint(addr(relay_port))?;
+    let key = crate::encode64(&pk.0);
+    let server = Server {
+        state: Arc::new(Mutex::new(State {
+            store,
+            peers,
+            ..Default::default()
+        })),
+        sk,
+        key: key.clone(),
+        udp: udp.clone(),
+    };
+    log::info!(
+        "rendezvous server started on port {}, {} peers, key: {}",
+        port,
+        server.state.lock().unwrap().peers.len(),
+        key
+    );
+    println!(
+        "Listening on {} (udp/tcp), {} (nat test), {} (relay, tcp/quic)",
+        port, nat_port, relay_port
+    );
+    println!("Key: {}", key);
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut prune_timer = interval(Duration::from_millis(RELAY_TIMEOUT));
+    loop {
+        select! {
+            _ = prune_timer.tick() => {
+                server.state.lock().unwrap().prune_relays();
+            }
+            res = udp.recv_from(&mut buf) => {
+                if let Ok((len, addr)) = res {
+                    if let Ok(msg) = Message::parse_from_bytes(&buf[..len]) {
+                        allow_err!(server.handle_udp(msg, addr).await);
+                    }
+                }
+            }
+            Ok((stream, addr)) = listener.accept() => {
+                let server = server.clone();
+                tokio::spawn(async move {
+                    allow_err!(server.handle_tcp(stream, addr, true).await);
+                });
+            }
+            Ok((stream, addr)) = nat_listener.accept() => {
+                let server = server.clone();
+                tokio::spawn(async move {
+                    allow_err!(server.handle_tcp(stream, addr, false).await);
+                });
+            }
+            Ok((stream, _)) = relay_listener.accept() => {
+                let server = server.clone();
+                tokio::spawn(async move {
+                    stream.set_nodelay(true).ok();
+                    let local_addr = stream.local_addr().unwrap_or(addr(relay_port));
+                    allow_err!(server.handle_relay(Stream::from(stream, local_addr), None).await);
+                });
+            }
+            Some(incoming) = relay_endpoint.accept() => {
+                let server = server.clone();
+                tokio::spawn(async move {
+                    match crate::quic_stream::accept_relay(incoming, addr(relay_port)).await {
+                        Ok((stream, connection)) => {
+                            allow_err!(server.handle_relay(stream, Some(connection)).await);
+                        }
+                        Err(err) => log::debug!("QUIC relay connection failed: {}", err),
+                    }
+                });
+            }
+        }
+    }
+}
+
+impl Server {
+    async fn handle_udp(&self, msg: Message, addr: SocketAddr) -> ResultType<()> {
+        let msg_out = match msg.union {
+            Some(rendezvous_message::Union::RegisterPeer(rp)) => self.register_peer(rp, addr, None),
+            Some(rendezvous_message::Union::RegisterPk(rk)) => self.register_pk(rk, addr, None),
+            _ => return Ok(()),
+        };
+        self.udp.send_to(&msg_out.write_to_bytes()?, addr).await?;
+        Ok(())
+    }
+
+    async fn handle_tcp(
+        &self,
+        stream: TcpStream,
+        addr: SocketAddr,
+        secure: bool,
+    ) -> ResultType<()> {
+        stream.set_nodelay(true).ok();
+        let local_addr = stream.local_addr()?;
+        let mut stream = Stream::from(stream, local_addr);
+        let (our_pk_b, our_sk_b) = box_::gen_keypair();
+        if secure {
+            let mut msg_out = Message::new();
+            msg_out.set_key_exchange(KeyExchange {
+                keys: vec![sign::sign(&our_pk_b.0, &self.sk).into()],
+                ..Default::default()
+            });
+            stream.send(&msg_out).await?;
+        }
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        // the peer registered over this connection
+        let mut id = String::new();
+        let mut last_recv = Instant::now();
+        let mut timer = interval(Duration::from_millis(HEARTBEAT_INTERVAL));
+        let res: ResultType<()> = async {
+            loop {
+                select! {
+                    res = stream.next() => {
+                        let Some(Ok(bytes)) = res else {
+                            break;
+                        };
+                        last_recv = Instant::now();
+                        if !id.is_empty() {
+                            self.touch(&id);
+                        }
+                        let Ok(msg) = Message::parse_from_bytes(&bytes) else {
+                            continue;
+                        };
+                        match msg.union {
+                            Some(rendezvous_message::Union::KeyExchange(ex)) => {
+                                if secure && ex.keys.len() == 2 {
+                                    stream.set_key(tcp::Encrypt::decode(
+                                        &ex.keys[1],
+                                        &ex.keys[0],
+                                        &our_sk_b,
+                                    )?);
+                                }
+                            }
+                            Some(rendezvous_message::Union::RegisterPeer(rp)) => {
+                                id = rp.id.clone();
+                                stream.send(&self.register_peer(rp, addr, Some(&tx))).await?;
+                            }
+                            Some(rendezvous_message::Union::RegisterPk(rk)) => {
+                                id = rk.id.clone();
+                                stream.send(&self.register_pk(rk, addr, Some(&tx))).await?;
+                            }
+                            Some(rendezvous_message::Union::PunchHoleRequest(ph)) => {
+                                if let Some(msg_out) = self.punch_hole(ph, addr, &tx).await? {
+                                    stream.send(&msg_out).await?;
+                                }
+                            }
+                            Some(rendezvous_message::Union::RelayResponse(rr)) => {
+                                self.relay_response(rr);
+                            }
+                            Some(rendezvous_message::Union::TestNatRequest(_)) => {
+                                let mut msg_out = Message::new();
+                                msg_out.set_test_nat_response(TestNatResponse {
+                                    port: addr.port() as _,
+                                    ..Default::default()
+                                });
+                                stream.send(&msg_out).await?;
+                            }
+                            Some(rendezvous_message::Union::OnlineRequest(or)) => {
+                                stream.send(&self.online_response(or)).await?;
+                            }
+                            _ => {}
+                        }
+                    }
+                    Some(msg_out) = rx.recv() => {
+                        stream.send(&msg_out).await?;
+                    }
+                    _ = timer.tick() => {
+                        if !id.is_empty() {
+                            stream.send_bytes(Bytes::new()).await?;
+                        } else if last_recv.elapsed().as_millis() as u64 > RELAY_TIMEOUT {
+                            break;
+                        }
+                    }
+                }
+            }
+            Ok(())
+        }
+        .await;
+        let mut state = self.state.lock().unwrap();
+        state.waiting.remove(&addr);
+        if let Some(peer) = state.peers.get_mut(&id) {
+            if peer.tcp.as_ref().map(|x| x.same_channel(&tx)) == Some(true) {
+                peer.tcp = None;
+                peer.last_seen = None;
+            }
+        }
+        res
+    }
+
+    fn touch(&self, id: &str) {
+        if let Some(peer) = self.state.lock().unwrap().peers.get_mut(id) {
+            peer.last_seen = Some(Instant::now());
+        }
+    }
+
+    fn register_peer(&self, rp: RegisterPeer, addr: SocketAddr, tcp: Option<&Sender>) -> Message {
+        let mut state = self.state.lock().unwrap();
+        let request_pk = match state.peers.get_mut(&rp.id) {
+            Some(peer) if !peer.pk.is_empty() => {
+                peer.update(addr, tcp);
+                false
+            }
+            _ => true,
+        };
+        let mut msg_out = Message::new();
+        msg_out.set_register_peer_response(RegisterPeerResponse {
+            request_pk,
+            ..Default::default()
+        });
+        msg_out
+    }
+
+    // `RegisterPk` carries no key, so as hbbs the key is checked on the connection requests.
+    fn register_pk(&self, rk: RegisterPk, addr: SocketAddr, tcp: Option<&Sender>) -> Message {
+        let mut guard = self.state.lock().unwrap();
+        let state = &mut *guard;
+        let result = if rk.id.is_empty() || rk.pk.is_empty() {
+            register_pk_response::Result::INVALID_ID_FORMAT
+        } else {
+            let peer = state.peers.entry(rk.id.clone()).or_default();
+            if !peer.uuid.is_empty() && peer.uuid != rk.uuid {
+                log::warn!("uuid mismatch of {} from {}", rk.id, addr);
+                register_pk_response::Result::UUID_MISMATCH
+            } else {
+                if peer.uuid != rk.uuid || peer.pk != rk.pk {
+                    log::info!("peer {} registered from {}", rk.id, addr);
+                    peer.uuid = rk.uuid.clone();
+                    peer.pk = rk.pk.clone();
+                    state.store.peers.insert(
+                        rk.id.clone(),
+                        StoredPeer {
+                            uuid: crate::encode64(&rk.uuid),
+                            pk: crate::encode64(&rk.pk),
+                        },
+                    );
+                    state.store.store();
+                }
+                peer.update(addr, tcp);
+                register_pk_response::Result::OK
+            }
+        };
+        let mut msg_out = Message::new();
+        msg_out.set_register_pk_response(RegisterPkResponse {
+            result: result.into(),
+            keep_alive: KEEP_ALIVE,
+            ..Default::default()
+        });
+        msg_out
+    }
+
+    /// Asks the peer to connect to the relay, the client gets the relay response later.
+    async fn punch_hole(
+        &self,
+        ph: PunchHoleRequest,
+        addr: SocketAddr,
+        tx: &Sender,
+    ) -> ResultType<Option<Message>> {
+        let target = {
+            let mut state = self.state.lock().unwrap();
+            let target = match state.peers.get(&ph.id) {
+                _ if ph.licence_key != self.key => {
+                    log::warn!("key mismatch of the connection request from {}", addr);
+                    Err(punch_hole_response::Failure::LICENSE_MISMATCH)
+                }
+                None => Err(punch_hole_response::Failure::ID_NOT_EXIST),
+                Some(peer) if !peer.is_online() => Err(punch_hole_response::Failure::OFFLINE),
+                Some(peer) => Ok((peer.udp_addr, peer.tcp.clone())),
+            };
+            if target.is_ok() {
+                state.waiting.insert(addr, tx.clone());
+            }
+            target
+        };
+        let (udp_addr, tcp) = match target {
+            Ok(target) => target,
+            Err(failure) => {
+                let mut msg_out = Message::new();
+                msg_out.set_punch_hole_response(PunchHoleResponse {
+                    failure: failure.into(),
+                    ..Default::default()
+                });
+                return Ok(Some(msg_out));
+            }
+        };
+        log::info!("connection request from {} to {}", addr, ph.id);
+        let mut msg_out = Message::new();
+        msg_out.set_punch_hole(PunchHole {
+            socket_addr: AddrMangle::encode(addr).into(),
+            nat_type: ph.nat_type,
+            force_relay: true,
+            ..Default::default()
+        });
+        if let Some(tcp) = tcp {
+            tcp.send(msg_out).ok();
+        } else if let Some(udp_addr) = udp_addr {
+            self.udp
+                .send_to(&msg_out.write_to_bytes()?, udp_addr)
+                .await?;
+        }
+        Ok(None)
+    }
+
+    /// Forwards the relay response of the peer to the waiting client, with the signed pk of the peer.
+    fn relay_response(&self, mut rr: RelayResponse) {
+        let addr = AddrMangle::decode(&rr.socket_addr);
+        let mut state = self.state.lock().unwrap();
+        let Some(tx) = state.waiting.remove(&addr) else {
+            return;
+        };
+        let id = rr.id().to_owned();
+        if let Some(peer) = state.peers.get(&id) {
+            let id_pk = IdPk {
+                id,
+                pk: peer.pk.clone(),
+                ..Default::default()
+            };
+            rr.set_pk(sign::sign(&id_pk.write_to_bytes().unwrap_or_default(), &self.sk).into());
+        }
+        let mut msg_out = Message::new();
+        msg_out.set_relay_response(rr);
+        tx.send(msg_out).ok();
+    }
+
+    fn online_response(&self, or: OnlineRequest) -> Message {
+        let state = self.state.lock().unwrap();
+        let mut states = vec![0u8; (or.peers.len() + 7) / 8];
+        for (i, id) in or.peers.iter().enumerate() {
+            if state.peers.get(id).map(|x| x.is_online()) == Some(true) {
+                states[i / 8] |= 0x01 << (7 - i % 8);
+            }
+        }
+        let mut msg_out = Message::new();
+        msg_out.set_online_response(OnlineResponse {
+            states: states.into(),
+            ..Default::default()
+        });
+        msg_out
+    }
+
+    /// Pairs the two relay connections with the same uuid and pipes them.
+    async fn handle_relay(
+        &self,
+        mut stream: Stream,
+        quic: Option<quinn::Connection>,
+    ) -> ResultType<()> {
+        let Some(Ok(bytes)) = timeout(RELAY_TIMEOUT, stream.next()).await? else {
+            return Ok(());
+        };
+        let Some(rendezvous_message::Union::RequestRelay(rr)) =
+            Message::parse_from_bytes(&bytes)?.union
+        else {
+            return Ok(());
+        };
+        if rr.uuid.is_empty() {
+            return Ok(());
+        }
+        if rr.licence_key != self.key {
+            log::warn!("key mismatch of the relay request {}", rr.uuid);
+            return Ok(());
+        }
+        let (peer, peer_quic) = {
+            let mut state = self.state.lock().unwrap();
+            state.prune_relays();
+            match state.relays.remove(&rr.uuid) {
+                Some((peer, peer_quic, _)) => (peer, peer_quic),
+                None => {
+                    state.relays.insert(rr.uuid, (stream, quic, Instant::now()));
+                    return Ok(());
+                }
+            }
+        };
+        match (quic, peer_quic) {
+            (Some(a), Some(b)) => {
+                tokio::spawn(crate::quic_stream::pipe_lanes(a, b));
+            }
+            (Some(x), None) | (None, Some(x)) => {
+                tokio::spawn(crate::quic_stream::reject_lanes(x));
+            }
+            (None, None) => {}
+        }
+        log::info!("relay {} started", rr.uuid);
+        pipe(stream, peer).await;
+        log::info!("relay {} closed", rr.uuid);
+        Ok(())
+    }
+}
+
+async fn pipe(mut a: Stream, mut b: Stream) {
+    loop {
+        select! {
+            res = a.next() => match res {
+                Some(Ok(bytes)) => {
+                    if b.send_bytes(bytes.freeze()).await.is_err() {
+                        break;
+                    }
+                }
+                _ => break,
+            },
+            res = b.next() => match res {
+                Some(Ok(bytes)) => {
+                    if a.send_bytes(bytes.freeze()).await.is_err() {
+                        break;
+                    }
+                }
+                _ => break,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_ports() {
+        assert_eq!(get_ports(21116).unwrap(), (21115, 21117));
+        assert_eq!(get_ports(2).unwrap(), (1, 3));
+        assert!(get_ports(0).is_err());
+        assert!(get_ports(1).is_err());
+        assert!(get_ports(u16::MAX).is_err());
+    }
+
+    #[test]
+    fn test_parse_port() {
+        assert_eq!(parse_port(&[]), RENDEZVOUS_PORT as u16);
+        assert_eq!(parse_port(&["12345".to_owned()]), 12345);
+        assert_eq!(parse_port(&["65536".to_owned()]), RENDEZVOUS_PORT as u16);
+    }
+
+    #[tokio::test]
+    async fn test_prune_relays() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let local_addr = stream.local_addr().unwrap();
+        let mut state = State::default();
+        let stale = Instant::now()
+            .checked_sub(Duration::from_millis(RELAY_TIMEOUT + 1))
+            .unwrap();
+        state.relays.insert(
+            "stale".to_owned(),
+            (Stream::from(stream, local_addr), None, stale),
+        );
+        let stream = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        state.relays.insert(
+            "fresh".to_owned(),
+            (Stream::from(stream, local_addr), None, Instant::now()),
+        );
+        state.prune_relays();
+        assert_eq!(state.relays.keys().collect::<Vec<_>>(), vec!["fresh"]);
+    }
+}
@@ -33,6 +33,7 @@ use video_service::VideoSource;
 use crate::ipc::Data;
 
 pub mod audio_service;
+pub mod bandwidth_estimator;
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 pub mod terminal_service;
 cfg_if::cfg_if! {
@@ -47,6 +48,8 @@ pub mod uinput;
 #[cfg(target_os = "linux")]
 pub mod rdp_input;
 #[cfg(target_os = "linux")]
+pub mod wlr_input;
+#[cfg(target_os = "linux")]
 pub mod dbus;
 #[cfg(not(target_os = "android"))]
 pub mod input_service;
@@ -69,8 +72,10 @@ pub mod display_service;
 #[cfg(windows)]
 pub mod portable_service;
 mod service;
-mod video_qos;
+pub mod video_qos;
 pub mod video_service;
+#[cfg(any(target_os = "windows", target_os = "linux"))]
+pub mod window_share;
 
 #[cfg(all(target_os = "windows", feature = "flutter"))]
 pub mod printer_service;
@@ -163,7 +168,14 @@ async fn accept_connection_(server: ServerPtr, socket: Stream, secure: bool) ->
     if let Ok((stream, addr)) = timeout(CONNECT_TIMEOUT, listener.accept()).await? {
         stream.set_nodelay(true).ok();
         let stream_addr = stream.local_addr()?;
-        create_tcp_connection(server, Stream::from(stream, stream_addr), addr, secure).await?;
+        create_tcp_connection(
+            server,
+            Stream::from(stream, stream_addr),
+            addr,
+            secure,
+            None,
+        )
+        .await?;
     }
     Ok(())
 }
@@ -173,6 +185,7 @@ pub async fn create_tcp_connection(
     stream: Stream,
     addr: SocketAddr,
     secure: bool,
+    tls_identity: Option<String>,
 ) -> ResultType<()> {
     let mut stream = stream;
     let id = server.write().unwrap().get_new_id();
@@ -204,11 +217,13 @@ pub async fn create_tcp_connection(
                 if let Ok(msg_in) = Message::parse_from_bytes(&bytes) {
                     if let Some(message::Union::PublicKey(pk)) = msg_in.union {
                         if pk.asymmetric_value.len() == box_::PUBLICKEYBYTES {
-                            stream.set_key(tcp::Encrypt::decode(
+                            let key = tcp::Encrypt::decode(
                                 &pk.symmetric_value,
                                 &pk.asymmetric_value,
                                 &our_sk_b,
-                            )?);
+                            )?;
+                            crate::quic_stream::set_key(&stream.local_addr(), &key);
+                            stream.set_key(key);
                         } else if pk.asymmetric_value.is_empty() {
                             Config::set_key_confirmed(false);
                             log::info!("Force to update pk");
@@ -240,7 +255,15 @@ pub async fn create_tcp_connection(
         }
         log::info!("wake up macos");
     }
-    Connection::start(addr, stream, id, Arc::downgrade(&server)).await;
+    Connection::start(
+        addr,
+        stream,
+        id,
+        Arc::downgrade(&server),
+        tls_identity,
+        None,
+    )
+    .await;
     Ok(())
 }
 
@@ -283,11 +306,12 @@ async fn create_relay_connection_(
     secure: bool,
     ipv4: bool,
 ) -> ResultType<()> {
-    let mut stream = socket_client::connect_tcp(
-        socket_client::ipv4_to_ipv6(crate::check_port(relay_server, RELAY_PORT), ipv4),
-        CONNECT_TIMEOUT,
-    )
-    .await?;
+    let relay_server =
+        socket_client::ipv4_to_ipv6(crate::check_port(relay_server, RELAY_PORT), ipv4);
+    let mut stream = match crate::quic_stream::connect_relay(&relay_server, true).await {
+        Some(stream) => stream,
+        None => socket_client::connect_tcp(relay_server, CONNECT_TIMEOUT).await?,
+    };
     let mut msg_out = RendezvousMessage::new();
     let licence_key = crate::get_key(true).await;
     msg_out.set_request_relay(RequestRelay {
@@ -296,7 +320,7 @@ async fn create_relay_connection_(
         ..Default::default()
     });
     stream.send(&msg_out).await?;
-    create_tcp_connection(server, stream, peer_addr, secure).await?;
+    create_tcp_connection(server, stream, peer_addr, secure, None).await?;
     Ok(())
 }
 
@@ -492,7 +516,7 @@ impl Drop for Server {
             s.join();
         }
         #[cfg(target_os = "linux")]
-        wayland::clear();
+        wayland::try_clear();
     }
 }
 
@@ -564,7 +588,8 @@ pub async fn start_server(is_server: bool, no_server: bool) {
         input_service::fix_key_down_timeout_loop();
         #[cfg(target_os = "linux")]
         if input_service::wayland_use_uinput() {
-            allow_err!(input_service::setup_uinput(0, 1920, 0, 1080).await);
+            let (minx, maxx, miny, maxy) = wayland::get_uinput_resolution();
+            allow_err!(input_service::setup_uinput(minx, maxx, miny, maxy).await);
         }
         #[cfg(any(target_os = "macos", target_os = "linux"))]
         tokio::spawn(async { sync_and_watch_config_dir().await });
@@ -610,6 +635,100 @@ pub async fn start_server(is_server: bool, no_server: bool) {
     }
 }
 
+// The first frame from the main server to the server of a headless session,
+// the login request of the handed off connection is checked with the hash sent by the main server.
+#[cfg(target_os = "linux")]
+#[derive(Debug, serde_derive::Serialize, serde_derive::Deserialize)]
+pub struct HeadlessHandOff {
+    pub addr: SocketAddr,
+    pub salt: String,
+    pub challenge: String,
+    pub tls_identity: Option<String>,
+}
+
+/// Start the server of a headless session, it serves the connections handed off by the main server.
+#[cfg(target_os = "linux")]
+#[tokio::main]
+pub async fn start_headless_session_server(display_num: u32) {
+    let listener = match crate::platform::linux_desktop_manager::bind_session_server(display_num) {
+        Ok(listener) => listener,
+        Err(e) => {
+            log::error!(
+                "Failed to listen for the headless session :{}, {}",
+                display_num,
+                e
+            );
+            return;
+        }
+    };
+    input_service::fix_key_down_timeout_loop();
+    tokio::spawn(async { sync_config_from_server().await });
+    let server = new();
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                let server = server.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = accept_hand_off(server, stream).await {
+                        log::error!("Failed to accept the handed off connection: {}", e);
+                    }
+                });
+            }
+            Err(e) => {
+                log::error!("Failed to accept the handed off connection: {}", e);
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+async fn accept_hand_off(server: ServerPtr, stream: tokio::net::UnixStream) -> ResultType<()> {
+    let local_addr: SocketAddr = ([127, 0, 0, 1], 0).into();
+    let mut stream = crate::direct_tls::create_framed(stream, local_addr);
+    let hand_off = match timeout(CONNECT_TIMEOUT, stream.next()).await? {
+        Some(res) => serde_json::from_slice::<HeadlessHandOff>(&res?)?,
+        None => bail!("Failed to receive the hand-off"),
+    };
+    let id = server.write().unwrap().get_new_id();
+    let hash = Hash {
+        salt: hand_off.salt,
+        challenge: hand_off.challenge,
+        ..Default::default()
+    };
+    Connection::start(
+        hand_off.addr,
+        stream,
+        id,
+        Arc::downgrade(&server),
+        hand_off.tls_identity,
+        Some(hash),
+    )
+    .await;
+    Ok(())
+}
+
+// The config is changed in the main server, e.g. the password.
+#[cfg(target_os = "linux")]
+async fn sync_config_from_server() {
+    loop {
+        if let Ok(mut conn) = crate::ipc::connect(1000, "").await {
+            if conn.send(&Data::SyncConfig(None)).await.is_ok() {
+                if let Ok(Some(Data::SyncConfig(Some(configs)))) = conn.next_timeout(1000).await {
+                    let (config, config2) = *configs;
+                    if Config::set(config) {
+                        log::info!("config synced");
+                    }
+                    if Config2::set(config2) {
+                        log::info!("config2 synced");
+                    }
+                }
+            }
+        }
+        hbb_common::sleep(1.0).await;
+    }
+}
+
 #[cfg(target_os = "macos")]
 #[tokio::main(flavor = "current_thread")]
 pub async fn start_ipc_url_server() {
@@ -13,17 +13,37 @@
 // https://github.com/krruzic/pulsectl
 
 use super::*;
+#[cfg(target_os = "linux")]
+use crate::common::{make_audio_msg, AudioMessage};
+use crate::hifi_audio::AudioEncoder;
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+use crate::hifi_audio::{HIFI_MAX_CHANNELS, HIFI_SAMPLE_RATE};
 #[cfg(not(any(target_os = "linux", target_os = "android")))]
 use hbb_common::anyhow::anyhow;
-use magnum_opus::{Application::*, Channels::*, Encoder};
+use std::collections::HashSet;
 use std::sync::atomic::{AtomicBool, Ordering};
 
 pub const NAME: &'static str = "audio";
 pub const AUDIO_DATA_SIZE_U8: usize = 960 * 4; // 10ms in 48000 stereo
 static RESTARTING: AtomicBool = AtomicBool::new(false);
+// The audio input of the `_pa` ipc to capture an application instead of a source.
+#[cfg(target_os = "linux")]
+pub const APP_AUDIO_PREFIX: &'static str = "app:";
 
 lazy_static::lazy_static! {
     static ref VOICE_CALL_INPUT_DEVICE: Arc::<Mutex::<Option<String>>> = Default::default();
+    // The connections that requested the hi-fi mode, the others get the standard frames.
+    static ref HIFI_CONNS: Arc::<Mutex::<HashSet<i32>>> = Default::default();
+}
+
+#[cfg(target_os = "linux")]
+lazy_static::lazy_static! {
+    // (application name, conn id). There is one audio service for all connections, so the
+    // application captured is global: the last request of any connection wins, and the capture
+    // stops when the connection of that request closes. The peers see it in `AudioMessage::Apps`.
+    static ref CAPTURE_APP: Arc::<Mutex::<Option<(String, i32)>>> = Default::default();
+    // The connections playing the microphones of the peers to the virtual microphone.
+    static ref MIC_PASSTHROUGH_CONNS: Arc::<Mutex::<HashSet<i32>>> = Default::default();
 }
 
 #[cfg(not(any(target_os = "linux", target_os = "android")))]
@@ -60,11 +80,110 @@ pub fn set_voice_call_input_device(device: Option<String>, set_if_present: bool)
 
 #[inline]
 fn get_audio_input() -> String {
-    VOICE_CALL_INPUT_DEVICE
-        .lock()
-        .unwrap()
-        .clone()
-        .unwrap_or(Config::get_option("audio-input"))
+    let device = VOICE_CALL_INPUT_DEVICE.lock().unwrap().clone();
+    #[cfg(target_os = "linux")]
+    if device.is_none() {
+        if let Some((app, _)) = CAPTURE_APP.lock().unwrap().as_ref() {
+            return format!("{}{}", APP_AUDIO_PREFIX, app);
+        }
+    }
+    device.unwrap_or(Config::get_option("audio-input"))
+}
+
+#[cfg(target_os = "linux")]
+pub fn handle_request(conn_id: i32, msg: AudioMessage) -> Option<Message> {
+    let res = match msg {
+        AudioMessage::ListApps => AudioMessage::Apps {
+            apps: crate::platform::linux::get_pa_applications(),
+            current: CAPTURE_APP
+                .lock()
+                .unwrap()
+                .as_ref()
+                .map(|(app, _)| app.clone()),
+        },
+        AudioMessage::CaptureApp { app } => {
+            set_capture_app(conn_id, app);
+            return None;
+        }
+        _ => return None,
+    };
+    Some(make_audio_msg(&res))
+}
+
+// See `CAPTURE_APP`, the request replaces the one of another connection.
+#[cfg(target_os = "linux")]
+fn set_capture_app(conn_id: i32, app: Option<String>) {
+    let app = app.filter(|app| !app.is_empty());
+    let mut lock = CAPTURE_APP.lock().unwrap();
+    let changed = lock.as_ref().map(|(app, _)| app) != app.as_ref();
+    if changed {
+        log::info!("#{} captures the audio of {:?}", conn_id, app);
+    }
+    if let Some((old, id)) = lock.as_ref().filter(|(_, id)| *id != conn_id) {
+        log::info!(
+            "#{} replaces the audio capture of {} by #{}",
+            conn_id,
+            old,
+            id
+        );
+    }
+    *lock = app.map(|app| (app, conn_id));
+    drop(lock);
+    if changed {
+        restart();
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub fn set_mic_passthrough(conn_id: i32, enable: bool) -> ResultType<()> {
+    let mut conns = MIC_PASSTHROUGH_CONNS.lock().unwrap();
+    if enable {
+        if conns.is_empty() {
+            crate::platform::linux::load_pa_virtual_mic()?;
+        }
+        conns.insert(conn_id);
+    } else if conns.remove(&conn_id) && conns.is_empty() {
+        crate::platform::linux::unload_pa_virtual_mic();
+    }
+    Ok(())
+}
+
+#[inline]
+fn get_hifi_conns() -> HashSet<i32> {
+    HIFI_CONNS.lock().unwrap().clone()
+}
+
+// The capture is shared by all connections, it is encoded once for each mode in use.
+// The restart sends the format of its mode to the connection.
+pub fn set_hifi(conn_id: i32, hifi: bool) {
+    let mut lock = HIFI_CONNS.lock().unwrap();
+    let changed = if hifi {
+        lock.insert(conn_id)
+    } else {
+        lock.remove(&conn_id)
+    };
+    drop(lock);
+    if changed {
+        log::info!("#{} sets the hi-fi audio: {}", conn_id, hifi);
+        restart();
+    }
+}
+
+pub fn on_connection_close(conn_id: i32) {
+    // No restart, the other connections keep their modes.
+    HIFI_CONNS.lock().unwrap().remove(&conn_id);
+    #[cfg(target_os = "linux")]
+    {
+        let is_capturing = CAPTURE_APP
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map_or(false, |(_, id)| *id == conn_id);
+        if is_capturing {
+            set_capture_app(conn_id, None);
+        }
+        allow_err!(set_mic_passthrough(conn_id, false));
+    }
 }
 
 pub fn restart() {
@@ -100,7 +219,12 @@ mod pa_impl {
         unsafe {
             AUDIO_ZERO_COUNT = 0;
         }
-        let mut encoder = Encoder::new(crate::platform::PA_SAMPLE_RATE, Stereo, LowDelay)?;
+        // The `_pa` ipc is stereo, the hi-fi mode only raises the bitrate.
+        let mut encoders = Encoders::new(
+            crate::platform::PA_SAMPLE_RATE,
+            2,
+            !get_hifi_conns().is_empty(),
+        )?;
         #[cfg(target_os = "linux")]
         allow_err!(
             stream
@@ -123,7 +247,7 @@ mod pa_impl {
             #[cfg(target_os = "linux")]
             if let Ok(data) = stream.next_raw().await {
                 if data.len() == 0 {
-                    send_f32(&zero_audio_frame, &mut encoder, &sp);
+                    send_f32(&zero_audio_frame, &mut encoders, &sp);
                     continue;
                 }
 
@@ -135,7 +259,7 @@ mod pa_impl {
                 let data = unsafe {
                     std::slice::from_raw_parts::<f32>(data.as_ptr() as _, data.len() / 4)
                 };
-                send_f32(data, &mut encoder, &sp);
+                send_f32(data, &mut encoders, &sp);
             }
 
             #[cfg(target_os = "android")]
@@ -147,7 +271,7 @@ mod pa_impl {
                         android_data.len() / 4,
                     )
                 };
-                send_f32(data, &mut encoder, &sp);
+                send_f32(data, &mut encoders, &sp);
             } else {
                 hbb_common::sleep(0.1).await;
             }
@@ -185,7 +309,7 @@ mod cpal_impl {
 
     #[derive(Default)]
     pub struct State {
-        stream: Option<(Box<dyn StreamTrait>, Arc<Message>)>,
+        stream: Option<(Box<dyn StreamTrait>, Formats)>,
     }
 
     impl super::service::Reset for State {
@@ -203,8 +327,14 @@ mod cpal_impl {
             }
             _ => {}
         }
-        if let Some((_, format)) = &state.stream {
-            sp.send_shared(format.clone());
+        if let Some((_, formats)) = &state.stream {
+            let hifi_conns = get_hifi_conns();
+            sp.send_shared_if(formats.standard.clone(), |id| {
+                !formats.is_hifi_conn(&hifi_conns, id)
+            });
+            if let Some(hifi) = &formats.hifi {
+                sp.send_shared_if(hifi.clone(), |id| formats.is_hifi_conn(&hifi_conns, id));
+            }
         }
         RESTARTING.store(false, Ordering::SeqCst);
         Ok(())
@@ -218,8 +348,14 @@ mod cpal_impl {
                 }
                 _ => {}
             }
-            if let Some((_, format)) = &state.stream {
-                sps.send_shared(format.clone());
+            if let Some((_, formats)) = &state.stream {
+                let hifi_conns = get_hifi_conns();
+                sps.send_shared_if(formats.standard.clone(), |id| {
+                    !formats.is_hifi_conn(&hifi_conns, id)
+                });
+                if let Some(hifi) = &formats.hifi {
+                    sps.send_shared_if(hifi.clone(), |id| formats.is_hifi_conn(&hifi_conns, id));
+                }
             }
             Ok(())
         })?;
@@ -240,7 +376,7 @@ mod cpal_impl {
         sample_rate: u32,
         device_channel: u16,
         encode_channel: u16,
-        encoder: &mut Encoder,
+        encoders: &mut Encoders,
         sp: &GenericService,
     ) {
         let mut data = data;
@@ -256,7 +392,7 @@ mod cpal_impl {
                 encode_channel,
             )
         }
-        send_f32(&data, encoder, sp);
+        send_f32(&data, encoders, sp);
     }
 
     #[cfg(feature = "screencapturekit")]
@@ -346,13 +482,17 @@ mod cpal_impl {
         Ok((device, format))
     }
 
-    fn play(sp: &GenericService) -> ResultType<(Box<dyn StreamTrait>, Arc<Message>)> {
+    fn play(sp: &GenericService) -> ResultType<(Box<dyn StreamTrait>, Formats)> {
         use cpal::SampleFormat::*;
         let (device, config) = get_device()?;
         let sp = sp.clone();
+        // The standard frames are encoded at the hi-fi sample rate too, opus supports 48 kHz.
+        let hifi = !get_hifi_conns().is_empty();
         // Sample rate must be one of 8000, 12000, 16000, 24000, or 48000.
         let sample_rate_0 = config.sample_rate().0;
-        let sample_rate = if sample_rate_0 < 12000 {
+        let sample_rate = if hifi {
+            HIFI_SAMPLE_RATE
+        } else if sample_rate_0 < 12000 {
             8000
         } else if sample_rate_0 < 16000 {
             12000
@@ -363,25 +503,28 @@ mod cpal_impl {
         } else {
             48000
         };
-        let ch = if config.channels() > 1 { Stereo } else { Mono };
+        let ch: u16 = if hifi && config.channels() >= HIFI_MAX_CHANNELS {
+            HIFI_MAX_CHANNELS
+        } else if config.channels() > 1 {
+            2
+        } else {
+            1
+        };
         let stream = match config.sample_format() {
-            I8 => build_input_stream::<i8>(device, &config, sp, sample_rate, ch)?,
-            I16 => build_input_stream::<i16>(device, &config, sp, sample_rate, ch)?,
-            I32 => build_input_stream::<i32>(device, &config, sp, sample_rate, ch)?,
-            I64 => build_input_stream::<i64>(device, &config, sp, sample_rate, ch)?,
-            U8 => build_input_stream::<u8>(device, &config, sp, sample_rate, ch)?,
-            U16 => build_input_stream::<u16>(device, &config, sp, sample_rate, ch)?,
-            U32 => build_input_stream::<u32>(device, &config, sp, sample_rate, ch)?,
-            U64 => build_input_stream::<u64>(device, &config, sp, sample_rate, ch)?,
-            F32 => build_input_stream::<f32>(device, &config, sp, sample_rate, ch)?,
-            F64 => build_input_stream::<f64>(device, &config, sp, sample_rate, ch)?,
+            I8 => build_input_stream::<i8>(device, &config, sp, sample_rate, ch, hifi)?,
+            I16 => build_input_stream::<i16>(device, &config, sp, sample_rate, ch, hifi)?,
+            I32 => build_input_stream::<i32>(device, &config, sp, sample_rate, ch, hifi)?,
+            I64 => build_input_stream::<i64>(device, &config, sp, sample_rate, ch, hifi)?,
+            U8 => build_input_stream::<u8>(device, &config, sp, sample_rate, ch, hifi)?,
+            U16 => build_input_stream::<u16>(device, &config, sp, sample_rate, ch, hifi)?,
+            U32 => build_input_stream::<u32>(device, &config, sp, sample_rate, ch, hifi)?,
+            U64 => build_input_stream::<u64>(device, &config, sp, sample_rate, ch, hifi)?,
+            F32 => build_input_stream::<f32>(device, &config, sp, sample_rate, ch, hifi)?,
+            F64 => build_input_stream::<f64>(device, &config, sp, sample_rate, ch, hifi)?,
             f => bail!("unsupported audio format: {:?}", f),
         };
         stream.play()?;
-        Ok((
-            Box::new(stream),
-            Arc::new(create_format_msg(sample_rate, ch as _)),
-        ))
+        Ok((Box::new(stream), Formats::new(sample_rate, ch, hifi)))
     }
 
     fn build_input_stream<T>(
@@ -389,7 +532,8 @@ mod cpal_impl {
         config: &cpal::SupportedStreamConfig,
         sp: GenericService,
         sample_rate: u32,
-        encode_channel: magnum_opus::Channels,
+        encode_channel: u16,
+        hifi: bool,
     ) -> ResultType<cpal::Stream>
     where
         T: cpal::SizedSample + dasp::sample::ToSample<f32>,
@@ -404,7 +548,7 @@ mod cpal_impl {
             AUDIO_ZERO_COUNT = 0;
         }
         let device_channel = config.channels();
-        let mut encoder = Encoder::new(sample_rate, encode_channel, LowDelay)?;
+        let mut encoders = Encoders::new(sample_rate, encode_channel, hifi)?;
         // https://www.opus-codec.org/docs/html_api/group__opusencoder.html#gace941e4ef26ed844879fde342ffbe546
         // https://chromium.googlesource.com/chromium/deps/opus/+/1.1.1/include/opus.h
         // Do not set `frame_size = sample_rate as usize / 100;`
@@ -434,7 +578,7 @@ mod cpal_impl {
                         sample_rate,
                         device_channel,
                         encode_channel as _,
-                        &mut encoder,
+                        &mut encoders,
                         &sp,
                     );
                 }
@@ -446,6 +590,57 @@ mod cpal_impl {
     }
 }
 
+// The standard frames are mono or stereo, the hi-fi frames have all the channels.
+#[inline]
+fn standard_channels(channels: u16) -> u16 {
+    channels.min(2)
+}
+
+// The formats of the standard and the hi-fi frames.
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+struct Formats {
+    standard: Arc<Message>,
+    hifi: Option<Arc<Message>>,
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+impl Formats {
+    fn new(sample_rate: u32, channels: u16, hifi: bool) -> Self {
+        Self {
+            standard: Arc::new(create_format_msg(sample_rate, standard_channels(channels))),
+            hifi: hifi.then(|| Arc::new(create_format_msg(sample_rate, channels))),
+        }
+    }
+
+    // The hi-fi connections get the standard frames until the hi-fi encoder is created.
+    fn is_hifi_conn(&self, hifi_conns: &HashSet<i32>, id: i32) -> bool {
+        self.hifi.is_some() && hifi_conns.contains(&id)
+    }
+}
+
+// The encoders of the standard and the hi-fi frames, see `HIFI_CONNS`.
+struct Encoders {
+    standard: AudioEncoder,
+    hifi: Option<AudioEncoder>,
+    sample_rate: u32,
+    channels: u16,
+}
+
+impl Encoders {
+    fn new(sample_rate: u32, channels: u16, hifi: bool) -> ResultType<Self> {
+        Ok(Self {
+            standard: AudioEncoder::new(sample_rate, standard_channels(channels), false)?,
+            hifi: if hifi {
+                Some(AudioEncoder::new(sample_rate, channels, true)?)
+            } else {
+                None
+            },
+            sample_rate,
+            channels,
+        })
+    }
+}
+
 fn create_format_msg(sample_rate: u32, channels: u16) -> Message {
     let format = AudioFormat {
         sample_rate,
@@ -465,7 +660,7 @@ fn create_format_msg(sample_rate: u32, channels: u16) -> Message {
 const MAX_AUDIO_ZERO_COUNT: u16 = 800;
 static mut AUDIO_ZERO_COUNT: u16 = 0;
 
-fn send_f32(data: &[f32], encoder: &mut Encoder, sp: &GenericService) {
+fn send_f32(data: &[f32], encoders: &mut Encoders, sp: &GenericService) {
     if data.iter().filter(|x| **x != 0.).next().is_some() {
         unsafe {
             AUDIO_ZERO_COUNT = 0;
@@ -482,6 +677,42 @@ fn send_f32(data: &[f32], encoder: &mut Encoder, sp: &GenericService) {
             AUDIO_ZERO_COUNT += 1;
         }
     }
+    let hifi_conns = get_hifi_conns();
+    let has_hifi = encoders.hifi.is_some() && !hifi_conns.is_empty();
+    if has_hifi {
+        if let Some(encoder) = encoders.hifi.as_mut() {
+            for msg in encode_f32(data, encoder) {
+                sp.send_shared_if(Arc::new(msg), |id| hifi_conns.contains(&id));
+            }
+        }
+    }
+    let channels = standard_channels(encoders.channels);
+    let data = if channels != encoders.channels {
+        std::borrow::Cow::Owned(crate::common::audio_rechannel(
+            data.to_vec(),
+            encoders.sample_rate,
+            encoders.sample_rate,
+            encoders.channels,
+            channels,
+        ))
+    } else {
+        std::borrow::Cow::Borrowed(data)
+    };
+    for msg in encode_f32(&data, &mut encoders.standard) {
+        sp.send_shared_if(Arc::new(msg), |id| !has_hifi || !hifi_conns.contains(&id));
+    }
+}
+
+fn make_audio_frame(data: Vec<u8>) -> Message {
+    let mut msg_out = Message::new();
+    msg_out.set_audio_frame(AudioFrame {
+        data: data.into(),
+        ..Default::default()
+    });
+    msg_out
+}
+
+fn encode_f32(data: &[f32], encoder: &mut AudioEncoder) -> Vec<Message> {
     #[cfg(target_os = "android")]
     {
         // the permitted opus data size are 120, 240, 480, 960, 1920, and 2880
@@ -491,37 +722,45 @@ fn send_f32(data: &[f32], encoder: &mut Encoder, sp: &GenericService) {
         let input_size = data.len();
         if input_size > BATCH_SIZE && input_size % BATCH_SIZE == 0 {
             let n = input_size / BATCH_SIZE;
+            let mut msgs = vec![];
             for i in 0..n {
                 match encoder
                     .encode_vec_float(&data[i * BATCH_SIZE..(i + 1) * BATCH_SIZE], BATCH_SIZE)
                 {
-                    Ok(data) => {
-                        let mut msg_out = Message::new();
-                        msg_out.set_audio_frame(AudioFrame {
-                            data: data.into(),
-                            ..Default::default()
-                        });
-                        sp.send(msg_out);
-                    }
+                    Ok(data) => msgs.push(make_audio_frame(data)),
                     Err(_) => {}
                 }
             }
+            return msgs;
         } else {
             log::debug!("invalid audio data size:{} ", input_size);
-            return;
+            return vec![];
         }
     }
 
     #[cfg(not(target_os = "android"))]
     match encoder.encode_vec_float(data, data.len() * 6) {
-        Ok(data) => {
-            let mut msg_out = Message::new();
-            msg_out.set_audio_frame(AudioFrame {
-                data: data.into(),
-                ..Default::default()
-            });
-            sp.send(msg_out);
-        }
-        Err(_) => {}
+        Ok(data) => vec![make_audio_frame(data)],
+        Err(_) => vec![],
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capture_app_last_request_wins() {
+        let current = || CAPTURE_APP.lock().unwrap().clone();
+        set_capture_app(1, Some("a".to_owned()));
+        set_capture_app(2, Some("b".to_owned()));
+        assert_eq!(current(), Some(("b".to_owned(), 2)));
+        // Only the connection of the last request stops it when closed.
+        on_connection_close(1);
+        assert_eq!(current(), Some(("b".to_owned(), 2)));
+        on_connection_close(2);
+        assert_eq!(current(), None);
+        set_capture_app(3, Some("".to_owned()));
+        assert_eq!(current(), None);
     }
 }
@@ -0,0 +1,673 @@
+// Send-side bandwidth estimation of the video, GCC-like.
+//
+// Delay based: the delay of each video frame, from the time it is encoded to the time it is
+// acknowledged by the client (`VideoReceived`), or to the time it is written to the socket if the
+// client does not acknowledge the frames, is smoothed, and the slope of it (trendline) tells if
+// the link is overused: the queues are growing. The threshold of the trend adapts to the noise.
+// Rate control (AIMD): decrease to 85% of the measured throughput on overuse, hold on underuse
+// (the queues are draining), otherwise increase, multiplicatively far from the last congestion
+// and slowly near it, but not above 1.5x the throughput so that an idle screen does not inflate it.
+// Loss based: the packet loss of the transport (QUIC) decreases the estimate above 10% loss and
+// lets it grow below 2%. The estimate is the minimum of the two.
+//
+// The acknowledgement does not tell the display, so the frames are acknowledged in order per
+// display, and the delay is measured to the acknowledgement only if one display is streaming.
+// Otherwise the delay is measured to the socket write as if the client does not acknowledge.
+//
+// If the server option `bwe-trace-dir` is set, the feedback of each connection is recorded to
+// `bwe-<conn id>-<time>.jsonl` in it, up to `MAX_TRACE_SIZE`, which
+// `--bwe-replay <file> [--json] [--init-kbps <kbps>]` replays through the estimator and prints
+// the decisions. The trace is written by its own thread, not to block the video.
+
+use hbb_common::{config::Config, log};
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    io::Write,
+    sync::mpsc::{sync_channel, Receiver, RecvTimeoutError, SyncSender},
+    time::{Duration, Instant},
+};
+
+pub const OPTION_TRACE_DIR: &str = "bwe-trace-dir";
+
+pub const INIT_KBPS: f32 = 2_000.;
+const MIN_KBPS: f32 = 50.;
+const MAX_KBPS: f32 = 100_000.;
+
+const TRENDLINE_WINDOW: usize = 20;
+const SMOOTHING: f64 = 0.9;
+const THRESHOLD_GAIN: f64 = 4.;
+const MAX_DELTAS: usize = 60;
+const INIT_THRESHOLD: f64 = 12.5;
+const MIN_THRESHOLD: f64 = 6.;
+const MAX_THRESHOLD: f64 = 600.;
+const K_UP: f64 = 0.0087;
+const K_DOWN: f64 = 0.039;
+// Spikes above the threshold by this are not used to adapt the threshold.
+const MAX_ADAPT_OFFSET: f64 = 15.;
+
+const BETA: f32 = 0.85;
+const THROUGHPUT_WINDOW: u64 = 1_000;
+const MAX_THROUGHPUT_MULTIPLE: f32 = 1.5;
+const FAR_INCREASE_PER_SEC: f32 = 1.08;
+const NEAR_INCREASE_PER_SEC: f32 = 1.03;
+const HIGH_LOSS: f32 = 0.1;
+const LOW_LOSS: f32 = 0.02;
+// Frames not acknowledged within this time are not waited for, per display.
+const MAX_UNACKED: usize = 120;
+
+const MAX_TRACE_SIZE: u64 = 64 * 1024 * 1024;
+const TRACE_QUEUE_SIZE: usize = 1024;
+const TRACE_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The feedback of the video sent to one connection.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Feedback {
+    /// A video frame is written to the socket, `queue_ms` after encoded, taking `send_ms`.
+    Sent {
+        #[serde(default)]
+        display: usize,
+        bytes: usize,
+        queue_ms: u32,
+        send_ms: u32,
+    },
+    /// The oldest unacknowledged frame of a display is acknowledged by the client.
+    Acked,
+    /// The round trip time of `TestDelay`.
+    Rtt { ms: u32 },
+    /// The packet loss ratio of the transport since the last report.
+    Loss { ratio: f32 },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TraceRecord {
+    /// Milliseconds since the connection opened.
+    pub t: u64,
+    #[serde(flatten)]
+    pub feedback: Feedback,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Usage {
+    Normal,
+    Overuse,
+    Underuse,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RateState {
+    Hold,
+    Increase,
+    Decrease,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Decision {
+    pub t: u64,
+    pub usage: Usage,
+    pub state: RateState,
+    pub estimate_kbps: u32,
+    pub throughput_kbps: u32,
+    pub reason: String,
+}
+
+/// Linear regression of the accumulated delay variation.
+#[derive(Debug, Default)]
+struct Trendline {
+    first_t: Option<u64>,
+    last_delay: Option<f64>,
+    accumulated: f64,
+    smoothed: f64,
+    num_deltas: usize,
+    samples: VecDeque<(f64, f64)>,
+}
+
+impl Trendline {
+    /// Adds the delay sample, returns the modified trend once the window is filled.
+    fn add(&mut self, t: u64, delay: f64) -> Option<f64> {
+        let first_t = *self.first_t.get_or_insert(t);
+        let Some(last_delay) = self.last_delay.replace(delay) else {
+            return None;
+        };
+        self.num_deltas = (self.num_deltas + 1).min(MAX_DELTAS);
+        self.accumulated += delay - last_delay;
+        self.smoothed = SMOOTHING * self.smoothed + (1. - SMOOTHING) * self.accumulated;
+        self.samples
+            .push_back(((t - first_t) as f64, self.smoothed));
+        if self.samples.len() > TRENDLINE_WINDOW {
+            self.samples.pop_front();
+        }
+        if self.samples.len() < TRENDLINE_WINDOW {
+            return None;
+        }
+        let n = self.samples.len() as f64;
+        let avg_x = self.samples.iter().map(|x| x.0).sum::<f64>() / n;
+        let avg_y = self.samples.iter().map(|x| x.1).sum::<f64>() / n;
+        let (mut num, mut den) = (0., 0.);
+        for (x, y) in self.samples.iter() {
+            num += (x - avg_x) * (y - avg_y);
+            den += (x - avg_x) * (x - avg_x);
+        }
+        let slope = if den > 0. { num / den } else { 0. };
+        Some(self.num_deltas as f64 * slope * THRESHOLD_GAIN)
+    }
+}
+
+#[derive(Debug)]
+pub struct BandwidthEstimator {
+    trendline: Trendline,
+    threshold: f64,
+    last_detect_t: Option<u64>,
+    usage: Usage,
+    state: RateState,
+    delay_kbps: f32,
+    loss_kbps: f32,
+    estimate_kbps: f32,
+    // the rate when the last overuse is detected
+    congestion_kbps: Option<f32>,
+    last_update_t: Option<u64>,
+    rtt_ms: u32,
+    loss: Option<f32>,
+    // display -> (sent time, bytes, queue + send delay) of the frames not acknowledged
+    unacked: HashMap<usize, VecDeque<(u64, usize, u32)>>,
+    acked: bool,
+    // display -> the time of the last frame sent
+    last_sent: HashMap<usize, u64>,
+    // the delay is measured to the acknowledgement
+    ack_delay: bool,
+    // (time, bytes) of the frames sent or acknowledged within the throughput window
+    sent_window: VecDeque<(u64, usize)>,
+    acked_window: VecDeque<(u64, usize)>,
+    frame_bits: Option<f32>,
+}
+
+impl BandwidthEstimator {
+    pub fn new(init_kbps: f32) -> Self {
+        let init_kbps = init_kbps.clamp(MIN_KBPS, MAX_KBPS);
+        Self {
+            trendline: Default::default(),
+            threshold: INIT_THRESHOLD,
+            last_detect_t: None,
+            usage: Usage::Normal,
+            state: RateState::Hold,
+            delay_kbps: init_kbps,
+            loss_kbps: init_kbps,
+            estimate_kbps: init_kbps,
+            congestion_kbps: None,
+            last_update_t: None,
+            rtt_ms: 0,
+            loss: None,
+            unacked: Default::default(),
+            acked: false,
+            last_sent: Default::default(),
+            ack_delay: false,
+            sent_window: Default::default(),
+            acked_window: Default::default(),
+            frame_bits: None,
+        }
+    }
+
+    #[inline]
+    pub fn estimate_kbps(&self) -> f32 {
+        self.estimate_kbps
+    }
+
+    #[inline]
+    pub fn usage(&self) -> Usage {
+        self.usage
+    }
+
+    /// The fps the estimate can carry with the average frame size.
+    pub fn sustainable_fps(&self) -> Option<u32> {
+        self.frame_bits
+            .filter(|x| *x > 0.)
+            .map(|bits| (self.estimate_kbps * 1000. / bits) as u32)
+    }
+
+    pub fn on_feedback(&mut self, t: u64, feedback: &Feedback) {
+        match feedback {
+            Feedback::Sent {
+                display,
+                bytes,
+                queue_ms,
+                send_ms,
+            } => {
+                let bits = (*bytes * 8) as f32;
+                self.frame_bits = Some(match self.frame_bits {
+                    Some(x) => x * 0.9 + bits * 0.1,
+                    None => bits,
+                });
+                Self::push_window(&mut self.sent_window, t, *bytes);
+                self.last_sent.insert(*display, t);
+                self.last_sent.retain(|_, x| *x + THROUGHPUT_WINDOW >= t);
+                // The frames of the displays not streaming any more are not waited for.
+                let last_sent = &self.last_sent;
+                self.unacked.retain(|d, _| last_sent.contains_key(d));
+                self.update_ack_delay();
+                let delay = queue_ms + send_ms;
+                if self.acked {
+                    let unacked = self.unacked.entry(*display).or_default();
+                    if unacked.len() >= MAX_UNACKED {
+                        unacked.pop_front();
+                    }
+                    unacked.push_back((t, *bytes, delay));
+                }
+                if !self.ack_delay {
+                    self.on_delay(t, delay as f64);
+                }
+            }
+            Feedback::Acked => {
+                // The delay is measured to the acknowledgement from now on.
+                if !self.acked {
+                    self.acked = true;
+                    self.update_ack_delay();
+                }
+                // The oldest frame of all displays, it is the right one if one display is streaming.
+                let oldest = self
+                    .unacked
+                    .iter_mut()
+                    .filter(|(_, x)| !x.is_empty())
+                    .min_by_key(|(_, x)| x.front().map(|f| f.0))
+                    .and_then(|(_, x)| x.pop_front());
+                if let Some((sent_t, bytes, delay)) = oldest {
+                    Self::push_window(&mut self.acked_window, t, bytes);
+                    if self.ack_delay {
+                        // The round trip is not a queue, only its variation matters.
+                        let delay = delay as u64 + t.saturating_sub(sent_t);
+                        self.on_delay(t, delay as f64);
+                    }
+                }
+            }
+            Feedback::Rtt { ms } => self.rtt_ms = *ms,
+            Feedback::Loss { ratio } => self.loss = Some(*ratio),
+        }
+    }
+
+    // The delays to the acknowledgement and to the socket write are not comparable,
+    // the trend starts over if switched.
+    fn update_ack_delay(&mut self) {
+        let ack_delay = self.acked && self.last_sent.len() <= 1;
+        if ack_delay != self.ack_delay {
+            self.ack_delay = ack_delay;
+            self.trendline = Default::default();
+        }
+    }
+
+    fn push_window(window: &mut VecDeque<(u64, usize)>, t: u64, bytes: usize) {
+        window.push_back((t, bytes));
+        while window
+            .front()
+            .map_or(false, |x| x.0 + THROUGHPUT_WINDOW < t)
+        {
+            window.pop_front();
+        }
+    }
+
+    fn throughput_kbps(&self, t: u64) -> f32 {
+        let window = if self.ack_delay {
+            &self.acked_window
+        } else {
+            &self.sent_window
+        };
+        let bytes: usize = window
+            .iter()
+            .filter(|x| x.0 + THROUGHPUT_WINDOW >= t)
+            .map(|x| x.1)
+            .sum();
+        (bytes * 8) as f32 / THROUGHPUT_WINDOW as f32
+    }
+
+    /// The overuse detector.
+    fn on_delay(&mut self, t: u64, delay: f64) {
+        let Some(trend) = self.trendline.add(t, delay) else {
+            return;
+        };
+        self.usage = if trend > self.threshold {
+            Usage::Overuse
+        } else if trend < -self.threshold {
+            Usage::Underuse
+        } else {
+            Usage::Normal
+        };
+        let dt = self
+            .last_detect_t
+            .map_or(0, |x| t.saturating_sub(x).min(100)) as f64;
+        self.last_detect_t = Some(t);
+        if trend.abs() <= self.threshold + MAX_ADAPT_OFFSET {
+            let k = if trend.abs() < self.threshold {
+                K_DOWN
+            } else {
+                K_UP
+            };
+            self.threshold += k * (trend.abs() - self.threshold) * dt;
+            self.threshold = self.threshold.clamp(MIN_THRESHOLD, MAX_THRESHOLD);
+        }
+    }
+
+    /// Updates the estimate, returns the decision if the state or the estimate changed.
+    pub fn update(&mut self, t: u64) -> Option<Decision> {
+        let dt = (t.saturating_sub(self.last_update_t.unwrap_or(t)) as f32 / 1000.).min(1.);
+        self.last_update_t = Some(t);
+        let throughput = self.throughput_kbps(t);
+        let (old_state, old_estimate) = (self.state, self.estimate_kbps);
+        let mut reason;
+
+        self.state = match (self.usage, self.state) {
+            (Usage::Overuse, _) => RateState::Decrease,
+            (Usage::Underuse, _) => RateState::Hold,
+            (Usage::Normal, RateState::Decrease) => RateState::Hold,
+            (Usage::Normal, _) => RateState::Increase,
+        };
+        match self.state {
+            RateState::Decrease => {
+                let base = if throughput > 0. {
+                    throughput.min(self.delay_kbps)
+                } else {
+                    self.delay_kbps
+                };
+                // Decrease once per overuse, until it is over.
+                if old_state != RateState::Decrease {
+                    self.delay_kbps = base * BETA;
+                    self.congestion_kbps = Some(self.delay_kbps);
+                }
+                reason = format!("overuse, threshold {:.1}", self.threshold);
+            }
+            RateState::Hold => {
+                reason = "hold while the queues drain".to_owned();
+            }
+            RateState::Increase => {
+                let near = self
+                    .congestion_kbps
+                    .map_or(false, |x| self.delay_kbps < x * 1.5);
+                let factor = if near {
+                    NEAR_INCREASE_PER_SEC
+                } else {
+                    FAR_INCREASE_PER_SEC
+                };
+                let limit = self.delay_kbps.max(throughput * MAX_THROUGHPUT_MULTIPLE);
+                self.delay_kbps = (self.delay_kbps * factor.powf(dt)).min(limit);
+                reason = format!(
+                    "{} increase",
+                    if near { "additive" } else { "multiplicative" }
+                );
+            }
+        }
+
+        if let Some(loss) = self.loss.take() {
+            if loss > HIGH_LOSS {
+                self.loss_kbps = self.loss_kbps.min(self.estimate_kbps) * (1. - 0.5 * loss);
+                reason += &format!(", {:.1}% loss", loss * 100.);
+            } else if loss < LOW_LOSS {
+                self.loss_kbps = (self.loss_kbps * 1.05).max(self.delay_kbps);
+            }
+        }
+        self.delay_kbps = self.delay_kbps.clamp(MIN_KBPS, MAX_KBPS);
+        self.loss_kbps = self.loss_kbps.clamp(MIN_KBPS, MAX_KBPS);
+        self.estimate_kbps = self.delay_kbps.min(self.loss_kbps);
+
+        if self.state == old_state
+            && (self.estimate_kbps - old_estimate).abs() < old_estimate * 0.01
+        {
+            return None;
+        }
+        let decision = Decision {
+            t,
+            usage: self.usage,
+            state: self.state,
+            estimate_kbps: self.estimate_kbps as u32,
+            throughput_kbps: throughput as u32,
+            reason,
+        };
+        log::debug!(
+            "bwe: {:?}/{:?}, {} -> {}kbps, throughput {}kbps, rtt {}ms, {}",
+            decision.usage,
+            decision.state,
+            old_estimate as u32,
+            decision.estimate_kbps,
+            decision.throughput_kbps,
+            self.rtt_ms,
+            decision.reason
+        );
+        Some(decision)
+    }
+}
+
+/// The estimator of a connection, with the time base and the trace.
+pub struct BweSession {
+    epoch: Instant,
+    estimator: BandwidthEstimator,
+    trace: Option<SyncSender<TraceRecord>>,
+}
+
+impl BweSession {
+    pub fn new(conn_id: i32, init_kbps: f32) -> Self {
+        let dir = Config::get_option(OPTION_TRACE_DIR);
+        let trace = if dir.is_empty() {
+            None
+        } else {
+            let path = std::path::Path::new(&dir).join(format!(
+                "bwe-{}-{}.jsonl",
+                conn_id,
+                hbb_common::get_time()
+            ));
+            let (tx, rx) = sync_channel(TRACE_QUEUE_SIZE);
+            std::thread::spawn(move || write_trace(path, rx));
+            Some(tx)
+        };
+        Self {
+            epoch: Instant::now(),
+            estimator: BandwidthEstimator::new(init_kbps),
+            trace,
+        }
+    }
+
+    #[inline]
+    pub fn estimator(&self) -> &BandwidthEstimator {
+        &self.estimator
+    }
+
+    pub fn feed(&mut self, feedback: Feedback) {
+        let t = self.epoch.elapsed().as_millis() as u64;
+        self.estimator.on_feedback(t, &feedback);
+        if let Some(trace) = self.trace.as_ref() {
+            // The records are dropped if the writer can not keep up, the trace is closed if
+            // the writer has stopped.
+            if let Err(std::sync::mpsc::TrySendError::Disconnected(_)) =
+                trace.try_send(TraceRecord { t, feedback })
+            {
+                self.trace = None;
+            }
+        }
+    }
+
+    pub fn update(&mut self) -> Option<Decision> {
+        self.estimator
+            .update(self.epoch.elapsed().as_millis() as u64)
+    }
+}
+
+// Writes the records until the session is dropped or the trace is full.
+fn write_trace(path: std::path::PathBuf, rx: Receiver<TraceRecord>) {
+    let mut file = match std::fs::File::create(&path) {
+        Ok(file) => std::io::BufWriter::new(file),
+        Err(err) => {
+            log::error!("Failed to create the bwe trace {:?}: {}", path, err);
+            return;
+        }
+    };
+    let mut size = 0;
+    let mut last_flush = Instant::now();
+    loop {
+        match rx.recv_timeout(TRACE_FLUSH_INTERVAL) {
+            Ok(record) => {
+                let Ok(line) = serde_json::to_string(&record) else {
+                    continue;
+                };
+                size += line.len() as u64 + 1;
+                if size > MAX_TRACE_SIZE {
+                    log::warn!("The bwe trace {:?} is full", path);
+                    break;
+                }
+                if writeln!(file, "{}", line).is_err() {
+                    break;
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+        if last_flush.elapsed() >= TRACE_FLUSH_INTERVAL {
+            last_flush = Instant::now();
+            if file.flush().is_err() {
+                break;
+            }
+        }
+    }
+    file.flush().ok();
+}
+
+/// Replays the trace through the estimator, updated every second as by the video service.
+pub fn replay(records: &[TraceRecord], init_kbps: f32) -> Vec<Decision> {
+    let mut estimator = BandwidthEstimator::new(init_kbps);
+    let mut decisions = Vec::new();
+    let mut next_update = records.first().map_or(0, |x| x.t) + 1000;
+    for record in records {
+        while record.t >= next_update {
+            decisions.extend(estimator.update(next_update));
+            next_update += 1000;
+        }
+        estimator.on_feedback(record.t, &record.feedback);
+    }
+    decisions.extend(estimator.update(next_update));
+    decisions
+}
+
+pub fn handle_cli(args: &[String]) {
+    let Some(path) = args.first() else {
+        println!("Usage: --bwe-replay <trace.jsonl> [--json] [--init-kbps <kbps>]");
+        return;
+    };
+    let init_kbps = args
+        .iter()
+        .position(|x| x == "--init-kbps")
+        .and_then(|i| args.get(i + 1))
+        .and_then(|x| x.parse().ok())
+        .unwrap_or(INIT_KBPS);
+    let records = match std::fs::read_to_string(path) {
+        Ok(content) => content
+            .lines()
+            .filter_map(|line| serde_json::from_str::<TraceRecord>(line).ok())
+            .collect::<Vec<_>>(),
+        Err(err) => {
+            println!("Failed to read {}: {}", path, err);
+            return;
+        }
+    };
+    let decisions = replay(&records, init_kbps);
+    if args.iter().any(|x| x == "--json") {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&decisions).unwrap_or_default()
+        );
+    } else {
+        for d in decisions {
+            println!(
+                "{:>8}ms {:?}/{:?} {}kbps (throughput {}kbps): {}",
+                d.t, d.usage, d.state, d.estimate_kbps, d.throughput_kbps, d.reason
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 30 fps of 10KB frames, the delay grows by `growth_ms` per frame after `from_t`.
+    fn trace(duration: u64, from_t: u64, growth_ms: u32) -> Vec<TraceRecord> {
+        let mut records = Vec::new();
+        let mut delay = 10;
+        for i in 0..duration * 30 / 1000 {
+            let t = i * 1000 / 30;
+            if t >= from_t {
+                delay += growth_ms;
+            }
+            records.push(TraceRecord {
+                t,
+                feedback: Feedback::Sent {
+                    display: 0,
+                    bytes: 10_000,
+                    queue_ms: delay,
+                    send_ms: 1,
+                },
+            });
+        }
+        records
+    }
+
+    #[test]
+    fn test_increase_on_stable_delay() {
+        let decisions = replay(&trace(10_000, u64::MAX, 0), 1_000.);
+        let last = decisions.last().unwrap();
+        assert_eq!(last.state, RateState::Increase);
+        assert!(last.estimate_kbps > 1_000);
+        // 30 * 10KB = 2400kbps, limited to 1.5x of it.
+        assert!(last.estimate_kbps <= 3_600);
+    }
+
+    #[test]
+    fn test_decrease_on_growing_delay() {
+        let decisions = replay(&trace(10_000, 5_000, 5), 3_000.);
+        let decrease = decisions
+            .iter()
+            .find(|d| d.state == RateState::Decrease)
+            .unwrap();
+        assert!(decrease.t > 5_000);
+        assert!(decrease.estimate_kbps < 3_000);
+    }
+
+    #[test]
+    fn test_ack_delay_of_displays() {
+        let sent = |display| Feedback::Sent {
+            display,
+            bytes: 1_000,
+            queue_ms: 1,
+            send_ms: 1,
+        };
+        let mut bwe = BandwidthEstimator::new(1_000.);
+        bwe.on_feedback(0, &sent(0));
+        assert!(!bwe.ack_delay);
+        bwe.on_feedback(10, &Feedback::Acked);
+        assert!(bwe.ack_delay);
+        bwe.on_feedback(20, &sent(0));
+        bwe.on_feedback(30, &sent(1));
+        // Two displays are streaming, an acknowledgement can be of either.
+        assert!(!bwe.ack_delay);
+        assert_eq!(bwe.unacked[&0].len(), 1);
+        assert_eq!(bwe.unacked[&1].len(), 1);
+        bwe.on_feedback(40, &Feedback::Acked);
+        assert!(bwe.unacked[&0].is_empty());
+        assert_eq!(bwe.unacked[&1].len(), 1);
+        // Display 1 stops streaming.
+        bwe.on_feedback(2_000, &sent(0));
+        assert!(bwe.ack_delay);
+        assert!(!bwe.unacked.contains_key(&1));
+    }
+
+    #[test]
+    fn test_trace_record_compatible() {
+        let record: TraceRecord =
+            serde_json::from_str(r#"{"t":1,"type":"sent","bytes":2,"queue_ms":3,"send_ms":4}"#)
+                .unwrap();
+        assert!(matches!(
+            record.feedback,
+            Feedback::Sent {
+                display: 0,
+                bytes: 2,
+                ..
+            }
+        ));
+    }
+}
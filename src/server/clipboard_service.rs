@@ -148,21 +148,27 @@ impl Handler {
                     // The clipboard data should not be empty, the last line will try again to get the clipboard data.
                     if !data.is_empty() {
                         let mut msg = Message::new();
+                        let clipboards = data
+                            .into_iter()
+                            .map(|c| Clipboard {
+                                compress: c.compress,
+                                content: c.content,
+                                width: c.width,
+                                height: c.height,
+                                format: ClipboardFormat::from_i32(c.format)
+                                    .unwrap_or(ClipboardFormat::Text)
+                                    .into(),
+                                special_name: c.special_name,
+                                ..Default::default()
+                            })
+                            .collect();
+                        let clipboards =
+                            crate::clipboard_dlp::filter(clipboards, ClipboardSide::Host, true);
+                        if clipboards.is_empty() {
+                            return None;
+                        }
                         let multi_clipboards = MultiClipboards {
-                            clipboards: data
-                                .into_iter()
-                                .map(|c| Clipboard {
-                                    compress: c.compress,
-                                    content: c.content,
-                                    width: c.width,
-                                    height: c.height,
-                                    format: ClipboardFormat::from_i32(c.format)
-                                        .unwrap_or(ClipboardFormat::Text)
-                                        .into(),
-                                    special_name: c.special_name,
-                                    ..Default::default()
-                                })
-                                .collect(),
+                            clipboards,
                             ..Default::default()
                         };
                         msg.set_multi_clipboards(multi_clipboards);
@@ -203,7 +209,7 @@ impl Handler {
             };
         }
         if !is_sent {
-            let mut stream = rt.block_on(crate::ipc::connect(100, "_cm"))?;
+            let mut stream = rt.block_on(crate::ipc::connect(100, &crate::ipc::cm_postfix()))?;
             rt.block_on(stream.send(&Data::ClipboardNonFile(None)))?;
             self.stream = Some(stream);
         }
@@ -17,7 +17,9 @@ use crate::{
     client::{
         new_voice_call_request, new_voice_call_response, start_audio_thread, MediaData, MediaSender,
     },
-    display_service, ipc, privacy_mode, video_service, VERSION,
+    display_service, ipc, privacy_mode,
+    quic_stream::{QuicLane, QuicLanes},
+    video_service, VERSION,
 };
 #[cfg(any(target_os = "android", target_os = "ios"))]
 use crate::{common::DEVICE_NAME, flutter::connection_manager::start_channel};
@@ -59,7 +61,7 @@ use system_shutdown;
 #[cfg(target_os = "windows")]
 use windows::Win32::Foundation::{CloseHandle, HANDLE};
 
-#[cfg(windows)]
+#[cfg(any(windows, target_os = "linux"))]
 use crate::virtual_display_manager;
 #[cfg(not(any(target_os = "ios")))]
 use std::collections::HashSet;
@@ -205,8 +207,12 @@ pub struct Connection {
     inner: ConnInner,
     display_idx: usize,
     stream: super::Stream,
+    // The video, file, clipboard and terminal lanes of the QUIC connection.
+    quic_lanes: Option<QuicLanes>,
     server: super::ServerPtrWeak,
     hash: Hash,
+    // The hash was sent by the main server, which handed off this connection to a headless session.
+    handed_off: bool,
     read_jobs: Vec<fs::TransferJob>,
     timer: crate::CloudyDeskInterval,
     file_timer: crate::CloudyDeskInterval,
@@ -217,7 +223,7 @@ pub struct Connection {
     port_forward_address: String,
     tx_to_cm: mpsc::UnboundedSender<ipc::Data>,
     authorized: bool,
-    require_2fa: Option<totp_rs::TOTP>,
+    require_2fa: Option<crate::auth_2fa::Auth2fa>,
     keyboard: bool,
     clipboard: bool,
     audio: bool,
@@ -231,6 +237,8 @@ pub struct Connection {
     show_remote_cursor: bool,
     // by peer
     ip: String,
+    // the identity of the client certificate of the direct access tls
+    tls_identity: Option<String>,
     // by peer
     disable_keyboard: bool,
     // by peer
@@ -261,6 +269,8 @@ pub struct Connection {
     from_switch: bool,
     voice_call_request_timestamp: Option<NonZeroI64>,
     voice_calling: bool,
+    #[cfg(target_os = "linux")]
+    mic_passthrough: bool,
     options_in_login: Option<OptionMessage>,
     #[cfg(not(any(target_os = "ios")))]
     pressed_modifiers: HashSet<rdev::Key>,
@@ -343,13 +353,16 @@ impl Connection {
         stream: super::Stream,
         id: i32,
         server: super::ServerPtrWeak,
+        tls_identity: Option<String>,
+        hash: Option<Hash>,
     ) {
         let _raii_id = raii::ConnectionID::new(id);
-        let hash = Hash {
+        let handed_off = hash.is_some();
+        let hash = hash.unwrap_or_else(|| Hash {
             salt: Config::get_salt(),
             challenge: Config::get_auto_password(6),
             ..Default::default()
-        };
+        });
         let (tx_from_cm_holder, mut rx_from_cm) = mpsc::unbounded_channel::<ipc::Data>();
         // holding tx_from_cm_holder to avoid cpu burning of rx_from_cm.recv when all sender closed
         let tx_from_cm = tx_from_cm_holder.clone();
@@ -374,17 +387,20 @@ impl Connection {
 
         #[cfg(not(any(target_os = "android", target_os = "ios")))]
         let tx_cloned = tx.clone();
+        let (quic_lanes, mut rx_quic) = crate::quic_stream::take_lanes(&stream.local_addr());
         let mut conn = Self {
             inner: ConnInner {
                 id,
                 tx: Some(tx),
                 tx_video: Some(tx_video),
             },
-            require_2fa: crate::auth_2fa::get_2fa(None),
+            require_2fa: crate::auth_2fa::Auth2fa::get(),
             display_idx: *display_service::PRIMARY_DISPLAY_IDX,
             stream,
+            quic_lanes,
             server,
             hash,
+            handed_off,
             read_jobs: Vec::new(),
             timer: crate::cloudydesk_interval(time::interval(SEC30)),
             file_timer: crate::cloudydesk_interval(time::interval(SEC30)),
@@ -411,6 +427,7 @@ impl Connection {
             follow_remote_window: false,
             multi_ui_session: false,
             ip: "".to_owned(),
+            tls_identity,
             disable_audio: false,
             #[cfg(any(target_os = "windows", target_os = "linux", target_os = "macos"))]
             enable_file_transfer: false,
@@ -433,6 +450,8 @@ impl Connection {
             audio_sender: None,
             voice_call_request_timestamp: None,
             voice_calling: false,
+            #[cfg(target_os = "linux")]
+            mic_passthrough: false,
             options_in_login: None,
             #[cfg(not(any(target_os = "ios")))]
             pressed_modifiers: Default::default(),
@@ -665,7 +684,7 @@ impl Connection {
                                     );
                                 }
                                 _ => {
-                                    allow_err!(conn.stream.send(&clip_2_msg(clip)).await);
+                                    allow_err!(conn.send_on_lane(&clip_2_msg(clip)).await);
                                 }
                             }
                         }
@@ -747,10 +766,23 @@ impl Connection {
                         break;
                     }
                 },
+                Some(bytes) = rx_quic.recv() => {
+                    last_recv_time = Instant::now();
+                    conn.session_last_recv_time.as_mut().map(|t| *t.lock().unwrap() = Instant::now());
+                    if let Ok(msg_in) = Message::parse_from_bytes(&bytes) {
+                        if !conn.on_message(msg_in).await {
+                            break;
+                        }
+                    }
+                },
                 _ = conn.file_timer.tick() => {
                     if !conn.read_jobs.is_empty() {
                         conn.send_to_cm(ipc::Data::FileTransferLog(("transfer".to_string(), fs::serialize_transfer_jobs(&conn.read_jobs))));
-                        match fs::handle_read_jobs(&mut conn.read_jobs, &mut conn.stream).await {
+                        let stream = match conn.quic_lanes.as_mut() {
+                            Some(lanes) => lanes.stream(QuicLane::File, &mut conn.stream).await,
+                            None => &mut conn.stream,
+                        };
+                        match fs::handle_read_jobs(&mut conn.read_jobs, stream).await {
                             Ok(log) => {
                                 if !log.is_empty() {
                                     conn.send_to_cm(ipc::Data::FileTransferLog(("transfer".to_string(), log)));
@@ -776,10 +808,21 @@ impl Connection {
                     if !conn.video_ack_required {
                         video_service::notify_video_frame_fetched(id, Some(instant.into()));
                     }
-                    if let Err(err) = conn.stream.send(&value as &Message).await {
+                    let queue_ms = instant.elapsed().as_millis() as u32;
+                    let send_instant = Instant::now();
+                    if let Err(err) = conn.send_on_lane(&value).await {
                         conn.on_close(&err.to_string(), false).await;
                         break;
                     }
+                    if let Some(message::Union::VideoFrame(vf)) = &value.union {
+                        video_service::VIDEO_QOS.lock().unwrap().user_video_sent(
+                            id,
+                            vf.display as usize,
+                            value.compute_size() as usize,
+                            queue_ms,
+                            send_instant.elapsed().as_millis() as u32,
+                        );
+                    }
                 },
                 Some((instant, value)) = rx.recv() => {
                     let latency = instant.elapsed().as_millis() as i64;
@@ -826,7 +869,7 @@ impl Connection {
                         Some(message::Union::MultiClipboards(_multi_clipboards)) => {
                             #[cfg(not(target_os = "ios"))]
                             if let Some(msg_out) = crate::clipboard::get_msg_if_not_support_multi_clip(&conn.lr.version, &conn.lr.my_platform, _multi_clipboards) {
-                                if let Err(err) = conn.stream.send(&msg_out).await {
+                                if let Err(err) = conn.send_on_lane(&msg_out).await {
                                     conn.on_close(&err.to_string(), false).await;
                                     break;
                                 }
@@ -836,8 +879,7 @@ impl Connection {
                         _ => {}
                     }
 
-                    let msg: &Message = &msg;
-                    if let Err(err) = conn.stream.send(msg).await {
+                    if let Err(err) = conn.send_on_lane(&msg).await {
                         conn.on_close(&err.to_string(), false).await;
                         break;
                     }
@@ -889,6 +931,9 @@ impl Connection {
                         if let Some(last_test_delay) = conn.last_test_delay {
                             video_service::VIDEO_QOS.lock().unwrap().user_delay_response_elapsed(id, last_test_delay.elapsed().as_millis());
                         }
+                        if let Some(loss) = conn.quic_lanes.as_mut().and_then(|lanes| lanes.loss()) {
+                            video_service::VIDEO_QOS.lock().unwrap().user_transport_loss(id, loss);
+                        }
                     }
                 }
                 clip_file = rx_clip.recv() => match clip_file {
@@ -958,16 +1003,25 @@ impl Connection {
             match receiver.recv_timeout(std::time::Duration::from_millis(500)) {
                 Ok(v) => match v {
                     MessageInput::Mouse(mouse_input) => {
+                        #[cfg(any(target_os = "windows", target_os = "linux"))]
+                        let simulate = mouse_input.simulate
+                            && window_share::is_mouse_allowed(&mouse_input.msg);
+                        #[cfg(target_os = "macos")]
+                        let simulate = mouse_input.simulate;
                         handle_mouse(
                             &mouse_input.msg,
                             mouse_input.conn_id,
                             mouse_input.username,
                             mouse_input.argb,
-                            mouse_input.simulate,
+                            simulate,
                             mouse_input.show_cursor,
                         );
                     }
                     MessageInput::Key((mut msg, press)) => {
+                        #[cfg(any(target_os = "windows", target_os = "linux"))]
+                        if !window_share::is_key_allowed(press || msg.down) {
+                            continue;
+                        }
                         // Set the press state to false, use `down` only in `handle_key()`.
                         msg.press = false;
                         if press {
@@ -980,6 +1034,10 @@ impl Connection {
                         }
                     }
                     MessageInput::Pointer((msg, id)) => {
+                        #[cfg(any(target_os = "windows", target_os = "linux"))]
+                        if !window_share::is_pointer_allowed(&msg) {
+                            continue;
+                        }
                         handle_pointer(&msg, id);
                     }
                     MessageInput::BlockOn => {
@@ -1176,14 +1234,22 @@ impl Connection {
             }
         }
         self.ip = addr.ip().to_string();
+        if self.handed_off {
+            self.get_api_server();
+            return true;
+        }
         let mut msg_out = Message::new();
         msg_out.set_hash(self.hash.clone());
         self.send(msg_out).await;
         self.get_api_server();
-        self.post_conn_audit(json!({
+        let mut v = json!({
             "ip": addr.ip(),
             "action": "new",
-        }));
+        });
+        if let Some(identity) = self.tls_identity.as_ref() {
+            v["tls_identity"] = json!(identity);
+        }
+        self.post_conn_audit(v);
         true
     }
 
@@ -1278,9 +1344,16 @@ impl Connection {
         v["uuid"] = json!(crate::encode64(hbb_common::get_uuid()));
         v["typ"] = json!(typ as i8);
         v["info"] = serde_json::Value::String(info.to_string());
-        tokio::spawn(async move {
-            allow_err!(Self::post_audit_async(url, v).await);
-        });
+        // The clipboard filters may post alarms outside of the tokio runtime.
+        if tokio::runtime::Handle::try_current().is_ok() {
+            tokio::spawn(async move {
+                allow_err!(Self::post_audit_async(url, v).await);
+            });
+        } else {
+            std::thread::spawn(move || {
+                allow_err!(crate::post_request_sync(url, v.to_string(), ""));
+            });
+        }
     }
 
     #[inline]
@@ -1293,33 +1366,14 @@ impl Connection {
             return;
         }
         if self.require_2fa.is_some() && !self.is_recent_session(true) && !self.from_switch {
-            self.require_2fa.as_ref().map(|totp| {
-                let bot = crate::auth_2fa::TelegramBot::get();
-                let bot = match bot {
-                    Ok(Some(bot)) => bot,
-                    Err(err) => {
-                        log::error!("Failed to get telegram bot: {}", err);
-                        return;
-                    }
-                    _ => return,
-                };
-                let code = totp.generate_current();
-                if let Ok(code) = code {
-                    let text = format!(
-                        "2FA code: {}\n\nA new connection has been established to your device with ID {}. The source IP address is {}.",
-                        code,
-                        Config::get_id(),
-                        self.ip,
-                    );
-                    tokio::spawn(async move {
-                        if let Err(err) =
-                            crate::auth_2fa::send_2fa_code_to_telegram(&text, bot).await
-                        {
-                            log::error!("Failed to send 2fa code to telegram bot: {}", err);
-                        }
-                    });
-                }
-            });
+            let challenge = crate::auth_2fa::Challenge {
+                ip: self.ip.clone(),
+                peer_id: self.lr.my_id.clone(),
+                peer_name: self.lr.my_name.clone(),
+            };
+            if let Some(tfa) = self.require_2fa.as_mut() {
+                tfa.challenge(&challenge);
+            }
             self.send_login_error(crate::client::REQUIRE_2FA).await;
             return;
         }
@@ -1388,6 +1442,11 @@ impl Connection {
                     platform_additions.insert("headless".into(), json!(true));
                 }
             }
+            platform_additions.extend(virtual_display_manager::get_platform_additions());
+            platform_additions.insert(
+                "supported_privacy_mode_impl".into(),
+                json!(privacy_mode::get_supported_privacy_mode_impl()),
+            );
         }
         #[cfg(target_os = "windows")]
         {
@@ -1425,7 +1484,12 @@ impl Connection {
         #[cfg(any(target_os = "windows", target_os = "linux"))]
         {
             platform_additions.insert("support_view_camera".into(), json!(true));
+            if window_share::is_supported() {
+                platform_additions.insert("support_window_share".into(), json!(true));
+            }
         }
+        #[cfg(target_os = "linux")]
+        platform_additions.insert("support_app_audio".into(), json!(true));
 
         #[cfg(any(target_os = "linux", target_os = "windows", target_os = "macos"))]
         if !platform_additions.is_empty() {
@@ -1576,6 +1640,8 @@ impl Connection {
                         // use rdp_input when uinput is not available in wayland. Ex: flatpak
                         if input_service::wayland_use_rdp_input() {
                             let _ = setup_rdp_input().await;
+                        } else if crate::is_server() && !crate::platform::is_x11() {
+                            allow_err!(input_service::check_wlr_input().await);
                         }
                     }
                 }
@@ -2009,6 +2075,44 @@ impl Connection {
         }
     }
 
+    // Proxy the connection to the server of the headless session of `username`.
+    #[cfg(target_os = "linux")]
+    async fn hand_off_to_session(&mut self, username: &str, lr: &LoginRequest) -> ResultType<()> {
+        let stream = linux_desktop_manager::connect_session_server(username).await?;
+        if linux_desktop_manager::add_connection(username) {
+            self.linux_headless_handle.session_username = Some(username.to_owned());
+        }
+        let mut session = crate::direct_tls::create_framed(stream, self.stream.local_addr());
+        let hand_off = super::HeadlessHandOff {
+            addr: SocketAddr::new(self.ip.parse()?, 0),
+            salt: self.hash.salt.clone(),
+            challenge: self.hash.challenge.clone(),
+            tls_identity: self.tls_identity.clone(),
+        };
+        session.send_raw(serde_json::to_vec(&hand_off)?).await?;
+        let mut msg = Message::new();
+        msg.set_login_request(lr.clone());
+        session.send_raw(msg.write_to_bytes()?).await?;
+        log::info!(
+            "#{} handed off to the session of {}",
+            self.inner.id,
+            username
+        );
+        loop {
+            tokio::select! {
+                res = self.stream.next() => match res {
+                    Some(Ok(bytes)) => session.send_raw(bytes.to_vec()).await?,
+                    _ => break,
+                },
+                res = session.next() => match res {
+                    Some(Ok(bytes)) => self.stream.send_raw(bytes.to_vec()).await?,
+                    _ => break,
+                },
+            }
+        }
+        Ok(())
+    }
+
     async fn on_message(&mut self, msg: Message) -> bool {
         if let Some(message::Union::Misc(misc)) = &msg.union {
             // Move the CloseReason forward, as this message needs to be received when unauthorized, especially for kcp.
@@ -2133,9 +2237,6 @@ impl Connection {
                 }
             }
 
-            #[cfg(not(any(target_os = "android", target_os = "ios")))]
-            self.try_start_cm_ipc();
-
             #[cfg(not(target_os = "linux"))]
             let err_msg = "".to_owned();
             #[cfg(target_os = "linux")]
@@ -2143,6 +2244,39 @@ impl Connection {
                 .linux_headless_handle
                 .try_start_desktop(lr.os_login.as_ref());
 
+            // Before the client is asked for the os login.
+            #[cfg(target_os = "linux")]
+            if !err_msg.is_empty()
+                && self.linux_headless_handle.is_headless
+                && !self.linux_headless_handle.templates_sent
+            {
+                self.linux_headless_handle.templates_sent = true;
+                let templates = linux_desktop_manager::get_session_template_names();
+                if !templates.is_empty() {
+                    self.send(crate::common::make_session_templates_msg(&templates))
+                        .await;
+                }
+            }
+
+            // The headless session is served by its own server, which checks the login request again.
+            #[cfg(target_os = "linux")]
+            if err_msg.is_empty() {
+                if let Some(username) = self
+                    .linux_headless_handle
+                    .get_hand_off_username(lr.os_login.as_ref())
+                {
+                    if let Err(e) = self.hand_off_to_session(&username, &lr).await {
+                        log::error!("Failed to hand off the connection to {}: {}", username, e);
+                        self.send_login_error(crate::client::LOGIN_MSG_DESKTOP_XSESSION_FAILED)
+                            .await;
+                    }
+                    return false;
+                }
+            }
+
+            #[cfg(not(any(target_os = "android", target_os = "ios")))]
+            self.try_start_cm_ipc();
+
             // If err is LOGIN_MSG_DESKTOP_SESSION_NOT_READY, just keep this msg and go on checking password.
             if !err_msg.is_empty() && err_msg != crate::client::LOGIN_MSG_DESKTOP_SESSION_NOT_READY
             {
@@ -2259,32 +2393,31 @@ impl Connection {
             if !res {
                 return true;
             }
-            if let Some(totp) = self.require_2fa.as_ref() {
-                if let Ok(res) = totp.check_current(&tfa.code) {
-                    if res {
-                        self.update_failure(failure, true, 1);
-                        self.require_2fa.take();
-                        raii::AuthedConnID::set_session_2fa(self.session_key());
-                        self.send_logon_response().await;
-                        self.try_start_cm(
-                            self.lr.my_id.to_owned(),
-                            self.lr.my_name.to_owned(),
-                            self.authorized,
-                        );
-                        if !tfa.hwid.is_empty() && Self::enable_trusted_devices() {
-                            Config::add_trusted_device(TrustedDevice {
-                                hwid: tfa.hwid,
-                                time: hbb_common::get_time(),
-                                id: self.lr.my_id.clone(),
-                                name: self.lr.my_name.clone(),
-                                platform: self.lr.my_platform.clone(),
-                            });
-                        }
-                    } else {
-                        self.update_failure(failure, false, 1);
-                        self.send_login_error(crate::client::LOGIN_MSG_2FA_WRONG)
-                            .await;
+            if let Some(verified) = self.require_2fa.as_mut().map(|x| x.verify(&tfa.code)) {
+                if let Some(provider) = verified {
+                    log::info!("2FA verified by {}", provider);
+                    self.update_failure(failure, true, 1);
+                    self.require_2fa.take();
+                    raii::AuthedConnID::set_session_2fa(self.session_key());
+                    self.send_logon_response().await;
+                    self.try_start_cm(
+                        self.lr.my_id.to_owned(),
+                        self.lr.my_name.to_owned(),
+                        self.authorized,
+                    );
+                    if !tfa.hwid.is_empty() && Self::enable_trusted_devices() {
+                        Config::add_trusted_device(TrustedDevice {
+                            hwid: tfa.hwid,
+                            time: hbb_common::get_time(),
+                            id: self.lr.my_id.clone(),
+                            name: self.lr.my_name.clone(),
+                            platform: self.lr.my_platform.clone(),
+                        });
                     }
+                } else {
+                    self.update_failure(failure, false, 1);
+                    self.send_login_error(crate::client::LOGIN_MSG_2FA_WRONG)
+                        .await;
                 }
             }
         } else if let Some(message::Union::TestDelay(t)) = msg.union {
@@ -2568,6 +2701,9 @@ impl Connection {
                 }
                 #[cfg(any(target_os = "windows", feature = "unix-file-copy-paste"))]
                 Some(message::Union::Cliprdr(clip)) => {
+                    if !crate::clipboard_dlp::allow_cliprdr(&clip, ClipboardSide::Host) {
+                        return true;
+                    }
                     if let Some(cliprdr::Union::Files(files)) = &clip.union {
                         self.post_file_audit(
                             FileAuditType::RemoteReceive,
@@ -2907,7 +3043,7 @@ impl Connection {
                         let set = displays.set.iter().map(|d| *d as usize).collect::<Vec<_>>();
                         self.capture_displays(&add, &sub, &set).await;
                     }
-                    #[cfg(windows)]
+                    #[cfg(any(windows, target_os = "linux"))]
                     Some(misc::Union::ToggleVirtualDisplay(t)) => {
                         self.toggle_virtual_display(t).await;
                     }
@@ -2939,6 +3075,10 @@ impl Connection {
                             self.inner.id,
                             Some(Instant::now().into()),
                         );
+                        video_service::VIDEO_QOS
+                            .lock()
+                            .unwrap()
+                            .user_video_acked(self.inner.id);
                     }
                     Some(misc::Union::RestartRemoteDevice(_)) => {
                         #[cfg(not(any(target_os = "android", target_os = "ios")))]
@@ -2974,7 +3114,16 @@ impl Connection {
                         if !self.disable_audio {
                             // Drop the audio sender previously.
                             drop(std::mem::replace(&mut self.audio_sender, None));
-                            self.audio_sender = Some(start_audio_thread());
+                            #[cfg(target_os = "linux")]
+                            if self.mic_passthrough {
+                                self.audio_sender =
+                                    Some(crate::client::start_audio_thread_with_device(Some(
+                                        crate::platform::linux::PA_VIRTUAL_MIC_SINK.to_owned(),
+                                    )));
+                            }
+                            if self.audio_sender.is_none() {
+                                self.audio_sender = Some(start_audio_thread());
+                            }
                             self.audio_sender
                                 .as_ref()
                                 .map(|a| allow_err!(a.send(MediaData::AudioFormat(format))));
@@ -3000,6 +3149,59 @@ impl Connection {
                     Some(misc::Union::ChangeDisplayResolution(dr)) => {
                         self.change_resolution(Some(dr.display as _), &dr.resolution)
                     }
+                    #[cfg(any(target_os = "windows", target_os = "linux"))]
+                    Some(misc::Union::PluginRequest(p))
+                        if p.id == crate::common::WINDOW_SHARE_REQUEST_ID =>
+                    {
+                        let allowed = self.is_authed_remote_conn() && self.peer_keyboard_enabled();
+                        if let Some(msg) =
+                            window_share::handle_request(self.inner.id(), &p.content, allowed)
+                        {
+                            self.send(msg).await;
+                        }
+                    }
+                    Some(misc::Union::PluginRequest(p))
+                        if p.id == crate::common::CAPABILITIES_REQUEST_ID =>
+                    {
+                        match serde_json::from_slice::<crate::common::Capabilities>(&p.content) {
+                            Ok(capabilities) => {
+                                video_service::VIDEO_QOS
+                                    .lock()
+                                    .unwrap()
+                                    .user_lossless_tiles(
+                                        self.inner.id(),
+                                        capabilities.lossless_tiles,
+                                    );
+                            }
+                            Err(e) => log::error!("Failed to parse capabilities: {}", e),
+                        }
+                    }
+                    Some(misc::Union::PluginRequest(p))
+                        if p.id == crate::common::AUDIO_REQUEST_ID =>
+                    {
+                        self.handle_audio_request(&p.content).await;
+                    }
+                    Some(misc::Union::PluginRequest(p))
+                        if p.id == crate::common::WOL_RELAY_REQUEST_ID =>
+                    {
+                        use crate::common::{make_wol_relay_msg, WolRelayMessage};
+                        match serde_json::from_slice::<WolRelayMessage>(&p.content) {
+                            Ok(WolRelayMessage::Request { id, macs, ips }) => {
+                                log::info!("wol relay request from {} to {}", self.ip, id);
+                                let error = match crate::lan::handle_wol_relay(&id, macs, ips) {
+                                    Ok(_) => "".to_owned(),
+                                    Err(e) => e.to_string(),
+                                };
+                                self.send(make_wol_relay_msg(&WolRelayMessage::Result {
+                                    id,
+                                    error,
+                                }))
+                                .await;
+                            }
+                            Ok(_) => {}
+                            Err(e) => log::error!("Invalid wol relay message: {}", e),
+                        }
+                    }
                     #[cfg(all(feature = "flutter", feature = "plugin_framework"))]
                     #[cfg(not(any(target_os = "android", target_os = "ios")))]
                     Some(misc::Union::PluginRequest(p)) => {
@@ -3387,7 +3589,7 @@ impl Connection {
         }
     }
 
-    #[cfg(windows)]
+    #[cfg(any(windows, target_os = "linux"))]
     async fn toggle_virtual_display(&mut self, t: ToggleVirtualDisplay) {
         let make_msg = |text: String| {
             let mut msg_out = Message::new();
@@ -3404,8 +3606,11 @@ impl Connection {
 
         if t.on {
             if !virtual_display_manager::is_virtual_display_supported() {
-                self.send(make_msg("idd_not_support_under_win10_2004_tip".to_string()))
-                    .await;
+                #[cfg(windows)]
+                let tip = "idd_not_support_under_win10_2004_tip";
+                #[cfg(target_os = "linux")]
+                let tip = "virtual_output_not_support_tip";
+                self.send(make_msg(tip.to_string())).await;
             } else {
                 if let Err(e) = virtual_display_manager::plug_in_monitor(t.display as _, Vec::new())
                 {
@@ -3454,6 +3659,16 @@ impl Connection {
                     {
                         return;
                     }
+                    #[cfg(target_os = "linux")]
+                    if let Some(_ok) =
+                        virtual_display_manager::linux::change_resolution_if_is_virtual_display(
+                            &name,
+                            r.width as _,
+                            r.height as _,
+                        )
+                    {
+                        return;
+                    }
                     #[allow(unused_mut)]
                     let mut record_changed = true;
                     #[cfg(windows)]
@@ -3495,6 +3710,9 @@ impl Connection {
         if let Some(ts) = self.voice_call_request_timestamp.take() {
             let msg = new_voice_call_response(ts.get(), accepted);
             if accepted {
+                // The voice call uses the same audio frames.
+                #[cfg(target_os = "linux")]
+                self.set_mic_passthrough(false);
                 crate::audio_service::set_voice_call_input_device(
                     crate::get_default_sound_input(),
                     false,
@@ -3519,6 +3737,77 @@ impl Connection {
         }
     }
 
+    async fn handle_audio_request(&mut self, content: &[u8]) {
+        use crate::common::{make_audio_msg, AudioMessage};
+        let msg: AudioMessage = match serde_json::from_slice(content) {
+            Ok(msg) => msg,
+            Err(e) => {
+                log::error!("Invalid audio request: {}", e);
+                return;
+            }
+        };
+        let res = match msg {
+            AudioMessage::Quality { hifi } => {
+                if !self.audio_enabled() {
+                    return;
+                }
+                crate::audio_service::set_hifi(self.inner.id(), hifi);
+                AudioMessage::Quality { hifi }
+            }
+            #[cfg(target_os = "linux")]
+            AudioMessage::MicPassthrough { enable } => {
+                if !enable {
+                    self.set_mic_passthrough(false);
+                    AudioMessage::MicPassthrough { enable }
+                } else if !self.audio || !self.peer_keyboard_enabled() {
+                    AudioMessage::Error {
+                        msg: "No permission of microphone passthrough".to_owned(),
+                    }
+                } else if self.voice_calling {
+                    AudioMessage::Error {
+                        msg: "Microphone passthrough is not available during a voice call"
+                            .to_owned(),
+                    }
+                } else {
+                    match crate::audio_service::set_mic_passthrough(self.inner.id(), true) {
+                        Ok(()) => {
+                            self.mic_passthrough = true;
+                            // The next audio format starts the playback to the virtual microphone.
+                            self.audio_sender.take();
+                            AudioMessage::MicPassthrough { enable }
+                        }
+                        Err(e) => AudioMessage::Error { msg: e.to_string() },
+                    }
+                }
+            }
+            #[cfg(target_os = "linux")]
+            msg => {
+                if !self.audio_enabled() {
+                    return;
+                }
+                if let Some(msg) = crate::audio_service::handle_request(self.inner.id(), msg) {
+                    self.send(msg).await;
+                }
+                return;
+            }
+            #[cfg(not(target_os = "linux"))]
+            _ => return,
+        };
+        self.send(make_audio_msg(&res)).await;
+    }
+
+    #[cfg(target_os = "linux")]
+    fn set_mic_passthrough(&mut self, enable: bool) {
+        if !enable && self.mic_passthrough {
+            self.mic_passthrough = false;
+            self.audio_sender.take();
+            allow_err!(crate::audio_service::set_mic_passthrough(
+                self.inner.id(),
+                false
+            ));
+        }
+    }
+
     pub async fn close_voice_call(&mut self) {
         crate::audio_service::set_voice_call_input_device(None, true);
         // Notify the connection manager that the voice call has been closed.
@@ -3956,7 +4245,15 @@ impl Connection {
 
     #[inline]
     async fn send(&mut self, msg: Message) {
-        allow_err!(self.stream.send(&msg).await);
+        allow_err!(self.send_on_lane(&msg).await);
+    }
+
+    /// Sends the message on its QUIC lane if any, or the stream.
+    async fn send_on_lane(&mut self, msg: &Message) -> ResultType<()> {
+        match self.quic_lanes.as_mut() {
+            Some(lanes) => lanes.send(&mut self.stream, msg).await,
+            None => self.stream.send(msg).await,
+        }
     }
 
     pub fn alive_conns() -> Vec<i32> {
@@ -4137,8 +4434,7 @@ impl Connection {
             } else {
                 // Maybe we should end the connection, because copy&paste files causes everything to wait.
                 allow_err!(
-                    self.stream
-                        .send(&crate::clipboard_file::clip_2_msg(clip))
+                    self.send_on_lane(&crate::clipboard_file::clip_2_msg(clip))
                         .await
                 );
             }
@@ -4263,7 +4559,7 @@ async fn start_ipc(
         sleep(1.).await;
     }
     let mut stream = None;
-    if let Ok(s) = crate::ipc::connect(1000, "_cm").await {
+    if let Ok(s) = crate::ipc::connect(1000, &crate::ipc::cm_postfix()).await {
         stream = Some(s);
     } else {
         #[allow(unused_mut)]
@@ -4279,16 +4575,26 @@ async fn start_ipc(
         let mut user = None;
 
         // Cm run as user, wait until desktop session is ready.
+        // The server of a headless session runs the cm of its own user.
         #[cfg(target_os = "linux")]
-        if crate::platform::is_headless_allowed() && linux_desktop_manager::is_headless() {
+        let session_username = if let Some((_, username)) =
+            linux_desktop_manager::get_session_server()
+        {
+            Some(username)
+        } else if crate::platform::is_headless_allowed() && linux_desktop_manager::is_headless() {
             let mut username = linux_desktop_manager::get_username();
-            loop {
-                if !username.is_empty() {
-                    break;
+            while username.is_empty() {
+                if let Ok(None) = timeout(1_000, _rx_desktop_ready.recv()).await {
+                    bail!("The connection is closed before the desktop is ready");
                 }
-                let _res = timeout(1_000, _rx_desktop_ready.recv()).await;
                 username = linux_desktop_manager::get_username();
             }
+            Some(username)
+        } else {
+            None
+        };
+        #[cfg(target_os = "linux")]
+        if let Some(username) = session_username {
             let uid = {
                 let output = run_cmds(&format!("id -u {}", &username))?;
                 let output = output.trim();
@@ -4340,7 +4646,7 @@ async fn start_ipc(
         }
         for _ in 0..20 {
             sleep(0.3).await;
-            if let Ok(s) = crate::ipc::connect(1000, "_cm").await {
+            if let Ok(s) = crate::ipc::connect(1000, &crate::ipc::cm_postfix()).await {
                 stream = Some(s);
                 break;
             }
@@ -4410,6 +4716,7 @@ pub enum AlarmAuditType {
     IpWhitelist = 0,
     ExceedThirtyAttempts = 1,
     SixAttemptsWithinOneMinute = 2,
+    ClipboardBlocked = 3,
 }
 
 pub enum FileAuditType {
@@ -4638,6 +4945,9 @@ struct LinuxHeadlessHandle {
     pub wait_ipc_timeout: u64,
     pub rx_cm_stream_ready: mpsc::Receiver<()>,
     pub tx_desktop_ready: mpsc::Sender<()>,
+    // The user of the headless session this connection is routed to.
+    pub session_username: Option<String>,
+    pub templates_sent: bool,
 }
 
 #[cfg(target_os = "linux")]
@@ -4651,6 +4961,8 @@ impl LinuxHeadlessHandle {
             wait_ipc_timeout: 10_000,
             rx_cm_stream_ready,
             tx_desktop_ready,
+            session_username: None,
+            templates_sent: false,
         }
     }
 
@@ -4667,6 +4979,20 @@ impl LinuxHeadlessHandle {
         }
     }
 
+    // The user of the headless session to hand off the connection to, the session is started and
+    // the os login is verified in `try_start_desktop`.
+    pub fn get_hand_off_username(&self, os_login: Option<&OSLogin>) -> Option<String> {
+        if !self.is_headless {
+            return None;
+        }
+        let username = linux_desktop_manager::get_login_username(&os_login?.username);
+        if username.is_empty() {
+            None
+        } else {
+            Some(username.to_owned())
+        }
+    }
+
     pub async fn wait_desktop_cm_ready(&mut self) {
         if self.is_headless {
             self.tx_desktop_ready.send(()).await.ok();
@@ -4675,6 +5001,15 @@ impl LinuxHeadlessHandle {
     }
 }
 
+#[cfg(target_os = "linux")]
+impl Drop for LinuxHeadlessHandle {
+    fn drop(&mut self) {
+        if let Some(username) = self.session_username.take() {
+            linux_desktop_manager::remove_connection(&username);
+        }
+    }
+}
+
 extern "C" fn connection_shutdown_hook() {
     // https://stackoverflow.com/questions/35980148/why-does-an-atexit-handler-panic-when-it-accesses-stdout
     // Please make sure there is no print in the call stack
@@ -4793,10 +5128,8 @@ mod raii {
                 shutdown_hooks::add_shutdown_hook(connection_shutdown_hook);
             });
             if conn_type == AuthConnType::Remote || conn_type == AuthConnType::ViewCamera {
-                video_service::VIDEO_QOS
-                    .lock()
-                    .unwrap()
-                    .on_connection_open(conn_id);
+                let mut video_qos = video_service::VIDEO_QOS.lock().unwrap();
+                video_qos.on_connection_open(conn_id);
             }
             Self(conn_id, conn_type)
         }
@@ -4911,6 +5244,9 @@ mod raii {
                     .unwrap()
                     .on_connection_close(self.0);
             }
+            #[cfg(any(target_os = "windows", target_os = "linux"))]
+            window_share::on_connection_close(self.0);
+            audio_service::on_connection_close(self.0);
             AUTHED_CONNS.lock().unwrap().retain(|c| c.conn_id != self.0);
             let remote_count = AUTHED_CONNS
                 .lock()
@@ -4925,7 +5261,7 @@ mod raii {
                 }
                 #[cfg(not(any(target_os = "android", target_os = "ios")))]
                 display_service::restore_resolutions();
-                #[cfg(windows)]
+                #[cfg(any(windows, target_os = "linux"))]
                 let _ = virtual_display_manager::reset_all();
                 #[cfg(target_os = "linux")]
                 scrap::wayland::pipewire::try_close_session();
@@ -188,6 +188,11 @@ fn displays_to_msg(displays: Vec<DisplayInfo>) -> Message {
         let m = crate::virtual_display_manager::get_platform_additions();
         pi.platform_additions = serde_json::to_string(&m).unwrap_or_default();
     }
+    #[cfg(target_os = "linux")]
+    {
+        let m = crate::virtual_display_manager::get_platform_additions();
+        pi.platform_additions = serde_json::to_string(&m).unwrap_or_default();
+    }
 
     // current_display should not be used in server.
     // It is set to 0 for compatibility with old clients.
@@ -1,5 +1,5 @@
 #[cfg(target_os = "linux")]
-use super::rdp_input::client::{RdpInputKeyboard, RdpInputMouse};
+use super::rdp_input::client::{RdpInputKeyboard, RdpInputMouse, RdpSession};
 use super::*;
 use crate::input::*;
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
@@ -185,7 +185,7 @@ impl LockModesHandler {
                     break;
                 }
             }
-        } else if wayland_use_rdp_input() {
+        } else if wayland_use_rdp_input() || wayland_use_wlr_input() {
             // We can't call `en.get_key_state(k)` because there's no api for this.
             std::thread::sleep(std::time::Duration::from_millis(50));
         }
@@ -592,22 +592,22 @@ pub async fn setup_rdp_input() -> ResultType<(), Box<dyn std::error::Error>> {
     let rdp_info_lock = RDP_SESSION_INFO.lock()?;
     let rdp_info = rdp_info_lock.as_ref().ok_or("RDP session is None")?;
 
-    let keyboard = RdpInputKeyboard::new(rdp_info.conn.clone(), rdp_info.session.clone())?;
+    let session = RdpSession::new(
+        rdp_info.conn.clone(),
+        rdp_info.session.clone(),
+        rdp_info.input.clone(),
+    );
+    let keyboard = RdpInputKeyboard::new(session.clone())?;
     en.set_custom_keyboard(Box::new(keyboard));
     log::info!("RdpInput keyboard created");
 
-    if let Some(stream) = rdp_info.streams.clone().into_iter().next() {
+    if let Some(stream) = rdp_info.streams.first() {
         let resolution = rdp_info
             .resolution
             .lock()
             .unwrap()
             .unwrap_or(stream.get_size());
-        let mouse = RdpInputMouse::new(
-            rdp_info.conn.clone(),
-            rdp_info.session.clone(),
-            stream,
-            resolution,
-        )?;
+        let mouse = RdpInputMouse::new(session, rdp_info.streams.clone(), resolution)?;
         en.set_custom_mouse(Box::new(mouse));
         log::info!("RdpInput mouse created");
     }
@@ -635,6 +635,31 @@ pub async fn update_mouse_resolution(minx: i32, maxx: i32, miny: i32, maxy: i32)
     Ok(())
 }
 
+// Switch between the wlr virtual devices and uinput when the capture backend changes,
+// see `scrap::wayland::pipewire::OPTION_WAYLAND_HEADLESS`.
+#[cfg(target_os = "linux")]
+pub async fn check_wlr_input() -> ResultType<()> {
+    let use_wlr =
+        scrap::wayland::pipewire::is_headless_capture() && super::wlr_input::is_supported();
+    if use_wlr == WLR_INPUT_ACTIVE.load(Ordering::SeqCst) {
+        return Ok(());
+    }
+    if use_wlr {
+        let keyboard = super::wlr_input::client::WlrInputKeyboard::new()?;
+        let mouse = super::wlr_input::client::WlrInputMouse::new()?;
+        let mut en = ENIGO.lock().unwrap();
+        en.set_custom_keyboard(Box::new(keyboard));
+        en.set_custom_mouse(Box::new(mouse));
+        WLR_INPUT_ACTIVE.store(true, Ordering::SeqCst);
+        log::info!("wlr virtual keyboard and pointer created");
+    } else {
+        WLR_INPUT_ACTIVE.store(false, Ordering::SeqCst);
+        let (minx, maxx, miny, maxy) = super::wayland::get_uinput_resolution();
+        setup_uinput(minx, maxx, miny, maxy).await?;
+    }
+    Ok(())
+}
+
 #[cfg(target_os = "linux")]
 async fn set_uinput_resolution(minx: i32, maxx: i32, miny: i32, maxy: i32) -> ResultType<()> {
     super::uinput::client::set_resolution(minx, maxx, miny, maxy).await
@@ -1822,16 +1847,27 @@ async fn send_sas() -> ResultType<()> {
     Ok(())
 }
 
+#[cfg(target_os = "linux")]
+static WLR_INPUT_ACTIVE: AtomicBool = AtomicBool::new(false);
+
 #[inline]
 #[cfg(target_os = "linux")]
 pub fn wayland_use_uinput() -> bool {
-    !crate::platform::is_x11() && crate::is_server()
+    !crate::platform::is_x11() && crate::is_server() && !wayland_use_wlr_input()
+}
+
+#[inline]
+#[cfg(target_os = "linux")]
+pub fn wayland_use_wlr_input() -> bool {
+    WLR_INPUT_ACTIVE.load(Ordering::SeqCst)
 }
 
 #[inline]
 #[cfg(target_os = "linux")]
 pub fn wayland_use_rdp_input() -> bool {
-    !crate::platform::is_x11() && !crate::is_server()
+    // The headless GNOME session is captured by Mutter, which also takes the input.
+    !crate::platform::is_x11()
+        && (!crate::is_server() || scrap::wayland::pipewire::is_mutter_session())
 }
 
 lazy_static::lazy_static! {
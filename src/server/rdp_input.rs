@@ -2,7 +2,8 @@ use crate::uinput::service::map_key;
 use dbus::{blocking::SyncConnection, Path};
 use enigo::{Key, KeyboardControllable, MouseButton, MouseControllable};
 use hbb_common::ResultType;
-use scrap::wayland::pipewire::{get_portal, PwStreamInfo};
+use scrap::wayland::mutter_screencast as mutter;
+use scrap::wayland::pipewire::{get_portal, PwStreamInfo, RdpInputSession};
 use scrap::wayland::remote_desktop_portal::OrgFreedesktopPortalRemoteDesktop as remote_desktop_portal;
 use std::collections::HashMap;
 use std::sync::Arc;
@@ -19,14 +20,139 @@ pub mod client {
     const PRESSED_DOWN_STATE: u32 = 1;
     const PRESSED_UP_STATE: u32 = 0;
 
-    pub struct RdpInputKeyboard {
+    const AXIS_VERTICAL: u32 = 0;
+    const AXIS_HORIZONTAL: u32 = 1;
+
+    // The remote desktop session of the portal or Mutter, see `RdpInputSession`.
+    #[derive(Clone)]
+    pub struct RdpSession {
         conn: Arc<SyncConnection>,
         session: Path<'static>,
+        input: RdpInputSession,
+    }
+
+    impl RdpSession {
+        pub fn new(
+            conn: Arc<SyncConnection>,
+            session: Path<'static>,
+            input: RdpInputSession,
+        ) -> Self {
+            Self {
+                conn,
+                session,
+                input,
+            }
+        }
+
+        fn notify_keycode(&self, keycode: i32, state: u32) -> Result<(), dbus::Error> {
+            match &self.input {
+                RdpInputSession::Portal => remote_desktop_portal::notify_keyboard_keycode(
+                    &get_portal(&self.conn),
+                    &self.session,
+                    HashMap::new(),
+                    keycode,
+                    state,
+                ),
+                RdpInputSession::Mutter(_) => mutter::notify_keyboard_keycode(
+                    &self.conn,
+                    &self.session,
+                    keycode as _,
+                    state == PRESSED_DOWN_STATE,
+                ),
+            }
+        }
+
+        fn notify_button(&self, button: i32, state: u32) -> Result<(), dbus::Error> {
+            match &self.input {
+                RdpInputSession::Portal => remote_desktop_portal::notify_pointer_button(
+                    &get_portal(&self.conn),
+                    &self.session,
+                    HashMap::new(),
+                    button,
+                    state,
+                ),
+                RdpInputSession::Mutter(_) => mutter::notify_pointer_button(
+                    &self.conn,
+                    &self.session,
+                    button,
+                    state == PRESSED_DOWN_STATE,
+                ),
+            }
+        }
+
+        fn notify_axis(&self, axis: u32, length: i32) -> Result<(), dbus::Error> {
+            match &self.input {
+                RdpInputSession::Portal => {
+                    let (dx, dy) = if axis == AXIS_HORIZONTAL {
+                        (length as f64, 0.)
+                    } else {
+                        (0., length as f64)
+                    };
+                    remote_desktop_portal::notify_pointer_axis(
+                        &get_portal(&self.conn),
+                        &self.session,
+                        HashMap::new(),
+                        dx,
+                        dy,
+                    )
+                }
+                RdpInputSession::Mutter(_) => {
+                    mutter::notify_pointer_axis_discrete(&self.conn, &self.session, axis, length)
+                }
+            }
+        }
+
+        fn notify_motion(&self, dx: f64, dy: f64) -> Result<(), dbus::Error> {
+            match &self.input {
+                RdpInputSession::Portal => remote_desktop_portal::notify_pointer_motion(
+                    &get_portal(&self.conn),
+                    &self.session,
+                    HashMap::new(),
+                    dx,
+                    dy,
+                ),
+                RdpInputSession::Mutter(_) => {
+                    mutter::notify_pointer_motion_relative(&self.conn, &self.session, dx, dy)
+                }
+            }
+        }
+
+        fn notify_motion_absolute(
+            &self,
+            stream: &PwStreamInfo,
+            x: f64,
+            y: f64,
+        ) -> Result<(), dbus::Error> {
+            match &self.input {
+                RdpInputSession::Portal => remote_desktop_portal::notify_pointer_motion_absolute(
+                    &get_portal(&self.conn),
+                    &self.session,
+                    HashMap::new(),
+                    stream.path as u32,
+                    x,
+                    y,
+                ),
+                RdpInputSession::Mutter(objects) => match objects.get(&stream.path) {
+                    Some(object) => mutter::notify_pointer_motion_absolute(
+                        &self.conn,
+                        &self.session,
+                        object,
+                        x,
+                        y,
+                    ),
+                    None => Ok(()),
+                },
+            }
+        }
+    }
+
+    pub struct RdpInputKeyboard {
+        session: RdpSession,
     }
 
     impl RdpInputKeyboard {
-        pub fn new(conn: Arc<SyncConnection>, session: Path<'static>) -> ResultType<Self> {
-            Ok(Self { conn, session })
+        pub fn new(session: RdpSession) -> ResultType<Self> {
+            Ok(Self { session })
         }
     }
 
@@ -47,39 +173,41 @@ pub mod client {
         fn key_sequence(&mut self, s: &str) {
             for c in s.chars() {
                 let key = Key::Layout(c);
-                let _ = handle_key(true, key, self.conn.clone(), &self.session);
-                let _ = handle_key(false, key, self.conn.clone(), &self.session);
+                let _ = handle_key(true, key, &self.session);
+                let _ = handle_key(false, key, &self.session);
             }
         }
 
         fn key_down(&mut self, key: Key) -> enigo::ResultType {
-            handle_key(true, key, self.conn.clone(), &self.session)?;
+            handle_key(true, key, &self.session)?;
             Ok(())
         }
         fn key_up(&mut self, key: Key) {
-            let _ = handle_key(false, key, self.conn.clone(), &self.session);
+            let _ = handle_key(false, key, &self.session);
         }
         fn key_click(&mut self, key: Key) {
-            let _ = handle_key(true, key, self.conn.clone(), &self.session);
-            let _ = handle_key(false, key, self.conn.clone(), &self.session);
+            let _ = handle_key(true, key, &self.session);
+            let _ = handle_key(false, key, &self.session);
         }
     }
 
     pub struct RdpInputMouse {
-        conn: Arc<SyncConnection>,
-        session: Path<'static>,
-        stream: PwStreamInfo,
+        session: RdpSession,
+        // One for each display.
+        streams: Vec<PwStreamInfo>,
         resolution: (usize, usize),
         scale: Option<f64>,
     }
 
     impl RdpInputMouse {
         pub fn new(
-            conn: Arc<SyncConnection>,
-            session: Path<'static>,
-            stream: PwStreamInfo,
+            session: RdpSession,
+            streams: Vec<PwStreamInfo>,
             resolution: (usize, usize),
         ) -> ResultType<Self> {
+            let Some(stream) = streams.first() else {
+                hbb_common::bail!("No stream for the mouse");
+            };
             // https://github.com/cloudydesk/cloudydesk/pull/9019#issuecomment-2295252388
             // There may be a bug in Rdp input on Gnome util Ubuntu 24.04 (Gnome 46)
             //
@@ -99,15 +227,33 @@ pub mod client {
                 None
             };
             Ok(Self {
-                conn,
                 session,
-                stream,
+                streams,
                 resolution,
                 scale,
             })
         }
     }
 
+    // The index of the display at (x, y) in the rects (position, size), and the position in it.
+    // The rects of the streams are in the coordinates of the displays sent to the peer.
+    pub(super) fn map_to_display(
+        rects: &[((i32, i32), (usize, usize))],
+        x: f64,
+        y: f64,
+    ) -> Option<(usize, f64, f64)> {
+        let contains = |((left, top), (width, height)): &((i32, i32), (usize, usize))| {
+            x >= *left as f64
+                && y >= *top as f64
+                && x < *left as f64 + *width as f64
+                && y < *top as f64 + *height as f64
+        };
+        // The pointer may be out of the displays a little, keep it on the first one.
+        let index = rects.iter().position(contains).unwrap_or(0);
+        let ((left, top), _) = rects.get(index)?;
+        Some((index, x - *left as f64, y - *top as f64))
+    }
+
     impl MouseControllable for RdpInputMouse {
         fn as_any(&self) -> &dyn std::any::Any {
             self
@@ -128,15 +274,16 @@ pub mod client {
             } else {
                 y as f64
             };
-            let portal = get_portal(&self.conn);
-            let _ = remote_desktop_portal::notify_pointer_motion_absolute(
-                &portal,
-                &self.session,
-                HashMap::new(),
-                self.stream.path as u32,
-                x,
-                y,
-            );
+            let rects: Vec<_> = self
+                .streams
+                .iter()
+                .map(|s| (s.get_position(), s.get_size()))
+                .collect();
+            if let Some((index, x, y)) = map_to_display(&rects, x, y) {
+                let _ = self
+                    .session
+                    .notify_motion_absolute(&self.streams[index], x, y);
+            }
         }
         fn mouse_move_relative(&mut self, x: i32, y: i32) {
             let x = if let Some(s) = self.scale {
@@ -149,45 +296,24 @@ pub mod client {
             } else {
                 y as f64
             };
-            let portal = get_portal(&self.conn);
-            let _ = remote_desktop_portal::notify_pointer_motion(
-                &portal,
-                &self.session,
-                HashMap::new(),
-                x,
-                y,
-            );
+            let _ = self.session.notify_motion(x, y);
         }
         fn mouse_down(&mut self, button: MouseButton) -> enigo::ResultType {
-            handle_mouse(true, button, self.conn.clone(), &self.session);
+            handle_mouse(true, button, &self.session);
             Ok(())
         }
         fn mouse_up(&mut self, button: MouseButton) {
-            handle_mouse(false, button, self.conn.clone(), &self.session);
+            handle_mouse(false, button, &self.session);
         }
         fn mouse_click(&mut self, button: MouseButton) {
-            handle_mouse(true, button, self.conn.clone(), &self.session);
-            handle_mouse(false, button, self.conn.clone(), &self.session);
+            handle_mouse(true, button, &self.session);
+            handle_mouse(false, button, &self.session);
         }
         fn mouse_scroll_x(&mut self, length: i32) {
-            let portal = get_portal(&self.conn);
-            let _ = remote_desktop_portal::notify_pointer_axis(
-                &portal,
-                &self.session,
-                HashMap::new(),
-                length as f64,
-                0 as f64,
-            );
+            let _ = self.session.notify_axis(AXIS_HORIZONTAL, length);
         }
         fn mouse_scroll_y(&mut self, length: i32) {
-            let portal = get_portal(&self.conn);
-            let _ = remote_desktop_portal::notify_pointer_axis(
-                &portal,
-                &self.session,
-                HashMap::new(),
-                0 as f64,
-                length as f64,
-            );
+            let _ = self.session.notify_axis(AXIS_VERTICAL, length);
         }
     }
 
@@ -201,60 +327,30 @@ pub mod client {
         key
     }
 
-    fn handle_key(
-        down: bool,
-        key: Key,
-        conn: Arc<SyncConnection>,
-        session: &Path<'static>,
-    ) -> ResultType<()> {
+    fn handle_key(down: bool, key: Key, session: &RdpSession) -> ResultType<()> {
         let state: u32 = if down {
             PRESSED_DOWN_STATE
         } else {
             PRESSED_UP_STATE
         };
-        let portal = get_portal(&conn);
         match key {
             Key::Raw(key) => {
                 let key = get_raw_evdev_keycode(key);
-                remote_desktop_portal::notify_keyboard_keycode(
-                    &portal,
-                    &session,
-                    HashMap::new(),
-                    key,
-                    state,
-                )?;
+                session.notify_keycode(key, state)?;
             }
             _ => {
                 if let Ok((key, is_shift)) = map_key(&key) {
                     if is_shift {
-                        remote_desktop_portal::notify_keyboard_keycode(
-                            &portal,
-                            &session,
-                            HashMap::new(),
-                            evdev::Key::KEY_LEFTSHIFT.code() as i32,
-                            state,
-                        )?;
+                        session.notify_keycode(evdev::Key::KEY_LEFTSHIFT.code() as i32, state)?;
                     }
-                    remote_desktop_portal::notify_keyboard_keycode(
-                        &portal,
-                        &session,
-                        HashMap::new(),
-                        key.code() as i32,
-                        state,
-                    )?;
+                    session.notify_keycode(key.code() as i32, state)?;
                 }
             }
         }
         Ok(())
     }
 
-    fn handle_mouse(
-        down: bool,
-        button: MouseButton,
-        conn: Arc<SyncConnection>,
-        session: &Path<'static>,
-    ) {
-        let portal = get_portal(&conn);
+    fn handle_mouse(down: bool, button: MouseButton, session: &RdpSession) {
         let but_key = match button {
             MouseButton::Left => EVDEV_MOUSE_LEFT,
             MouseButton::Right => EVDEV_MOUSE_RIGHT,
@@ -268,12 +364,32 @@ pub mod client {
         } else {
             PRESSED_UP_STATE
         };
-        let _ = remote_desktop_portal::notify_pointer_button(
-            &portal,
-            &session,
-            HashMap::new(),
-            but_key,
-            state,
+        let _ = session.notify_button(but_key, state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::client::map_to_display;
+
+    #[test]
+    fn test_map_to_display() {
+        let rects = [((0, 0), (1920, 1080)), ((1920, 0), (1280, 1024))];
+        assert_eq!(map_to_display(&rects, 100., 200.), Some((0, 100., 200.)));
+        assert_eq!(map_to_display(&rects, 1920., 10.), Some((1, 0., 10.)));
+        assert_eq!(
+            map_to_display(&rects, 3199., 1023.),
+            Some((1, 1279., 1023.))
+        );
+        // Out of the displays.
+        assert_eq!(
+            map_to_display(&rects, 3000., 1050.),
+            Some((0, 3000., 1050.))
+        );
+        assert_eq!(
+            map_to_display(&[((-1280, 0), (1280, 720))], -1., 0.),
+            Some((0, 1279., 0.))
         );
+        assert_eq!(map_to_display(&[], 0., 0.), None);
     }
 }
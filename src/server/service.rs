@@ -228,6 +228,16 @@ impl<T: Subscriber + From<ConnInner>> ServiceTmpl<T> {
         }
     }
 
+    // To the subscribers whose ids pass `filter`.
+    pub fn send_shared_if<F: Fn(i32) -> bool>(&self, msg: Arc<Message>, filter: F) {
+        let mut lock = self.0.write().unwrap();
+        for s in lock.subscribes.values_mut() {
+            if filter(s.id()) {
+                s.send(msg.clone());
+            }
+        }
+    }
+
     pub fn send_video_frame(&self, msg: Message) -> HashSet<i32> {
         self.send_video_frame_shared(Arc::new(msg))
     }
@@ -345,6 +355,15 @@ impl<T: Subscriber + From<ConnInner>> ServiceSwap<T> {
         (self.0).0.write().unwrap().send_new_subscribes(msg);
     }
 
+    pub fn send_shared_if<F: Fn(i32) -> bool>(&self, msg: Arc<Message>, filter: F) {
+        let mut lock = (self.0).0.write().unwrap();
+        for s in lock.new_subscribes.values_mut() {
+            if filter(s.id()) {
+                s.send(msg.clone());
+            }
+        }
+    }
+
     #[inline]
     pub fn has_subscribes(&self) -> bool {
         (self.0).0.read().unwrap().subscribes.len() > 0
@@ -173,12 +173,89 @@ pub mod client {
         let _ = conn.next().await?;
         Ok(())
     }
+
+    /// The local input devices are grabbed by the uinput service until this is dropped.
+    ///
+    /// The ipc connection lives in a dedicated thread with its own runtime,
+    /// so the blocker can be created and dropped inside or outside a tokio runtime.
+    pub struct UInputBlocker {
+        tx_stop: Option<std::sync::mpsc::Sender<()>>,
+        handle: Option<std::thread::JoinHandle<()>>,
+    }
+
+    impl UInputBlocker {
+        pub fn new() -> ResultType<Self> {
+            let (tx_res, rx_res) = std::sync::mpsc::channel::<ResultType<()>>();
+            let (tx_stop, rx_stop) = std::sync::mpsc::channel::<()>();
+            let handle = std::thread::spawn(move || {
+                let rt = match tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                {
+                    Ok(rt) => rt,
+                    Err(e) => {
+                        tx_res.send(Err(e.into())).ok();
+                        return;
+                    }
+                };
+                let mut conn = match rt.block_on(Self::block()) {
+                    Ok(conn) => {
+                        tx_res.send(Ok(())).ok();
+                        conn
+                    }
+                    Err(e) => {
+                        tx_res.send(Err(e)).ok();
+                        return;
+                    }
+                };
+                // Until the blocker is dropped.
+                rx_stop.recv().ok();
+                // Closing the connection releases the grabs too, this only makes it explicit.
+                allow_err!(
+                    rt.block_on(conn.send(&Data::Control(ipc::DataControl::BlockInput(false))))
+                );
+            });
+            let blocker = Self {
+                tx_stop: Some(tx_stop),
+                handle: Some(handle),
+            };
+            match rx_res.recv() {
+                Ok(Ok(())) => Ok(blocker),
+                Ok(Err(e)) => Err(e),
+                Err(_) => bail!("The input blocking thread exited unexpectedly"),
+            }
+        }
+
+        async fn block() -> ResultType<Connection> {
+            let mut conn = ipc::connect(IPC_CONN_TIMEOUT, IPC_POSTFIX_CONTROL).await?;
+            conn.send(&Data::Control(ipc::DataControl::BlockInput(true)))
+                .await?;
+            match conn.next_timeout(IPC_REQUEST_TIMEOUT).await? {
+                Some(Data::Empty) => Ok(conn),
+                resp => bail!("Unexpected block input response: {:?}", resp),
+            }
+        }
+    }
+
+    impl Drop for UInputBlocker {
+        fn drop(&mut self) {
+            self.tx_stop.take();
+            if let Some(handle) = self.handle.take() {
+                if handle.join().is_err() {
+                    log::error!("Failed to join the input blocking thread");
+                }
+            }
+        }
+    }
 }
 
 pub mod service {
     use super::*;
     use hbb_common::lazy_static;
-    use std::{collections::HashMap, sync::Mutex};
+    use std::{collections::HashMap, path::PathBuf, sync::Mutex, time::Duration};
+
+    // Check the plugged in input devices while the local input is blocked.
+    const HOTPLUG_CHECK_INTERVAL: Duration = Duration::from_secs(1);
 
     lazy_static::lazy_static! {
     static ref KEY_MAP: HashMap<enigo::Key, evdev::Key> = HashMap::from(
@@ -680,8 +757,63 @@ pub mod service {
         });
     }
 
+    // The physical keyboards, mice and touchpads, the local user can not interfere if they are grabbed.
+    // The devices created by us (uinput) have no physical path and are skipped,
+    // otherwise the input from the peer would be blocked on Wayland.
+    // The power button and the lid switch are kept working.
+    fn is_blockable(device: &evdev::Device) -> bool {
+        if device.physical_path().is_none() {
+            return false;
+        }
+        let events = device.supported_events();
+        if events.contains(EventType::SWITCH) {
+            return false;
+        }
+        if events.contains(EventType::RELATIVE) || events.contains(EventType::ABSOLUTE) {
+            return true;
+        }
+        match device.supported_keys() {
+            Some(keys) => keys.iter().any(|key| !POWER_KEYS.contains(&key)),
+            None => false,
+        }
+    }
+
+    const POWER_KEYS: [evdev::Key; 4] = [
+        evdev::Key::KEY_POWER,
+        evdev::Key::KEY_SLEEP,
+        evdev::Key::KEY_SUSPEND,
+        evdev::Key::KEY_WAKEUP,
+    ];
+
+    // Grab the blockable devices which are not grabbed yet, the devices may be plugged in later.
+    fn grab_input_devices(grabbed: &mut HashMap<PathBuf, evdev::Device>) {
+        grabbed.retain(|path, _| path.exists());
+        let count = grabbed.len();
+        for (path, mut device) in evdev::enumerate() {
+            if grabbed.contains_key(&path) || !is_blockable(&device) {
+                continue;
+            }
+            match device.grab() {
+                Ok(_) => {
+                    log::debug!("Grabbed input device {:?} {:?}", path, device.name());
+                    grabbed.insert(path, device);
+                }
+                Err(e) => {
+                    log::warn!("Failed to grab input device {:?}, {}", path, e);
+                }
+            }
+        }
+        if grabbed.len() != count {
+            log::info!("Grabbed {} input devices", grabbed.len());
+        }
+    }
+
     fn spawn_controller_handler(mut stream: ipc::Connection) {
         tokio::spawn(async move {
+            // Dropping the devices releases the grabs, also when the connection is closed.
+            let mut grabbed_devices: HashMap<PathBuf, evdev::Device> = HashMap::new();
+            let mut blocking = false;
+            let mut hotplug_timer = tokio::time::interval(HOTPLUG_CHECK_INTERVAL);
             loop {
                 tokio::select! {
                     res = stream.next() => {
@@ -702,6 +834,15 @@ pub mod service {
                                             *RESOLUTION.lock().unwrap() = ((minx, maxx), (miny, maxy));
                                             allow_err!(stream.send(&Data::Empty).await);
                                         }
+                                        ipc::DataControl::BlockInput(block) => {
+                                            blocking = block;
+                                            if block {
+                                                grab_input_devices(&mut grabbed_devices);
+                                            } else {
+                                                grabbed_devices.clear();
+                                            }
+                                            allow_err!(stream.send(&Data::Empty).await);
+                                        }
                                     }
                                     _ => {
                                     }
@@ -710,6 +851,11 @@ pub mod service {
                             _ => {}
                         }
                     }
+                    _ = hotplug_timer.tick() => {
+                        if blocking {
+                            grab_input_devices(&mut grabbed_devices);
+                        }
+                    }
                 }
             }
         });
@@ -1,4 +1,5 @@
 use super::*;
+use crate::server::bandwidth_estimator::{self, BweSession, Feedback};
 use scrap::codec::{Quality, BR_BALANCED, BR_BEST, BR_SPEED};
 use std::{
     collections::VecDeque,
@@ -25,6 +26,28 @@ adjust betwen FPS and ratio:
 
 delay:
     use delay minus RTT as the actual network delay
+
+bandwidth estimation (option enable-bandwidth-estimation, see bandwidth_estimator.rs):
+    Each user has an estimator fed with the send time, queue time and size of the video frames,
+    the acknowledgements of the frames, the RTT and the transport loss.
+    ratio: adjusted every second toward the minimum estimate of all users instead of by the delay thresholds,
+        decrease when the estimate is lower than the bitrate, increase on dynamic screen only,
+        by at most -15%/+10% per step.
+    fps: capped to what the estimate can carry with the average frame size,
+        and changed by at most +2 / -30% per second.
+
+per-display budget (option enable-display-budget, off by default, more than one display):
+    The ratio above is the average of the displays, each display gets a share of the total weighted by
+    its activity (encodes per second) and focus (cursor on it, or the active window on it).
+    The display with the cursor gets up to MAX_DISPLAY_SHARE times of the average.
+    bitrate is the total of all displays.
+
+lossless tiles (option allow-lossless-tiles, see scrap::tile):
+    Enabled only if all users understand them.
+
+region of interest (option enable-video-roi, off by default):
+    The encoders supporting it encode the active window under the cursor, or the area around the cursor,
+    with higher quality.
 */
 
 // Constants
@@ -43,6 +66,14 @@ const HISTORY_DELAY_LEN: usize = 2;
 const ADJUST_RATIO_INTERVAL: usize = 3; // Adjust quality ratio every 3 seconds
 const DYNAMIC_SCREEN_THRESHOLD: usize = 2; // Allow increase quality ratio if encode more than 2 times in one second
 const DELAY_THRESHOLD_150MS: u32 = 150; // 150ms is the threshold for good network condition
+const BWE_MAX_RATIO_DECREASE: f32 = 0.85;
+const BWE_MAX_RATIO_INCREASE: f32 = 1.1;
+const BWE_MAX_FPS_INCREASE: u32 = 2;
+const BWE_MAX_FPS_DECREASE: f32 = 0.7;
+const DISPLAY_IDLE_WEIGHT: f32 = 0.5;
+const DISPLAY_CURSOR_WEIGHT: f32 = 2.0;
+const DISPLAY_WINDOW_WEIGHT: f32 = 1.5;
+const MAX_DISPLAY_SHARE: f32 = 2.0;
 
 #[derive(Default, Debug, Clone)]
 struct UserDelay {
@@ -93,12 +124,17 @@ struct UserData {
     quality: Option<(i64, Quality)>, // (time, quality)
     delay: UserDelay,
     record: bool,
+    lossless_tiles: bool, // The peer understands the lossless tiles
 }
 
 #[derive(Default, Debug, Clone)]
 struct DisplayData {
     send_counter: usize, // Number of times encode during period
     support_changing_quality: bool,
+    bitrate: u32,
+    activity: f32, // Smoothed number of times encode per second
+    cursor: bool,  // The cursor is on the display
+    window: bool,  // The active window is on the display
 }
 
 // Main QoS controller structure
@@ -111,6 +147,10 @@ pub struct VideoQoS {
     adjust_ratio_instant: Instant,
     abr_config: bool,
     new_user_instant: Instant,
+    bwe: HashMap<i32, BweSession>,
+    display_budget: bool,
+    roi: bool,
+    lossless_tiles: bool,
 }
 
 impl Default for VideoQoS {
@@ -124,6 +164,10 @@ impl Default for VideoQoS {
             adjust_ratio_instant: Instant::now(),
             abr_config: true,
             new_user_instant: Instant::now(),
+            bwe: Default::default(),
+            display_budget: false,
+            roi: false,
+            lossless_tiles: false,
         }
     }
 }
@@ -145,12 +189,20 @@ impl VideoQoS {
         }
     }
 
-    // Store bitrate for later use
-    pub fn store_bitrate(&mut self, bitrate: u32) {
-        self.bitrate_store = bitrate;
+    // Store bitrate of the display for later use
+    pub fn store_bitrate(&mut self, video_service_name: &str, bitrate: u32) {
+        if let Some(display) = self.displays.get_mut(video_service_name) {
+            display.bitrate = bitrate;
+        }
+        self.bitrate_store = self
+            .displays
+            .iter()
+            .map(|d| d.1.bitrate)
+            .sum::<u32>()
+            .max(bitrate);
     }
 
-    // Get stored bitrate
+    // Get stored bitrate of all displays
     pub fn bitrate(&self) -> u32 {
         self.bitrate_store
     }
@@ -163,6 +215,51 @@ impl VideoQoS {
         self.ratio
     }
 
+    // Get the ratio of the display, its share of the budget of all displays
+    pub fn display_ratio(&mut self, video_service_name: &str) -> f32 {
+        let ratio = self.ratio();
+        if !self.display_budget || self.displays.len() <= 1 || !self.in_vbr_state() {
+            return ratio;
+        }
+        let fps = self.fps() as f32;
+        let weight = |d: &DisplayData| {
+            let mut w = DISPLAY_IDLE_WEIGHT + (d.activity / fps).min(1.0);
+            if d.cursor {
+                w *= DISPLAY_CURSOR_WEIGHT;
+            } else if d.window {
+                w *= DISPLAY_WINDOW_WEIGHT;
+            }
+            w
+        };
+        let total: f32 = self.displays.iter().map(|d| weight(d.1)).sum();
+        let Some(display) = self.displays.get(video_service_name) else {
+            return ratio;
+        };
+        let share = weight(display) * self.displays.len() as f32 / total;
+        // Round to avoid reconfiguring the encoder on every small change
+        let share = ((share * 20.0).round() / 20.0).min(MAX_DISPLAY_SHARE);
+        (ratio * share).clamp(BR_MIN_HIGH_RESOLUTION, BR_MAX)
+    }
+
+    pub fn set_display_focus(&mut self, video_service_name: &str, cursor: bool, window: bool) {
+        if let Some(display) = self.displays.get_mut(video_service_name) {
+            display.cursor = cursor;
+            display.window = window;
+        }
+    }
+
+    #[inline]
+    pub fn roi_enabled(&self) -> bool {
+        self.roi
+    }
+
+    // Check if the lossless tiles are allowed and all users understand them
+    pub fn lossless_tiles_enabled(&self) -> bool {
+        self.lossless_tiles
+            && !self.users.is_empty()
+            && self.users.iter().all(|u| u.1.lossless_tiles)
+    }
+
     // Check if any user is in recording mode
     pub fn record(&self) -> bool {
         self.users.iter().any(|u| u.1.record)
@@ -186,12 +283,26 @@ impl VideoQoS {
     pub fn on_connection_open(&mut self, id: i32) {
         self.users.insert(id, UserData::default());
         self.abr_config = Config::get_option("enable-abr") != "N";
+        self.display_budget = Config::get_option("enable-display-budget") == "Y";
+        self.roi = Config::get_option("enable-video-roi") == "Y";
+        self.lossless_tiles = hbb_common::config::option2bool(
+            scrap::tile::OPTION_LOSSLESS_TILES,
+            &Config::get_option(scrap::tile::OPTION_LOSSLESS_TILES),
+        );
         self.new_user_instant = Instant::now();
+        if hbb_common::config::option2bool(
+            "enable-bandwidth-estimation",
+            &Config::get_option("enable-bandwidth-estimation"),
+        ) {
+            let init_kbps = (self.bitrate() as f32).max(bandwidth_estimator::INIT_KBPS);
+            self.bwe.insert(id, BweSession::new(id, init_kbps));
+        }
     }
 
     // Clean up user session
     pub fn on_connection_close(&mut self, id: i32) {
         self.users.remove(&id);
+        self.bwe.remove(&id);
         if self.users.is_empty() {
             *self = Default::default();
         }
@@ -237,6 +348,12 @@ impl VideoQoS {
         }
     }
 
+    pub fn user_lossless_tiles(&mut self, id: i32, v: bool) {
+        if let Some(user) = self.users.get_mut(&id) {
+            user.lossless_tiles = v;
+        }
+    }
+
     pub fn user_record(&mut self, id: i32, v: bool) {
         if let Some(user) = self.users.get_mut(&id) {
             user.record = v;
@@ -259,6 +376,9 @@ impl VideoQoS {
         // Calculate minimum acceptable delay-fps product
         let dividend_ms = DELAY_THRESHOLD_150MS * min_fps;
 
+        if let Some(bwe) = self.bwe.get_mut(&id) {
+            bwe.feed(Feedback::Rtt { ms: delay });
+        }
         let mut adjust_ratio = false;
         if let Some(user) = self.users.get_mut(&id) {
             let delay = delay.max(10);
@@ -342,6 +462,36 @@ impl VideoQoS {
             }
         }
     }
+
+    pub fn user_video_sent(
+        &mut self,
+        id: i32,
+        display: usize,
+        bytes: usize,
+        queue_ms: u32,
+        send_ms: u32,
+    ) {
+        if let Some(bwe) = self.bwe.get_mut(&id) {
+            bwe.feed(Feedback::Sent {
+                display,
+                bytes,
+                queue_ms,
+                send_ms,
+            });
+        }
+    }
+
+    pub fn user_video_acked(&mut self, id: i32) {
+        if let Some(bwe) = self.bwe.get_mut(&id) {
+            bwe.feed(Feedback::Acked);
+        }
+    }
+
+    pub fn user_transport_loss(&mut self, id: i32, ratio: f32) {
+        if let Some(bwe) = self.bwe.get_mut(&id) {
+            bwe.feed(Feedback::Loss { ratio });
+        }
+    }
 }
 
 // Common adjust functions
@@ -358,15 +508,25 @@ impl VideoQoS {
     pub fn update_display_data(&mut self, video_service_name: &str, send_counter: usize) {
         if let Some(display) = self.displays.get_mut(video_service_name) {
             display.send_counter += send_counter;
+            display.activity = display.activity * 0.7 + send_counter as f32 * 0.3;
         }
+        self.bwe.iter_mut().for_each(|(_, bwe)| {
+            bwe.update();
+        });
         self.adjust_fps();
         let abr_enabled = self.in_vbr_state();
         if abr_enabled {
-            if self.adjust_ratio_instant.elapsed().as_secs() >= ADJUST_RATIO_INTERVAL as u64 {
+            // The estimate reacts faster than the delay thresholds
+            let interval = if self.bwe.is_empty() {
+                ADJUST_RATIO_INTERVAL
+            } else {
+                1
+            };
+            if self.adjust_ratio_instant.elapsed().as_secs() >= interval as u64 {
                 let dynamic_screen = self
                     .displays
                     .iter()
-                    .any(|d| d.1.send_counter >= ADJUST_RATIO_INTERVAL * DYNAMIC_SCREEN_THRESHOLD);
+                    .any(|d| d.1.send_counter >= interval * DYNAMIC_SCREEN_THRESHOLD);
                 self.displays.iter_mut().for_each(|d| {
                     d.1.send_counter = 0;
                 });
@@ -400,6 +560,14 @@ impl VideoQoS {
         fps.clamp(MIN_FPS, MAX_FPS)
     }
 
+    // Minimum bandwidth estimate from all users
+    fn bwe_estimate_kbps(&self) -> Option<f32> {
+        self.bwe
+            .iter()
+            .map(|(_, bwe)| bwe.estimator().estimate_kbps())
+            .reduce(f32::min)
+    }
+
     // Get latest quality settings from all users
     pub fn latest_quality(&self) -> Quality {
         self.users
@@ -470,8 +638,17 @@ impl VideoQoS {
 
         let mut v = current_ratio;
 
-        // Adjust ratio based on network delay thresholds
-        if max_delay < 50 {
+        if let Some(estimate) = self.bwe_estimate_kbps().filter(|_| current_bitrate > 0) {
+            // Adjust ratio toward the bandwidth estimate
+            let estimate_ratio = current_ratio * estimate / current_bitrate as f32;
+            if estimate_ratio < current_ratio || dynamic_screen {
+                v = estimate_ratio.clamp(
+                    current_ratio * BWE_MAX_RATIO_DECREASE,
+                    current_ratio * BWE_MAX_RATIO_INCREASE,
+                );
+            }
+        } else if max_delay < 50 {
+            // Adjust ratio based on network delay thresholds
             if dynamic_screen {
                 v = current_ratio * 1.15;
             }
@@ -531,6 +708,17 @@ impl VideoQoS {
             }
         }
 
+        // Cap fps to what the bandwidth estimate can carry, and follow it smoothly
+        if let Some(bwe_fps) = self
+            .bwe
+            .iter()
+            .filter_map(|(_, bwe)| bwe.estimator().sustainable_fps())
+            .min()
+        {
+            let lowest = ((self.fps as f32 * BWE_MAX_FPS_DECREASE) as u32).max(MIN_FPS);
+            fps = fps.min(bwe_fps.clamp(lowest, self.fps + BWE_MAX_FPS_INCREASE));
+        }
+
         // Ensure fps stays within valid range
         self.fps = fps.clamp(MIN_FPS, highest_fps);
     }
@@ -593,3 +781,63 @@ impl RttCalculator {
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn qos(names: &[&str]) -> VideoQoS {
+        let mut qos = VideoQoS::default();
+        qos.display_budget = true;
+        for name in names {
+            qos.new_display(name.to_string());
+            qos.set_support_changing_quality(name, true);
+        }
+        qos
+    }
+
+    #[test]
+    fn test_display_ratio() {
+        let mut qos = qos(&["0", "1"]);
+        let ratio = qos.ratio();
+        // Equal weights share the budget evenly
+        assert_eq!(qos.display_ratio("0"), ratio);
+        assert_eq!(qos.display_ratio("1"), ratio);
+        assert_eq!(qos.display_ratio("unknown"), ratio);
+
+        qos.set_display_focus("0", true, false);
+        let focused = qos.display_ratio("0");
+        let other = qos.display_ratio("1");
+        assert!(focused > ratio);
+        assert!(other < ratio);
+        assert!(focused <= ratio * MAX_DISPLAY_SHARE);
+
+        // Capped when the focused display is busy and the others are idle
+        let mut qos = qos(&["0", "1", "2", "3"]);
+        qos.set_display_focus("0", true, false);
+        let fps = qos.fps() as f32;
+        qos.displays.get_mut("0").unwrap().activity = fps;
+        assert_eq!(qos.display_ratio("0"), ratio * MAX_DISPLAY_SHARE);
+    }
+
+    #[test]
+    fn test_display_ratio_disabled() {
+        let mut single = qos(&["0"]);
+        single.set_display_focus("0", true, false);
+        let ratio = single.ratio();
+        assert_eq!(single.display_ratio("0"), ratio);
+
+        let mut disabled = qos(&["0", "1"]);
+        disabled.display_budget = false;
+        disabled.set_display_focus("0", true, false);
+        assert_eq!(disabled.display_ratio("0"), ratio);
+
+        let mut cbr = qos(&["0", "1"]);
+        cbr.set_support_changing_quality("1", false);
+        cbr.set_display_focus("0", true, false);
+        assert_eq!(cbr.display_ratio("0"), ratio);
+
+        assert!(!VideoQoS::default().display_budget);
+        assert!(!VideoQoS::default().roi_enabled());
+    }
+}
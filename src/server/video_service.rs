@@ -48,6 +48,7 @@ use scrap::{
     aom::AomEncoderConfig,
     codec::{Encoder, EncoderCfg},
     record::{Recorder, RecorderContext},
+    tile::TileEncoder,
     vpxcodec::{VpxEncoderConfig, VpxVideoCodecId},
     CodecFormat, Display, EncodeInput, TraitCapturer, TraitPixelBuffer,
 };
@@ -61,6 +62,12 @@ use std::{
 };
 
 pub const OPTION_REFRESH: &'static str = "refresh";
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+const FOCUS_INTERVAL: Duration = Duration::from_millis(200);
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+const ROI_CURSOR_SIZE: i32 = 320;
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+const ROI_MAX_WINDOW_AREA: f32 = 0.5; // Of the display
 
 lazy_static::lazy_static! {
     static ref FRAME_FETCHED_NOTIFIER: (UnboundedSender<(i32, Option<Instant>)>, Arc<TokioMutex<UnboundedReceiver<(i32, Option<Instant>)>>>) = {
@@ -301,6 +308,8 @@ pub(super) struct CapturerInfo {
     pub current: usize,
     pub privacy_mode_id: i32,
     pub _capturer_privacy_mode_id: i32,
+    // The shared window and its rect, see `window_share`.
+    pub window: Option<(u64, (i32, i32, i32, i32))>,
     pub capturer: Box<dyn TraitCapturer>,
 }
 
@@ -325,7 +334,7 @@ fn get_capturer_monitor(
     #[cfg(target_os = "linux")]
     {
         if !is_x11() {
-            return super::wayland::get_capturer();
+            return super::wayland::get_capturer(current);
         }
     }
 
@@ -400,7 +409,7 @@ fn get_capturer_monitor(
         current,
         portable_service_running,
     )?;
-    Ok(CapturerInfo {
+    let c = CapturerInfo {
         origin,
         width,
         height,
@@ -408,8 +417,16 @@ fn get_capturer_monitor(
         current,
         privacy_mode_id,
         _capturer_privacy_mode_id: capturer_privacy_mode_id,
+        window: None,
         capturer,
-    })
+    };
+    #[cfg(any(target_os = "windows", target_os = "linux"))]
+    if privacy_mode_id == INVALID_PRIVACY_MODE_CONN_ID {
+        if let Some(window) = super::window_share::get_shared_window(current) {
+            return super::window_share::wrap_capturer(c, window);
+        }
+    }
+    Ok(c)
 }
 
 fn get_capturer_camera(current: usize) -> ResultType<CapturerInfo> {
@@ -449,6 +466,7 @@ fn get_capturer_camera(current: usize) -> ResultType<CapturerInfo> {
         current,
         privacy_mode_id,
         _capturer_privacy_mode_id: privacy_mode_id,
+        window: None,
         capturer,
     });
 }
@@ -465,18 +483,16 @@ fn get_capturer(
 
 fn run(vs: VideoService) -> ResultType<()> {
     let mut _raii = Raii::new(vs.sp.name());
-    // Wayland only support one video capturer for now. It is ok to call ensure_inited() here.
+    // The wayland capturers of all displays are created together, and shared by the video services.
     //
-    // ensure_inited() is needed because clear() may be called.
-    // to-do: wayland ensure_inited should pass current display index.
-    // But for now, we do not support multi-screen capture on wayland.
+    // acquire() is needed because clear() may be called.
     #[cfg(target_os = "linux")]
-    super::wayland::ensure_inited()?;
+    super::wayland::acquire()?;
     #[cfg(target_os = "linux")]
     let _wayland_call_on_ret = SimpleCallOnReturn {
         b: true,
         f: Box::new(|| {
-            super::wayland::clear();
+            super::wayland::release();
         }),
     };
 
@@ -493,14 +509,29 @@ fn run(vs: VideoService) -> ResultType<()> {
         log::info!("disable dxgi with option, fall back to gdi");
         c.set_gdi();
     }
+    #[cfg(any(target_os = "windows", target_os = "linux"))]
+    if c.window.is_some() {
+        // The frames are cropped in memory.
+        #[cfg(all(windows, feature = "vram"))]
+        VRamEncoder::set_not_use(sp.name(), true);
+        if let Some(msg_out) = super::window_share::make_display_changed_msg(display_idx, &c) {
+            let msg_out = Arc::new(msg_out);
+            sp.send_shared(msg_out.clone());
+            sp.snapshot(move |sps| {
+                sps.send_shared(msg_out.clone());
+                Ok(())
+            })?;
+        }
+    }
     let mut video_qos = VIDEO_QOS.lock().unwrap();
     let mut spf = video_qos.spf();
-    let mut quality = video_qos.ratio();
+    let mut quality = video_qos.display_ratio(&sp.name());
     let record_incoming = config::option2bool(
         "allow-auto-record-incoming",
         &Config::get_option("allow-auto-record-incoming"),
     );
     let client_record = video_qos.record();
+    let lossless_tiles = video_qos.lossless_tiles_enabled();
     drop(video_qos);
     let (mut encoder, encoder_cfg, codec_format, use_i444, recorder) = match setup_encoder(
         &c,
@@ -543,7 +574,10 @@ fn run(vs: VideoService) -> ResultType<()> {
             bail!(e);
         }
     }
-    VIDEO_QOS.lock().unwrap().store_bitrate(encoder.bitrate());
+    VIDEO_QOS
+        .lock()
+        .unwrap()
+        .store_bitrate(&sp.name(), encoder.bitrate());
     VIDEO_QOS
         .lock()
         .unwrap()
@@ -555,6 +589,12 @@ fn run(vs: VideoService) -> ResultType<()> {
     }
 
     let mut frame_controller = VideoFrameController::new();
+    let mut tile_encoder = if lossless_tiles {
+        log::info!("lossless tiles enabled");
+        Some(TileEncoder::new(c.width, c.height))
+    } else {
+        None
+    };
 
     let start = time::Instant::now();
     let mut last_check_displays = time::Instant::now();
@@ -576,6 +616,8 @@ fn run(vs: VideoService) -> ResultType<()> {
     let capture_width = c.width;
     let capture_height = c.height;
     let (mut second_instant, mut send_counter) = (Instant::now(), 0);
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    let (mut focus_instant, mut roi) = (Instant::now(), None);
 
     while sp.ok() {
         #[cfg(windows)]
@@ -585,10 +627,22 @@ fn run(vs: VideoService) -> ResultType<()> {
             &mut quality,
             &mut spf,
             client_record,
+            lossless_tiles,
             &mut send_counter,
             &mut second_instant,
             &sp.name(),
         )?;
+        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+        if vs.source.is_monitor() {
+            check_focus(
+                &mut encoder,
+                (c.width, c.height),
+                display_idx,
+                &sp.name(),
+                &mut roi,
+                &mut focus_instant,
+            );
+        }
         if sp.is_option_true(OPTION_REFRESH) {
             if vs.source.is_monitor() {
                 let _ = try_broadcast_display_changed(&sp, display_idx, &c, true);
@@ -596,6 +650,21 @@ fn run(vs: VideoService) -> ResultType<()> {
             log::info!("switch to refresh");
             bail!("SWITCH");
         }
+        #[cfg(any(target_os = "windows", target_os = "linux"))]
+        if vs.source.is_monitor()
+            && c.window.map(|w| w.0) != super::window_share::get_shared_window(display_idx)
+        {
+            log::info!("switch due to shared window changed");
+            if c.window.is_some() {
+                // Restore the display on the peer side.
+                if let Some(msg_out) =
+                    make_display_changed_msg(display_idx, None, VideoSource::Monitor)
+                {
+                    sp.send(msg_out);
+                }
+            }
+            bail!("SWITCH");
+        }
         if codec_format != Encoder::negotiated_codec() {
             log::info!(
                 "switch due to codec changed, {:?} -> {:?}",
@@ -689,6 +758,15 @@ fn run(vs: VideoService) -> ResultType<()> {
                         }
                     }
 
+                    if let (Some(tiles), scrap::Frame::PixelBuffer(f)) =
+                        (tile_encoder.as_mut(), &frame)
+                    {
+                        tiles.update(
+                            f.pixfmt(),
+                            f.data(),
+                            f.stride().first().copied().unwrap_or(0),
+                        );
+                    }
                     let frame = frame.to(encoder.yuvfmt(), &mut yuv, &mut mid_data)?;
                     let send_conn_ids = handle_one_frame(
                         display_idx,
@@ -701,6 +779,7 @@ fn run(vs: VideoService) -> ResultType<()> {
                         &mut first_frame,
                         capture_width,
                         capture_height,
+                        tile_encoder.as_mut(),
                     )?;
                     frame_controller.set_send(now, send_conn_ids);
                     send_counter += 1;
@@ -760,11 +839,21 @@ fn run(vs: VideoService) -> ResultType<()> {
                             &mut first_frame,
                             capture_width,
                             capture_height,
+                            tile_encoder.as_mut(),
                         )?;
                         frame_controller.set_send(now, send_conn_ids);
                         send_counter += 1;
                     }
                 }
+                // The settled tiles of the static screen
+                if let Some(data) = tile_encoder.as_mut().and_then(|t| t.poll()) {
+                    if let Some(mut vf) = scrap::tile::video_frame(codec_format, data) {
+                        vf.display = display_idx as _;
+                        let mut msg = Message::new();
+                        msg.set_video_frame(vf);
+                        frame_controller.set_send(now, sp.send_video_frame(msg));
+                    }
+                }
             }
             Err(err) => {
                 // This check may be redundant, but it is better to be safe.
@@ -1058,6 +1147,7 @@ fn handle_one_frame(
     first_frame: &mut bool,
     width: usize,
     height: usize,
+    tiles: Option<&mut TileEncoder>,
 ) -> ResultType<HashSet<i32>> {
     sp.snapshot(|sps| {
         // so that new sub and old sub share the same encoder after switch
@@ -1082,6 +1172,10 @@ fn handle_one_frame(
                 .unwrap()
                 .as_mut()
                 .map(|r| r.write_message(&msg, width, height));
+            // After recording, the tiles are not a part of the codec stream
+            if let Some(data) = tiles.and_then(|t| t.poll()) {
+                scrap::tile::append(msg.mut_video_frame(), data);
+            }
             send_conn_ids = sp.send_video_frame(msg);
         }
         Err(e) => {
@@ -1151,6 +1245,10 @@ fn try_broadcast_display_changed(
     cap: &CapturerInfo,
     refresh: bool,
 ) -> ResultType<()> {
+    // The changes of the shared window are checked by its capturer.
+    if cap.window.is_some() {
+        return Ok(());
+    }
     if refresh {
         // Get display information immediately.
         crate::display_service::check_displays_changed().ok();
@@ -1233,17 +1331,19 @@ fn check_qos(
     ratio: &mut f32,
     spf: &mut Duration,
     client_record: bool,
+    lossless_tiles: bool,
     send_counter: &mut usize,
     second_instant: &mut Instant,
     name: &str,
 ) -> ResultType<()> {
     let mut video_qos = VIDEO_QOS.lock().unwrap();
     *spf = video_qos.spf();
-    if *ratio != video_qos.ratio() {
-        *ratio = video_qos.ratio();
+    let display_ratio = video_qos.display_ratio(name);
+    if *ratio != display_ratio {
+        *ratio = display_ratio;
         if encoder.support_changing_quality() {
             allow_err!(encoder.set_quality(*ratio));
-            video_qos.store_bitrate(encoder.bitrate());
+            video_qos.store_bitrate(name, encoder.bitrate());
         } else {
             // Now only vaapi doesn't support changing quality
             if !video_qos.in_vbr_state() && !video_qos.latest_quality().is_custom() {
@@ -1256,6 +1356,10 @@ fn check_qos(
         log::info!("switch due to record changed");
         bail!("SWITCH");
     }
+    if lossless_tiles != video_qos.lossless_tiles_enabled() {
+        log::info!("switch due to lossless tiles changed");
+        bail!("SWITCH");
+    }
     if second_instant.elapsed() > Duration::from_secs(1) {
         *second_instant = Instant::now();
         video_qos.update_display_data(&name, *send_counter);
@@ -1265,6 +1369,83 @@ fn check_qos(
     Ok(())
 }
 
+// Updates the focus of the display for its share of the quality, and the region of interest of the encoder.
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+fn check_focus(
+    encoder: &mut Encoder,
+    (width, height): (usize, usize),
+    display_idx: usize,
+    name: &str,
+    last_roi: &mut Option<scrap::codec::Roi>,
+    focus_instant: &mut Instant,
+) {
+    if focus_instant.elapsed() < FOCUS_INTERVAL {
+        return;
+    }
+    *focus_instant = Instant::now();
+    let Some(display) = display_service::get_display_info(display_idx) else {
+        return;
+    };
+    let contains = |x: i32, y: i32| {
+        x >= display.x
+            && x < display.x + display.width
+            && y >= display.y
+            && y < display.y + display.height
+    };
+    let cursor = crate::get_cursor_pos().filter(|(x, y)| contains(*x, *y));
+    let window = crate::get_active_window_rect();
+    let window_focused = window.map_or(false, |(x, y, w, h)| contains(x + w / 2, y + h / 2));
+    let roi_enabled = {
+        let mut video_qos = VIDEO_QOS.lock().unwrap();
+        video_qos.set_display_focus(name, cursor.is_some(), window_focused);
+        video_qos.roi_enabled()
+    };
+    if !encoder.support_roi() {
+        return;
+    }
+    if !roi_enabled || display.width <= 0 || display.height <= 0 {
+        // Leave the encoder untouched unless a region was set before the option was turned off
+        if last_roi.take().is_some() {
+            allow_err!(encoder.set_roi(None));
+        }
+        return;
+    }
+    let roi = cursor.and_then(|(cx, cy)| {
+        // The active window under the cursor if not too large, or the area around the cursor
+        let max_area = (display.width * display.height) as f32 * ROI_MAX_WINDOW_AREA;
+        let (x, y, w, h) = window
+            .filter(|(x, y, w, h)| {
+                cx >= *x && cx < x + w && cy >= *y && cy < y + h && (w * h) as f32 <= max_area
+            })
+            .unwrap_or((
+                cx - ROI_CURSOR_SIZE / 2,
+                cy - ROI_CURSOR_SIZE / 2,
+                ROI_CURSOR_SIZE,
+                ROI_CURSOR_SIZE,
+            ));
+        let x0 = (x - display.x).max(0);
+        let y0 = (y - display.y).max(0);
+        let x1 = (x + w - display.x).min(display.width);
+        let y1 = (y + h - display.y).min(display.height);
+        if x1 <= x0 || y1 <= y0 {
+            return None;
+        }
+        // The display info is in logical pixels, the frame is in physical pixels
+        let sx = width as f32 / display.width as f32;
+        let sy = height as f32 / display.height as f32;
+        Some(scrap::codec::Roi {
+            x: (x0 as f32 * sx) as usize,
+            y: (y0 as f32 * sy) as usize,
+            width: ((x1 - x0) as f32 * sx) as usize,
+            height: ((y1 - y0) as f32 * sy) as usize,
+        })
+    });
+    if roi != *last_roi {
+        *last_roi = roi;
+        allow_err!(encoder.set_roi(roi));
+    }
+}
+
 pub fn set_take_screenshot(display_idx: usize, sid: String, tx: Sender) {
     SCREENSHOTS.lock().unwrap().insert(
         display_idx,
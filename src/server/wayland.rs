@@ -16,6 +16,8 @@ use crate::{
 
 lazy_static::lazy_static! {
     static ref CAP_DISPLAY_INFO: RwLock<u64> = RwLock::new(0);
+    // The video services of the displays share the capturers, they are cleared after the last one exits.
+    static ref CAP_DISPLAY_REFS: Mutex<usize> = Mutex::new(0);
     static ref LOG_SCRAP_COUNT: Mutex<u32> = Mutex::new(0);
 }
 
@@ -80,8 +82,8 @@ struct CapDisplayInfo {
     displays: Vec<DisplayInfo>,
     num: usize,
     primary: usize,
-    current: usize,
-    capturer: CapturerPtr,
+    // One for each display, the streams of the displays are selected together in the portal.
+    capturers: Vec<CapturerPtr>,
 }
 
 #[tokio::main(flavor = "current_thread")]
@@ -89,6 +91,31 @@ pub(super) async fn ensure_inited() -> ResultType<()> {
     check_init().await
 }
 
+// Init the capturers if needed, `release()` must be called after the video service exits.
+pub(super) fn acquire() -> ResultType<()> {
+    let mut refs = CAP_DISPLAY_REFS.lock().unwrap();
+    ensure_inited()?;
+    *refs += 1;
+    Ok(())
+}
+
+pub(super) fn release() {
+    let mut refs = CAP_DISPLAY_REFS.lock().unwrap();
+    *refs = refs.saturating_sub(1);
+    if *refs == 0 {
+        clear();
+    }
+}
+
+// The capturers may be inited by `get_displays()` without a video service,
+// they are kept if a video service is still using them.
+pub(super) fn try_clear() {
+    let refs = CAP_DISPLAY_REFS.lock().unwrap();
+    if *refs == 0 {
+        clear();
+    }
+}
+
 pub(super) fn is_inited() -> Option<Message> {
     if is_x11() {
         None
@@ -126,6 +153,58 @@ fn get_max_desktop_resolution() -> Option<String> {
     }
 }
 
+// The bottom right corner of all displays.
+fn bottom_right(rects: &[((i32, i32), usize, usize)]) -> (i32, i32) {
+    rects.iter().fold((0, 0), |(r, b), (origin, w, h)| {
+        (r.max(origin.0 + *w as i32), b.max(origin.1 + *h as i32))
+    })
+}
+
+// `xrandr` prints "current 1920 x 1080,", it is only used if it covers all displays.
+fn parse_max_desktop_resolution(result: &str, right: i32, bottom: i32) -> (i32, i32) {
+    let resolution: Vec<&str> = result.split(" ").collect();
+    if resolution.len() < 3 {
+        return (right, bottom);
+    }
+    let w: i32 = resolution[0].parse().unwrap_or(right);
+    let h: i32 = resolution[2]
+        .trim_end_matches(",")
+        .parse()
+        .unwrap_or(bottom);
+    if w < right || h < bottom {
+        (right, bottom)
+    } else {
+        (w, h)
+    }
+}
+
+fn max_desktop_resolution(right: i32, bottom: i32) -> (i32, i32) {
+    match get_max_desktop_resolution() {
+        Some(result) if !result.is_empty() => parse_max_desktop_resolution(&result, right, bottom),
+        _ => (right, bottom),
+    }
+}
+
+// The uinput mouse range before or after the capturers are created.
+// The displays are not queried here, it would pop up the portal dialog on Wayland.
+pub(super) fn get_uinput_resolution() -> (i32, i32, i32, i32) {
+    let (right, bottom) = {
+        let addr = *CAP_DISPLAY_INFO.read().unwrap();
+        if addr != 0 {
+            let cap_display_info: *const CapDisplayInfo = addr as _;
+            unsafe { bottom_right(&(*cap_display_info).rects) }
+        } else {
+            (0, 0)
+        }
+    };
+    let (w, h) = max_desktop_resolution(right, bottom);
+    if w <= 0 || h <= 0 {
+        log::warn!("No display resolution found for uinput, use 1920x1080");
+        return (0, 1920, 0, 1080);
+    }
+    (0, w, 0, h)
+}
+
 pub(super) async fn check_init() -> ResultType<()> {
     if !is_x11() {
         let mut minx = 0;
@@ -137,10 +216,9 @@ pub(super) async fn check_init() -> ResultType<()> {
         if *CAP_DISPLAY_INFO.read().unwrap() == 0 {
             let mut lock = CAP_DISPLAY_INFO.write().unwrap();
             if *lock == 0 {
-                let mut all = Display::all()?;
+                let all = Display::all()?;
                 let num = all.len();
                 let primary = super::display_service::get_primary_2(&all);
-                let current = primary;
                 super::display_service::check_update_displays(&all);
                 let mut displays = super::display_service::get_sync_displays();
                 for display in displays.iter_mut() {
@@ -151,37 +229,18 @@ pub(super) async fn check_init() -> ResultType<()> {
                 for d in &all {
                     rects.push((d.origin(), d.width(), d.height()));
                 }
-
-                let display = all.remove(current);
-                let (origin, width, height) = (display.origin(), display.width(), display.height());
+                let (right, bottom) = bottom_right(&rects);
                 log::debug!(
-                    "#displays={}, current={}, origin: {:?}, width={}, height={}, cpus={}/{}",
+                    "#displays={}, primary={}, rects: {:?}, cpus={}/{}",
                     num,
-                    current,
-                    &origin,
-                    width,
-                    height,
+                    primary,
+                    &rects,
                     num_cpus::get_physical(),
                     num_cpus::get(),
                 );
 
                 if use_uinput {
-                    let (max_width, max_height) = match get_max_desktop_resolution() {
-                        Some(result) if !result.is_empty() => {
-                            let resolution: Vec<&str> = result.split(" ").collect();
-                            let w: i32 = resolution[0].parse().unwrap_or(origin.0 + width as i32);
-                            let h: i32 = resolution[2]
-                                .trim_end_matches(",")
-                                .parse()
-                                .unwrap_or(origin.1 + height as i32);
-                            if w < origin.0 + width as i32 || h < origin.1 + height as i32 {
-                                (origin.0 + width as i32, origin.1 + height as i32)
-                            } else {
-                                (w, h)
-                            }
-                        }
-                        _ => (origin.0 + width as i32, origin.1 + height as i32),
-                    };
+                    let (max_width, max_height) = max_desktop_resolution(right, bottom);
 
                     minx = 0;
                     maxx = max_width;
@@ -189,17 +248,19 @@ pub(super) async fn check_init() -> ResultType<()> {
                     maxy = max_height;
                 }
 
-                let capturer = Box::into_raw(Box::new(
-                    Capturer::new(display).with_context(|| "Failed to create capturer")?,
-                ));
-                let capturer = CapturerPtr(capturer);
+                let capturers = all
+                    .into_iter()
+                    .map(|d| Capturer::new(d).with_context(|| "Failed to create capturer"))
+                    .collect::<ResultType<Vec<_>>>()?
+                    .into_iter()
+                    .map(|c| CapturerPtr(Box::into_raw(Box::new(c))))
+                    .collect();
                 let cap_display_info = Box::into_raw(Box::new(CapDisplayInfo {
                     rects,
                     displays,
                     num,
                     primary,
-                    current,
-                    capturer,
+                    capturers,
                 }));
                 *lock = cap_display_info as _;
             }
@@ -248,7 +309,7 @@ pub(super) fn get_primary() -> ResultType<usize> {
     }
 }
 
-pub fn clear() {
+fn clear() {
     if is_x11() {
         return;
     }
@@ -256,14 +317,16 @@ pub fn clear() {
     if *write_lock != 0 {
         let cap_display_info: *mut CapDisplayInfo = *write_lock as _;
         unsafe {
-            let _box_capturer = Box::from_raw((*cap_display_info).capturer.0);
+            for capturer in (*cap_display_info).capturers.iter() {
+                let _box_capturer = Box::from_raw(capturer.0);
+            }
             let _box_cap_display_info = Box::from_raw(cap_display_info);
             *write_lock = 0;
         }
     }
 }
 
-pub(super) fn get_capturer() -> ResultType<super::video_service::CapturerInfo> {
+pub(super) fn get_capturer(current: usize) -> ResultType<super::video_service::CapturerInfo> {
     if is_x11() {
         bail!("Do not call this function if not wayland");
     }
@@ -272,16 +335,26 @@ pub(super) fn get_capturer() -> ResultType<super::video_service::CapturerInfo> {
         let cap_display_info: *const CapDisplayInfo = addr as _;
         unsafe {
             let cap_display_info = &*cap_display_info;
-            let rect = cap_display_info.rects[cap_display_info.current];
+            let (Some(rect), Some(capturer)) = (
+                cap_display_info.rects.get(current),
+                cap_display_info.capturers.get(current),
+            ) else {
+                bail!(
+                    "Failed to get display {}, displays len: {}",
+                    current,
+                    cap_display_info.num
+                );
+            };
             Ok(super::video_service::CapturerInfo {
                 origin: rect.0,
                 width: rect.1,
                 height: rect.2,
                 ndisplay: cap_display_info.num,
-                current: cap_display_info.current,
+                current,
                 privacy_mode_id: 0,
                 _capturer_privacy_mode_id: 0,
-                capturer: Box::new(cap_display_info.capturer.clone()),
+                window: None,
+                capturer: Box::new(capturer.clone()),
             })
         }
     } else {
@@ -299,3 +372,25 @@ pub fn common_get_error() -> String {
     }
     return "".to_owned();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_uinput_resolution() {
+        assert_eq!(bottom_right(&[]), (0, 0));
+        let rects = [((0, 0), 1920, 1080), ((1920, -200), 1280, 1024)];
+        assert_eq!(bottom_right(&rects), (3200, 1080));
+        assert_eq!(
+            parse_max_desktop_resolution("3840 x 2160,", 3200, 1080),
+            (3840, 2160)
+        );
+        // Smaller than the displays or garbage, the displays win.
+        assert_eq!(
+            parse_max_desktop_resolution("1920 x 1080,", 3200, 1080),
+            (3200, 1080)
+        );
+        assert_eq!(parse_max_desktop_resolution("Can't open", 0, 0), (0, 0));
+    }
+}
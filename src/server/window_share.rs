@@ -0,0 +1,360 @@
+// Share a single application window instead of the whole display.
+//
+// The content of the window is captured by itself, X Composite on X11 and `PrintWindow` on Windows,
+// so the windows above it are not sent. The peer receives the window rect as the display
+// (`SwitchDisplay`), so its mouse positions land in the window. Wayland is not supported, the
+// windows of the compositors are not exposed to other clients.
+//
+// The input of all connections is restricted to the shared windows: the mouse outside of them is
+// ignored, and the keyboard is ignored if another window is in the foreground.
+
+use super::display_service;
+use super::video_service::CapturerInfo;
+use crate::common::{make_window_share_msg, ShareableWindow, WindowShareMessage};
+use crate::input::MOUSE_TYPE_UP;
+use hbb_common::{bail, log, message_proto::*, ResultType};
+use scrap::{Frame, Pixfmt, TraitCapturer};
+use std::{
+    collections::HashMap,
+    io,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+const CHECK_WINDOW_INTERVAL: Duration = Duration::from_millis(200);
+
+lazy_static::lazy_static! {
+    // display index -> (window id, conn id)
+    static ref SHARED_WINDOWS: Mutex<HashMap<usize, (u64, i32)>> = Default::default();
+}
+
+pub fn is_supported() -> bool {
+    #[cfg(target_os = "linux")]
+    return crate::platform::linux::is_x11();
+    #[cfg(not(target_os = "linux"))]
+    return true;
+}
+
+pub fn get_shared_window(display: usize) -> Option<u64> {
+    SHARED_WINDOWS
+        .lock()
+        .unwrap()
+        .get(&display)
+        .map(|(window, _)| *window)
+}
+
+fn get_shared_windows() -> Vec<u64> {
+    SHARED_WINDOWS
+        .lock()
+        .unwrap()
+        .values()
+        .map(|(window, _)| *window)
+        .collect()
+}
+
+pub fn on_connection_close(conn_id: i32) {
+    SHARED_WINDOWS
+        .lock()
+        .unwrap()
+        .retain(|_, (_, id)| *id != conn_id);
+}
+
+// `allowed` is whether the connection is a remote desktop one with the keyboard permission,
+// the window titles are not revealed to the others, and sharing restricts the input of all.
+pub fn handle_request(conn_id: i32, content: &[u8], allowed: bool) -> Option<Message> {
+    let msg: WindowShareMessage = match serde_json::from_slice(content) {
+        Ok(msg) => msg,
+        Err(e) => {
+            log::error!("Invalid window share request: {}", e);
+            return None;
+        }
+    };
+    let res = match msg {
+        WindowShareMessage::List | WindowShareMessage::Share { .. } if !allowed => {
+            WindowShareMessage::Error {
+                msg: "No permission of window sharing".to_owned(),
+            }
+        }
+        WindowShareMessage::List => WindowShareMessage::Windows {
+            windows: get_shareable_windows(),
+        },
+        WindowShareMessage::Share { display, window } => match share(conn_id, display, window) {
+            Ok(()) => return None,
+            Err(e) => WindowShareMessage::Error { msg: e.to_string() },
+        },
+        WindowShareMessage::Stop { display } => {
+            if stop(conn_id, display) {
+                log::info!(
+                    "#{} stops sharing the window of display {}",
+                    conn_id,
+                    display
+                );
+            }
+            return None;
+        }
+        _ => return None,
+    };
+    Some(make_window_share_msg(&res))
+}
+
+fn get_shareable_windows() -> Vec<ShareableWindow> {
+    if !is_supported() {
+        return vec![];
+    }
+    let displays = display_service::get_sync_displays();
+    crate::platform::get_windows()
+        .into_iter()
+        .filter_map(|(id, title)| {
+            let (x, y, w, h) = crate::platform::get_window_rect(id)?;
+            let (cx, cy) = (x + w / 2, y + h / 2);
+            let display = displays.iter().position(|d| {
+                cx >= d.x && cx < d.x + d.width && cy >= d.y && cy < d.y + d.height
+            })?;
+            Some(ShareableWindow { id, title, display })
+        })
+        .collect()
+}
+
+fn share(conn_id: i32, display: usize, window: u64) -> ResultType<()> {
+    if !is_supported() {
+        bail!("Window sharing is not supported on this desktop");
+    }
+    if crate::platform::get_window_rect(window).is_none() {
+        bail!("The window is closed or minimized");
+    }
+    crate::platform::activate_window(window);
+    SHARED_WINDOWS
+        .lock()
+        .unwrap()
+        .insert(display, (window, conn_id));
+    log::info!(
+        "#{} shares window {} on display {}",
+        conn_id,
+        window,
+        display
+    );
+    Ok(())
+}
+
+// Only the connection sharing the window can stop it.
+fn stop(conn_id: i32, display: usize) -> bool {
+    let mut windows = SHARED_WINDOWS.lock().unwrap();
+    match windows.get(&display) {
+        Some((_, id)) if *id == conn_id => windows.remove(&display).is_some(),
+        Some((window, id)) => {
+            log::warn!(
+                "#{} can not stop window {} of display {}, it is shared by #{}",
+                conn_id,
+                window,
+                display,
+                id
+            );
+            false
+        }
+        None => false,
+    }
+}
+
+fn is_in_windows(windows: &[u64], (x, y): (i32, i32)) -> bool {
+    windows
+        .iter()
+        .filter_map(|w| crate::platform::get_window_rect(*w))
+        .any(|(wx, wy, w, h)| x >= wx && x < wx + w && y >= wy && y < wy + h)
+}
+
+// The button up events are always allowed, to not leave the buttons pressed.
+pub fn is_mouse_allowed(evt: &MouseEvent) -> bool {
+    let windows = get_shared_windows();
+    if windows.is_empty() || evt.mask & 0x7 == MOUSE_TYPE_UP {
+        return true;
+    }
+    is_in_windows(&windows, (evt.x, evt.y))
+}
+
+// The touch events. The scale is a ctrl + wheel at the cursor, the end events are always allowed.
+pub fn is_pointer_allowed(evt: &PointerDeviceEvent) -> bool {
+    let windows = get_shared_windows();
+    if windows.is_empty() {
+        return true;
+    }
+    let pos = match &evt.union {
+        Some(pointer_device_event::Union::TouchEvent(touch)) => match &touch.union {
+            Some(touch_event::Union::ScaleUpdate(scale)) if scale.scale == 0 => return true,
+            Some(touch_event::Union::PanEnd(_)) => return true,
+            Some(touch_event::Union::PanStart(pan)) => Some((pan.x, pan.y)),
+            _ => crate::platform::get_cursor_pos(),
+        },
+        _ => crate::platform::get_cursor_pos(),
+    };
+    pos.map_or(false, |pos| is_in_windows(&windows, pos))
+}
+
+// The key up events are always allowed, to not leave the keys pressed.
+pub fn is_key_allowed(down: bool) -> bool {
+    let windows = get_shared_windows();
+    if windows.is_empty() || !down {
+        return true;
+    }
+    crate::platform::get_active_window_id().map_or(false, |id| windows.contains(&id))
+}
+
+pub(super) fn wrap_capturer(c: CapturerInfo, window: u64) -> ResultType<CapturerInfo> {
+    let Some(rect) = crate::platform::get_window_rect(window) else {
+        bail!("The shared window {} is closed or minimized", window);
+    };
+    let mut capture = crate::platform::WindowCapture::new(window)?;
+    let mut data = Vec::new();
+    let (width, height) = capture.frame(&mut data)?;
+    // Even, for the yuv420 encoders.
+    let size = (width & !1, height & !1);
+    if size.0 == 0 || size.1 == 0 {
+        bail!("The shared window {} is too small", window);
+    }
+    log::info!(
+        "Share window {}, rect: {:?}, size: {:?}",
+        window,
+        rect,
+        size
+    );
+    Ok(CapturerInfo {
+        origin: (rect.0, rect.1),
+        width: size.0,
+        height: size.1,
+        window: Some((window, (rect.0, rect.1, size.0 as _, size.1 as _))),
+        // The display capturer is dropped.
+        capturer: Box::new(WindowCapturer {
+            capture,
+            window,
+            rect,
+            size,
+            last_check: Instant::now(),
+            data,
+            saved_raw_data: Vec::new(),
+        }),
+        ..c
+    })
+}
+
+// The peer gets the window rect as the display.
+pub(super) fn make_display_changed_msg(display_idx: usize, c: &CapturerInfo) -> Option<Message> {
+    let (_, (x, y, width, height)) = c.window?;
+    let mut display = display_service::get_display_info(display_idx)?;
+    display.x = x;
+    display.y = y;
+    display.width = width;
+    display.height = height;
+    // The resolutions of the display do not apply to the window.
+    display.name = "".to_owned();
+    super::video_service::make_display_changed_msg(
+        display_idx,
+        Some(display),
+        super::video_service::VideoSource::Monitor,
+    )
+}
+
+struct WindowCapturer {
+    capture: crate::platform::WindowCapture,
+    window: u64,
+    rect: (i32, i32, i32, i32),
+    size: (usize, usize),
+    last_check: Instant,
+    data: Vec<u8>,
+    // The last frame, the same frames are not sent again.
+    saved_raw_data: Vec<u8>,
+}
+
+impl WindowCapturer {
+    fn changed() -> io::Error {
+        io::Error::new(io::ErrorKind::Other, "The shared window is changed")
+    }
+}
+
+impl TraitCapturer for WindowCapturer {
+    fn frame<'a>(&'a mut self, _timeout: Duration) -> io::Result<Frame<'a>> {
+        // Moved, resized, minimized or closed.
+        // The video service restarts and sends the new rect to the peer.
+        if self.last_check.elapsed() >= CHECK_WINDOW_INTERVAL {
+            self.last_check = Instant::now();
+            if crate::platform::get_window_rect(self.window) != Some(self.rect) {
+                return Err(Self::changed());
+            }
+        }
+        let (width, height) = self
+            .capture
+            .frame(&mut self.data)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+        let (w, h) = self.size;
+        if (width & !1, height & !1) != (w, h) {
+            return Err(Self::changed());
+        }
+        // Drop the odd column and row in place.
+        let row = w * 4;
+        if width != w {
+            for i in 1..h {
+                let start = i * width * 4;
+                self.data.copy_within(start..start + row, i * row);
+            }
+        }
+        self.data.truncate(row * h);
+        scrap::would_block_if_equal(&mut self.saved_raw_data, &self.data)?;
+        Ok(Frame::PixelBuffer(scrap::PixelBuffer::new(
+            &self.saved_raw_data,
+            Pixfmt::BGRA,
+            w,
+            h,
+        )))
+    }
+
+    #[cfg(windows)]
+    fn is_gdi(&self) -> bool {
+        true
+    }
+
+    #[cfg(windows)]
+    fn set_gdi(&mut self) -> bool {
+        false
+    }
+
+    #[cfg(feature = "vram")]
+    fn device(&self) -> scrap::AdapterDevice {
+        scrap::AdapterDevice::default()
+    }
+
+    // The frames are in memory.
+    #[cfg(feature = "vram")]
+    fn set_output_texture(&mut self, _texture: bool) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stop_by_owner() {
+        SHARED_WINDOWS.lock().unwrap().insert(100, (1, 10));
+        assert!(!stop(11, 100));
+        assert_eq!(get_shared_window(100), Some(1));
+        assert!(!stop(10, 101));
+        assert!(stop(10, 100));
+        assert_eq!(get_shared_window(100), None);
+    }
+    #[test]
+    fn test_share_not_allowed() {
+        let request = WindowShareMessage::Share {
+            display: 200,
+            window: 1,
+        };
+        let msg = handle_request(10, &serde_json::to_vec(&request).unwrap(), false).unwrap();
+        let Some(message::Union::Misc(misc)) = msg.union else {
+            panic!("Not a misc message");
+        };
+        let Some(misc::Union::PluginRequest(p)) = misc.union else {
+            panic!("Not a plugin request");
+        };
+        assert!(matches!(
+            serde_json::from_slice(&p.content).unwrap(),
+            WindowShareMessage::Error { .. }
+        ));
+        assert_eq!(get_shared_window(200), None);
+    }
+}
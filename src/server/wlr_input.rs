@@ -0,0 +1,365 @@
+// Input through `wlr-virtual-pointer-unstable-v1` and `virtual-keyboard-unstable-v1`,
+// supported by wlroots compositors (Sway, Hyprland).
+//
+// It is used with the wlr-screencopy capturer, no portal and no root service are involved.
+
+use crate::uinput::service::map_key;
+use enigo::{Key, KeyboardControllable, MouseButton, MouseControllable};
+use hbb_common::{allow_err, bail, log, ResultType};
+use scrap::wayland::wlr_screencopy;
+use std::{
+    fs::File,
+    io::Write,
+    os::fd::{AsFd, FromRawFd},
+    time::Instant,
+};
+use wayland_client::{
+    delegate_noop,
+    globals::{registry_queue_init, GlobalList, GlobalListContents},
+    protocol::{
+        wl_pointer::{self, Axis, AxisSource},
+        wl_registry::WlRegistry,
+        wl_seat::WlSeat,
+    },
+    Connection, Dispatch, EventQueue, Proxy, QueueHandle,
+};
+use wayland_protocols_misc::zwp_virtual_keyboard_v1::client::{
+    zwp_virtual_keyboard_manager_v1::ZwpVirtualKeyboardManagerV1,
+    zwp_virtual_keyboard_v1::ZwpVirtualKeyboardV1,
+};
+use wayland_protocols_wlr::virtual_pointer::v1::client::{
+    zwlr_virtual_pointer_manager_v1::ZwlrVirtualPointerManagerV1,
+    zwlr_virtual_pointer_v1::ZwlrVirtualPointerV1,
+};
+
+const EVDEV_MOUSE_LEFT: u32 = 272;
+const EVDEV_MOUSE_RIGHT: u32 = 273;
+const EVDEV_MOUSE_MIDDLE: u32 = 274;
+
+const KEY_STATE_RELEASED: u32 = 0;
+const KEY_STATE_PRESSED: u32 = 1;
+const KEYMAP_FORMAT_XKB_V1: u32 = 1;
+// The distance of one wheel step, the same as libinput.
+const AXIS_STEP: f64 = 15.0;
+
+// The keys are sent as evdev codes, see `map_key`, the same as uinput.
+const KEYMAP: &str = r#"xkb_keymap {
+    xkb_keycodes { include "evdev+aliases(qwerty)" };
+    xkb_types { include "complete" };
+    xkb_compat { include "complete" };
+    xkb_symbols { include "pc+us+inet(evdev)" };
+};
+"#;
+
+struct State;
+
+impl Dispatch<WlRegistry, GlobalListContents> for State {
+    fn event(
+        _: &mut Self,
+        _: &WlRegistry,
+        _: <WlRegistry as Proxy>::Event,
+        _: &GlobalListContents,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+delegate_noop!(State: ignore WlSeat);
+delegate_noop!(State: ZwlrVirtualPointerManagerV1);
+delegate_noop!(State: ZwlrVirtualPointerV1);
+delegate_noop!(State: ZwpVirtualKeyboardManagerV1);
+delegate_noop!(State: ZwpVirtualKeyboardV1);
+
+fn connect() -> ResultType<(Connection, GlobalList, EventQueue<State>)> {
+    let conn = Connection::connect_to_env()?;
+    let (globals, queue) = registry_queue_init::<State>(&conn)?;
+    Ok((conn, globals, queue))
+}
+
+fn has_global(globals: &GlobalList, interface: &str) -> bool {
+    globals
+        .contents()
+        .with_list(|list| list.iter().any(|g| g.interface == interface))
+}
+
+pub fn is_supported() -> bool {
+    match connect() {
+        Ok((_, globals, _)) => {
+            has_global(&globals, ZwlrVirtualPointerManagerV1::interface().name)
+                && has_global(&globals, ZwpVirtualKeyboardManagerV1::interface().name)
+        }
+        Err(_) => false,
+    }
+}
+
+// An anonymous memory file, nobody else can open or replace it by the name.
+fn create_keymap_file() -> ResultType<(File, u32)> {
+    let name = b"wlr-input-keymap\0";
+    let fd = unsafe { libc::memfd_create(name.as_ptr() as _, libc::MFD_CLOEXEC) };
+    if fd < 0 {
+        bail!(
+            "Failed to create the keymap file, {}",
+            std::io::Error::last_os_error()
+        );
+    }
+    let mut file = unsafe { File::from_raw_fd(fd) };
+    // The keymap string must be null terminated.
+    file.write_all(KEYMAP.as_bytes())?;
+    file.write_all(&[0])?;
+    Ok((file, KEYMAP.len() as u32 + 1))
+}
+
+pub mod client {
+    use super::*;
+
+    pub struct WlrInputKeyboard {
+        conn: Connection,
+        _queue: EventQueue<State>,
+        keyboard: ZwpVirtualKeyboardV1,
+        start: Instant,
+    }
+
+    impl WlrInputKeyboard {
+        pub fn new() -> ResultType<Self> {
+            let (conn, globals, mut queue) = connect()?;
+            let qh = queue.handle();
+            let seat: WlSeat = globals.bind(&qh, 1..=7, ())?;
+            let manager: ZwpVirtualKeyboardManagerV1 = globals.bind(&qh, 1..=1, ())?;
+            let keyboard = manager.create_virtual_keyboard(&seat, &qh, ());
+            let (file, size) = create_keymap_file()?;
+            keyboard.keymap(KEYMAP_FORMAT_XKB_V1, file.as_fd(), size);
+            queue.roundtrip(&mut State)?;
+            Ok(Self {
+                conn,
+                _queue: queue,
+                keyboard,
+                start: Instant::now(),
+            })
+        }
+
+        fn send_key(&mut self, code: u32, down: bool) {
+            let state = if down {
+                KEY_STATE_PRESSED
+            } else {
+                KEY_STATE_RELEASED
+            };
+            self.keyboard
+                .key(self.start.elapsed().as_millis() as _, code, state);
+        }
+
+        fn handle_key(&mut self, down: bool, key: Key) -> ResultType<()> {
+            match key {
+                Key::Raw(code) => {
+                    // 8 is the offset between xkb and evdev
+                    if code < 8 {
+                        bail!("Invalid raw key code {}", code);
+                    }
+                    self.send_key(code as u32 - 8, down);
+                }
+                _ => {
+                    let (key, is_shift) = map_key(&key)?;
+                    if is_shift {
+                        self.send_key(evdev::Key::KEY_LEFTSHIFT.code() as _, down);
+                    }
+                    self.send_key(key.code() as _, down);
+                }
+            }
+            self.conn.flush()?;
+            Ok(())
+        }
+    }
+
+    impl KeyboardControllable for WlrInputKeyboard {
+        fn as_any(&self) -> &dyn std::any::Any {
+            self
+        }
+
+        fn as_mut_any(&mut self) -> &mut dyn std::any::Any {
+            self
+        }
+
+        fn get_key_state(&mut self, _: Key) -> bool {
+            // no api for this
+            false
+        }
+
+        fn key_sequence(&mut self, s: &str) {
+            for c in s.chars() {
+                let key = Key::Layout(c);
+                allow_err!(self.handle_key(true, key));
+                allow_err!(self.handle_key(false, key));
+            }
+        }
+
+        fn key_down(&mut self, key: Key) -> enigo::ResultType {
+            self.handle_key(true, key)?;
+            Ok(())
+        }
+        fn key_up(&mut self, key: Key) {
+            allow_err!(self.handle_key(false, key));
+        }
+        fn key_click(&mut self, key: Key) {
+            allow_err!(self.handle_key(true, key));
+            allow_err!(self.handle_key(false, key));
+        }
+    }
+
+    impl Drop for WlrInputKeyboard {
+        fn drop(&mut self) {
+            self.keyboard.destroy();
+            self.conn.flush().ok();
+        }
+    }
+
+    pub struct WlrInputMouse {
+        conn: Connection,
+        _queue: EventQueue<State>,
+        pointer: ZwlrVirtualPointerV1,
+        // The bounding box of the output layout, (x, y, width, height).
+        layout: (i32, i32, u32, u32),
+        start: Instant,
+    }
+
+    impl WlrInputMouse {
+        pub fn new() -> ResultType<Self> {
+            let layout = get_output_layout()?;
+            let (conn, globals, mut queue) = connect()?;
+            let qh = queue.handle();
+            let seat: WlSeat = globals.bind(&qh, 1..=7, ())?;
+            let manager: ZwlrVirtualPointerManagerV1 = globals.bind(&qh, 1..=2, ())?;
+            let pointer = manager.create_virtual_pointer(Some(&seat), &qh, ());
+            queue.roundtrip(&mut State)?;
+            log::info!("wlr virtual pointer, output layout: {:?}", layout);
+            Ok(Self {
+                conn,
+                _queue: queue,
+                pointer,
+                layout,
+                start: Instant::now(),
+            })
+        }
+
+        fn time(&self) -> u32 {
+            self.start.elapsed().as_millis() as _
+        }
+
+        fn frame(&self) {
+            self.pointer.frame();
+            allow_err!(self.conn.flush());
+        }
+
+        fn handle_button(&self, down: bool, button: MouseButton) {
+            let button = match button {
+                MouseButton::Left => EVDEV_MOUSE_LEFT,
+                MouseButton::Right => EVDEV_MOUSE_RIGHT,
+                MouseButton::Middle => EVDEV_MOUSE_MIDDLE,
+                _ => {
+                    return;
+                }
+            };
+            let state = if down {
+                wl_pointer::ButtonState::Pressed
+            } else {
+                wl_pointer::ButtonState::Released
+            };
+            self.pointer.button(self.time(), button, state);
+            self.frame();
+        }
+
+        fn scroll(&self, axis: Axis, length: i32) {
+            self.pointer.axis_source(AxisSource::Wheel);
+            self.pointer
+                .axis_discrete(self.time(), axis, length as f64 * AXIS_STEP, length);
+            self.frame();
+        }
+    }
+
+    impl MouseControllable for WlrInputMouse {
+        fn as_any(&self) -> &dyn std::any::Any {
+            self
+        }
+
+        fn as_mut_any(&mut self) -> &mut dyn std::any::Any {
+            self
+        }
+
+        fn mouse_move_to(&mut self, x: i32, y: i32) {
+            let (left, top, width, height) = self.layout;
+            let x = (x - left).clamp(0, width as i32 - 1);
+            let y = (y - top).clamp(0, height as i32 - 1);
+            self.pointer
+                .motion_absolute(self.time(), x as _, y as _, width, height);
+            self.frame();
+        }
+        fn mouse_move_relative(&mut self, x: i32, y: i32) {
+            self.pointer.motion(self.time(), x as _, y as _);
+            self.frame();
+        }
+        fn mouse_down(&mut self, button: MouseButton) -> enigo::ResultType {
+            self.handle_button(true, button);
+            Ok(())
+        }
+        fn mouse_up(&mut self, button: MouseButton) {
+            self.handle_button(false, button);
+        }
+        fn mouse_click(&mut self, button: MouseButton) {
+            self.handle_button(true, button);
+            self.handle_button(false, button);
+        }
+        fn mouse_scroll_x(&mut self, length: i32) {
+            self.scroll(Axis::HorizontalScroll, length);
+        }
+        fn mouse_scroll_y(&mut self, length: i32) {
+            self.scroll(Axis::VerticalScroll, length);
+        }
+    }
+
+    impl Drop for WlrInputMouse {
+        fn drop(&mut self) {
+            self.pointer.destroy();
+            self.conn.flush().ok();
+        }
+    }
+
+    fn get_output_layout() -> ResultType<(i32, i32, u32, u32)> {
+        let outputs = match wlr_screencopy::get_capturables() {
+            Ok(outputs) => outputs,
+            Err(e) => bail!("Failed to get the outputs: {}", e),
+        };
+        let (mut left, mut top, mut right, mut bottom) = (i32::MAX, i32::MAX, i32::MIN, i32::MIN);
+        for o in outputs.iter() {
+            left = left.min(o.position.0);
+            top = top.min(o.position.1);
+            right = right.max(o.position.0 + o.size.0 as i32);
+            bottom = bottom.max(o.position.1 + o.size.1 as i32);
+        }
+        if outputs.is_empty() || right <= left || bottom <= top {
+            bail!("No valid output, {:?}", outputs);
+        }
+        Ok((left, top, (right - left) as _, (bottom - top) as _))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io::{Read, Seek, SeekFrom},
+        os::fd::AsRawFd,
+    };
+
+    #[test]
+    fn test_create_keymap_file() {
+        let (mut file, size) = create_keymap_file().unwrap();
+        let mut data = Vec::new();
+        file.seek(SeekFrom::Start(0)).unwrap();
+        file.read_to_end(&mut data).unwrap();
+        assert_eq!(data.len(), size as usize);
+        assert_eq!(data.last(), Some(&0));
+        assert_eq!(&data[..data.len() - 1], KEYMAP.as_bytes());
+        // Not linked anywhere in the file system.
+        let link = std::fs::read_link(format!("/proc/self/fd/{}", file.as_raw_fd())).unwrap();
+        assert!(link.to_string_lossy().starts_with("/memfd:"));
+    }
+}
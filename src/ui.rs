@@ -453,6 +453,10 @@ impl UI {
         crate::lan::send_wol(id)
     }
 
+    fn get_wol_status(&self, id: String) -> String {
+        crate::lan::get_wol_status(&id)
+    }
+
     fn new_remote(&mut self, id: String, remote_type: String, force_relay: bool) {
         new_remote(id, remote_type, force_relay)
     }
@@ -646,6 +650,22 @@ impl UI {
         verify2fa(code)
     }
 
+    fn generate_2fa_backup_codes(&self) -> String {
+        generate_2fa_backup_codes()
+    }
+
+    fn get_2fa_backup_codes_left(&self) -> i32 {
+        get_2fa_backup_codes_left() as _
+    }
+
+    fn set_2fa_webhook(&self, url: String, header: String) -> String {
+        set_2fa_webhook(url, header)
+    }
+
+    fn set_2fa_smtp(&self, config: String) -> String {
+        set_2fa_smtp(config)
+    }
+
     fn verify_login(&self, raw: String, id: String) -> bool {
         crate::verify_login(&raw, &id)
     }
@@ -682,6 +702,7 @@ impl sciter::EventHandler for UI {
         fn get_size();
         fn new_remote(String, String, bool);
         fn send_wol(String);
+        fn get_wol_status(String);
         fn remove_peer(String);
         fn remove_discovered(String);
         fn get_connect_status();
@@ -754,6 +775,10 @@ impl sciter::EventHandler for UI {
         fn generate2fa();
         fn generate_2fa_img_src(String);
         fn verify2fa(String);
+        fn generate_2fa_backup_codes();
+        fn get_2fa_backup_codes_left();
+        fn set_2fa_webhook(String, String);
+        fn set_2fa_smtp(String);
         fn check_hwcodec();
         fn verify_login(String, String);
     }
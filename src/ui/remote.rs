@@ -165,7 +165,8 @@ impl InvokeUiSession for SciterHandler {
                 status
                     .codec_format
                     .map_or(Value::null(), |it| it.to_string().into()),
-                status.chroma.map_or(Value::null(), |it| it.into())
+                status.chroma.map_or(Value::null(), |it| it.into()),
+                status.transport.map_or(Value::null(), |it| it.into())
             ),
         );
     }
@@ -235,6 +236,30 @@ impl InvokeUiSession for SciterHandler {
         self.call("updateTransferList", &make_args!());
     }
 
+    fn update_transfer_queue(&self, queue_json: &str) {
+        self.call("updateTransferQueue", &make_args!(queue_json));
+    }
+
+    fn update_session_templates(&self, _templates_json: &str) {
+        // Session templates are entered as "username:template" in Sciter UI
+    }
+
+    fn update_shareable_windows(&self, _windows_json: &str) {
+        // Window sharing is not implemented for Sciter UI
+    }
+
+    fn update_audio_apps(&self, _apps_json: &str) {
+        // Application audio capture is not implemented for Sciter UI
+    }
+
+    fn update_mic_passthrough(&self, _enabled: bool) {
+        // Microphone passthrough is not implemented for Sciter UI
+    }
+
+    fn update_input_macro(&self, _recording: bool, _playing: bool) {
+        // Input macros are not implemented for Sciter UI
+    }
+
     fn confirm_delete_files(&self, id: i32, i: i32, name: String) {
         self.call("confirmDeleteFiles", &make_args!(id, i, name));
     }
@@ -517,6 +542,11 @@ impl sciter::EventHandler for SciterSession {
         fn send_files(i32, i32, String, String, i32, bool, bool);
         fn add_job(i32, i32, String, String, i32, bool, bool);
         fn resume_job(i32, bool);
+        fn get_transfer_queue();
+        fn enqueue_transfer(String, String, bool, bool, i32);
+        fn remove_queued_transfer(i32);
+        fn set_queued_transfer_priority(i32, i32);
+        fn retry_queued_transfer(i32);
         fn get_platform(bool);
         fn get_path_sep(bool);
         fn get_icon_path(i32, String);
@@ -641,7 +641,7 @@ pub async fn start_ipc<T: InvokeUiCM>(cm: ConnectionManager<T>) {
         OPTION_ENABLE_FILE_TRANSFER,
         &Config::get_option(OPTION_ENABLE_FILE_TRANSFER),
     ));
-    match ipc::new_listener("_cm").await {
+    match ipc::new_listener(&ipc::cm_postfix()).await {
         Ok(mut incoming) => {
             while let Some(result) = incoming.next().await {
                 match result {
@@ -757,6 +757,7 @@ pub fn remove_discovered(id: String) {
     let mut peers = config::LanPeers::load().peers;
     peers.retain(|x| x.id != id);
     config::LanPeers::store(&peers);
+    crate::lan::remove_direct_profile(&id);
 }
 
 #[inline]
@@ -1277,7 +1278,7 @@ pub fn option_synced() -> bool {
 #[cfg(not(any(target_os = "ios")))]
 #[tokio::main(flavor = "current_thread")]
 pub(crate) async fn send_to_cm(data: &ipc::Data) {
-    if let Ok(mut c) = ipc::connect(1000, "_cm").await {
+    if let Ok(mut c) = ipc::connect(1000, &ipc::cm_postfix()).await {
         c.send(data).await.ok();
     }
 }
@@ -1430,9 +1431,9 @@ pub fn support_remove_wallpaper() -> bool {
     return false;
 }
 
+/// Whether any second factor makes the 2FA required, not only the TOTP.
 pub fn has_valid_2fa() -> bool {
-    let raw = get_option("2fa");
-    crate::auth_2fa::get_2fa(Some(raw)).is_some()
+    crate::auth_2fa::Auth2fa::get_by(|k| get_option(k)).is_some()
 }
 
 pub fn generate2fa() -> String {
@@ -1461,6 +1462,75 @@ pub fn verify_bot(token: String) -> String {
     }
 }
 
+/// Returns the new backup codes in json, they are shown to the user only once.
+pub fn generate_2fa_backup_codes() -> String {
+    match crate::auth_2fa::generate_backup_codes() {
+        Ok(codes) => {
+            refresh_options();
+            serde_json::to_string(&codes).unwrap_or_default()
+        }
+        Err(err) => {
+            log::error!("Failed to generate 2fa backup codes: {}", err);
+            "".to_owned()
+        }
+    }
+}
+
+#[inline]
+pub fn get_2fa_backup_codes_left() -> usize {
+    crate::auth_2fa::get_backup_codes_left(|k| get_option(k))
+}
+
+pub fn set_2fa_webhook(url: String, header: String) -> String {
+    let webhook = crate::auth_2fa::Webhook {
+        url,
+        header_str: header,
+        ..Default::default()
+    };
+    match webhook.save() {
+        Ok(_) => {
+            refresh_options();
+            "".to_owned()
+        }
+        Err(err) => err.to_string(),
+    }
+}
+
+/// `config` is the json of `{server, port, security, username, password, from, to}`.
+///
+/// A test email is sent before saving the config.
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub fn set_2fa_smtp(config: String) -> String {
+    let v = match serde_json::from_str::<serde_json::Value>(&config) {
+        Ok(v) => v,
+        Err(err) => return err.to_string(),
+    };
+    let get = |k: &str| v[k].as_str().unwrap_or_default().to_owned();
+    let smtp = crate::auth_2fa::Smtp {
+        server: get("server"),
+        port: v["port"].as_u64().unwrap_or_default() as _,
+        security: get("security"),
+        username: get("username"),
+        password_str: get("password"),
+        from: get("from"),
+        to: get("to"),
+        ..Default::default()
+    };
+    if !smtp.server.is_empty() {
+        let text = "This is a test email, the 2FA codes will be sent to this address.".to_owned();
+        if let Err(err) = smtp.send("CloudyDesk 2FA", text) {
+            return err.to_string();
+        }
+    }
+    match smtp.save() {
+        Ok(_) => {
+            refresh_options();
+            "".to_owned()
+        }
+        Err(err) => err.to_string(),
+    }
+}
+
 pub fn check_hwcodec() {
     #[cfg(feature = "hwcodec")]
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
@@ -1,5 +1,9 @@
 use crate::{
-    common::{get_supported_keyboard_modes, is_keyboard_mode_supported},
+    common::{
+        get_supported_keyboard_modes, is_keyboard_mode_supported, make_audio_msg, AudioMessage,
+        WindowShareMessage,
+    },
+    hifi_audio::OPTION_HIFI_AUDIO,
     input::{MOUSE_BUTTON_LEFT, MOUSE_TYPE_DOWN, MOUSE_TYPE_UP, MOUSE_TYPE_WHEEL},
     ui_interface::use_texture_render,
 };
@@ -34,7 +38,11 @@ use std::{
 };
 use uuid::Uuid;
 
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+use crate::client::input_macro::{self, InputMacro};
 use crate::client::io_loop::Remote;
+use crate::client::transfer_queue::TransferQueue;
+use crate::client::transport_qos::TransportPreference;
 use crate::client::{
     check_if_retry, handle_hash, handle_login_error, handle_login_from_ui, handle_test_delay,
     input_os_password, send_mouse, send_pointer_device_event, FileManager, Key, LoginConfigHandler,
@@ -61,6 +69,8 @@ pub struct Session<T: InvokeUiSession> {
     pub last_change_display: Arc<Mutex<ChangeDisplayRecord>>,
     pub connection_round_state: Arc<Mutex<ConnectionRoundState>>,
     pub printer_names: Arc<RwLock<HashMap<i32, String>>>,
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    pub input_macro: Arc<Mutex<InputMacro>>,
 }
 
 #[derive(Clone)]
@@ -361,6 +371,15 @@ impl<T: InvokeUiSession> Session<T> {
         if let Some(msg) = msg {
             self.send(Data::Message(msg));
         }
+        if name == OPTION_HIFI_AUDIO {
+            self.send_audio_quality(self.get_toggle_option(name));
+        }
+    }
+
+    fn send_audio_quality(&self, hifi: bool) {
+        self.send(Data::Message(make_audio_msg(&AudioMessage::Quality {
+            hifi,
+        })));
     }
 
     pub fn toggle_privacy_mode(&self, impl_key: String, on: bool) {
@@ -438,6 +457,22 @@ impl<T: InvokeUiSession> Session<T> {
         self.send(Data::TakeScreenshot((display, sid)));
     }
 
+    pub fn request_shareable_windows(&self) {
+        self.send_window_share_msg(WindowShareMessage::List);
+    }
+
+    pub fn share_window(&self, display: usize, window: u64) {
+        self.send_window_share_msg(WindowShareMessage::Share { display, window });
+    }
+
+    pub fn stop_sharing_window(&self, display: usize) {
+        self.send_window_share_msg(WindowShareMessage::Stop { display });
+    }
+
+    fn send_window_share_msg(&self, msg: WindowShareMessage) {
+        self.send(Data::Message(crate::common::make_window_share_msg(&msg)));
+    }
+
     pub fn is_recording(&self) -> bool {
         self.lc.read().unwrap().record_state
     }
@@ -742,6 +777,11 @@ impl<T: InvokeUiSession> Session<T> {
     pub fn send_key_event(&self, evt: &KeyEvent) {
         // mode: legacy(0), map(1), translate(2), auto(3)
 
+        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+        self.input_macro
+            .lock()
+            .unwrap()
+            .record_key(&self.peer_platform(), evt);
         let mut msg = evt.clone();
         self.swap_modifier_key(&mut msg);
         let mut msg_out = Message::new();
@@ -749,6 +789,32 @@ impl<T: InvokeUiSession> Session<T> {
         self.send(Data::Message(msg_out));
     }
 
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    pub fn get_clipboard_history(&self) -> String {
+        let entries = crate::clipboard_history::get_history(&self.get_id());
+        serde_json::to_string(&entries).unwrap_or_default()
+    }
+
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    pub fn push_clipboard_history(&self, id: i64) {
+        #[cfg(feature = "unix-file-copy-paste")]
+        if self.is_file_clipboard_required() {
+            if let Some(msg) = crate::clipboard_history::get_files_msg(id) {
+                self.send(Data::Message(msg));
+                return;
+            }
+        }
+        if !self.is_text_clipboard_required() {
+            return;
+        }
+        let Some(pi) = self.lc.read().unwrap().peer_info.clone() else {
+            return;
+        };
+        if let Some(msg) = crate::clipboard_history::get_entry_msg(id, &pi.version, &pi.platform) {
+            self.send(Data::Message(msg));
+        }
+    }
+
     pub fn send_chat(&self, text: String) {
         let mut misc = Misc::new();
         misc.set_chat_message(ChatMessage {
@@ -1226,6 +1292,8 @@ impl<T: InvokeUiSession> Session<T> {
             }
         }
 
+        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+        self.input_macro.lock().unwrap().record_mouse(mask, x, y);
         send_mouse(mask, x, y, alt, ctrl, shift, command, self);
         // on macos, ctrl + left button down = right button down, up won't emit, so we need to
         // emit up myself if peer is not macos
@@ -1251,6 +1319,12 @@ impl<T: InvokeUiSession> Session<T> {
         }
     }
 
+    /// Reconnects with the transport switched by the transport QoS.
+    pub fn switch_transport(&self, transport: TransportPreference) {
+        self.lc.write().unwrap().transport_preference = transport;
+        self.reconnect(false);
+    }
+
     pub fn reconnect(&self, force_relay: bool) {
         // 1. If current session is connecting, do not reconnect.
         // 2. If the connection is established, send `Data::Close`.
@@ -1369,6 +1443,8 @@ impl<T: InvokeUiSession> Session<T> {
     }
 
     pub fn close(&self) {
+        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+        self.input_macro.lock().unwrap().stop_playing();
         self.send(Data::Close);
     }
 
@@ -1398,6 +1474,34 @@ impl<T: InvokeUiSession> Session<T> {
         self.update_transfer_list();
     }
 
+    pub fn get_transfer_queue(&self) -> String {
+        let queue = TransferQueue::load(&self.get_id());
+        serde_json::to_string(queue.jobs()).unwrap_or_default()
+    }
+
+    pub fn enqueue_transfer(
+        &self,
+        path: String,
+        to: String,
+        include_hidden: bool,
+        is_remote: bool,
+        priority: i32,
+    ) -> i32 {
+        TransferQueue::load(&self.get_id()).push(path, to, include_hidden, is_remote, priority)
+    }
+
+    pub fn remove_queued_transfer(&self, id: i32) {
+        allow_err!(TransferQueue::load(&self.get_id()).remove(id));
+    }
+
+    pub fn set_queued_transfer_priority(&self, id: i32, priority: i32) {
+        allow_err!(TransferQueue::load(&self.get_id()).set_priority(id, priority));
+    }
+
+    pub fn retry_queued_transfer(&self, id: i32) {
+        allow_err!(TransferQueue::load(&self.get_id()).retry(id));
+    }
+
     pub fn elevate_direct(&self) {
         self.send(Data::ElevateDirect);
     }
@@ -1527,6 +1631,95 @@ impl<T: InvokeUiSession> Session<T> {
         self.send(Data::CloseVoiceCall);
     }
 
+    pub fn request_audio_apps(&self) {
+        self.send(Data::Message(make_audio_msg(&AudioMessage::ListApps)));
+    }
+
+    // An empty app captures the whole audio again.
+    pub fn capture_app_audio(&self, app: String) {
+        let app = if app.is_empty() { None } else { Some(app) };
+        self.send(Data::Message(make_audio_msg(&AudioMessage::CaptureApp {
+            app,
+        })));
+    }
+
+    pub fn set_mic_passthrough(&self, enable: bool) {
+        #[cfg(target_os = "linux")]
+        if enable {
+            std::thread::spawn(crate::ipc::start_pa);
+        }
+        self.send(Data::MicPassthrough(enable));
+    }
+
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    fn update_input_macro_state(&self) {
+        let (recording, playing) = {
+            let lock = self.input_macro.lock().unwrap();
+            (lock.is_recording(), lock.is_playing())
+        };
+        self.update_input_macro(recording, playing);
+    }
+
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    pub fn start_input_macro_recording(&self) {
+        self.input_macro.lock().unwrap().start_recording();
+        self.update_input_macro_state();
+    }
+
+    // Returns the recorded script.
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    pub fn stop_input_macro_recording(&self) -> String {
+        let script = self.input_macro.lock().unwrap().stop_recording();
+        self.update_input_macro_state();
+        script
+    }
+
+    // Returns the error of the script, empty if it is valid.
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    pub fn check_input_macro(&self, script: &str) -> String {
+        match input_macro::check(script) {
+            Ok(_) => "".to_owned(),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    pub fn play_input_macro(&self, script: String) {
+        if !*self.server_keyboard_enabled.read().unwrap()
+            || self.get_toggle_option("view-only".to_owned())
+        {
+            return;
+        }
+        let tokens = match input_macro::check(&script) {
+            Ok(tokens) => tokens,
+            Err(e) => {
+                self.msgbox("custom-nocancel-error", "Input macros", &e.to_string(), "");
+                return;
+            }
+        };
+        let Some(stop) = self.input_macro.lock().unwrap().start_playing() else {
+            return;
+        };
+        self.update_input_macro_state();
+        let session = self.clone();
+        std::thread::spawn(move || {
+            input_macro::play(&session, tokens, &stop);
+            let mut lock = session.input_macro.lock().unwrap();
+            // Not stopped and started again.
+            if !stop.load(std::sync::atomic::Ordering::SeqCst) {
+                lock.stop_playing();
+            }
+            drop(lock);
+            session.update_input_macro_state();
+        });
+    }
+
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    pub fn stop_input_macro(&self) {
+        self.input_macro.lock().unwrap().stop_playing();
+        self.update_input_macro_state();
+    }
+
     pub fn send_selected_session_id(&self, sid: String) {
         if let Ok(sid) = sid.parse::<u32>() {
             self.lc.write().unwrap().selected_windows_session_id = Some(sid);
@@ -1624,6 +1817,12 @@ pub trait InvokeUiSession: Send + Sync + Clone + 'static + Sized + Default {
     fn new_message(&self, msg: String);
     fn update_transfer_list(&self);
     fn load_last_job(&self, cnt: i32, job_json: &str);
+    fn update_transfer_queue(&self, queue_json: &str);
+    fn update_session_templates(&self, templates_json: &str);
+    fn update_shareable_windows(&self, windows_json: &str);
+    fn update_audio_apps(&self, apps_json: &str);
+    fn update_mic_passthrough(&self, enabled: bool);
+    fn update_input_macro(&self, recording: bool, playing: bool);
     fn update_folder_files(
         &self,
         id: i32,
@@ -1767,6 +1966,9 @@ impl<T: InvokeUiSession> Interface for Session<T> {
                 "Connected, waiting for image...",
                 "",
             );
+            if self.get_toggle_option(OPTION_HIFI_AUDIO.to_owned()) {
+                self.send_audio_quality(true);
+            }
         }
         self.on_connected(self.lc.read().unwrap().conn_type);
         #[cfg(windows)]
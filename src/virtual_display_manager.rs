@@ -1,19 +1,38 @@
-use hbb_common::{bail, platform::windows::is_windows_version_or_greater, ResultType};
+#[cfg(windows)]
+use hbb_common::platform::windows::is_windows_version_or_greater;
+use hbb_common::{bail, ResultType};
+
+#[cfg(target_os = "linux")]
+pub mod linux;
+
+#[cfg(target_os = "linux")]
+pub use self::linux::MonitorMode;
+#[cfg(windows)]
+pub use virtual_display::MonitorMode;
 
 // This string is defined here.
 //  https://github.com/cloudydesk/CloudyDeskIddDriver/blob/b370aad3f50028b039aad211df60c8051c4a64d6/CloudyDeskIddDriver/CloudyDeskIddDriver.inf#LL73C1-L73C40
+#[cfg(windows)]
 pub const CLOUDYDESK_IDD_DEVICE_STRING: &'static str = "CloudyDeskIddDriver Device\0";
+#[cfg(windows)]
 pub const AMYUNI_IDD_DEVICE_STRING: &'static str = "USB Mobile Monitor Virtual Display\0";
 
+#[cfg(windows)]
 const IDD_IMPL: &str = IDD_IMPL_AMYUNI;
+#[cfg(target_os = "linux")]
+const IDD_IMPL: &str = linux::IMPL_NAME;
+#[cfg(windows)]
 const IDD_IMPL_CLOUDYDESK: &str = "cloudydesk_idd";
+#[cfg(windows)]
 const IDD_IMPL_AMYUNI: &str = "amyuni_idd";
 const IDD_PLUG_OUT_ALL_INDEX: i32 = -1;
 
+#[cfg(windows)]
 pub fn is_amyuni_idd() -> bool {
     IDD_IMPL == IDD_IMPL_AMYUNI
 }
 
+#[cfg(windows)]
 pub fn get_cur_device_string() -> &'static str {
     match IDD_IMPL {
         IDD_IMPL_CLOUDYDESK => CLOUDYDESK_IDD_DEVICE_STRING,
@@ -27,12 +46,13 @@ pub fn is_virtual_display_supported() -> bool {
     {
         is_windows_version_or_greater(10, 0, 19041, 0, 0)
     }
-    #[cfg(not(target_os = "windows"))]
+    #[cfg(target_os = "linux")]
     {
-        false
+        linux::is_supported()
     }
 }
 
+#[cfg(windows)]
 pub fn plug_in_headless() -> ResultType<()> {
     match IDD_IMPL {
         IDD_IMPL_CLOUDYDESK => cloudydesk_idd::plug_in_headless(),
@@ -43,11 +63,17 @@ pub fn plug_in_headless() -> ResultType<()> {
 
 pub fn get_platform_additions() -> serde_json::Map<String, serde_json::Value> {
     let mut map = serde_json::Map::new();
+    #[cfg(windows)]
     if !crate::platform::windows::is_self_service_running() {
         return map;
     }
+    #[cfg(target_os = "linux")]
+    if !linux::is_supported() {
+        return map;
+    }
     map.insert("idd_impl".into(), serde_json::json!(IDD_IMPL));
     match IDD_IMPL {
+        #[cfg(windows)]
         IDD_IMPL_CLOUDYDESK => {
             let virtual_displays = cloudydesk_idd::get_virtual_displays();
             if !virtual_displays.is_empty() {
@@ -57,28 +83,45 @@ pub fn get_platform_additions() -> serde_json::Map<String, serde_json::Value> {
                 );
             }
         }
+        #[cfg(windows)]
         IDD_IMPL_AMYUNI => {
             let c = amyuni_idd::get_monitor_count();
             if c > 0 {
                 map.insert("amyuni_virtual_displays".into(), serde_json::json!(c));
             }
         }
+        // The peer shows the same index based menu as `cloudydesk_idd`.
+        #[cfg(target_os = "linux")]
+        linux::IMPL_NAME => {
+            let virtual_displays = linux::get_virtual_displays();
+            if !virtual_displays.is_empty() {
+                map.insert(
+                    "cloudydesk_virtual_displays".into(),
+                    serde_json::json!(virtual_displays),
+                );
+            }
+        }
         _ => {}
     }
     map
 }
 
 #[inline]
-pub fn plug_in_monitor(idx: u32, modes: Vec<virtual_display::MonitorMode>) -> ResultType<()> {
+pub fn plug_in_monitor(idx: u32, modes: Vec<MonitorMode>) -> ResultType<()> {
     match IDD_IMPL {
+        #[cfg(windows)]
         IDD_IMPL_CLOUDYDESK => cloudydesk_idd::plug_in_index_modes(idx, modes),
+        #[cfg(windows)]
         IDD_IMPL_AMYUNI => amyuni_idd::plug_in_monitor(),
+        #[cfg(target_os = "linux")]
+        linux::IMPL_NAME => linux::plug_in_index_modes(idx, modes),
         _ => bail!("Unsupported virtual display implementation."),
     }
 }
 
 pub fn plug_out_monitor(index: i32, force_all: bool, force_one: bool) -> ResultType<()> {
     match IDD_IMPL {
+        #[cfg(windows)]
         IDD_IMPL_CLOUDYDESK => {
             let indices = if index == IDD_PLUG_OUT_ALL_INDEX {
                 cloudydesk_idd::get_virtual_displays()
@@ -87,18 +130,33 @@ pub fn plug_out_monitor(index: i32, force_all: bool, force_one: bool) -> ResultT
             };
             cloudydesk_idd::plug_out_peer_request(&indices)
         }
+        #[cfg(windows)]
         IDD_IMPL_AMYUNI => amyuni_idd::plug_out_monitor(index, force_all, force_one),
+        #[cfg(target_os = "linux")]
+        linux::IMPL_NAME => {
+            let _ = (force_all, force_one);
+            let indices = if index == IDD_PLUG_OUT_ALL_INDEX {
+                linux::get_virtual_displays()
+            } else {
+                vec![index as _]
+            };
+            linux::plug_out_peer_request(&indices)
+        }
         _ => bail!("Unsupported virtual display implementation."),
     }
 }
 
-pub fn plug_in_peer_request(modes: Vec<Vec<virtual_display::MonitorMode>>) -> ResultType<Vec<u32>> {
+pub fn plug_in_peer_request(modes: Vec<Vec<MonitorMode>>) -> ResultType<Vec<u32>> {
     match IDD_IMPL {
+        #[cfg(windows)]
         IDD_IMPL_CLOUDYDESK => cloudydesk_idd::plug_in_peer_request(modes),
+        #[cfg(windows)]
         IDD_IMPL_AMYUNI => {
             amyuni_idd::plug_in_monitor()?;
             Ok(vec![0])
         }
+        #[cfg(target_os = "linux")]
+        linux::IMPL_NAME => linux::plug_in_peer_request(modes),
         _ => bail!("Unsupported virtual display implementation."),
     }
 }
@@ -109,25 +167,37 @@ pub fn plug_out_monitor_indices(
     force_one: bool,
 ) -> ResultType<()> {
     match IDD_IMPL {
+        #[cfg(windows)]
         IDD_IMPL_CLOUDYDESK => cloudydesk_idd::plug_out_peer_request(indices),
+        #[cfg(windows)]
         IDD_IMPL_AMYUNI => {
             for _idx in indices.iter() {
                 amyuni_idd::plug_out_monitor(0, force_all, force_one)?;
             }
             Ok(())
         }
+        #[cfg(target_os = "linux")]
+        linux::IMPL_NAME => {
+            let _ = (force_all, force_one);
+            linux::plug_out_peer_request(indices)
+        }
         _ => bail!("Unsupported virtual display implementation."),
     }
 }
 
 pub fn reset_all() -> ResultType<()> {
     match IDD_IMPL {
+        #[cfg(windows)]
         IDD_IMPL_CLOUDYDESK => cloudydesk_idd::reset_all(),
+        #[cfg(windows)]
         IDD_IMPL_AMYUNI => amyuni_idd::reset_all(),
+        #[cfg(target_os = "linux")]
+        linux::IMPL_NAME => linux::reset_all(),
         _ => bail!("Unsupported virtual display implementation."),
     }
 }
 
+#[cfg(windows)]
 pub mod cloudydesk_idd {
     use super::windows;
     use hbb_common::{allow_err, bail, lazy_static, log, ResultType};
@@ -381,6 +451,7 @@ pub mod cloudydesk_idd {
     }
 }
 
+#[cfg(windows)]
 pub mod amyuni_idd {
     use super::windows;
     use crate::platform::{reg_display_settings, win_device};
@@ -735,6 +806,7 @@ pub mod amyuni_idd {
     }
 }
 
+#[cfg(windows)]
 mod windows {
     use std::ptr::null_mut;
     use winapi::{
@@ -0,0 +1,481 @@
+// Virtual displays on Linux.
+//
+// X11: a disconnected RandR output of the running server is switched on with a generated mode.
+// Such outputs are provided by e.g. the `VIRTUAL*` heads of the intel/modesetting drivers,
+// or by the extra heads of newer dummy drivers used by the headless session.
+// The mode is a CVT reduced blanking mode, so any resolution requested by the peer is allowed.
+//
+// Wayland: wlroots compositors (sway) create headless outputs on request (`create_output`).
+// The new outputs are captured once the peer selects them in the screen cast portal.
+
+use crate::platform::linux::{get_active_userid, get_env, is_x11};
+use hbb_common::{allow_err, bail, lazy_static, log, ResultType};
+use std::{
+    collections::{HashMap, HashSet},
+    process::Command,
+    sync::{Arc, Mutex},
+};
+
+pub const IMPL_NAME: &str = "linux_output";
+
+// Keep the same index range as `cloudydesk_idd`, the peer side is shared.
+const VIRTUAL_DISPLAY_START_FOR_PEER: u32 = 1;
+const VIRTUAL_DISPLAY_MAX_COUNT: u32 = 5;
+const MODE_NAME_PREFIX: &str = "cloudydesk_";
+const DEFAULT_MODE: MonitorMode = MonitorMode {
+    width: 1920,
+    height: 1080,
+    sync: 60,
+};
+
+lazy_static::lazy_static! {
+    static ref VIRTUAL_DISPLAY_MANAGER: Arc<Mutex<HashMap<u32, VirtualOutput>>> = Default::default();
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MonitorMode {
+    pub width: u32,
+    pub height: u32,
+    pub sync: u32,
+}
+
+enum Backend {
+    X11,
+    // The socket of the wlroots compositor.
+    Wlroots(String),
+}
+
+struct VirtualOutput {
+    name: String,
+    // The RandR mode added for the output, it is removed on plug out.
+    x11_mode: Option<String>,
+}
+
+fn get_backend() -> Option<Backend> {
+    if is_x11() {
+        return Some(Backend::X11);
+    }
+    let mut sock = std::env::var("SWAYSOCK").unwrap_or_default();
+    if sock.is_empty() {
+        sock = get_env("SWAYSOCK", &get_active_userid(), "sway");
+    }
+    if sock.is_empty() {
+        None
+    } else {
+        Some(Backend::Wlroots(sock))
+    }
+}
+
+pub fn is_supported() -> bool {
+    match get_backend() {
+        Some(Backend::X11) => {
+            let used = used_output_names();
+            xrandr_outputs()
+                .map(|outputs| {
+                    outputs
+                        .iter()
+                        .any(|o| !o.connected && !used.contains(&o.name))
+                })
+                .unwrap_or(false)
+        }
+        Some(Backend::Wlroots(sock)) => swaymsg(&sock, &["-t", "get_version"]).is_ok(),
+        None => false,
+    }
+}
+
+#[inline]
+fn used_output_names() -> HashSet<String> {
+    VIRTUAL_DISPLAY_MANAGER
+        .lock()
+        .unwrap()
+        .values()
+        .map(|o| o.name.clone())
+        .collect()
+}
+
+pub fn get_virtual_displays() -> Vec<u32> {
+    VIRTUAL_DISPLAY_MANAGER
+        .lock()
+        .unwrap()
+        .keys()
+        .cloned()
+        .collect()
+}
+
+pub fn plug_in_index_modes(idx: u32, modes: Vec<MonitorMode>) -> ResultType<()> {
+    let mut manager = VIRTUAL_DISPLAY_MANAGER.lock().unwrap();
+    if !manager.contains_key(&idx) {
+        let used = manager.values().map(|o| o.name.clone()).collect();
+        let mode = modes.first().cloned().unwrap_or(DEFAULT_MODE);
+        let output = plug_in_output(&used, &mode)?;
+        manager.insert(idx, output);
+    }
+    Ok(())
+}
+
+pub fn plug_in_peer_request(modes: Vec<Vec<MonitorMode>>) -> ResultType<Vec<u32>> {
+    let mut manager = VIRTUAL_DISPLAY_MANAGER.lock().unwrap();
+    let mut indices: Vec<u32> = Vec::new();
+    for m in modes.iter() {
+        for idx in VIRTUAL_DISPLAY_START_FOR_PEER..VIRTUAL_DISPLAY_MAX_COUNT {
+            if !manager.contains_key(&idx) {
+                let used = manager.values().map(|o| o.name.clone()).collect();
+                let mode = m.first().cloned().unwrap_or(DEFAULT_MODE);
+                match plug_in_output(&used, &mode) {
+                    Ok(output) => {
+                        manager.insert(idx, output);
+                        indices.push(idx);
+                    }
+                    Err(e) => {
+                        log::error!("Plug in virtual output failed {}", e);
+                    }
+                }
+                break;
+            }
+        }
+    }
+    Ok(indices)
+}
+
+pub fn plug_out_peer_request(indices: &[u32]) -> ResultType<()> {
+    let mut manager = VIRTUAL_DISPLAY_MANAGER.lock().unwrap();
+    for idx in indices.iter() {
+        if let Some(output) = manager.remove(idx) {
+            allow_err!(plug_out_output(&output));
+        }
+    }
+    Ok(())
+}
+
+pub fn reset_all() -> ResultType<()> {
+    plug_out_peer_request(&get_virtual_displays())
+}
+
+pub fn is_virtual_display(name: &str) -> bool {
+    VIRTUAL_DISPLAY_MANAGER
+        .lock()
+        .unwrap()
+        .values()
+        .any(|o| o.name == name)
+}
+
+pub fn change_resolution_if_is_virtual_display(name: &str, w: u32, h: u32) -> Option<bool> {
+    let mut manager = VIRTUAL_DISPLAY_MANAGER.lock().unwrap();
+    let output = manager.values_mut().find(|o| o.name == name)?;
+    let mode = MonitorMode {
+        width: w,
+        height: h,
+        sync: DEFAULT_MODE.sync,
+    };
+    match change_output_mode(output, &mode) {
+        Ok(_) => Some(true),
+        Err(e) => {
+            log::error!("Change virtual output {} to {:?} failed: {}", name, mode, e);
+            Some(false)
+        }
+    }
+}
+
+fn plug_in_output(used: &HashSet<String>, mode: &MonitorMode) -> ResultType<VirtualOutput> {
+    match get_backend() {
+        Some(Backend::X11) => {
+            let outputs = xrandr_outputs()?;
+            let Some(free) = pick_free_output(&outputs, used) else {
+                bail!("No free RandR output");
+            };
+            // Place the new output on the right of the others.
+            let x = outputs
+                .iter()
+                .filter_map(|o| o.rect.map(|(x, _, w, _)| x + w))
+                .max()
+                .unwrap_or(0);
+            let mut output = VirtualOutput {
+                name: free.to_owned(),
+                x11_mode: None,
+            };
+            x11_set_mode(&mut output, mode, Some(x))?;
+            log::info!("Plugged in virtual output {} {:?}", output.name, mode);
+            Ok(output)
+        }
+        Some(Backend::Wlroots(sock)) => {
+            let before = sway_output_names(&sock)?;
+            swaymsg(&sock, &["create_output"])?;
+            let mut name = None;
+            for _ in 0..3 {
+                let after = sway_output_names(&sock)?;
+                let diff: Vec<_> = after.difference(&before).collect();
+                if diff.len() == 1 {
+                    name = Some(diff[0].clone());
+                    break;
+                }
+                // Wait for the compositor to announce the new output.
+                std::thread::sleep(std::time::Duration::from_millis(50));
+            }
+            let Some(name) = name else {
+                bail!("Failed to find the created headless output");
+            };
+            let output = VirtualOutput {
+                name,
+                x11_mode: None,
+            };
+            if let Err(e) = sway_set_mode(&sock, &output.name, mode) {
+                allow_err!(swaymsg(&sock, &["output", &output.name, "unplug"]));
+                return Err(e);
+            }
+            log::info!("Plugged in virtual output {} {:?}", output.name, mode);
+            Ok(output)
+        }
+        None => bail!("Virtual displays are not supported by the current session"),
+    }
+}
+
+fn plug_out_output(output: &VirtualOutput) -> ResultType<()> {
+    match get_backend() {
+        Some(Backend::X11) => {
+            xrandr(&["--output", &output.name, "--off"])?;
+            if let Some(mode) = &output.x11_mode {
+                x11_remove_mode(&output.name, mode);
+            }
+        }
+        Some(Backend::Wlroots(sock)) => {
+            swaymsg(&sock, &["output", &output.name, "unplug"])?;
+        }
+        None => bail!("Virtual displays are not supported by the current session"),
+    }
+    log::info!("Plugged out virtual output {}", output.name);
+    Ok(())
+}
+
+fn change_output_mode(output: &mut VirtualOutput, mode: &MonitorMode) -> ResultType<()> {
+    match get_backend() {
+        Some(Backend::X11) => x11_set_mode(output, mode, None),
+        Some(Backend::Wlroots(sock)) => sway_set_mode(&sock, &output.name, mode),
+        None => bail!("Virtual displays are not supported by the current session"),
+    }
+}
+
+struct XrandrOutput {
+    name: String,
+    connected: bool,
+    // x, y, width, height
+    rect: Option<(i32, i32, i32, i32)>,
+}
+
+fn xrandr(args: &[&str]) -> ResultType<String> {
+    let output = Command::new("xrandr").args(args).output()?;
+    if !output.status.success() {
+        bail!(
+            "xrandr {:?} failed: {}",
+            args,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+fn xrandr_outputs() -> ResultType<Vec<XrandrOutput>> {
+    Ok(parse_xrandr_outputs(&xrandr(&["--query"])?))
+}
+
+fn parse_xrandr_outputs(query: &str) -> Vec<XrandrOutput> {
+    // Output lines are not indented, e.g.
+    // eDP-1 connected primary 1920x1080+0+0 (normal left inverted right x axis y axis) 344mm x 193mm
+    // VIRTUAL1 disconnected (normal left inverted right x axis y axis)
+    let mut outputs = Vec::new();
+    for line in query.lines() {
+        if line.starts_with(char::is_whitespace) || line.starts_with("Screen ") {
+            continue;
+        }
+        let mut words = line.split_whitespace();
+        let (Some(name), Some(state)) = (words.next(), words.next()) else {
+            continue;
+        };
+        if state != "connected" && state != "disconnected" {
+            continue;
+        }
+        let rect = words.take(2).find_map(|w| {
+            let (size, pos) = w.split_once('+')?;
+            let (width, height) = size.split_once('x')?;
+            let (x, y) = pos.split_once('+')?;
+            Some((
+                x.parse().ok()?,
+                y.parse().ok()?,
+                width.parse().ok()?,
+                height.parse().ok()?,
+            ))
+        });
+        outputs.push(XrandrOutput {
+            name: name.to_owned(),
+            connected: state == "connected",
+            rect,
+        });
+    }
+    outputs
+}
+
+// Prefer the outputs which are virtual by design, a disconnected physical connector is the last choice.
+fn pick_free_output<'a>(outputs: &'a [XrandrOutput], used: &HashSet<String>) -> Option<&'a str> {
+    let free = outputs
+        .iter()
+        .filter(|o| !o.connected && o.rect.is_none() && !used.contains(&o.name));
+    let mut fallback = None;
+    for o in free {
+        let upper = o.name.to_uppercase();
+        if upper.starts_with("VIRTUAL") || upper.starts_with("DUMMY") {
+            return Some(&o.name);
+        }
+        fallback.get_or_insert(o.name.as_str());
+    }
+    fallback
+}
+
+fn x11_set_mode(output: &mut VirtualOutput, mode: &MonitorMode, x: Option<i32>) -> ResultType<()> {
+    let name = format!(
+        "{}{}x{}_{}",
+        MODE_NAME_PREFIX, mode.width, mode.height, mode.sync
+    );
+    if output.x11_mode.as_ref() == Some(&name) {
+        return Ok(());
+    }
+    let mut args = vec!["--newmode".to_owned(), name.clone()];
+    args.extend(
+        cvt_reduced_blanking(mode)
+            .split_whitespace()
+            .map(|s| s.to_owned()),
+    );
+    let args: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
+    // The mode may be left by a previous run or used by another virtual output.
+    if let Err(e) = xrandr(&args) {
+        log::debug!("{}", e);
+    }
+    xrandr(&["--addmode", &output.name, &name])?;
+    let pos = x.map(|x| format!("{}x0", x));
+    let mut args = vec!["--output", &output.name, "--mode", &name];
+    if let Some(pos) = pos.as_ref() {
+        args.extend(["--pos", pos.as_str()]);
+    }
+    if let Err(e) = xrandr(&args) {
+        x11_remove_mode(&output.name, &name);
+        return Err(e);
+    }
+    if let Some(old) = output.x11_mode.replace(name) {
+        x11_remove_mode(&output.name, &old);
+    }
+    Ok(())
+}
+
+fn x11_remove_mode(output: &str, mode: &str) {
+    let _ = xrandr(&["--delmode", output, mode]);
+    // Fails if the mode is still used by another output, it is fine.
+    let _ = xrandr(&["--rmmode", mode]);
+}
+
+// CVT 1.2 reduced blanking timings, the same as `cvt -r`.
+// Returns "clock hdisp hsyncstart hsyncend htotal vdisp vsyncstart vsyncend vtotal flags".
+fn cvt_reduced_blanking(mode: &MonitorMode) -> String {
+    const CLOCK_STEP: f64 = 0.25;
+    const MIN_V_BLANK: f64 = 460.0;
+    const RB_H_BLANK: u32 = 160;
+    const RB_H_SYNC: u32 = 32;
+    const RB_V_FPORCH: u32 = 3;
+    const RB_MIN_V_BPORCH: u32 = 6;
+
+    let refresh = if mode.sync == 0 {
+        60.0
+    } else {
+        mode.sync as f64
+    };
+    let width = mode.width / 8 * 8;
+    let height = mode.height;
+    // The vertical sync width depends on the aspect ratio.
+    let v_sync = if width * 3 == height * 4 {
+        4
+    } else if width * 9 == height * 16 {
+        5
+    } else if width * 10 == height * 16 {
+        6
+    } else if width * 4 == height * 5 || width * 9 == height * 15 {
+        7
+    } else {
+        10
+    };
+    let h_period = (1000000.0 / refresh - MIN_V_BLANK) / height as f64;
+    let vbi_lines =
+        ((MIN_V_BLANK / h_period) as u32 + 1).max(RB_V_FPORCH + v_sync + RB_MIN_V_BPORCH);
+    let v_total = height + vbi_lines;
+    let h_total = width + RB_H_BLANK;
+    let clock =
+        CLOCK_STEP * ((refresh * v_total as f64 * h_total as f64 / 1000000.0) / CLOCK_STEP).floor();
+    let h_sync_end = width + RB_H_BLANK / 2;
+    let h_sync_start = h_sync_end - RB_H_SYNC;
+    let v_sync_start = height + RB_V_FPORCH;
+    format!(
+        "{:.2} {} {} {} {} {} {} {} {} +hsync -vsync",
+        clock,
+        width,
+        h_sync_start,
+        h_sync_end,
+        h_total,
+        height,
+        v_sync_start,
+        v_sync_start + v_sync,
+        v_total
+    )
+}
+
+fn swaymsg(sock: &str, args: &[&str]) -> ResultType<String> {
+    let output = Command::new("swaymsg")
+        .arg("-s")
+        .arg(sock)
+        .args(args)
+        .output()?;
+    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+    if !output.status.success() {
+        bail!("swaymsg {:?} failed: {}", args, stdout.trim());
+    }
+    Ok(stdout)
+}
+
+fn sway_output_names(sock: &str) -> ResultType<HashSet<String>> {
+    let outputs: serde_json::Value =
+        serde_json::from_str(&swaymsg(sock, &["-r", "-t", "get_outputs"])?)?;
+    Ok(outputs
+        .as_array()
+        .map(|v| {
+            v.iter()
+                .filter_map(|o| o["name"].as_str().map(|s| s.to_owned()))
+                .collect()
+        })
+        .unwrap_or_default())
+}
+
+fn sway_set_mode(sock: &str, name: &str, mode: &MonitorMode) -> ResultType<()> {
+    let mode = format!("{}x{}@{}Hz", mode.width, mode.height, mode.sync);
+    swaymsg(sock, &["output", name, "mode", "--custom", &mode])?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cvt_reduced_blanking() {
+        assert_eq!(
+            cvt_reduced_blanking(&DEFAULT_MODE),
+            "138.50 1920 1968 2000 2080 1080 1083 1088 1111 +hsync -vsync"
+        );
+        let query = "Screen 0: minimum 8 x 8, current 1920 x 1080, maximum 32767 x 32767\n\
+            eDP-1 connected primary 1920x1080+0+0 (normal left inverted right x axis y axis) 344mm x 193mm\n   \
+            1920x1080     60.01*+\n\
+            HDMI-1 disconnected (normal left inverted right x axis y axis)\n\
+            VIRTUAL1 disconnected (normal left inverted right x axis y axis)\n";
+        let outputs = parse_xrandr_outputs(query);
+        assert_eq!(outputs.len(), 3);
+        assert_eq!(outputs[0].rect, Some((0, 0, 1920, 1080)));
+        assert_eq!(
+            pick_free_output(&outputs, &HashSet::new()),
+            Some("VIRTUAL1")
+        );
+    }
+}